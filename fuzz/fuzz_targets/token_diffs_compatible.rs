@@ -0,0 +1,53 @@
+//! Whenever `token_diffs_compatible` accepts a pair of diffs, the solver
+//! sends at least what the user asks for and only takes what the user
+//! offers.
+
+#![no_main]
+
+use {
+	libfuzzer_sys::fuzz_target,
+	near_intents::{
+		auction::token_diffs_compatible,
+		types::{AssetId, TokenDiff},
+	},
+};
+
+/// One of a handful of assets, so the two diffs often overlap. The last
+/// is non-fungible.
+fn asset(index: u8) -> AssetId {
+	match index % 4 {
+		3 => AssetId::Nep171 {
+			contract: "nft.near".into(),
+			token_id: "1".into(),
+		},
+		i => AssetId::Nep141(format!("token{i}.near")),
+	}
+}
+
+fn diff(entries: Vec<(u8, i128)>) -> TokenDiff {
+	entries
+		.into_iter()
+		.map(|(index, amount)| (asset(index), amount))
+		.collect()
+}
+
+fuzz_target!(|input: (Vec<(u8, i128)>, Vec<(u8, i128)>)| {
+	let (user, solver) = (diff(input.0), diff(input.1));
+	if !token_diffs_compatible(&user, &solver) {
+		return;
+	}
+	for (asset, &amount) in &solver {
+		let offered = user.get(asset).copied();
+		assert!(offered.is_some(), "solver names {asset:?} the user doesn't");
+		if amount > 0 {
+			let offered = offered.unwrap_or(0);
+			assert!(offered < 0, "solver takes {asset:?} the user wants");
+			assert!(amount.unsigned_abs() <= offered.unsigned_abs());
+		}
+	}
+	for (asset, &wanted) in user.iter().filter(|&(_, &amount)| amount > 0) {
+		let sent = solver.get(asset).copied().unwrap_or(0);
+		assert!(sent < 0, "solver doesn't send {asset:?}");
+		assert!(sent.unsigned_abs() >= wanted.unsigned_abs());
+	}
+});
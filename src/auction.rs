@@ -1,20 +1,432 @@
 use {
-	crate::types::{Intent, IntentStatus, Quote, Settlement, TokenDiff},
+	crate::{
+		journal::{NullSink, SettlementSink},
+		types::{
+			AssetId, AssetMetadata, AssetRegistry, Fill, Intent, IntentAction,
+			IntentId, IntentStatus, LimitPrice, Outcome, Quote, SettledIntent,
+			Settlement, SettlementReceipt, TokenDiff, to_i128, to_u128,
+			verify_ed25519,
+		},
+	},
+	ed25519_dalek::{Signer, SigningKey},
 	mosaik::{groups::StateMachine, primitives::UniqueId, unique_id},
 	serde::{Deserialize, Serialize},
-	std::collections::BTreeMap,
+	sha2::{Digest, Sha256},
+	std::{
+		borrow::Cow,
+		cmp::Reverse,
+		collections::{BTreeMap, BTreeSet, VecDeque},
+		sync::{
+			Arc,
+			atomic::{AtomicBool, Ordering},
+		},
+	},
 };
 
 /// Commands that mutate the auction state machine.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AuctionCommand {
-	/// Submit a user intent with token_diff.
-	SubmitIntent(Intent),
-	/// Submit a solver quote (response to an RFQ).
-	SubmitQuote(Quote),
-	/// Clear the current round: match intents with best quotes
-	/// and produce settlements.
-	ClearRound,
+	/// Submit a user intent with token_diff, received at `now_ms`.
+	/// Intents with an invalid signature are dropped.
+	SubmitIntent { intent: Intent, now_ms: u64 },
+	/// Submit a solver quote (response to an RFQ), received by the relay
+	/// at `now_ms`. Only accepted while a round is open and its quote
+	/// window has not passed. A solver's later quote for an intent
+	/// replaces its earlier one.
+	SubmitQuote { quote: Quote, now_ms: u64 },
+	/// Submit several quotes from one solver at once, received at
+	/// `now_ms`. Each quote is validated as by `SubmitQuote`. With
+	/// `atomic`, the quotes only settle if every one of them wins its
+	/// intent in the same `ClearRound`; otherwise the whole bundle is
+	/// forfeited for the round. A later quote from the solver for one of
+	/// the bundle's intents stays bound by it. Atomic bundles never settle
+	/// on arrival in `ClearingMode::Continuous`.
+	SubmitQuoteBundle {
+		solver_id: String,
+		quotes: Vec<Quote>,
+		atomic: bool,
+		now_ms: u64,
+	},
+	/// Open the current round for quotes until `deadline_ms`, modelling
+	/// the Solver Relay's bounded quote collection window. Ignored while
+	/// the round is already open, so its window can't be extended.
+	OpenRound { deadline_ms: u64 },
+	/// Clear the current round at `now_ms`: match intents with the best
+	/// quotes that arrived in its window and produce settlements. Intents
+	/// past their deadline aren't matched but stay pending until
+	/// `ExpireStale`. Closes the round.
+	ClearRound { now_ms: u64 },
+	/// `ClearRound`, but only pending intents that name `asset` and
+	/// whose signer starts with `signer_prefix` (each if set) can
+	/// settle; the rest stay pending. The round still
+	/// closes, and its quotes are dropped, for every intent.
+	ClearRoundFiltered {
+		now_ms: u64,
+		asset: Option<AssetId>,
+		signer_prefix: Option<String>,
+	},
+	/// Publish a round sealed under `reveal_delay_ms`, once the delay has
+	/// passed since it cleared. Ignored earlier, or if it isn't sealed.
+	RevealRound { round: u64, now_ms: u64 },
+	/// Mark a previously cleared round as failed on-chain (e.g. the
+	/// settlement tx reverted). Its intents go back to pending if they are
+	/// still within their deadline at `now_ms`, otherwise they become
+	/// `Expired`.
+	FailSettlement { round: u64, now_ms: u64 },
+	/// Clear the current round as `ClearRound` would, then expire every
+	/// intent still pending, leaving an empty book to snapshot before
+	/// shutting down.
+	Drain { now_ms: u64 },
+	/// Expire every pending intent past its deadline at `now_ms` and drop
+	/// its quotes, without clearing a round. Lets a background task keep
+	/// the book clean between rounds.
+	ExpireStale { now_ms: u64 },
+	/// Add `amount` to a solver's bond. Solvers need at least
+	/// `min_solver_bond` posted for their quotes to be accepted. Only
+	/// applied if `auth` is a valid signature by one of the config's
+	/// `operator_keys`; build it with [`AuctionCommand::deposit_bond`].
+	DepositBond {
+		solver_id: String,
+		amount: u128,
+		auth: OperatorSignature,
+	},
+	/// Bind account `signer_id`, a user or a solver, to `public_key`: its
+	/// intents and quotes are then only accepted if signed by that key.
+	/// Replaces any key registered before. Keys are only ever bound this
+	/// way, so an account with none accepts any valid signature, and one
+	/// that needs protecting from impersonation must be registered. Only
+	/// applied with a valid `auth`, like `DepositBond`; build it with
+	/// [`AuctionCommand::register_key`].
+	RegisterKey {
+		signer_id: String,
+		public_key: Vec<u8>,
+		auth: OperatorSignature,
+	},
+	/// Record that a solver was online at `now_ms`. With
+	/// `solver_liveness_ms` set, only recently seen solvers' quotes can
+	/// win.
+	SolverHeartbeat { solver_id: String, now_ms: u64 },
+	/// Record that settlements, expirations and round boundaries up to
+	/// and including `round` were published downstream, so a relay on
+	/// another node doesn't send them again. Only applied with a valid
+	/// `auth`, and for a round that is publishable; build it with
+	/// [`AuctionCommand::mark_published`].
+	MarkPublished { round: u64, auth: OperatorSignature },
+	/// Record that `Outcome::RoundOpened` for rounds up to and including
+	/// `round` was published, which happens before the round closes.
+	/// Only applied with a valid `auth`, and for a round no later than
+	/// the current one; build it with
+	/// [`AuctionCommand::mark_open_published`].
+	MarkOpenPublished { round: u64, auth: OperatorSignature },
+	/// Replace a pending intent's terms in place at `now_ms`, keeping
+	/// its id and place in the book. `signature` must be the intent's key
+	/// signing the amended intent, and `authorization` that key signing
+	/// `nonce` with `signature`; build it with
+	/// [`AuctionCommand::amend_intent`]. `nonce` must exceed the intent's
+	/// last amendment's, so an old amendment can't be replayed, and
+	/// `new_deadline_ms` must be after `now_ms`. Quotes gathered against
+	/// the old terms are dropped.
+	AmendIntent {
+		id: IntentId,
+		signer_id: String,
+		new_token_diff: TokenDiff,
+		new_deadline_ms: u64,
+		nonce: u64,
+		now_ms: u64,
+		signature: Vec<u8>,
+		authorization: Vec<u8>,
+	},
+	/// Replace the matching configuration through the log, so every
+	/// replica switches at the same point. Pending intents and quotes are
+	/// kept; the new values apply from the next `ClearRound`. Invalid
+	/// values (a fee or surplus split above 10_000 bps, or a zero
+	/// settlement cap) reject the whole command. Like `DepositBond`, only
+	/// applied with a valid `auth`; build it with
+	/// [`AuctionCommand::reconfigure`].
+	Reconfigure {
+		fee_bps: u16,
+		max_settlements_per_round: Option<usize>,
+		surplus_policy: SurplusPolicy,
+		auth: OperatorSignature,
+	},
+	/// Stop or resume accepting intents and quotes, e.g. for maintenance.
+	/// While paused, submissions and amendments are rejected as `Paused`;
+	/// rounds still clear so the book can drain, and no state is lost.
+	/// Like `Reconfigure`, only applied with a valid `auth`; build it with
+	/// [`AuctionCommand::set_paused`].
+	SetPaused {
+		paused: bool,
+		auth: OperatorSignature,
+	},
+	/// Apply several commands in order as one log entry, e.g. all quotes
+	/// collected for a round. Each command is accepted or rejected as if
+	/// submitted alone. Nested batches are ignored, also when wrapped in
+	/// a `Request`.
+	Batch(Vec<AuctionCommand>),
+	/// Apply `command` unless a `Request` with the same `request_id` was
+	/// applied before, so a client can resend a command whose reply was
+	/// lost without applying it twice. The most recent
+	/// `REQUEST_ID_CAPACITY` ids are remembered. A request wrapping
+	/// another is ignored.
+	Request {
+		request_id: u64,
+		command: Box<AuctionCommand>,
+	},
+}
+
+impl AuctionCommand {
+	/// The variant name, for logs.
+	pub fn kind(&self) -> &'static str {
+		match self {
+			Self::SubmitIntent { .. } => "submit_intent",
+			Self::SubmitQuote { .. } => "submit_quote",
+			Self::SubmitQuoteBundle { .. } => "submit_quote_bundle",
+			Self::OpenRound { .. } => "open_round",
+			Self::ClearRound { .. } => "clear_round",
+			Self::ClearRoundFiltered { .. } => "clear_round_filtered",
+			Self::RevealRound { .. } => "reveal_round",
+			Self::FailSettlement { .. } => "fail_settlement",
+			Self::ExpireStale { .. } => "expire_stale",
+			Self::Drain { .. } => "drain",
+			Self::DepositBond { .. } => "deposit_bond",
+			Self::RegisterKey { .. } => "register_key",
+			Self::SolverHeartbeat { .. } => "solver_heartbeat",
+			Self::MarkPublished { .. } => "mark_published",
+			Self::MarkOpenPublished { .. } => "mark_open_published",
+			Self::AmendIntent { .. } => "amend_intent",
+			Self::Reconfigure { .. } => "reconfigure",
+			Self::SetPaused { .. } => "set_paused",
+			Self::Batch(_) => "batch",
+			Self::Request { command, .. } => command.kind(),
+		}
+	}
+
+	/// Whether applying the command again has no further effect, so it
+	/// can be resent as is. Others need wrapping in a `Request`.
+	pub fn is_idempotent(&self) -> bool {
+		matches!(
+			self,
+			// Deduplicated by signer sequence, or by operator nonce.
+			Self::SubmitIntent { .. }
+				| Self::DepositBond { .. }
+				| Self::RegisterKey { .. }
+				| Self::Reconfigure { .. }
+				| Self::SolverHeartbeat { .. }
+				| Self::MarkPublished { .. }
+				| Self::MarkOpenPublished { .. }
+				| Self::ExpireStale { .. }
+				| Self::SetPaused { .. }
+				| Self::Request { .. }
+		)
+	}
+
+	/// `DepositBond` of `amount` for `solver_id`, signed by `operator`
+	/// with `nonce`, which must exceed every nonce the state machine has
+	/// applied from that key.
+	pub fn deposit_bond(
+		operator: &SigningKey,
+		nonce: u64,
+		solver_id: impl Into<String>,
+		amount: u128,
+	) -> Self {
+		let solver_id = solver_id.into();
+		let auth = OperatorSignature::sign(
+			operator,
+			nonce,
+			&("deposit_bond", &solver_id, amount),
+		);
+		Self::DepositBond {
+			solver_id,
+			amount,
+			auth,
+		}
+	}
+
+	/// `RegisterKey` of `public_key` for `signer_id`, signed by
+	/// `operator` with `nonce` as for [`deposit_bond`](Self::deposit_bond).
+	pub fn register_key(
+		operator: &SigningKey,
+		nonce: u64,
+		signer_id: impl Into<String>,
+		public_key: Vec<u8>,
+	) -> Self {
+		let signer_id = signer_id.into();
+		let auth = OperatorSignature::sign(
+			operator,
+			nonce,
+			&("register_key", &signer_id, &public_key),
+		);
+		Self::RegisterKey {
+			signer_id,
+			public_key,
+			auth,
+		}
+	}
+
+	/// `AmendIntent` of `intent` to `new_token_diff` and
+	/// `new_deadline_ms` at `now_ms`, signed by the intent's `key` with
+	/// `nonce`.
+	pub fn amend_intent(
+		key: &SigningKey,
+		intent: &Intent,
+		nonce: u64,
+		new_token_diff: TokenDiff,
+		new_deadline_ms: u64,
+		now_ms: u64,
+	) -> Self {
+		let mut amended = Intent {
+			action: IntentAction::TokenDiff {
+				diff: new_token_diff.clone(),
+			},
+			deadline_ms: new_deadline_ms,
+			..intent.clone()
+		};
+		amended.sign(key);
+		let payload = amendment_payload(intent.id, nonce, &amended.signature);
+		Self::AmendIntent {
+			id: intent.id,
+			signer_id: intent.signer_id.clone(),
+			new_token_diff,
+			new_deadline_ms,
+			nonce,
+			now_ms,
+			authorization: key.sign(&payload).to_bytes().to_vec(),
+			signature: amended.signature,
+		}
+	}
+
+	/// `Reconfigure` to the given values, signed by `operator` with
+	/// `nonce` as for [`deposit_bond`](Self::deposit_bond).
+	pub fn reconfigure(
+		operator: &SigningKey,
+		nonce: u64,
+		fee_bps: u16,
+		max_settlements_per_round: Option<usize>,
+		surplus_policy: SurplusPolicy,
+	) -> Self {
+		let auth = OperatorSignature::sign(
+			operator,
+			nonce,
+			&(
+				"reconfigure",
+				fee_bps,
+				max_settlements_per_round,
+				surplus_policy,
+			),
+		);
+		Self::Reconfigure {
+			fee_bps,
+			max_settlements_per_round,
+			surplus_policy,
+			auth,
+		}
+	}
+
+	/// `SetPaused` to `paused`, signed by `operator` with `nonce` as for
+	/// [`deposit_bond`](Self::deposit_bond).
+	pub fn set_paused(operator: &SigningKey, nonce: u64, paused: bool) -> Self {
+		let auth =
+			OperatorSignature::sign(operator, nonce, &("set_paused", paused));
+		Self::SetPaused { paused, auth }
+	}
+
+	/// `MarkPublished` of `round`, signed by `operator` with `nonce` as
+	/// for [`deposit_bond`](Self::deposit_bond).
+	pub fn mark_published(
+		operator: &SigningKey,
+		nonce: u64,
+		round: u64,
+	) -> Self {
+		let auth = OperatorSignature::sign(
+			operator,
+			nonce,
+			&("mark_published", round),
+		);
+		Self::MarkPublished { round, auth }
+	}
+
+	/// `MarkOpenPublished` of `round`, signed by `operator` with `nonce`
+	/// as for [`deposit_bond`](Self::deposit_bond).
+	pub fn mark_open_published(
+		operator: &SigningKey,
+		nonce: u64,
+		round: u64,
+	) -> Self {
+		let fields = ("mark_open_published", round);
+		let auth = OperatorSignature::sign(operator, nonce, &fields);
+		Self::MarkOpenPublished { round, auth }
+	}
+}
+
+/// An [`AuctionCommand`] as replicated in the log, tagged with the
+/// [`PROTOCOL_VERSION`] it was encoded under. Replicas ignore commands
+/// of any other version rather than misapply them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionedCommand {
+	pub version: u32,
+	pub command: AuctionCommand,
+}
+
+impl From<AuctionCommand> for VersionedCommand {
+	/// Tag `command` with the current [`PROTOCOL_VERSION`].
+	fn from(command: AuctionCommand) -> Self {
+		Self {
+			version: PROTOCOL_VERSION,
+			command,
+		}
+	}
+}
+
+/// An operator's authorization of a privileged command: an ed25519
+/// signature over the command's fields and a nonce, so it can't be
+/// replayed or moved to another command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperatorSignature {
+	/// Must exceed the last nonce applied from `public_key`.
+	pub nonce: u64,
+
+	/// The signing operator, one of the config's `operator_keys`.
+	pub public_key: Vec<u8>,
+
+	pub signature: Vec<u8>,
+}
+
+impl OperatorSignature {
+	fn sign(
+		operator: &SigningKey,
+		nonce: u64,
+		fields: &impl Serialize,
+	) -> Self {
+		Self {
+			nonce,
+			public_key: operator.verifying_key().to_bytes().to_vec(),
+			signature: operator
+				.sign(&Self::payload(nonce, fields))
+				.to_bytes()
+				.to_vec(),
+		}
+	}
+
+	/// Whether `signature` is `public_key`'s over `fields` and `nonce`.
+	fn verify(&self, fields: &impl Serialize) -> bool {
+		verify_ed25519(
+			&self.public_key,
+			&self.signature,
+			&Self::payload(self.nonce, fields),
+		)
+	}
+
+	fn payload(nonce: u64, fields: &impl Serialize) -> Vec<u8> {
+		bincode::serialize(&(nonce, fields))
+			.expect("command fields are always serializable")
+	}
+}
+
+/// What an intent's key signs to authorize amendment `nonce`, whose
+/// amended intent carries `signature`.
+fn amendment_payload(id: IntentId, nonce: u64, signature: &[u8]) -> Vec<u8> {
+	bincode::serialize(&("amend_intent", id, nonce, signature))
+		.expect("amendment fields are always serializable")
 }
 
 /// Queries against the auction state.
@@ -22,14 +434,125 @@ pub enum AuctionCommand {
 pub enum AuctionQuery {
 	/// List all pending (unsettled) intents.
 	PendingIntents,
-	/// Get the settlement result for a specific round.
+	/// Get the settlement result for a specific round, or just its
+	/// commitment while it is sealed under `reveal_delay_ms`.
 	RoundResult(u64),
 	/// Get the current round number.
 	CurrentRound,
-	/// Get the status of a specific intent.
-	IntentStatus(u64),
+	/// The current round's quote window deadline, `None` while closed.
+	QuoteWindow,
+	/// Get the status of a specific intent. One past its deadline reads
+	/// `Pending` until `ExpireStale` expires it.
+	IntentStatus(IntentId),
 	/// Get all quotes for a specific intent.
-	QuotesForIntent(u64),
+	QuotesForIntent(IntentId),
+	/// Intents currently in a status. Beyond `Pending`, only the most
+	/// recent `TERMINAL_INTENT_CAPACITY` settled or expired intents are
+	/// remembered.
+	IntentsByStatus(IntentStatus),
+	/// Number of intents accepted from a signer in the current round.
+	IntentsBySigner(String),
+	/// Number of intents dropped by the per-signer rate limit.
+	RateLimitedIntents,
+	/// Number of quotes rejected for arriving outside a quote window.
+	LateQuotes,
+	/// Get the total surplus (price improvement) delivered in a round.
+	SurplusForRound(u64),
+	/// A retained round's [`Settlement::residual`], which must be all
+	/// zeros. `None` if the round isn't retained.
+	RoundBalance(u64),
+	/// Page through retained settlements for rounds `[start, start + limit)`,
+	/// ordered by round ascending. Pruned or empty rounds are skipped, and
+	/// the page ends before the first round sealed under
+	/// `reveal_delay_ms`.
+	RoundHistory { start: u64, limit: usize },
+	/// Lifetime gross settled volume of one asset: every cleared round
+	/// that hasn't failed, including rounds since pruned under
+	/// `max_retained_rounds`, so it can exceed what `RoundHistory` sums.
+	AssetVolume(AssetId),
+	/// `AssetVolume` for every asset.
+	AllVolumes,
+	/// Volume-weighted average price of `asset_out` in `asset_in` over
+	/// the intents that swapped exactly that pair in the last
+	/// `last_n_rounds` closed, retained and non-failed rounds.
+	Twap {
+		asset_in: AssetId,
+		asset_out: AssetId,
+		last_n_rounds: u64,
+	},
+	/// The most recent `limit` rejected intents and quotes, oldest first.
+	RecentRejections { limit: usize },
+	/// A solver's current bond balance.
+	SolverBond(String),
+	/// Solvers whose last heartbeat is within `solver_liveness_ms` of
+	/// `now_ms`, or every solver that ever sent one if liveness isn't
+	/// checked.
+	LiveSolvers { now_ms: u64 },
+	/// Retained settlements not yet marked published, by round ascending.
+	UnpublishedRounds,
+	/// Settlements, expirations and boundaries of closed rounds not yet
+	/// marked published, each with its round, by round ascending, then
+	/// the open round's `RoundOpened` until it is marked published.
+	/// Within a round `RoundOpened` comes first, then expirations, then
+	/// the settlement, then `RoundClosed`.
+	UnpublishedOutcomes,
+	/// The settlement `ClearRound { now_ms }` would produce right now,
+	/// without clearing. `None` if nothing would settle.
+	PreviewRound { now_ms: u64 },
+	/// Why `ClearRound { now_ms }` wouldn't settle a pending intent right
+	/// now. `None` if it would.
+	WhyUnsettled { intent_id: IntentId, now_ms: u64 },
+	/// Counters describing the auction's activity so far.
+	Metrics,
+	/// Number of distinct solvers with a compatible quote for an intent in
+	/// the current round.
+	CompetitionLevel(IntentId),
+	/// An intent's quotes sorted by `amount_out` descending, each flagged
+	/// if it would currently win.
+	RankedQuotes(IntentId),
+	/// Display metadata for an asset, if registered.
+	AssetMeta(AssetId),
+	/// The quote currently winning an intent, if any. For a split fill,
+	/// the largest contributing quote.
+	BestQuote(IntentId),
+	/// What a settled intent paid and received. Answers with the intent's
+	/// `Status` instead if it hasn't settled or its round was pruned.
+	Receipt(IntentId),
+	/// The status of every member of an intent group. Empty if no intent
+	/// in the group was ever accepted.
+	GroupStatus(String),
+	/// Whether a solver is in `preferred_solvers`.
+	IsPreferred(String),
+	/// Whether submissions are paused by `SetPaused`.
+	IsPaused,
+	/// Receipts for every retained round that filled part or all of an
+	/// intent, by round ascending.
+	FillHistory(IntentId),
+	/// Whether `candidate_diff` would be a compatible counter-diff for a
+	/// pending intent, so a solver can check a quote before submitting.
+	CheckCompatible {
+		intent_id: IntentId,
+		candidate_diff: TokenDiff,
+	},
+	/// Distribution of how long intents waited to settle.
+	LatencyStats,
+	/// The highest intent sequence accepted from a signer, if any.
+	SignerNonce(String),
+	/// A solver's decaying reputation, zero if it never won a fill.
+	Reputation(String),
+	/// How many pending intents trade each `(asset_in, asset_out)` pair,
+	/// `asset_in` being sent and `asset_out` received. A basket counts
+	/// once towards every pair of an asset it sends and one it receives.
+	ActivePairs,
+	/// Every pending intent with its best quote and competition level,
+	/// and the current round, in one round-trip.
+	OrderBook,
+	/// Sealed rounds whose `reveal_delay_ms` has passed by `now_ms`, so a
+	/// `RevealRound` for each would apply, by round ascending.
+	DueReveals { now_ms: u64 },
+	/// The highest nonce applied from an operator key, if any, so a
+	/// long-running signer such as the outcome relay can pick its next.
+	OperatorNonce(Vec<u8>),
 }
 
 /// Results returned by auction queries.
@@ -40,6 +563,806 @@ pub enum AuctionQueryResult {
 	RoundNumber(u64),
 	Status(IntentStatus),
 	Quotes(Vec<Quote>),
+	Count(u64),
+	Surplus(Option<TokenDiff>),
+	Balance(Option<TokenDiff>),
+	Rounds(Vec<Settlement>),
+	Volume(u128),
+	Volumes(BTreeMap<AssetId, u128>),
+	Rejections(Vec<RejectionRecord>),
+	Bond(u128),
+	Metrics(AuctionMetrics),
+	RankedQuotes(Vec<RankedQuote>),
+	Quote(Option<Quote>),
+	AssetMeta(Option<AssetMetadata>),
+	Receipt(SettlementReceipt),
+	Statuses(BTreeMap<IntentId, IntentStatus>),
+	Flag(bool),
+	Latency(LatencyStats),
+	Nonce(Option<u64>),
+	Receipts(Vec<SettlementReceipt>),
+	Compatibility(Result<(), Incompatibility>),
+	Outcomes(Vec<(u64, Outcome)>),
+	Unsettled(Option<UnsettledReason>),
+	Solvers(Vec<String>),
+	Price(Option<Price>),
+	Deadline(Option<u64>),
+	Book(OrderBook),
+	Score(u64),
+	/// The commitment of a round that is sealed until revealed.
+	Sealed([u8; 32]),
+	Pairs(BTreeMap<(AssetId, AssetId), u64>),
+	RoundNumbers(Vec<u64>),
+}
+
+/// An exact price: `amount_out` units of one asset per `amount_in` of
+/// another. Kept as a ratio of totals so no precision is lost until the
+/// caller picks a scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Price {
+	pub amount_out: u128,
+	pub amount_in: u128,
+}
+
+impl Price {
+	/// The price as a fixed-point number with `decimals` fractional
+	/// digits, rounded down. `None` on overflow or a zero `amount_in`.
+	pub fn to_fixed(self, decimals: u32) -> Option<u128> {
+		let scale = 10u128.checked_pow(decimals)?;
+		let whole = self.amount_out.checked_div(self.amount_in)?;
+		let fraction = self.amount_out % self.amount_in;
+		whole
+			.checked_mul(scale)?
+			.checked_add(fraction.checked_mul(scale)? / self.amount_in)
+	}
+}
+
+/// A quote in `RankedQuotes` order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankedQuote {
+	pub quote: Quote,
+
+	/// Whether clearing now would settle the intent with this quote.
+	pub winning: bool,
+}
+
+/// A snapshot of the pending intents, as answered to `OrderBook`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderBook {
+	pub round: u64,
+
+	/// One entry per pending intent, by intent id.
+	pub entries: Vec<BookEntry>,
+}
+
+/// A pending intent in an [`OrderBook`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookEntry {
+	pub intent: Intent,
+
+	/// What `BestQuote` would answer for the intent.
+	pub best_quote: Option<Quote>,
+
+	/// What `CompetitionLevel` would answer for the intent.
+	pub competition_level: u64,
+}
+
+/// A snapshot of the auction's counters, for export to a metrics system.
+///
+/// Counters only ever increase, except `pending_count` and `volume`
+/// (which drops when a settlement fails).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuctionMetrics {
+	/// `SubmitIntent` commands applied, accepted or not.
+	pub intents_submitted: u64,
+
+	/// Intents settled by `ClearRound`, including ones whose settlement
+	/// later failed. A partly filled intent counts once, when its last
+	/// fill settles it.
+	pub intents_settled: u64,
+
+	/// `SubmitQuote` commands applied, accepted or not.
+	pub quotes_submitted: u64,
+
+	/// Quotes rejected on submission or while matching.
+	pub quotes_rejected: u64,
+
+	/// `ClearRound` commands applied.
+	pub rounds_cleared: u64,
+
+	/// Expirations dropped unpublished because more than
+	/// `EXPIRY_LOG_CAPACITY` awaited publishing. Their intents still
+	/// report `IntentStatus::Expired` while retained.
+	#[serde(default)]
+	pub expiries_dropped: u64,
+
+	/// Intents currently pending.
+	pub pending_count: u64,
+
+	/// Lifetime gross settled volume per asset over non-failed rounds,
+	/// as `AuctionQuery::AllVolumes`.
+	pub volume: BTreeMap<AssetId, u128>,
+}
+
+/// Upper bounds of the settlement latency buckets, in milliseconds. A
+/// final bucket counts everything slower.
+pub const LATENCY_BUCKETS_MS: [u64; 9] =
+	[100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000];
+
+/// How long intents waited between submission and settlement.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyStats {
+	/// Settled intents per bucket of [`LATENCY_BUCKETS_MS`], followed by
+	/// the overflow bucket.
+	pub counts: Vec<u64>,
+
+	/// Upper bound of the bucket holding the median, or the slowest
+	/// latency seen if that is the overflow bucket. `None` before the
+	/// first settlement.
+	pub p50_ms: Option<u64>,
+
+	/// Like `p50_ms`, for the 95th percentile.
+	pub p95_ms: Option<u64>,
+}
+
+impl LatencyStats {
+	fn new(counts: &[u64], max_ms: u64) -> Self {
+		let total = counts.iter().sum::<u64>();
+		let percentile = |pct: u64| {
+			// The 1-based rank of the sample at `pct`, rounded up.
+			let rank = (total * pct).div_ceil(100).max(1);
+			let mut seen = 0;
+			let bucket = counts.iter().position(|&count| {
+				seen += count;
+				seen >= rank
+			})?;
+			Some(LATENCY_BUCKETS_MS.get(bucket).copied().unwrap_or(max_ms))
+		};
+		Self {
+			counts: counts.to_vec(),
+			p50_ms: percentile(50),
+			p95_ms: percentile(95),
+		}
+	}
+}
+
+/// Version of the command, query and snapshot encodings, bumped on every
+/// breaking change to them. It is the last word of
+/// `AuctionStateMachine::ID`, so replicas of different versions form
+/// different groups instead of misreading each other's log, it tags
+/// every [`VersionedCommand`] and it prefixes every snapshot.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// How many recent intent signers and sequences are remembered for
+/// dedup.
+const SEEN_INTENT_CAPACITY: usize = 4096;
+
+/// How many intents that left the book are kept for status queries.
+pub const TERMINAL_INTENT_CAPACITY: usize = 1024;
+
+/// How many unpublished expirations the state machine keeps.
+const EXPIRY_LOG_CAPACITY: usize = 4096;
+
+/// How many unpublished round boundaries the state machine keeps.
+const ROUND_EVENT_CAPACITY: usize = 4096;
+
+/// How many recent `Request` ids are remembered for dedup.
+const REQUEST_ID_CAPACITY: usize = 4096;
+
+/// How many rejections the state machine keeps for diagnostics.
+const REJECTION_LOG_CAPACITY: usize = 1024;
+
+/// Why an intent or quote was dropped by the state machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectionReason {
+	/// A quote referenced an intent that is not pending.
+	UnknownIntent,
+	/// The intent, or a quote or the intent it targets, is past its
+	/// deadline.
+	Expired,
+	/// The quote's counter-diff doesn't satisfy the intent.
+	Incompatible,
+	/// An intent with the same id, or recently one with the same terms,
+	/// was already submitted.
+	Duplicate,
+	/// The signer exceeded its per-round intent limit.
+	RateLimited,
+	/// The intent's or quote's signature did not verify.
+	InvalidSignature,
+	/// The quote arrived while no round was open for quotes.
+	QuoteWindowClosed,
+	/// Settling the quote would overflow an amount.
+	Overflow,
+	/// The quoting solver hasn't posted the minimum bond.
+	InsufficientBond,
+	/// Settling the quote would leave the user short of what it is owed,
+	/// or move more than the intent or a winning quote signed for.
+	Unbalanced,
+	/// The winning quotes' surplus doesn't cover the protocol fee.
+	FeeUncovered,
+	/// A token_diff intent lacks a send or receive entry, or has a zero
+	/// amount.
+	Malformed,
+	/// The quote sends at least the reference value of what it takes,
+	/// leaving the solver no spread, and `allow_zero_spread` is off.
+	ZeroSpread,
+	/// An amendment came from someone other than the intent's signer.
+	SignerMismatch,
+	/// The quote targets an intent that already left the book (settled,
+	/// expired or invalid).
+	LateQuote,
+	/// The intent's sequence is not above the last one accepted from its
+	/// signer, or an amendment's nonce not above the intent's last
+	/// amendment's, so it may be a replay.
+	StaleNonce,
+	/// The intent's or quote's token_diff names more assets than
+	/// `max_assets_per_diff` allows.
+	TooLarge,
+	/// The quote belongs to an atomic bundle that didn't win all of its
+	/// intents.
+	BundleForfeited,
+	/// The quote book is full: the quote was evicted by better ones for
+	/// its intent, or arrived while `max_total_quotes` were held.
+	Backpressure,
+	/// The token_diff names a denied asset, or one off the allowlist.
+	DisallowedAsset,
+	/// The quoting solver hasn't sent a heartbeat within
+	/// `solver_liveness_ms`, so it may not settle on-chain.
+	SolverStale,
+	/// The auction is paused and accepts no intents, amendments or quotes.
+	Paused,
+	/// The winning quotes' rate is below the intent's `limit_price`.
+	BelowLimitPrice,
+	/// The intent's `depends_on` chain leads back to itself.
+	DependencyCycle,
+	/// The intent or quote isn't signed with the key registered for its
+	/// signer or solver.
+	UnregisteredKey,
+}
+
+/// A rejection as reported to clients, folded into the categories an
+/// RPC layer exposes. [`AuctionError::code`] gives the JSON-RPC error
+/// code; the record's [`RejectionReason`] keeps the detail.
+#[derive(
+	Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, thiserror::Error,
+)]
+pub enum AuctionError {
+	#[error("intent is not pending")]
+	UnknownIntent,
+	#[error("intent or quote has expired")]
+	Expired,
+	#[error("quote does not satisfy the intent")]
+	Incompatible,
+	#[error("too many intents from this signer this round")]
+	RateLimited,
+	#[error("signature, signer or bond check failed")]
+	Unauthorized,
+	#[error("amount overflow")]
+	Overflow,
+	#[error("malformed or duplicate request")]
+	Malformed,
+	#[error("auction is paused")]
+	Paused,
+}
+
+impl AuctionError {
+	/// JSON-RPC error code, in the range reserved for server errors.
+	pub fn code(self) -> i64 {
+		match self {
+			Self::UnknownIntent => -32001,
+			Self::Expired => -32002,
+			Self::Incompatible => -32003,
+			Self::RateLimited => -32004,
+			Self::Unauthorized => -32005,
+			Self::Overflow => -32006,
+			Self::Malformed => -32007,
+			Self::Paused => -32008,
+		}
+	}
+}
+
+impl From<RejectionReason> for AuctionError {
+	fn from(reason: RejectionReason) -> Self {
+		use RejectionReason as R;
+		match reason {
+			R::UnknownIntent => Self::UnknownIntent,
+			R::Expired | R::QuoteWindowClosed | R::LateQuote => Self::Expired,
+			R::Incompatible
+			| R::Unbalanced
+			| R::FeeUncovered
+			| R::ZeroSpread
+			| R::BundleForfeited
+			| R::SolverStale
+			| R::BelowLimitPrice => Self::Incompatible,
+			R::RateLimited | R::Backpressure => Self::RateLimited,
+			R::InvalidSignature
+			| R::SignerMismatch
+			| R::InsufficientBond
+			| R::StaleNonce
+			| R::DisallowedAsset
+			| R::UnregisteredKey => Self::Unauthorized,
+			R::Overflow => Self::Overflow,
+			R::Duplicate
+			| R::Malformed
+			| R::TooLarge
+			| R::DependencyCycle => Self::Malformed,
+			R::Paused => Self::Paused,
+		}
+	}
+}
+
+/// Why a pending intent wouldn't settle, as reported by `WhyUnsettled`.
+/// The first reason that applies, in declaration order, is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnsettledReason {
+	/// The intent isn't pending: it settled, expired or is unknown.
+	NotPending,
+	/// No solver quoted it this round.
+	NoQuotes,
+	/// Every quote is past its expiration.
+	AllExpired,
+	/// No live quote's counter-diff fits the intent.
+	AllIncompatible,
+	/// Compatible quotes exist, but none delivers the user's minimum.
+	BelowMinimum,
+	/// Fewer distinct solvers quoted compatibly than
+	/// `min_quotes_to_settle`.
+	BelowCompetitionThreshold,
+	/// Matching rejected it or its quotes, for this reason (e.g. the
+	/// intent expired, or the fee isn't covered).
+	Rejected(RejectionReason),
+	/// It matches, but waits on its intent group or the per-round
+	/// settlement cap.
+	Deferred,
+}
+
+/// Why a solver's counter-diff can't settle an intent, as reported by
+/// `CheckCompatible`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Incompatibility {
+	/// The intent is not pending or has no token_diff.
+	UnknownIntent,
+	/// The counter-diff moves an asset the intent doesn't mention.
+	UnexpectedAsset(AssetId),
+	/// The counter-diff doesn't send an asset the user asks for.
+	MissingOutput(AssetId),
+	/// The counter-diff doesn't take an asset the user offers.
+	WrongSign(AssetId),
+	/// The counter-diff takes more of an asset than the user offers.
+	ExceedsOffer(AssetId),
+	/// The counter-diff sends less of an asset than the user's minimum.
+	InsufficientAmount(AssetId),
+	/// A non-fungible asset doesn't change hands exactly once.
+	NonFungibleMismatch(AssetId),
+}
+
+/// A rejected intent or quote, kept for debugging.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectionRecord {
+	/// The round that was current when the rejection happened.
+	pub round: u64,
+
+	/// The intent, or the intent a rejected quote targeted.
+	pub intent_id: IntentId,
+
+	/// Set when the rejected item was a quote.
+	pub quote_hash: Option<String>,
+
+	pub reason: RejectionReason,
+
+	/// For an `Incompatible` quote, the assets the intent asks for that
+	/// the quote doesn't provide.
+	#[serde(default)]
+	pub missing_assets: Vec<AssetId>,
+
+	/// For a `LateQuote`, the round that settled the intent, if it is
+	/// still retained.
+	#[serde(default)]
+	pub missed_round: Option<u64>,
+}
+
+impl RejectionRecord {
+	/// The rejection as a client-facing error.
+	pub fn error(&self) -> AuctionError {
+		self.reason.into()
+	}
+}
+
+/// Configuration for an [`AuctionStateMachine`].
+///
+/// Every replica in a group must be constructed with the same config,
+/// otherwise they will diverge when applying the same log.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuctionConfig {
+	/// How many of the most recent round results to retain. Older
+	/// settlements are pruned at the end of `ClearRound`, but a round
+	/// sealed under `reveal_delay_ms` is kept, along with every later
+	/// one, until it is revealed. `None` keeps every round.
+	pub max_retained_rounds: Option<usize>,
+
+	/// Maximum number of intents a single signer may submit per round.
+	/// Further intents are dropped until the next `ClearRound`. `None`
+	/// disables the limit.
+	pub max_intents_per_signer_per_round: Option<usize>,
+
+	/// Most assets an intent's or quote's token_diff may name, so a
+	/// single command can't bloat the Raft log. Larger ones are rejected
+	/// as `TooLarge`. `None` (the default) disables the limit.
+	pub max_assets_per_diff: Option<usize>,
+
+	/// Most quotes held per intent. Past it, the quote whose diff sends
+	/// the user least is evicted, whatever its `amount_out`. `None`
+	/// disables the limit.
+	pub max_quotes_per_intent: Option<usize>,
+
+	/// Most quotes held across all intents, bounding the book when
+	/// `ClearRound`s stall. New quotes beyond it are rejected as
+	/// `Backpressure` until a clear; revisions of held quotes still apply.
+	/// `None` disables the limit.
+	pub max_total_quotes: Option<usize>,
+
+	/// Assets auctions may run for. Intents and quotes naming any other
+	/// asset are rejected as `DisallowedAsset`. `None` allows every asset
+	/// not in `denied_assets`.
+	pub allowed_assets: Option<BTreeSet<AssetId>>,
+
+	/// Assets no intent or quote may name, even if allowlisted.
+	pub denied_assets: BTreeSet<AssetId>,
+
+	/// Who keeps the surplus when a winning quote beats the user's minimum.
+	pub surplus_policy: SurplusPolicy,
+
+	/// Who gets the indivisible unit left when `SurplusPolicy::SplitBps`
+	/// doesn't divide the surplus exactly.
+	pub surplus_rounding: SurplusRounding,
+
+	/// Protocol fee in basis points of each asset a user receives, paid
+	/// out of the winning quotes' surplus before it is shared. Quotes
+	/// without enough surplus to cover it don't settle.
+	pub fee_bps: u16,
+
+	/// Let `ClearRound` combine several quotes for one intent when no
+	/// single quote covers the user's minimum output. Only applies to
+	/// fungible intents with a single output asset.
+	pub allow_split_fills: bool,
+
+	/// Bond a solver must have posted for its quotes to be accepted.
+	/// Zero disables the requirement.
+	pub min_solver_bond: u128,
+
+	/// How recently a solver must have sent a `SolverHeartbeat` for its
+	/// quotes to win. Quotes from other solvers are rejected as
+	/// `SolverStale` at clearing, and the next-best live quote wins
+	/// instead. `None` disables the check.
+	pub solver_liveness_ms: Option<u64>,
+
+	/// Taken from each winning solver's bond when its round fails
+	/// on-chain via `FailSettlement`.
+	pub bond_slash_amount: u128,
+
+	/// Maximum number of intents `ClearRound` settles, matching the size
+	/// limit of an on-chain settlement batch. Intents beyond it, in
+	/// priority order, stay pending for the next round. `None` disables
+	/// the limit.
+	pub max_settlements_per_round: Option<usize>,
+
+	/// Distinct solvers that must have quoted compatibly for an intent to
+	/// settle, so a lone solver can't pick users off. Intents with less
+	/// competition stay pending. Zero or one disables the check.
+	pub min_quotes_to_settle: usize,
+
+	/// Decimals and symbols for rendering amounts. Never used in matching.
+	pub asset_metadata: AssetRegistry,
+
+	/// ed25519 public keys allowed to sign privileged commands:
+	/// `DepositBond`, `RegisterKey` and `Reconfigure`. Unsigned or
+	/// otherwise signed ones are ignored, and with no keys none are
+	/// applied.
+	pub operator_keys: BTreeSet<Vec<u8>>,
+
+	/// Reference rate per `(asset_in, asset_out)` pair, as a
+	/// `(numerator, denominator)` fraction of `asset_in` like
+	/// `ConstantSpread`'s. Only used to spot quotes without spread.
+	pub reference_rates: BTreeMap<(AssetId, AssetId), (u128, u128)>,
+
+	/// Accept quotes that gain their solver nothing, sending at least the
+	/// `reference_rates` value of what they take. Off, they are rejected
+	/// as `ZeroSpread`. Pairs without a reference rate always pass.
+	pub allow_zero_spread: bool,
+
+	/// Estimated gas per token transfer, used to fill in each
+	/// settlement's `estimated_gas`. Never used in matching.
+	pub gas_per_transfer: u64,
+
+	/// How the winning quote is picked among compatible ones.
+	pub quote_selection: QuoteSelection,
+
+	/// How exact ties between the best quotes are broken.
+	pub tie_break: TieBreak,
+
+	/// The order intents with equal priority fees are matched in.
+	pub intent_ordering: IntentOrdering,
+
+	/// Trusted solvers whose quote wins over a better one from another
+	/// solver, as long as it is within `preferred_tolerance_bps` of the
+	/// best `amount_out` and meets the user's minimum.
+	pub preferred_solvers: BTreeSet<String>,
+
+	/// How far below the best `amount_out` a preferred solver's quote may
+	/// be and still win, in basis points (capped at 10_000).
+	pub preferred_tolerance_bps: u16,
+
+	/// Let `ClearRound` partly fill an intent that no quote or split fill
+	/// covers in full, at no worse than the user's limit price, and carry
+	/// the rest over to later rounds until it is filled or expires. Only
+	/// applies to fungible intents with one input and one output asset.
+	pub allow_carryover: bool,
+
+	/// Fee-on-transfer tokens and the share of each transfer, in basis
+	/// points, their contract keeps. Solvers must over-send these assets
+	/// so users net their minimum; the fee appears in each settlement's
+	/// `transfer_fees`.
+	pub transfer_fee_bps: BTreeMap<AssetId, u16>,
+
+	/// Whether intents settle only on `ClearRound` or as soon as a quote
+	/// satisfies them.
+	pub clearing_mode: ClearingMode,
+
+	/// How solver reputation, scored by `QuoteSelection::Weighted`, decays
+	/// and grows.
+	pub reputation: ReputationParams,
+
+	/// With `Some`, a cleared settlement stays sealed until a
+	/// `RevealRound` at least this long after the clear: queries show only
+	/// its commitment, and it isn't published, journaled or counted in
+	/// the volume, so solvers can't trade ahead of the batch. The auto
+	/// clearer and the coordinator reveal due rounds each tick. `None`
+	/// publishes settlements as they clear.
+	pub reveal_delay_ms: Option<u64>,
+}
+
+/// Each round every solver's reputation is multiplied by
+/// `decay_num / decay_den` (rounding down), then a solver that won a fill
+/// in it gains `win_bonus`. A solver loses `fail_penalty`, down to zero,
+/// when a round it won fails on-chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReputationParams {
+	pub decay_num: u64,
+	pub decay_den: u64,
+	pub win_bonus: u64,
+	pub fail_penalty: u64,
+}
+
+impl Default for ReputationParams {
+	fn default() -> Self {
+		Self {
+			decay_num: 9,
+			decay_den: 10,
+			win_bonus: 1_000,
+			fail_penalty: 2_000,
+		}
+	}
+}
+
+impl ReputationParams {
+	/// `score` after one round of decay. A zero `decay_den` forgets it.
+	fn decay(self, score: u64) -> u64 {
+		let decayed = u128::from(score) * u128::from(self.decay_num);
+		decayed
+			.checked_div(u128::from(self.decay_den))
+			.map_or(0, |score| u64::try_from(score).unwrap_or(u64::MAX))
+	}
+}
+
+/// When pending intents are matched against their quotes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClearingMode {
+	/// Intents wait for `ClearRound` and settle together, so every quote
+	/// received in the window competes.
+	#[default]
+	Batch,
+	/// Each accepted quote immediately tries to settle its intent on its
+	/// own, and the first acceptable quote wins. Grouped intents still
+	/// wait for `ClearRound`. Every such settlement is its own round.
+	/// `ClearRound` still settles anything left over.
+	Continuous,
+}
+
+/// How surplus (price improvement over the user's minimum) is shared
+/// between the user and the winning solver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SurplusPolicy {
+	/// The solver delivers only the user's minimum and keeps the rest.
+	#[default]
+	KeepSolver,
+	/// The user receives everything the solver quoted.
+	ReturnUser,
+	/// The user receives this share of the surplus, in basis points
+	/// (capped at 10_000); the solver keeps the remainder.
+	SplitBps(u16),
+}
+
+/// Where the rounding remainder of a `SurplusPolicy::SplitBps` split
+/// goes. Both shares are rounded down, leaving at most one unit, so the
+/// user, solver and protocol flows still sum exactly to the surplus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SurplusRounding {
+	/// The solver keeps it, by delivering less.
+	#[default]
+	Solver,
+	/// The user receives it.
+	User,
+	/// The protocol receives it, alongside the fee.
+	Protocol,
+}
+
+impl AuctionConfig {
+	fn match_policy(&self) -> MatchPolicy {
+		MatchPolicy {
+			surplus_policy: self.surplus_policy,
+			surplus_rounding: self.surplus_rounding,
+			fee_bps: self.fee_bps,
+			allow_split_fills: self.allow_split_fills,
+			max_settlements_per_round: self.max_settlements_per_round,
+			min_quotes_to_settle: self.min_quotes_to_settle,
+			quote_selection: self.quote_selection,
+			solver_reliability: BTreeMap::new(),
+			solver_reputation: BTreeMap::new(),
+			tie_break: self.tie_break,
+			intent_ordering: self.intent_ordering,
+			gas_per_transfer: self.gas_per_transfer,
+			preferred_solvers: self.preferred_solvers.clone(),
+			preferred_tolerance_bps: self.preferred_tolerance_bps,
+			allow_carryover: self.allow_carryover,
+			transfer_fee_bps: self.transfer_fee_bps.clone(),
+			intent_groups: BTreeMap::new(),
+		}
+	}
+}
+
+/// How `ClearRound` picks the winning quote for a fungible intent.
+/// NFT intents always take the earliest-expiring compatible quote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuoteSelection {
+	/// The highest `amount_out` wins.
+	#[default]
+	BestPrice,
+	/// The highest weighted sum of four scores, each in basis points,
+	/// wins among the quotes that meet the user's minimum:
+	/// - price: `amount_out` relative to the best `amount_out`,
+	/// - reliability: the solver's share of won rounds that settled
+	///   on-chain, smoothed so solvers without history score 5_000,
+	/// - validity: where `expiration_ms` falls between the earliest and
+	///   latest expiring quote,
+	/// - reputation: the solver's decaying reputation (see
+	///   [`ReputationParams`]) relative to the best among the quotes.
+	///
+	/// Integer weights keep every replica's choice identical.
+	Weighted {
+		price_weight: u64,
+		reliability_weight: u64,
+		validity_weight: u64,
+		#[serde(default)]
+		reputation_weight: u64,
+	},
+}
+
+/// A solver's settlement track record, for `QuoteSelection::Weighted`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct SolverRecord {
+	/// Rounds in which the solver won at least one fill.
+	won: u64,
+	/// Of those, rounds whose settlement then failed on-chain.
+	failed: u64,
+	/// Decaying score, see [`ReputationParams`].
+	#[serde(default)]
+	reputation: u64,
+}
+
+impl SolverRecord {
+	/// Share of won rounds that settled, in basis points, with one
+	/// settled and one failed round assumed up front.
+	fn reliability_bps(self) -> u64 {
+		let settled = self.won.saturating_sub(self.failed);
+		settled.saturating_add(1).saturating_mul(10_000)
+			/ self.won.saturating_add(2)
+	}
+}
+
+/// An intent filled in a retained round, kept so it can be re-queued if
+/// the on-chain settlement fails.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FilledIntent {
+	/// The intent as signed.
+	intent: Intent,
+	/// Its fills' flows so far, summed per asset, less those of failed
+	/// rounds. Re-queueing it owes the signed terms less this.
+	filled: TokenDiff,
+	/// Retained, non-failed rounds with a fill for it. The record is
+	/// dropped once this is empty.
+	rounds: BTreeSet<u64>,
+}
+
+/// How `ClearRound` picks between compatible quotes that tie exactly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TieBreak {
+	/// Arrival order decides: the last quote for fungible intents, the
+	/// first for NFT intents.
+	#[default]
+	ArrivalOrder,
+	/// Rank tied quotes by a hash of `(seed, round, intent_id,
+	/// quote_hash)`, so the favored solver rotates from round to round
+	/// while every replica still picks the same winner.
+	RandomTieBreak { seed: u64 },
+}
+
+/// The order `ClearRound` matches intents offering the same priority fee
+/// in, which decides who settles first when quotes or capacity run out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IntentOrdering {
+	/// Ascending intent id. A signer picking a low id goes first.
+	#[default]
+	ById,
+	/// By a hash of `(round, intent.hash())`. The hash leaves out the id,
+	/// so picking one gains nothing, while every replica still orders
+	/// alike. Intents with identical terms fall back to id order.
+	FairOrdering,
+}
+
+impl IntentOrdering {
+	/// Sort key for `intent` in `round` among equal priority fees.
+	fn rank(self, round: u64, intent: &Intent) -> [u8; 32] {
+		match self {
+			Self::ById => [0; 32],
+			Self::FairOrdering => Sha256::new()
+				.chain_update(round.to_le_bytes())
+				.chain_update(intent.hash())
+				.finalize()
+				.into(),
+		}
+	}
+}
+
+impl TieBreak {
+	/// Secondary sort key for `quote` in `round`; higher wins a tie.
+	fn rank(self, round: u64, quote: &Quote) -> u64 {
+		match self {
+			Self::ArrivalOrder => 0,
+			Self::RandomTieBreak { seed } => {
+				let digest = Sha256::new()
+					.chain_update(seed.to_le_bytes())
+					.chain_update(round.to_le_bytes())
+					.chain_update(quote.intent_id.0)
+					.chain_update(quote.quote_hash.as_bytes())
+					.finalize();
+				u64::from_le_bytes(digest[..8].try_into().expect("8 bytes"))
+			}
+		}
+	}
+}
+
+impl SurplusPolicy {
+	/// The part of `surplus` that goes to the user, rounded down, and the
+	/// unit (if any) that neither rounded-down share covers.
+	fn user_share(self, surplus: i128) -> (i128, i128) {
+		match self {
+			Self::KeepSolver => (0, 0),
+			Self::ReturnUser => (surplus, 0),
+			Self::SplitBps(bps) => {
+				let user = bps_of(surplus, bps);
+				let solver = bps_of(surplus, 10_000 - bps.min(10_000));
+				(user, surplus - user - solver)
+			}
+		}
+	}
+}
+
+/// `bps` basis points (capped at 10_000) of `amount`, rounded toward zero.
+fn bps_of(amount: i128, bps: u16) -> i128 {
+	// Split the quotient and remainder separately so the multiplication
+	// can't overflow.
+	let bps = i128::from(bps.min(10_000));
+	amount / 10_000 * bps + amount % 10_000 * bps / 10_000
 }
 
 /// A batch-auction state machine replicated via Raft consensus.
@@ -49,193 +1372,6965 @@ pub enum AuctionQueryResult {
 /// 2. Solvers submit quotes with counter token_diffs
 /// 3. ClearRound picks the best quote per intent and verifies
 ///    that combined token_diffs balance (zero-sum per asset)
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AuctionStateMachine {
-	pending_intents: BTreeMap<u64, Intent>,
-	intent_status: BTreeMap<u64, IntentStatus>,
+	config: AuctionConfig,
+	pending_intents: BTreeMap<IntentId, Intent>,
+	intent_status: BTreeMap<IntentId, IntentStatus>,
 	current_round: u64,
-	quotes: BTreeMap<u64, Vec<Quote>>,
+	quotes: BTreeMap<IntentId, Vec<Quote>>,
+	/// The (solver, intent) pairs of each atomic bundle submitted this
+	/// round.
+	atomic_bundles: Vec<BTreeSet<(String, IntentId)>>,
 	round_results: Vec<Settlement>,
+	/// Rounds in `round_results` sealed under `reveal_delay_ms`, with
+	/// when they cleared.
+	sealed_rounds: BTreeMap<u64, u64>,
+	/// Intents filled in rounds still in `round_results`, pruned along
+	/// with them.
+	filled_intents: BTreeMap<IntentId, FilledIntent>,
+	/// Intents accepted per signer in the current round.
+	intents_by_signer: BTreeMap<String, u64>,
+	rate_limited_intents: u64,
+	/// Quote window deadline of the current round, `None` while closed.
+	round_deadline_ms: Option<u64>,
+	late_quotes: u64,
+	/// Running gross volume per asset over all non-failed settlements, so
+	/// volume queries don't scan round history. Pruning rounds doesn't
+	/// reduce it.
+	volume: BTreeMap<AssetId, u128>,
+	/// Most recent rejections, oldest first, capped at
+	/// `REJECTION_LOG_CAPACITY`.
+	rejections: VecDeque<RejectionRecord>,
+	/// Posted bond per solver, after slashing.
+	solver_bonds: BTreeMap<String, u128>,
+	/// Last nonce applied per operator key.
+	operator_nonces: BTreeMap<Vec<u8>, u64>,
+	/// Won and failed rounds per solver.
+	solver_records: BTreeMap<String, SolverRecord>,
+	/// Latest heartbeat per solver.
+	solver_last_seen: BTreeMap<String, u64>,
+	/// Set by `SetPaused`; intents and quotes are rejected while true.
+	paused: bool,
+	/// First round whose settlement hasn't been published downstream.
+	next_unpublished_round: u64,
+	/// Intents that expired, with the round they expired in, until that
+	/// round is published. Capped at `EXPIRY_LOG_CAPACITY`, counting
+	/// what overflows in `expiries_dropped`.
+	expiries: VecDeque<(u64, IntentId)>,
+	/// `RoundOpened` and `RoundClosed` outcomes with their round, until
+	/// that round is published. Capped at `ROUND_EVENT_CAPACITY`.
+	round_events: VecDeque<(u64, Outcome)>,
+	/// First round whose `RoundOpened` hasn't been published.
+	next_unannounced_round: u64,
+	/// Members of each intent group ever accepted.
+	intent_groups: BTreeMap<String, BTreeSet<IntentId>>,
+	/// Signer and sequence of the most recently accepted intents, oldest
+	/// first, capped at `SEEN_INTENT_CAPACITY`, with a set for lookups.
+	seen_intents: VecDeque<(String, u64)>,
+	seen_intent_set: BTreeSet<(String, u64)>,
+	/// Ids of the most recently applied `Request`s, oldest first, capped
+	/// at `REQUEST_ID_CAPACITY`, with a set for lookups.
+	request_ids: VecDeque<u64>,
+	request_id_set: BTreeSet<u64>,
+	/// Intents that most recently left the book, oldest first, capped at
+	/// `TERMINAL_INTENT_CAPACITY`. Re-queued ones are filtered out by
+	/// their current status.
+	terminal_intents: VecDeque<Intent>,
+	/// Highest intent sequence accepted per signer. Sequences must
+	/// strictly increase.
+	signer_sequences: BTreeMap<String, u64>,
+	/// The key each account's intents and quotes must be signed with, set
+	/// by `RegisterKey`.
+	account_keys: BTreeMap<String, Vec<u8>>,
+	/// Last amendment nonce applied per intent, kept while the intent is
+	/// pending or may be re-queued.
+	amendment_nonces: BTreeMap<IntentId, u64>,
+	/// What is left of partly filled pending intents' token_diffs. They
+	/// are matched on these terms instead of their signed ones.
+	unfilled: BTreeMap<IntentId, TokenDiff>,
+	/// When each pending intent entered the book, for latency tracking.
+	received_ms: BTreeMap<IntentId, u64>,
+	/// Settled intents per latency bucket, see `LatencyStats::counts`.
+	latency_counts: Vec<u64>,
+	/// Slowest settlement latency seen.
+	max_latency_ms: u64,
+	intents_submitted: u64,
+	intents_settled: u64,
+	quotes_submitted: u64,
+	quotes_rejected: u64,
+	rounds_cleared: u64,
+	expiries_dropped: u64,
+	/// Journal for cleared settlements. Local to this replica, so it is
+	/// neither snapshotted nor reset.
+	#[serde(skip, default = "null_sink")]
+	sink: Arc<dyn SettlementSink>,
+	/// Whether this replica leads its group, so journals. Local like
+	/// `sink`.
+	#[serde(skip, default = "always_leading")]
+	leading: Arc<AtomicBool>,
+}
+
+fn null_sink() -> Arc<dyn SettlementSink> {
+	Arc::new(NullSink)
+}
+
+fn always_leading() -> Arc<AtomicBool> {
+	Arc::new(AtomicBool::new(true))
 }
 
 impl AuctionStateMachine {
-	pub fn new() -> Self {
+	pub fn new(config: AuctionConfig) -> Self {
 		Self {
+			config,
 			pending_intents: BTreeMap::new(),
 			intent_status: BTreeMap::new(),
 			current_round: 0,
 			quotes: BTreeMap::new(),
+			atomic_bundles: Vec::new(),
 			round_results: Vec::new(),
+			sealed_rounds: BTreeMap::new(),
+			filled_intents: BTreeMap::new(),
+			intents_by_signer: BTreeMap::new(),
+			rate_limited_intents: 0,
+			round_deadline_ms: None,
+			late_quotes: 0,
+			volume: BTreeMap::new(),
+			rejections: VecDeque::new(),
+			solver_bonds: BTreeMap::new(),
+			operator_nonces: BTreeMap::new(),
+			solver_records: BTreeMap::new(),
+			solver_last_seen: BTreeMap::new(),
+			paused: false,
+			next_unpublished_round: 0,
+			expiries: VecDeque::new(),
+			round_events: VecDeque::new(),
+			next_unannounced_round: 0,
+			intent_groups: BTreeMap::new(),
+			seen_intents: VecDeque::new(),
+			seen_intent_set: BTreeSet::new(),
+			request_ids: VecDeque::new(),
+			request_id_set: BTreeSet::new(),
+			terminal_intents: VecDeque::new(),
+			signer_sequences: BTreeMap::new(),
+			account_keys: BTreeMap::new(),
+			amendment_nonces: BTreeMap::new(),
+			unfilled: BTreeMap::new(),
+			received_ms: BTreeMap::new(),
+			latency_counts: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+			max_latency_ms: 0,
+			intents_submitted: 0,
+			intents_settled: 0,
+			quotes_submitted: 0,
+			quotes_rejected: 0,
+			expiries_dropped: 0,
+			rounds_cleared: 0,
+			sink: null_sink(),
+			leading: always_leading(),
 		}
 	}
-}
 
-/// Verify that a user's token_diff and solver's counter token_diff are
-/// compatible: the solver provides what the user wants (positive entries)
-/// and takes what the user offers (negative entries).
-fn token_diffs_compatible(user_diff: &TokenDiff, solver_diff: &TokenDiff) -> bool {
-	// For each asset the user wants to receive (positive), the solver must
-	// be willing to send (negative for that same asset, or at least provide it).
-	// For each asset the user sends (negative), the solver must accept it.
-	for (asset, &user_amount) in user_diff {
-		if let Some(&solver_amount) = solver_diff.get(asset) {
-			// User sends (negative) and solver receives (positive), or vice versa
-			// The signs must be opposite for the trade to work.
-			if user_amount > 0 && solver_amount >= 0 {
-				return false; // solver not providing what user wants
-			}
-			if user_amount < 0 && solver_amount <= 0 {
-				return false; // solver not taking what user offers
-			}
-			// The solver must provide at least what the user expects
-			if user_amount > 0 && solver_amount.unsigned_abs() < user_amount.unsigned_abs() {
-				return false;
-			}
-		} else if user_amount > 0 {
-			// User wants this asset but solver doesn't mention it
-			return false;
+	/// Apply `command` as if committed at the current
+	/// [`PROTOCOL_VERSION`], for driving the state machine directly as
+	/// the simulator does.
+	pub fn apply(&mut self, command: AuctionCommand) {
+		StateMachine::apply(self, VersionedCommand::from(command));
+	}
+
+	/// Journal every settlement this replica clears to `sink`.
+	pub fn with_sink(mut self, sink: impl SettlementSink + 'static) -> Self {
+		self.sink = Arc::new(sink);
+		self
+	}
+
+	/// Only journal while `leading` is set, so that of a group's replicas
+	/// just the leader does. Without it, this replica always journals.
+	/// See [`spawn_leader_watch`](crate::journal::spawn_leader_watch).
+	pub fn with_leadership(mut self, leading: Arc<AtomicBool>) -> Self {
+		self.leading = leading;
+		self
+	}
+
+	/// Serialize the full auction state, for Raft snapshots and crash
+	/// recovery. `restore` on a fresh machine reproduces it exactly.
+	///
+	/// The state is prefixed with [`PROTOCOL_VERSION`] as a little-endian
+	/// `u32`.
+	pub fn snapshot(&self) -> Vec<u8> {
+		let mut bytes = PROTOCOL_VERSION.to_le_bytes().to_vec();
+		bincode::serialize_into(&mut bytes, self)
+			.expect("auction state is always serializable");
+		bytes
+	}
+
+	/// Current counters. They are part of the replicated state, so they
+	/// agree across replicas and survive `snapshot`/`restore`.
+	pub fn metrics(&self) -> AuctionMetrics {
+		AuctionMetrics {
+			intents_submitted: self.intents_submitted,
+			intents_settled: self.intents_settled,
+			quotes_submitted: self.quotes_submitted,
+			quotes_rejected: self.quotes_rejected,
+			rounds_cleared: self.rounds_cleared,
+			expiries_dropped: self.expiries_dropped,
+			pending_count: self.pending_intents.len() as u64,
+			volume: self.volume.clone(),
 		}
 	}
-	true
-}
 
-/// Compute the aggregate token flow for a user+solver pair.
-fn aggregate_token_flow(user_diff: &TokenDiff, solver_diff: &TokenDiff) -> TokenDiff {
-	let mut flow = user_diff.clone();
-	for (asset, &amount) in solver_diff {
-		*flow.entry(asset.clone()).or_insert(0) += amount;
+	/// Replace the current state with one produced by `snapshot`.
+	///
+	/// Fails on a snapshot from another [`PROTOCOL_VERSION`] rather than
+	/// misreading it. On error the current state is left untouched.
+	pub fn restore(&mut self, bytes: &[u8]) -> Result<(), bincode::Error> {
+		let invalid = |reason: String| bincode::ErrorKind::Custom(reason);
+		let (version, state) = bytes
+			.split_first_chunk::<4>()
+			.ok_or_else(|| invalid("snapshot has no version".into()))?;
+		let version = u32::from_le_bytes(*version);
+		if version != PROTOCOL_VERSION {
+			return Err(Box::new(invalid(format!(
+				"snapshot is protocol version {version}, expected \
+				 {PROTOCOL_VERSION}"
+			))));
+		}
+		let sink = Arc::clone(&self.sink);
+		let leading = Arc::clone(&self.leading);
+		*self = bincode::deserialize(state)?;
+		self.sink = sink;
+		self.leading = leading;
+		Ok(())
 	}
-	flow
 }
 
-impl StateMachine for AuctionStateMachine {
-	const ID: UniqueId = unique_id!(
-		"6e656172696e74656e74732d61756374696f6e2d763100000000000000000001"
-	);
+impl AuctionStateMachine {
+	/// Record a rejected intent or quote in the bounded rejection log.
+	fn reject(
+		&mut self,
+		intent_id: IntentId,
+		quote_hash: Option<String>,
+		reason: RejectionReason,
+	) {
+		self.log_rejection(RejectionRecord {
+			round: self.current_round,
+			intent_id,
+			quote_hash,
+			reason,
+			missing_assets: Vec::new(),
+			missed_round: None,
+		});
+	}
 
-	type Command = AuctionCommand;
-	type Query = AuctionQuery;
-	type QueryResult = AuctionQueryResult;
+	fn log_rejection(&mut self, record: RejectionRecord) {
+		if record.quote_hash.is_some() {
+			self.quotes_rejected += 1;
+		}
+		tracing::debug!(
+			round = record.round,
+			intent_id = %record.intent_id,
+			quote_hash = record.quote_hash.as_deref(),
+			reason = ?record.reason,
+			"rejected",
+		);
+		if self.rejections.len() >= REJECTION_LOG_CAPACITY {
+			self.rejections.pop_front();
+		}
+		self.rejections.push_back(record);
+	}
 
-	fn reset(&mut self) {
-		self.pending_intents.clear();
-		self.intent_status.clear();
-		self.current_round = 0;
-		self.quotes.clear();
-		self.round_results.clear();
+	fn reject_quote(&mut self, quote: &Quote, reason: RejectionReason) {
+		self.reject(quote.intent_id, Some(quote.quote_hash.clone()), reason);
 	}
 
-	fn apply(&mut self, command: Self::Command) {
-		match command {
-			AuctionCommand::SubmitIntent(intent) => {
-				let id = intent.id;
-				self.intent_status.insert(id, IntentStatus::Pending);
-				self.pending_intents.insert(id, intent);
-			}
-			AuctionCommand::SubmitQuote(quote) => {
-				// Only accept quotes for known pending intents
-				if self.pending_intents.contains_key(&quote.intent_id) {
-					self.quotes
-						.entry(quote.intent_id)
+	/// Match pending intents against the current round's quotes as
+	/// `ClearRound` would at `now_ms`, without mutating anything.
+	fn match_round(&self, now_ms: u64) -> MatchOutcome {
+		self.match_subset(now_ms, None)
+	}
+
+	/// `match_round` over only the pending intents in `only`, or all of
+	/// them if `None`.
+	fn match_subset(
+		&self,
+		now_ms: u64,
+		only: Option<&BTreeSet<IntentId>>,
+	) -> MatchOutcome {
+		let policy = self.match_policy();
+		let (quotes, stale) = self.live_quotes(&self.quotes, now_ms);
+		let pending = if self.unfilled.is_empty() && only.is_none() {
+			Cow::Borrowed(&self.pending_intents)
+		} else {
+			Cow::Owned(
+				self.pending_intents
+					.iter()
+					.filter(|(id, _)| only.is_none_or(|only| only.contains(id)))
+					.map(|(&id, intent)| (id, self.open_terms(intent)))
+					.collect(),
+			)
+		};
+		let mut outcome = compute_settlement(
+			self.current_round,
+			&pending,
+			&quotes,
+			&self.atomic_bundles,
+			now_ms,
+			&policy,
+		);
+		outcome.rejected.extend(stale);
+		outcome
+	}
+
+	/// Whether `solver_id` sent a heartbeat within `solver_liveness_ms`
+	/// of `now_ms`. Always true if liveness isn't checked.
+	fn solver_live(&self, solver_id: &str, now_ms: u64) -> bool {
+		self.config.solver_liveness_ms.is_none_or(|window| {
+			self.solver_last_seen
+				.get(solver_id)
+				.is_some_and(|&seen| now_ms.saturating_sub(seen) <= window)
+		})
+	}
+
+	/// `quotes` without those from stale solvers, and a `SolverStale`
+	/// rejection for each one left out.
+	fn live_quotes<'a>(
+		&self,
+		quotes: &'a BTreeMap<IntentId, Vec<Quote>>,
+		now_ms: u64,
+	) -> (Cow<'a, BTreeMap<IntentId, Vec<Quote>>>, Vec<RejectionRecord>) {
+		let stale = quotes
+			.values()
+			.flatten()
+			.filter(|quote| !self.solver_live(&quote.solver_id, now_ms))
+			.map(|quote| RejectionRecord {
+				round: self.current_round,
+				intent_id: quote.intent_id,
+				quote_hash: Some(quote.quote_hash.clone()),
+				reason: RejectionReason::SolverStale,
+				missing_assets: Vec::new(),
+				missed_round: None,
+			})
+			.collect::<Vec<_>>();
+		if stale.is_empty() {
+			return (Cow::Borrowed(quotes), stale);
+		}
+		let live = quotes
+			.iter()
+			.map(|(&id, quotes)| {
+				let live = quotes
+					.iter()
+					.filter(|quote| self.solver_live(&quote.solver_id, now_ms))
+					.cloned()
+					.collect();
+				(id, live)
+			})
+			.collect();
+		(Cow::Owned(live), stale)
+	}
+
+	/// `intent` with its token_diff replaced by what is still unfilled.
+	fn open_terms(&self, intent: &Intent) -> Intent {
+		match self.unfilled.get(&intent.id) {
+			Some(diff) => Intent {
+				action: IntentAction::TokenDiff { diff: diff.clone() },
+				..intent.clone()
+			},
+			None => intent.clone(),
+		}
+	}
+
+	/// `ClearRound`: settle what matches at `now_ms` and close the round.
+	///
+	/// With `only`, just those pending intents are matched; the rest stay
+	/// pending, though the round still closes for them.
+	fn clear_round(&mut self, now_ms: u64, only: Option<&BTreeSet<IntentId>>) {
+		let MatchOutcome {
+			settlement,
+			rejected,
+			..
+		} = self.match_subset(now_ms, only);
+		for record in rejected {
+			self.log_rejection(record);
+		}
+
+		let round = self.current_round;
+		self.record_settlement(settlement, now_ms);
+		self.quotes.clear();
+		self.atomic_bundles.clear();
+		self.intents_by_signer.clear();
+		self.round_deadline_ms = None;
+		self.journal(round);
+	}
+
+	/// Hand `round`'s settlement, if it settled anything, to the sink. A
+	/// no-op unless this replica leads, for a failed round, and for a
+	/// sealed round, which is journaled when revealed.
+	fn journal(&self, round: u64) {
+		if !self.leading.load(Ordering::Relaxed)
+			|| self.sealed_rounds.contains_key(&round)
+		{
+			return;
+		}
+		let settlement = self
+			.round_results
+			.iter()
+			.rfind(|s| s.round == round && !s.failed);
+		if let Some(settlement) = settlement {
+			self.sink.record(settlement);
+		}
+	}
+
+	/// Number of distinct solvers with a compatible quote for a pending
+	/// intent.
+	fn competition(&self, id: &IntentId) -> u64 {
+		let level = self
+			.pending_intents
+			.get(id)
+			.and_then(|intent| intent.token_diff())
+			.zip(self.quotes.get(id))
+			.map_or(0, |(user_diff, quotes)| {
+				let quotes = quotes.iter().collect::<Vec<_>>();
+				competition_level(user_diff, &quotes)
+			});
+		level as u64
+	}
+
+	/// Whether following `intent.depends_on` through the book leads back
+	/// to `intent`. A dependency that isn't pending ends the chain.
+	fn closes_cycle(&self, intent: &Intent) -> bool {
+		let mut next = intent.depends_on;
+		// A chain longer than the book must revisit an intent.
+		for _ in 0..=self.pending_intents.len() {
+			let Some(id) = next else {
+				return false;
+			};
+			if id == intent.id {
+				return true;
+			}
+			next = self.pending_intents.get(&id).and_then(|i| i.depends_on);
+		}
+		true
+	}
+
+	/// Pending intents that name `asset` and whose signer starts with
+	/// `signer_prefix`, each check skipped if `None`. A grouped intent
+	/// is only included with every other pending member of its group, so
+	/// groups still settle whole.
+	fn filtered_intents(
+		&self,
+		asset: Option<&AssetId>,
+		signer_prefix: Option<&str>,
+	) -> BTreeSet<IntentId> {
+		let matches = |intent: &Intent| {
+			asset.is_none_or(|asset| {
+				intent.token_diff().is_some_and(|d| d.contains_key(asset))
+			}) && signer_prefix
+				.is_none_or(|prefix| intent.signer_id.starts_with(prefix))
+		};
+		let split_groups = self
+			.pending_intents
+			.values()
+			.filter(|intent| !matches(intent))
+			.filter_map(|intent| intent.group_id.as_ref())
+			.collect::<BTreeSet<_>>();
+		self.pending_intents
+			.values()
+			.filter(|intent| matches(intent))
+			.filter(|intent| {
+				let group = intent.group_id.as_ref();
+				group.is_none_or(|group| !split_groups.contains(group))
+			})
+			.map(|intent| intent.id)
+			.collect()
+	}
+
+	/// Whether `diff` names more assets than `max_assets_per_diff`.
+	fn too_large(&self, diff: &TokenDiff) -> bool {
+		self.config
+			.max_assets_per_diff
+			.is_some_and(|max| diff.len() > max)
+	}
+
+	/// Validate a quote received at `now_ms` and add it to the book.
+	/// Returns whether it was accepted.
+	fn submit_quote(&mut self, quote: Quote, now_ms: u64) -> bool {
+		self.quotes_submitted += 1;
+
+		if self.paused {
+			self.reject_quote(&quote, RejectionReason::Paused);
+			return false;
+		}
+		if self.too_large(&quote.solver_token_diff) {
+			let intent_id = quote.intent_id;
+			let reason = RejectionReason::TooLarge;
+			self.reject(intent_id, Some(quote.quote_hash), reason);
+			return false;
+		}
+		if self.disallowed(&quote.solver_token_diff) {
+			self.reject_quote(&quote, RejectionReason::DisallowedAsset);
+			return false;
+		}
+		// The Verifier must be able to attribute every quote to its solver.
+		if !quote.verify_signature() {
+			self.reject_quote(&quote, RejectionReason::InvalidSignature);
+			return false;
+		}
+		if self
+			.account_keys
+			.get(&quote.solver_id)
+			.is_some_and(|key| *key != quote.public_key)
+		{
+			self.reject_quote(&quote, RejectionReason::UnregisteredKey);
+			return false;
+		}
+
+		// The intent already left the book; tell the solver which
+		// round it missed so it can tune its latency.
+		if self
+			.intent_status
+			.get(&quote.intent_id)
+			.is_some_and(|status| *status != IntentStatus::Pending)
+		{
+			let id = quote.intent_id;
+			let missed_round = self
+				.round_results
+				.iter()
+				.rev()
+				.find(|s| s.settled_intents().contains(&id))
+				.map(|s| s.round);
+			self.log_rejection(RejectionRecord {
+				round: self.current_round,
+				intent_id: quote.intent_id,
+				quote_hash: Some(quote.quote_hash),
+				reason: RejectionReason::LateQuote,
+				missing_assets: Vec::new(),
+				missed_round,
+			});
+			return false;
+		}
+
+		// Quotes only count while the round's window is open.
+		if self.round_deadline_ms.is_none_or(|deadline| now_ms > deadline) {
+			self.late_quotes += 1;
+			self.reject_quote(&quote, RejectionReason::QuoteWindowClosed);
+			return false;
+		}
+
+		// Solvers must have collateral at stake before quoting.
+		let bond =
+			self.solver_bonds.get(&quote.solver_id).copied().unwrap_or(0);
+		if bond < self.config.min_solver_bond {
+			self.reject_quote(&quote, RejectionReason::InsufficientBond);
+			return false;
+		}
+
+		// Only accept quotes for known pending intents
+		let Some(intent) = self.pending_intents.get(&quote.intent_id) else {
+			self.reject_quote(&quote, RejectionReason::UnknownIntent);
+			return false;
+		};
+
+		if quote.expiration_ms < now_ms || intent.deadline_ms < now_ms {
+			self.reject_quote(&quote, RejectionReason::Expired);
+			return false;
+		}
+
+		// Paying the market rate or better gains the solver nothing,
+		// which only makes sense as wash trading.
+		if !self.config.allow_zero_spread
+			&& has_zero_spread(&quote, &self.config.reference_rates)
+		{
+			self.reject_quote(&quote, RejectionReason::ZeroSpread);
+			return false;
+		}
+
+		// One live quote per solver and intent: a resubmission
+		// revises the solver's bid in place.
+		let id = quote.intent_id;
+		let revises = self
+			.quotes
+			.get(&id)
+			.is_some_and(|quotes| {
+				quotes.iter().any(|q| q.solver_id == quote.solver_id)
+			});
+		let held = self.quotes.values().map(Vec::len).sum::<usize>();
+		if !revises
+			&& self.config.max_total_quotes.is_some_and(|max| held >= max)
+		{
+			self.reject_quote(&quote, RejectionReason::Backpressure);
+			return false;
+		}
+		let hash = quote.quote_hash.clone();
+		let quotes = self.quotes.entry(id).or_default();
+		let previous =
+			quotes.iter_mut().find(|q| q.solver_id == quote.solver_id);
+		match previous {
+			Some(previous) => *previous = quote,
+			None => quotes.push(quote),
+		}
+
+		// Evict the quotes delivering least beyond the per-intent cap; on
+		// equal delivery the earlier quote stays.
+		let mut evicted = Vec::new();
+		let user_diff =
+			self.pending_intents.get(&id).and_then(Intent::token_diff);
+		if let Some(max) = self.config.max_quotes_per_intent
+			&& let Some(user_diff) = user_diff
+		{
+			let sent = |q: &Quote| delivered(user_diff, &q.solver_token_diff);
+			while quotes.len() > max {
+				let worst = quotes
+					.iter()
+					.enumerate()
+					.min_by_key(|&(i, q)| (sent(q), Reverse(i)))
+					.map(|(i, _)| i)
+					.expect("quotes is over a cap, so non-empty");
+				evicted.push(quotes.remove(worst));
+			}
+		}
+		let accepted = !evicted.iter().any(|q| q.quote_hash == hash);
+		for quote in evicted {
+			self.reject_quote(&quote, RejectionReason::Backpressure);
+		}
+		accepted
+	}
+
+	/// Whether `auth` is a fresh operator signature over `fields`,
+	/// recording its nonce if so.
+	fn authorize(
+		&mut self,
+		auth: &OperatorSignature,
+		fields: &impl Serialize,
+	) -> bool {
+		let last = self.operator_nonces.get(&auth.public_key).copied();
+		let authorized = self.config.operator_keys.contains(&auth.public_key)
+			&& last.is_none_or(|last| auth.nonce > last)
+			&& auth.verify(fields);
+		if authorized {
+			self.operator_nonces
+				.insert(auth.public_key.clone(), auth.nonce);
+		}
+		authorized
+	}
+
+	/// Whether `diff` names a denied asset, or one off the allowlist.
+	fn disallowed(&self, diff: &TokenDiff) -> bool {
+		diff.keys().any(|asset| {
+			self.config.denied_assets.contains(asset)
+				|| self
+					.config
+					.allowed_assets
+					.as_ref()
+					.is_some_and(|allowed| !allowed.contains(asset))
+		})
+	}
+
+	/// Remember an intent that left the book for `IntentsByStatus`.
+	fn retire(&mut self, intent: Intent) {
+		// A filled intent may still be re-queued by `FailSettlement`.
+		if !self.filled_intents.contains_key(&intent.id) {
+			self.amendment_nonces.remove(&intent.id);
+		}
+		if self.terminal_intents.len() >= TERMINAL_INTENT_CAPACITY {
+			self.terminal_intents.pop_front();
+		}
+		self.terminal_intents.push_back(intent);
+	}
+
+	/// The config's matching knobs plus the solver track records weighted
+	/// quote selection needs.
+	fn match_policy(&self) -> MatchPolicy {
+		let mut policy = self.config.match_policy();
+		if let QuoteSelection::Weighted { .. } = policy.quote_selection {
+			policy.solver_reliability = self
+				.solver_records
+				.iter()
+				.map(|(id, record)| (id.clone(), record.reliability_bps()))
+				.collect();
+			policy.solver_reputation = self
+				.solver_records
+				.iter()
+				.map(|(id, record)| (id.clone(), record.reputation))
+				.collect();
+		}
+		policy.intent_groups = self
+			.pending_intents
+			.values()
+			.filter_map(|intent| intent.group_id.as_ref())
+			.filter_map(|group| {
+				let members = self.intent_groups.get(group)?;
+				Some((group.clone(), members.clone()))
+			})
+			.collect();
+		policy
+	}
+
+	/// Drop a pending intent that missed its deadline, with its quotes.
+	fn expire(&mut self, id: IntentId) {
+		if let Some(intent) = self.pending_intents.remove(&id) {
+			self.retire(intent);
+		}
+		self.unfilled.remove(&id);
+		self.received_ms.remove(&id);
+		self.quotes.remove(&id);
+		self.intent_status.insert(id, IntentStatus::Expired);
+		self.reject(id, None, RejectionReason::Expired);
+		self.log_expiry(id);
+	}
+
+	/// Queue an expiration for publishing as an `Outcome`. Past
+	/// `EXPIRY_LOG_CAPACITY` the oldest is dropped and counted in
+	/// `expiries_dropped`.
+	fn log_expiry(&mut self, id: IntentId) {
+		if self.expiries.len() >= EXPIRY_LOG_CAPACITY
+			&& let Some((round, dropped)) = self.expiries.pop_front()
+		{
+			self.expiries_dropped += 1;
+			tracing::warn!(
+				round,
+				intent_id = %dropped,
+				"expiry log full, dropping unpublished expiration",
+			);
+		}
+		self.expiries.push_back((self.current_round, id));
+	}
+
+	/// Queue a round boundary of the current round for publishing.
+	fn log_round_event(&mut self, outcome: Outcome) {
+		if self.round_events.len() >= ROUND_EVENT_CAPACITY {
+			self.round_events.pop_front();
+		}
+		self.round_events.push_back((self.current_round, outcome));
+	}
+
+	/// Settle a single intent right away if its quotes already satisfy
+	/// it, as a round of its own. Used in `ClearingMode::Continuous`.
+	fn settle_on_arrival(&mut self, id: IntentId, now_ms: u64) {
+		let (Some(intent), Some(quotes)) =
+			(self.pending_intents.get(&id), self.quotes.get(&id))
+		else {
+			return;
+		};
+		// A group can only settle as a whole, at `ClearRound`.
+		if intent.group_id.is_some() {
+			return;
+		}
+		let quotes = BTreeMap::from([(id, quotes.clone())]);
+		let (quotes, stale) = self.live_quotes(&quotes, now_ms);
+		let MatchOutcome {
+			settlement,
+			mut rejected,
+			..
+		} = compute_settlement(
+			self.current_round,
+			&BTreeMap::from([(id, self.open_terms(intent))]),
+			&quotes,
+			&self.atomic_bundles,
+			now_ms,
+			&self.match_policy(),
+		);
+		rejected.extend(stale);
+		// Unmatched quotes stay in the book for later ones to combine
+		// with, so they are only rejected once the intent settles.
+		if settlement.settlements.is_empty() {
+			return;
+		}
+		for record in rejected {
+			self.log_rejection(record);
+		}
+		self.quotes.remove(&id);
+		let round = self.current_round;
+		self.record_settlement(settlement, now_ms);
+		self.journal(round);
+	}
+
+	/// Apply a settlement cleared at `now_ms`: mark its intents settled,
+	/// account their latency and volume, retain it and move on to the
+	/// next round.
+	fn record_settlement(&mut self, settlement: Settlement, now_ms: u64) {
+		// Value is conserved across the whole round, not just per intent.
+		let residual = settlement.residual();
+		let balanced = residual.values().all(|&amount| amount == 0);
+		debug_assert!(
+			balanced,
+			"round {} does not conserve value: {residual:?}",
+			settlement.round,
+		);
+		if !balanced {
+			tracing::error!(
+				round = settlement.round,
+				?residual,
+				"settlement does not conserve value",
+			);
+		}
+		for settled in &settlement.settlements {
+			tracing::info!(
+				round = settlement.round,
+				intent_id = %settled.intent_id,
+				signer_id = %settled.signer_id,
+				fills = settled.fills.len(),
+				"settled",
+			);
+		}
+		tracing::info!(
+			round = settlement.round,
+			settled = settlement.settlements.len(),
+			"round cleared",
+		);
+		self.rounds_cleared += 1;
+		let params = self.config.reputation;
+		for record in self.solver_records.values_mut() {
+			record.reputation = params.decay(record.reputation);
+		}
+		for solver_id in settlement_solvers(&settlement) {
+			let record =
+				self.solver_records.entry(solver_id.into()).or_default();
+			record.won = record.won.saturating_add(1);
+			record.reputation =
+				record.reputation.saturating_add(params.win_bonus);
+		}
+
+		// Update statuses and remove settled intents, except partly filled
+		// ones with something left to fill.
+		for settled in &settlement.settlements {
+			let id = settled.intent_id;
+			if let Some(intent) = self.pending_intents.get(&id) {
+				// Earlier fills are read off the open terms, since their
+				// rounds may have been pruned along with the record.
+				let earlier = self
+					.unfilled
+					.get(&id)
+					.zip(intent.token_diff())
+					.map(|(left, signed)| deduct(signed, left))
+					.unwrap_or_default();
+				let filled =
+					self.filled_intents.entry(id).or_insert_with(|| {
+						FilledIntent {
+							intent: intent.clone(),
+							filled: TokenDiff::new(),
+							rounds: BTreeSet::new(),
+						}
+					});
+				filled.filled = aggregate_token_flow(&earlier, &settled.flow)
+					.unwrap_or(earlier);
+				filled.rounds.insert(settlement.round);
+			}
+			if settlement.partial_fills.contains(&id)
+				&& self.carry_over(id, &settled.flow)
+			{
+				continue;
+			}
+			self.intents_settled += 1;
+			self.unfilled.remove(&id);
+			if let Some(intent) = self.pending_intents.remove(&id) {
+				self.retire(intent);
+			}
+			self.intent_status.insert(id, IntentStatus::Settled);
+			if let Some(received_ms) = self.received_ms.remove(&id) {
+				self.record_latency(now_ms.saturating_sub(received_ms));
+			}
+		}
+
+		let closed = Outcome::RoundClosed {
+			round: self.current_round,
+			settled_count: settlement.settlements.len(),
+		};
+		if !settlement.settlements.is_empty() {
+			// A sealed round's volume would give it away, so it only
+			// counts once revealed.
+			if self.config.reveal_delay_ms.is_some() {
+				self.sealed_rounds.insert(settlement.round, now_ms);
+			} else {
+				self.add_volume(&settlement);
+			}
+			self.round_results.push(settlement);
+		}
+
+		self.log_round_event(closed);
+		self.current_round += 1;
+		self.prune_round_results();
+	}
+
+	/// Count `settlement` in the lifetime volume.
+	fn add_volume(&mut self, settlement: &Settlement) {
+		for (asset, &amount) in &settlement.volume {
+			let total = self.volume.entry(asset.clone()).or_default();
+			*total = total.saturating_add(amount);
+		}
+	}
+
+	/// Deduct a partial fill's `flow` from what an intent still wants.
+	/// Returns whether anything is left, in which case the intent stays
+	/// pending on the reduced terms.
+	fn carry_over(&mut self, id: IntentId, flow: &TokenDiff) -> bool {
+		let terms = self
+			.unfilled
+			.get(&id)
+			.or_else(|| self.pending_intents.get(&id)?.token_diff());
+		let Some(mut left) = terms.cloned() else {
+			return false;
+		};
+		for (asset, &amount) in flow {
+			if let Some(wanted) = left.get_mut(asset) {
+				*wanted = wanted.saturating_sub(amount);
+			}
+		}
+		// Once all the input is spent or all the output received, a
+		// remainder is no longer a valid intent.
+		if !is_well_formed(&left) {
+			return false;
+		}
+		self.unfilled.insert(id, left);
+		true
+	}
+
+	fn record_latency(&mut self, latency_ms: u64) {
+		let bucket = LATENCY_BUCKETS_MS
+			.iter()
+			.position(|&bound| latency_ms <= bound)
+			.unwrap_or(LATENCY_BUCKETS_MS.len());
+		self.latency_counts[bucket] += 1;
+		self.max_latency_ms = self.max_latency_ms.max(latency_ms);
+	}
+
+	/// Total `asset_out` received over total `asset_in` sent by intents
+	/// that swapped exactly that pair in the last `last_n_rounds` rounds.
+	/// `None` if there were no such trades or the totals overflow.
+	fn average_price(
+		&self,
+		asset_in: &AssetId,
+		asset_out: &AssetId,
+		last_n_rounds: u64,
+	) -> Option<Price> {
+		let since = self.current_round.saturating_sub(last_n_rounds);
+		let mut total = Price {
+			amount_out: 0,
+			amount_in: 0,
+		};
+		let trades = self
+			.revealed_results()
+			.filter(|s| s.round >= since && !s.failed)
+			.flat_map(|s| &s.settlements)
+			.filter(|settled| settled.flow.len() == 2);
+		for settled in trades {
+			let sent = settled.flow.get(asset_in).copied().unwrap_or(0);
+			let received = settled.flow.get(asset_out).copied().unwrap_or(0);
+			if sent >= 0 || received <= 0 {
+				continue;
+			}
+			total.amount_in =
+				total.amount_in.checked_add(sent.unsigned_abs())?;
+			total.amount_out =
+				total.amount_out.checked_add(received.unsigned_abs())?;
+		}
+		(total.amount_in > 0).then_some(total)
+	}
+
+	/// Diagnose why `id` wouldn't settle if the round cleared at `now_ms`,
+	/// by running the same matching as `ClearRound`.
+	fn why_unsettled(
+		&self,
+		id: IntentId,
+		now_ms: u64,
+	) -> Option<UnsettledReason> {
+		use UnsettledReason as U;
+
+		let Some(intent) = self.pending_intents.get(&id) else {
+			return Some(U::NotPending);
+		};
+		let outcome = self.match_round(now_ms);
+		if outcome.settlement.settled_intents().contains(&id) {
+			return None;
+		}
+		if outcome.expired.contains(&id) {
+			return Some(U::Rejected(RejectionReason::Expired));
+		}
+
+		let quotes = self.quotes.get(&id).map_or(&[][..], Vec::as_slice);
+		if quotes.is_empty() {
+			return Some(U::NoQuotes);
+		}
+		let live = quotes
+			.iter()
+			.filter(|q| q.expiration_ms >= now_ms)
+			.collect::<Vec<_>>();
+		if live.is_empty() {
+			return Some(U::AllExpired);
+		}
+
+		let terms = self.open_terms(intent);
+		let Some(user_diff) = terms.token_diff() else {
+			return Some(U::AllIncompatible);
+		};
+		let compatible = live
+			.iter()
+			.copied()
+			.filter(|q| quote_compatible(user_diff, q))
+			.collect::<Vec<_>>();
+		if compatible.is_empty() {
+			let short = live.iter().any(|q| {
+				matches!(
+					check_compatible(user_diff, &q.solver_token_diff),
+					Err(Incompatibility::InsufficientAmount(_))
+				)
+			});
+			return Some(if short {
+				U::BelowMinimum
+			} else {
+				U::AllIncompatible
+			});
+		}
+		if competition_level(user_diff, &compatible)
+			< self.config.min_quotes_to_settle
+		{
+			return Some(U::BelowCompetitionThreshold);
+		}
+		if !compatible
+			.iter()
+			.any(|q| meets_minimum_output(user_diff, &q.solver_token_diff))
+		{
+			return Some(U::BelowMinimum);
+		}
+
+		let rejected = outcome
+			.rejected
+			.iter()
+			.rev()
+			.find(|record| record.intent_id == id);
+		Some(rejected.map_or(U::Deferred, |record| U::Rejected(record.reason)))
+	}
+
+	/// The receipt for an intent from the latest retained round that
+	/// settled it without failing.
+	fn receipt(&self, id: IntentId) -> Option<SettlementReceipt> {
+		self.revealed_results()
+			.rev()
+			.filter(|s| !s.failed)
+			.find_map(|s| {
+				let settled = s.settlements.iter().find(|x| x.intent_id == id)?;
+				Some(SettlementReceipt::new(s.round, settled))
+			})
+	}
+
+	/// The quotes that would currently win an intent under the configured
+	/// policy, best first. Empty if it wouldn't settle.
+	fn current_winners(&self, id: &IntentId) -> Vec<&Quote> {
+		let user_diff = self
+			.unfilled
+			.get(id)
+			.or_else(|| self.pending_intents.get(id)?.token_diff());
+		let (Some(user_diff), Some(quotes)) = (user_diff, self.quotes.get(id))
+		else {
+			return Vec::new();
+		};
+		let quotes = quotes.iter().collect::<Vec<_>>();
+		let policy = self.match_policy();
+		select_winners(user_diff, &quotes, self.current_round, &policy).0
+	}
+
+	/// Retained settlements that aren't sealed, by round ascending.
+	fn revealed_results(&self) -> impl DoubleEndedIterator<Item = &Settlement> {
+		self.round_results
+			.iter()
+			.filter(|s| !self.sealed_rounds.contains_key(&s.round))
+	}
+
+	/// The end of the rounds that may be published: the first sealed
+	/// round, or the open round.
+	fn publishable_until(&self) -> u64 {
+		let sealed = self.sealed_rounds.keys().next().copied();
+		sealed.map_or(self.current_round, |round| round.min(self.current_round))
+	}
+
+	/// Drop the oldest round results beyond `max_retained_rounds`, along
+	/// with the intents kept around for re-queueing them. A sealed round
+	/// and those after it are kept until it is revealed.
+	fn prune_round_results(&mut self) {
+		let Some(max) = self.config.max_retained_rounds else {
+			return;
+		};
+		let until = self.publishable_until();
+		let prunable = self
+			.round_results
+			.iter()
+			.take_while(|s| s.round < until)
+			.count();
+		let excess = self.round_results.len().saturating_sub(max).min(prunable);
+		let pruned = self.round_results.drain(..excess).collect::<Vec<_>>();
+		for pruned in pruned {
+			for id in pruned.settled_intents() {
+				self.forget_fill(id, pruned.round);
+				if !self.filled_intents.contains_key(&id)
+					&& !self.pending_intents.contains_key(&id)
+				{
+					self.amendment_nonces.remove(&id);
+				}
+			}
+		}
+	}
+
+	/// Forget that `round` filled `id`, dropping its record once no
+	/// retained round has. Returns the record as it was, if it had one.
+	fn forget_fill(
+		&mut self,
+		id: IntentId,
+		round: u64,
+	) -> Option<FilledIntent> {
+		let filled = self.filled_intents.get_mut(&id)?;
+		if !filled.rounds.remove(&round) {
+			return None;
+		}
+		let record = filled.clone();
+		if filled.rounds.is_empty() {
+			self.filled_intents.remove(&id);
+		}
+		Some(record)
+	}
+}
+
+impl Default for AuctionStateMachine {
+	fn default() -> Self {
+		Self::new(AuctionConfig::default())
+	}
+}
+
+/// `diff` less `flow`, per asset of either.
+fn deduct(diff: &TokenDiff, flow: &TokenDiff) -> TokenDiff {
+	let mut left = diff.clone();
+	for (asset, &amount) in flow {
+		let entry = left.entry(asset.clone()).or_default();
+		*entry = entry.saturating_sub(amount);
+	}
+	left
+}
+
+/// A swap needs something to send and something to receive, and a zero
+/// entry means nothing.
+fn is_well_formed(diff: &TokenDiff) -> bool {
+	diff.values().any(|&amount| amount < 0)
+		&& diff.values().any(|&amount| amount > 0)
+		&& diff.values().all(|&amount| amount != 0)
+}
+
+/// Verify that a user's token_diff and solver's counter token_diff are
+/// compatible: the solver provides what the user wants (positive entries)
+/// and takes what the user offers (negative entries).
+///
+/// Non-fungible (NEP-171) assets are unique, so they must move exactly one
+/// unit in each direction; partial fills never apply to them.
+///
+/// The solver may not ask for more of an asset than the user offers, nor
+/// for assets outside the user's diff, since nobody would provide them.
+///
+/// Matching relies on this contract: when it holds, the solver sends at
+/// least the user's amount of every asset the user receives, and only
+/// takes assets the user sends, never more than offered. The fuzz target
+/// in `fuzz/` checks it.
+pub fn token_diffs_compatible(
+	user_diff: &TokenDiff,
+	solver_diff: &TokenDiff,
+) -> bool {
+	check_compatible(user_diff, solver_diff).is_ok()
+}
+
+/// `token_diffs_compatible` with the first reason the diffs don't fit.
+fn check_compatible(
+	user_diff: &TokenDiff,
+	solver_diff: &TokenDiff,
+) -> Result<(), Incompatibility> {
+	use Incompatibility as I;
+
+	if let Some(asset) =
+		solver_diff.keys().find(|asset| !user_diff.contains_key(asset))
+	{
+		return Err(I::UnexpectedAsset(asset.clone()));
+	}
+
+	// A basket intent needs every one of its outputs; a quote covering
+	// only some of them is rejected outright.
+	if let Some(asset) =
+		missing_outputs(user_diff, solver_diff).into_iter().next()
+	{
+		return Err(I::MissingOutput(asset));
+	}
+
+	// For each asset the user wants to receive (positive), the solver must
+	// be willing to send (negative for that same asset, or at least provide it).
+	// For each asset the user sends (negative), the solver must accept it.
+	for (asset, &user_amount) in user_diff {
+		if asset.is_non_fungible() {
+			// The same token_id must change hands exactly once.
+			if user_amount.unsigned_abs() != 1
+				|| solver_diff.get(asset) != Some(&-user_amount)
+			{
+				return Err(I::NonFungibleMismatch(asset.clone()));
+			}
+			continue;
+		}
+		if let Some(&solver_amount) = solver_diff.get(asset) {
+			// The signs must be opposite for the trade to work; outputs
+			// were already checked above.
+			if user_amount < 0 && solver_amount <= 0 {
+				return Err(I::WrongSign(asset.clone()));
+			}
+			// Nor take more than offered, which is nothing for an asset
+			// the user lists at zero.
+			if user_amount <= 0
+				&& solver_amount > 0
+				&& solver_amount.unsigned_abs() > user_amount.unsigned_abs()
+			{
+				return Err(I::ExceedsOffer(asset.clone()));
+			}
+			// The solver must provide at least what the user expects
+			if user_amount > 0
+				&& solver_amount.unsigned_abs() < user_amount.unsigned_abs()
+			{
+				return Err(I::InsufficientAmount(asset.clone()));
+			}
+		}
+	}
+	Ok(())
+}
+
+/// The assets the user asks for (positive entries) that the solver
+/// doesn't send, in asset order.
+fn missing_outputs(user_diff: &TokenDiff, solver_diff: &TokenDiff) -> Vec<AssetId> {
+	user_diff
+		.iter()
+		.filter(|&(asset, &wanted)| {
+			wanted > 0 && solver_diff.get(asset).is_none_or(|&sent| sent >= 0)
+		})
+		.map(|(asset, _)| asset.clone())
+		.collect()
+}
+
+/// Whether a quote can settle an intent on its own: its counter-diff is
+/// compatible and it attests the right destination chain.
+fn quote_compatible(user_diff: &TokenDiff, quote: &Quote) -> bool {
+	token_diffs_compatible(user_diff, &quote.solver_token_diff)
+		&& attests_destination(user_diff, quote)
+}
+
+/// Whether `quotes` together take `limit.asset_in` and send
+/// `limit.asset_out` at no worse than the limit rate.
+fn meets_limit_price(limit: &LimitPrice, quotes: &[&Quote]) -> bool {
+	let total = |asset: &AssetId, sign: i128| {
+		quotes.iter().try_fold(0u128, |sum, quote| {
+			let amount = quote.solver_token_diff.get(asset).copied()?;
+			let amount = amount.checked_mul(sign).filter(|&a| a > 0)?;
+			sum.checked_add(amount.unsigned_abs())
+		})
+	};
+	match (total(&limit.asset_in, 1), total(&limit.asset_out, -1)) {
+		(Some(amount_in), Some(amount_out)) => {
+			limit.admits(amount_in, amount_out)
+		}
+		_ => false,
+	}
+}
+
+/// Bridged outputs are delivered off NEAR, so the solver must attest the
+/// chain they go to. All of an intent's bridged outputs must share one
+/// chain, since a quote attests a single destination.
+fn attests_destination(user_diff: &TokenDiff, quote: &Quote) -> bool {
+	let mut chains = user_diff
+		.iter()
+		.filter(|&(_, &amount)| amount > 0)
+		.filter_map(|(asset, _)| asset.chain())
+		.collect::<BTreeSet<_>>()
+		.into_iter();
+	match (chains.next(), chains.next()) {
+		(None, _) => true,
+		(Some(chain), None) => {
+			quote.destination_chain.as_deref() == Some(chain)
+		}
+		(Some(_), Some(_)) => false,
+	}
+}
+
+/// Slippage protection: every asset the user receives (positive entry) is
+/// their minimum acceptable amount, so the solver must send at least that
+/// much of it.
+///
+/// Kept separate from `token_diffs_compatible`, which only decides whether
+/// a quote is the right shape for the intent and may be relaxed for partial
+/// fills; this floor must always hold for a settled intent.
+fn meets_minimum_output(user_diff: &TokenDiff, solver_diff: &TokenDiff) -> bool {
+	user_diff.iter().filter(|&(_, &amount)| amount > 0).all(
+		|(asset, &min_out)| {
+			solver_diff
+				.get(asset)
+				.is_some_and(|&provided| {
+					provided < 0
+						&& provided.unsigned_abs() >= min_out.unsigned_abs()
+				})
+		},
+	)
+}
+
+/// How much of the assets the user receives a quote's diff actually
+/// sends, whatever `amount_out` it advertises.
+fn delivered(user_diff: &TokenDiff, solver_diff: &TokenDiff) -> u128 {
+	user_diff
+		.iter()
+		.filter(|&(_, &wanted)| wanted > 0)
+		.filter_map(|(asset, _)| solver_diff.get(asset))
+		.filter(|&&provided| provided < 0)
+		.map(|provided| provided.unsigned_abs())
+		.fold(0, u128::saturating_add)
+}
+
+/// Price improvement of a quote: for each asset the user receives, how
+/// much more the solver sends than the user's requested minimum.
+fn intent_surplus(user_diff: &TokenDiff, solver_diff: &TokenDiff) -> TokenDiff {
+	user_diff
+		.iter()
+		.filter(|&(_, &min_out)| min_out > 0)
+		.filter_map(|(asset, &min_out)| {
+			let provided = solver_diff.get(asset)?.checked_neg()?;
+			let excess = provided.checked_sub(min_out)?;
+			(excess > 0).then(|| (asset.clone(), excess))
+		})
+		.collect()
+}
+
+/// Whether single-pair `quote` sends at least what it takes is worth at
+/// its pair's reference rate. Quotes without a reference rate, or over
+/// more than one pair, pass.
+fn has_zero_spread(
+	quote: &Quote,
+	reference_rates: &BTreeMap<(AssetId, AssetId), (u128, u128)>,
+) -> bool {
+	let diff = &quote.solver_token_diff;
+	let mut taken = diff.iter().filter(|&(_, &amount)| amount > 0);
+	let mut sent = diff.iter().filter(|&(_, &amount)| amount < 0);
+	let (
+		Some((asset_in, amount_in)),
+		None,
+		Some((asset_out, amount_out)),
+		None,
+	) = (taken.next(), taken.next(), sent.next(), sent.next())
+	else {
+		return false;
+	};
+	let pair = (asset_in.clone(), asset_out.clone());
+	let Some(&(num, den)) = reference_rates.get(&pair) else {
+		return false;
+	};
+	amount_in
+		.unsigned_abs()
+		.checked_mul(num)
+		.and_then(|value| value.checked_div(den))
+		.is_some_and(|reference| amount_out.unsigned_abs() >= reference)
+}
+
+/// The transfers that settle an intent against its winning quotes.
+struct IntentFlows {
+	/// What the user sends (negative) and receives (positive).
+	user: TokenDiff,
+
+	/// One flow per winning quote, in the same order.
+	solvers: Vec<TokenDiff>,
+
+	/// Gross price improvement over the user's minimum.
+	surplus: TokenDiff,
+
+	/// What the protocol receives.
+	fee: TokenDiff,
+}
+
+/// The balanced transfers that settle an intent against its winning
+/// quotes.
+///
+/// For each asset the user receives, the protocol takes `fee_bps` of the
+/// user's minimum out of the combined surplus, and the user gets their
+/// minimum plus their share of what is left under `policy`, with its
+/// rounding remainder assigned by `rounding`. Quotes are then filled in
+/// order, each moving what it quoted of every asset until the user's side
+/// is covered, so no fill ever sends or takes more than its quote: the
+/// last quote of a split fill only sends what is still owed. The user
+/// pays what the fills take, never more than offered. The user, solver
+/// and fee flows sum to zero per asset.
+///
+/// Fails with `FeeUncovered` if the surplus can't pay the fee,
+/// `Unbalanced` if the quotes don't send all the user is owed, or
+/// `Overflow` if any amount overflows i128.
+fn settlement_flows(
+	user_diff: &TokenDiff,
+	quotes: &[&Quote],
+	policy: SurplusPolicy,
+	rounding: SurplusRounding,
+	fee_bps: u16,
+) -> Result<IntentFlows, RejectionReason> {
+	use RejectionReason::{FeeUncovered, Overflow, Unbalanced};
+
+	if quotes.is_empty() {
+		return Err(Overflow);
+	}
+	let combined = quotes
+		.iter()
+		.try_fold(TokenDiff::new(), |sum, q| {
+			aggregate_token_flow(&sum, &q.solver_token_diff)
+		})
+		.ok_or(Overflow)?;
+	let surplus = intent_surplus(user_diff, &combined);
+	let mut user_flow = TokenDiff::new();
+	let mut solver_flows = vec![TokenDiff::new(); quotes.len()];
+	let mut fee_flow = TokenDiff::new();
+	for (asset, &amount) in user_diff {
+		let receives = amount > 0;
+		// Total the solvers send (for outputs), or at most receive (for
+		// inputs).
+		let total = if receives {
+			let fee = bps_of(amount, fee_bps);
+			let excess = surplus.get(asset).copied().unwrap_or(0);
+			let shared = excess.checked_sub(fee).filter(|&left| left >= 0);
+			let shared = shared.ok_or(FeeUncovered)?;
+			let (share, remainder) = policy.user_share(shared);
+			let (share, protocol) = match rounding {
+				SurplusRounding::Solver => (share, fee),
+				SurplusRounding::User => (share + remainder, fee),
+				SurplusRounding::Protocol => (share, fee + remainder),
+			};
+			if protocol > 0 {
+				fee_flow.insert(asset.clone(), protocol);
+			}
+			let amount = amount.checked_add(share).ok_or(Overflow)?;
+			user_flow.insert(asset.clone(), amount);
+			amount.checked_add(protocol).ok_or(Overflow)?
+		} else {
+			amount.checked_neg().ok_or(Overflow)?
+		};
+
+		let mut left = total;
+		for (flow, quote) in solver_flows.iter_mut().zip(quotes) {
+			let quoted =
+				quote.solver_token_diff.get(asset).copied().unwrap_or(0);
+			// Only the side opposite the user's counts.
+			let quoted = if receives {
+				quoted.checked_neg().ok_or(Overflow)?
+			} else {
+				quoted
+			};
+			let moved = quoted.clamp(0, left);
+			if moved > 0 {
+				let moved = if receives { -moved } else { moved };
+				flow.insert(asset.clone(), moved);
+			}
+			left -= moved;
+		}
+		if receives && left > 0 {
+			return Err(Unbalanced);
+		}
+		if !receives && left < total {
+			user_flow.insert(asset.clone(), left - total);
+		}
+	}
+	Ok(IntentFlows {
+		user: user_flow,
+		solvers: solver_flows,
+		surplus,
+		fee: fee_flow,
+	})
+}
+
+/// The matching knobs of an [`AuctionConfig`].
+#[derive(Debug, Clone)]
+struct MatchPolicy {
+	surplus_policy: SurplusPolicy,
+	surplus_rounding: SurplusRounding,
+	fee_bps: u16,
+	allow_split_fills: bool,
+	max_settlements_per_round: Option<usize>,
+	min_quotes_to_settle: usize,
+	quote_selection: QuoteSelection,
+	/// `SolverRecord::reliability_bps` per solver with a record, filled
+	/// in by the state machine for `QuoteSelection::Weighted`.
+	solver_reliability: BTreeMap<String, u64>,
+	/// Likewise, `SolverRecord::reputation`.
+	solver_reputation: BTreeMap<String, u64>,
+	tie_break: TieBreak,
+	intent_ordering: IntentOrdering,
+	gas_per_transfer: u64,
+	preferred_solvers: BTreeSet<String>,
+	preferred_tolerance_bps: u16,
+	allow_carryover: bool,
+	transfer_fee_bps: BTreeMap<AssetId, u16>,
+	/// Every member ever accepted of each pending intent's group, filled
+	/// in by the state machine. A group settles only if all of them do.
+	intent_groups: BTreeMap<String, BTreeSet<IntentId>>,
+}
+
+/// What clearing a round would do, as computed by `compute_settlement`.
+struct MatchOutcome {
+	/// The round's settlement, with no entries if nothing matched.
+	settlement: Settlement,
+
+	/// Pending intents past their deadline, which can never settle. They
+	/// are left pending for `ExpireStale`.
+	expired: Vec<IntentId>,
+
+	/// Quotes rejected while matching.
+	rejected: Vec<RejectionRecord>,
+}
+
+/// Match `pending` intents against `quotes` for `round` at `now_ms`.
+///
+/// This is `ClearRound`'s matching logic as a pure function: the state
+/// machine applies its outcome, and `PreviewRound` reports it. Intents
+/// past their deadline and quotes past their expiration are not matched.
+/// If any member of an intent group can't settle, the whole group is
+/// left out and the rest of the round is matched again without it.
+/// Likewise, an atomic bundle (a solver's set of intents) that wins some
+/// but not all of its intents is forfeited and the round matched again
+/// without the solver's quotes for them.
+#[tracing::instrument(
+	level = "debug",
+	skip(pending, quotes, bundles, policy),
+	fields(pending = pending.len()),
+)]
+fn compute_settlement(
+	round: u64,
+	pending: &BTreeMap<IntentId, Intent>,
+	quotes: &BTreeMap<IntentId, Vec<Quote>>,
+	bundles: &[BTreeSet<(String, IntentId)>],
+	now_ms: u64,
+	policy: &MatchPolicy,
+) -> MatchOutcome {
+	let mut quotes = Cow::Borrowed(quotes);
+	let mut forfeited = Vec::new();
+	// Each pass forfeits at least one bundle, so this terminates.
+	loop {
+		let mut outcome = match_chains(round, pending, &quotes, now_ms, policy);
+		// A bundle's intent is won by any fill of the solver's, whichever
+		// quote of its for the intent that fill is.
+		let won = outcome
+			.settlement
+			.settlements
+			.iter()
+			.flat_map(|settled| {
+				settled
+					.fills
+					.iter()
+					.map(|fill| (fill.solver_id.clone(), settled.intent_id))
+			})
+			.collect::<BTreeSet<_>>();
+		let broken = bundles
+			.iter()
+			.filter(|bundle| {
+				bundle.iter().any(|key| won.contains(key))
+					&& !bundle.iter().all(|key| won.contains(key))
+			})
+			.flatten()
+			.collect::<BTreeSet<_>>();
+		if broken.is_empty() {
+			outcome.rejected.append(&mut forfeited);
+			return outcome;
+		}
+		for intent_quotes in quotes.to_mut().values_mut() {
+			intent_quotes.retain(|quote| {
+				let key = (quote.solver_id.clone(), quote.intent_id);
+				if !broken.contains(&key) {
+					return true;
+				}
+				forfeited.push(RejectionRecord {
+					round,
+					intent_id: quote.intent_id,
+					quote_hash: Some(quote.quote_hash.clone()),
+					reason: RejectionReason::BundleForfeited,
+					missing_assets: Vec::new(),
+					missed_round: None,
+				});
+				false
+			});
+		}
+	}
+}
+
+/// One pass of `compute_settlement` with a fixed set of quotes: an
+/// intent with `depends_on` only settles if its dependency settles in
+/// the same round, and is ordered after it.
+fn match_chains(
+	round: u64,
+	pending: &BTreeMap<IntentId, Intent>,
+	quotes: &BTreeMap<IntentId, Vec<Quote>>,
+	now_ms: u64,
+	policy: &MatchPolicy,
+) -> MatchOutcome {
+	let mut pending = Cow::Borrowed(pending);
+	// Each pass leaves out at least one intent, so this terminates.
+	loop {
+		let mut outcome = match_groups(round, &pending, quotes, now_ms, policy);
+		let settled = outcome
+			.settlement
+			.settled_intents()
+			.into_iter()
+			.collect::<BTreeSet<_>>();
+		let orphaned = |id: &&IntentId| {
+			pending
+				.get(*id)
+				.and_then(|intent| intent.depends_on)
+				.is_some_and(|dep| !settled.contains(&dep))
+		};
+		let orphans = settled
+			.iter()
+			.filter(orphaned)
+			.copied()
+			.collect::<BTreeSet<_>>();
+		if orphans.is_empty() {
+			order_chains(&mut outcome.settlement, &pending);
+			return outcome;
+		}
+		// Orphans settled, so none of them is among the expired.
+		pending.to_mut().retain(|id, _| !orphans.contains(id));
+	}
+}
+
+/// Stably reorder `settlement`'s entries so each intent comes after the
+/// one it depends on, and refresh its commitment.
+fn order_chains(
+	settlement: &mut Settlement,
+	pending: &BTreeMap<IntentId, Intent>,
+) {
+	let settled = settlement
+		.settled_intents()
+		.into_iter()
+		.collect::<BTreeSet<_>>();
+	let dependency = |id: &IntentId| {
+		pending.get(id)?.depends_on.filter(|dep| settled.contains(dep))
+	};
+	if !settled.iter().any(|id| dependency(id).is_some()) {
+		return;
+	}
+	// Submission rejects cycles, but bound the walk anyway.
+	let depth = |id: &IntentId| {
+		let mut next = dependency(id);
+		let mut depth = 0;
+		while let Some(dep) = next
+			&& depth < settled.len()
+		{
+			next = dependency(&dep);
+			depth += 1;
+		}
+		depth
+	};
+	settlement
+		.settlements
+		.sort_by_cached_key(|entry| depth(&entry.intent_id));
+	settlement.commitment = settlement.commitment();
+}
+
+/// One pass of `match_chains`: intent groups are settled whole or not at
+/// all.
+fn match_groups(
+	round: u64,
+	pending: &BTreeMap<IntentId, Intent>,
+	quotes: &BTreeMap<IntentId, Vec<Quote>>,
+	now_ms: u64,
+	policy: &MatchPolicy,
+) -> MatchOutcome {
+	let outcome = match_intents(round, pending, quotes, now_ms, policy);
+	let settled = outcome.settlement.settled_intents();
+	// A member that isn't pending here, say one that expired, can't
+	// settle this round either.
+	let unsettled = |id: &IntentId| !settled.contains(id);
+	let broken = pending
+		.values()
+		.filter(|intent| {
+			unsettled(&intent.id)
+				|| intent
+					.group_id
+					.as_ref()
+					.and_then(|group| policy.intent_groups.get(group))
+					.is_some_and(|members| members.iter().any(unsettled))
+		})
+		.filter_map(|intent| intent.group_id.as_ref())
+		.collect::<BTreeSet<_>>();
+	if broken.is_empty() {
+		return outcome;
+	}
+
+	// Leaving groups out only frees capacity, so every group that settled
+	// in full above still does.
+	let kept = pending
+		.iter()
+		.filter(|(_, intent)| {
+			intent.group_id.as_ref().is_none_or(|g| !broken.contains(g))
+		})
+		.map(|(&id, intent)| (id, intent.clone()))
+		.collect::<BTreeMap<_, _>>();
+	let mut retry = match_intents(round, &kept, quotes, now_ms, policy);
+	retry.expired.extend(
+		outcome
+			.expired
+			.into_iter()
+			.filter(|id| !kept.contains_key(id)),
+	);
+	retry
+}
+
+/// One matching pass of `match_groups`, ignoring intent groups.
+fn match_intents(
+	round: u64,
+	pending: &BTreeMap<IntentId, Intent>,
+	quotes: &BTreeMap<IntentId, Vec<Quote>>,
+	now_ms: u64,
+	policy: &MatchPolicy,
+) -> MatchOutcome {
+	let mut settlements = Vec::new();
+	let mut aggregate_flow = TokenDiff::new();
+	let mut surplus = TokenDiff::new();
+	let mut fees = TokenDiff::new();
+	let mut volume = BTreeMap::new();
+	let mut rejected = Vec::new();
+	let mut expired = Vec::new();
+	let mut partial_fills = Vec::new();
+	let mut transfer_fees = TokenDiff::new();
+
+	// Quotes are matched on what users net after token transfer fees.
+	let gross_quotes = quotes;
+	let netted;
+	let quotes = if policy.transfer_fee_bps.is_empty() {
+		quotes
+	} else {
+		netted = net_of_transfer_fees(quotes, &policy.transfer_fee_bps);
+		&netted
+	};
+
+	// Higher fees are matched first, then by `intent_ordering`; the sort
+	// is stable, so remaining ties keep ascending id order.
+	let mut by_priority = pending.iter().collect::<Vec<_>>();
+	by_priority.sort_by_cached_key(|(_, intent)| {
+		let rank = policy.intent_ordering.rank(round, intent);
+		(Reverse(intent.priority_fee), rank)
+	});
+
+	for (&intent_id, intent) in by_priority {
+		// Past its deadline the intent can never settle.
+		if intent.deadline_ms < now_ms {
+			expired.push(intent_id);
+			continue;
+		}
+
+		if policy
+			.max_settlements_per_round
+			.is_some_and(|max| settlements.len() >= max)
+		{
+			continue;
+		}
+
+		// Only TokenDiff intents participate in the auction.
+		let Some(user_diff) = intent.token_diff() else {
+			continue;
+		};
+
+		let Some(intent_quotes) = quotes.get(&intent_id) else {
+			continue;
+		};
+
+		let (live, stale): (Vec<_>, Vec<_>) =
+			intent_quotes.iter().partition(|q| now_ms <= q.expiration_ms);
+		for quote in stale {
+			rejected.push((quote, RejectionReason::Expired));
+		}
+
+		// Without enough competition the intent waits for more quotes.
+		if competition_level(user_diff, &live) < policy.min_quotes_to_settle {
+			continue;
+		}
+
+		let (winners, mut quote_rejections) =
+			select_winners(user_diff, &live, round, policy);
+		rejected.append(&mut quote_rejections);
+
+		// Without a full fill, settle what the quotes do cover and carry
+		// the rest over. Groups only ever settle in full.
+		let partial_diff;
+		let (winners, user_diff, partial) = if !winners.is_empty() {
+			(winners, user_diff, false)
+		} else if policy.allow_carryover
+			&& intent.group_id.is_none()
+			&& let Some((fills, diff)) = partial_fill(user_diff, &live)
+		{
+			rejected
+				.retain(|(q, _)| !fills.iter().any(|f| std::ptr::eq(*f, *q)));
+			partial_diff = diff;
+			(fills, &partial_diff, true)
+		} else {
+			continue;
+		};
+
+		// A limit price bounds the winners' rate, whatever the amounts.
+		if let Some(limit) = &intent.limit_price
+			&& !meets_limit_price(limit, &winners)
+		{
+			for quote in winners {
+				rejected.push((quote, RejectionReason::BelowLimitPrice));
+			}
+			continue;
+		}
+
+		// Accumulate the aggregate flow. Adversarial amounts can
+		// overflow; such an intent is left unsettled and the round
+		// totals are only updated once every sum has succeeded.
+		let mut flows = match settlement_flows(
+			user_diff,
+			&winners,
+			policy.surplus_policy,
+			policy.surplus_rounding,
+			policy.fee_bps,
+		) {
+			Ok(flows) => flows,
+			Err(reason) => {
+				for quote in winners {
+					rejected.push((quote, reason));
+				}
+				continue;
+			}
+		};
+		// Solvers send enough gross for the user to net their share; the
+		// difference goes to the token contracts.
+		let signed =
+			gross_quotes.get(&intent_id).map_or(&[][..], Vec::as_slice);
+		let transfer_fee = gross_up(
+			&mut flows.solvers,
+			&winners,
+			signed,
+			&policy.transfer_fee_bps,
+		);
+		let net_flow = transfer_fee.as_ref().and_then(|transfer_fee| {
+			flows
+				.solvers
+				.iter()
+				.chain([&flows.fee, transfer_fee])
+				.try_fold(flows.user.clone(), |net, flow| {
+					aggregate_token_flow(&net, flow)
+				})
+		});
+		let totals = net_flow.as_ref().zip(transfer_fee.as_ref()).and_then(
+			|(net_flow, transfer_fee)| {
+				Some((
+					aggregate_token_flow(&aggregate_flow, net_flow)?,
+					aggregate_token_flow(&surplus, &flows.surplus)?,
+					aggregate_token_flow(&fees, &flows.fee)?,
+					aggregate_token_flow(&transfer_fees, transfer_fee)?,
+				))
+			},
+		);
+		let (
+			Some(_),
+			Some((next_flow, next_surplus, next_fees, next_transfer_fees)),
+		) = (net_flow, totals)
+		else {
+			for quote in winners {
+				rejected.push((quote, RejectionReason::Overflow));
+			}
+			continue;
+		};
+
+		// The flows balance by construction, so check them against what
+		// was signed instead: a bug above must not move anyone's tokens
+		// beyond their terms.
+		let gross_winners = winners
+			.iter()
+			.map(|&winner| {
+				signed
+					.iter()
+					.find(|q| q.quote_hash == winner.quote_hash)
+					.unwrap_or(winner)
+			})
+			.collect::<Vec<_>>();
+		if !within_terms(user_diff, &flows, &gross_winners) {
+			for quote in winners {
+				rejected.push((quote, RejectionReason::Unbalanced));
+			}
+			continue;
+		}
+		aggregate_flow = next_flow;
+		fees = next_fees;
+		surplus = next_surplus;
+		transfer_fees = next_transfer_fees;
+
+		for (asset, &amount) in &flows.user {
+			let traded: &mut u128 = volume.entry(asset.clone()).or_default();
+			*traded = traded.saturating_add(amount.unsigned_abs());
+		}
+
+		let fills = winners
+			.into_iter()
+			.zip(flows.solvers)
+			.map(|(quote, flow)| Fill {
+				solver_id: quote.solver_id.clone(),
+				quote_hash: quote.quote_hash.clone(),
+				flow,
+			})
+			.collect();
+		if partial {
+			partial_fills.push(intent_id);
+		}
+		settlements.push(SettledIntent {
+			intent_id,
+			signer_id: intent.signer_id.clone(),
+			fills,
+			flow: flows.user,
+			surplus: flows.surplus,
+			fee: flows.fee,
+		});
+	}
+
+	let rejected = rejected
+		.into_iter()
+		.map(|(quote, reason)| {
+			let missing_assets = match pending
+				.get(&quote.intent_id)
+				.and_then(Intent::token_diff)
+			{
+				Some(user_diff) if reason == RejectionReason::Incompatible => {
+					missing_outputs(user_diff, &quote.solver_token_diff)
+				}
+				_ => Vec::new(),
+			};
+			RejectionRecord {
+				round,
+				intent_id: quote.intent_id,
+				quote_hash: Some(quote.quote_hash.clone()),
+				reason,
+				missing_assets,
+				missed_round: None,
+			}
+		})
+		.collect();
+	debug_assert!(
+		aggregate_flow.values().all(|&amount| amount == 0),
+		"round {round} settlement is not zero-sum: {aggregate_flow:?}",
+	);
+	let estimated_transfers = settlements
+		.iter()
+		.map(SettledIntent::transfer_count)
+		.sum::<usize>();
+	let mut settlement = Settlement {
+		round,
+		settlements,
+		aggregate_flow,
+		surplus,
+		volume,
+		failed: false,
+		fees,
+		estimated_transfers,
+		estimated_gas: (estimated_transfers as u64)
+			.saturating_mul(policy.gas_per_transfer),
+		partial_fills,
+		transfer_fees,
+		commitment: [0; 32],
+	};
+	settlement.commitment = settlement.commitment();
+	MatchOutcome {
+		settlement,
+		expired,
+		rejected,
+	}
+}
+
+/// Whether `flows` keep to the signed terms: the user sends no more
+/// than `user_diff` offers and gets at least its minimum, and no solver
+/// sends more of any asset than its quote in `winners` does.
+fn within_terms(
+	user_diff: &TokenDiff,
+	flows: &IntentFlows,
+	winners: &[&Quote],
+) -> bool {
+	let user_ok = user_diff.keys().chain(flows.user.keys()).all(|asset| {
+		let flow = flows.user.get(asset).copied().unwrap_or(0);
+		flow >= user_diff.get(asset).copied().unwrap_or(0)
+	});
+	let solvers_ok = flows.solvers.iter().zip(winners).all(|(flow, quote)| {
+		flow.iter().all(|(asset, &amount)| {
+			let quoted = quote.solver_token_diff.get(asset).copied();
+			amount >= quoted.unwrap_or(0).min(0)
+		})
+	});
+	user_ok && solvers_ok && flows.solvers.len() == winners.len()
+}
+
+/// Every solver with a fill in `settlement`, once each.
+fn settlement_solvers(settlement: &Settlement) -> BTreeSet<&str> {
+	settlement
+		.settlements
+		.iter()
+		.flat_map(|s| &s.fills)
+		.map(|f| f.solver_id.as_str())
+		.collect()
+}
+
+/// How many distinct solvers offer a compatible quote for an intent.
+/// Several quotes from one solver count once.
+fn competition_level(user_diff: &TokenDiff, quotes: &[&Quote]) -> usize {
+	quotes
+		.iter()
+		.filter(|q| quote_compatible(user_diff, q))
+		.map(|q| q.solver_id.as_str())
+		.collect::<BTreeSet<_>>()
+		.len()
+}
+
+/// Pick the winning quotes for an intent, returning them (empty if the
+/// intent can't settle this round) along with the quotes rejected on
+/// the way.
+///
+/// Only compatible quotes that meet the user's minimum on their own can
+/// win, so a quote can't outrank the others by advertising an
+/// `amount_out` its diff doesn't deliver. Of those, the highest
+/// `amount_out` wins, or for NFT intents (where `amount_out` is
+/// meaningless) the one expiring soonest. Exact ties go by the policy's
+/// `tie_break`. If none qualifies and `allow_split_fills` is set, the
+/// intent may instead be filled by several quotes together.
+fn select_winners<'a>(
+	user_diff: &TokenDiff,
+	quotes: &[&'a Quote],
+	round: u64,
+	policy: &MatchPolicy,
+) -> (Vec<&'a Quote>, Vec<(&'a Quote, RejectionReason)>) {
+	let (compatible, incompatible): (Vec<_>, Vec<_>) = quotes
+		.iter()
+		.copied()
+		.partition(|q| quote_compatible(user_diff, q));
+
+	let tie = |q: &&Quote| policy.tie_break.rank(round, q);
+	let (eligible, short): (Vec<_>, Vec<_>) = compatible
+		.into_iter()
+		.partition(|q| meets_minimum_output(user_diff, &q.solver_token_diff));
+	let best = if user_diff.keys().any(AssetId::is_non_fungible) {
+		eligible
+			.iter()
+			.copied()
+			.min_by_key(|q| (q.expiration_ms, Reverse(tie(q))))
+	} else {
+		let best = match policy.quote_selection {
+			QuoteSelection::BestPrice => None,
+			QuoteSelection::Weighted {
+				price_weight,
+				reliability_weight,
+				validity_weight,
+				reputation_weight,
+			} => weighted_best(
+				&eligible,
+				[
+					price_weight,
+					reliability_weight,
+					validity_weight,
+					reputation_weight,
+				],
+				policy,
+				tie,
+			),
+		};
+		// Without a weighted pick, the best price wins.
+		let best = best.or_else(|| {
+			eligible
+				.iter()
+				.copied()
+				.max_by_key(|q| (q.amount_out, tie(q)))
+		});
+
+		// A preferred solver close enough to the best quote takes it.
+		best.map(|best| {
+			let tolerance = policy.preferred_tolerance_bps;
+			let slack = to_i128(best.amount_out)
+				.and_then(|amount| to_u128(bps_of(amount, tolerance)))
+				.unwrap_or(0);
+			eligible
+				.iter()
+				.copied()
+				.filter(|q| policy.preferred_solvers.contains(&q.solver_id))
+				.filter(|q| q.amount_out >= best.amount_out - slack)
+				.max_by_key(|q| (q.amount_out, tie(q)))
+				.unwrap_or(best)
+		})
+	};
+
+	// Never settle below the user's minimum; leave the intent pending for
+	// a better quote instead.
+	let winners = match best {
+		Some(best) => vec![best],
+		None if policy.allow_split_fills => {
+			split_fill(user_diff, quotes).unwrap_or_default()
+		}
+		None => Vec::new(),
+	};
+
+	// Quotes that ended up in a split fill aren't rejections.
+	let mut rejected = short
+		.into_iter()
+		.chain(incompatible)
+		.map(|q| (q, RejectionReason::Incompatible))
+		.collect::<Vec<_>>();
+	rejected.retain(|(q, _)| !winners.iter().any(|w| std::ptr::eq(*w, *q)));
+	(winners, rejected)
+}
+
+/// The quote in `candidates` with the highest weighted score under
+/// `QuoteSelection::Weighted`, `weights` being price, reliability,
+/// validity and reputation. Ties go to the higher `amount_out`, then
+/// `tie`.
+fn weighted_best<'a>(
+	candidates: &[&'a Quote],
+	weights: [u64; 4],
+	policy: &MatchPolicy,
+	tie: impl Fn(&&Quote) -> u64,
+) -> Option<&'a Quote> {
+	let reliability = &policy.solver_reliability;
+	let reputation = |q: &&Quote| {
+		let score = policy.solver_reputation.get(&q.solver_id).copied();
+		u128::from(score.unwrap_or(0))
+	};
+	let best_out = candidates.iter().map(|q| q.amount_out).max()?;
+	let best_reputation = candidates.iter().map(reputation).max()?;
+	let earliest = candidates.iter().map(|q| q.expiration_ms).min()?;
+	let latest = candidates.iter().map(|q| q.expiration_ms).max()?;
+	let scaled = |amount: u128, max: u128| match max {
+		0 => 10_000,
+		max => amount.saturating_mul(10_000) / max,
+	};
+	candidates.iter().copied().max_by_key(|q| {
+		let scores = [
+			scaled(q.amount_out, best_out),
+			reliability.get(&q.solver_id).copied().map_or_else(
+				|| u128::from(SolverRecord::default().reliability_bps()),
+				u128::from,
+			),
+			scaled(
+				u128::from(q.expiration_ms - earliest),
+				u128::from(latest - earliest),
+			),
+			scaled(reputation(q), best_reputation),
+		];
+		let score = scores
+			.into_iter()
+			.zip(weights)
+			.map(|(score, weight)| score.saturating_mul(u128::from(weight)))
+			.fold(0u128, u128::saturating_add);
+		(score, q.amount_out, tie(q))
+	})
+}
+
+/// Combine quotes, highest `amount_out` first, until together they cover
+/// the user's minimum output without asking for more than the user
+/// offers. Only fungible intents with a single output asset can be split.
+///
+/// Returns `None` if the quotes can't cover the intent.
+fn split_fill<'a>(
+	user_diff: &TokenDiff,
+	quotes: &[&'a Quote],
+) -> Option<Vec<&'a Quote>> {
+	if user_diff.keys().any(AssetId::is_non_fungible)
+		|| user_diff.values().filter(|&&amount| amount > 0).count() != 1
+	{
+		return None;
+	}
+
+	let mut candidates: Vec<_> = quotes
+		.iter()
+		.copied()
+		.filter(|q| {
+			can_partially_fill(user_diff, &q.solver_token_diff)
+				&& attests_destination(user_diff, q)
+		})
+		.collect();
+	// Stable sort: ties keep arrival order.
+	candidates.sort_by(|a, b| b.amount_out.cmp(&a.amount_out));
+
+	let mut combined = TokenDiff::new();
+	let mut fills = Vec::new();
+	for quote in candidates {
+		let Some(next) =
+			aggregate_token_flow(&combined, &quote.solver_token_diff)
+		else {
+			continue;
+		};
+		// Together the solvers may not ask for more than the user offers.
+		let over_offer = user_diff.iter().any(|(asset, &offered)| {
+			offered < 0
+				&& next.get(asset).is_some_and(|&taken| {
+					taken.unsigned_abs() > offered.unsigned_abs()
+				})
+		});
+		if over_offer {
+			continue;
+		}
+		combined = next;
+		fills.push(quote);
+		if token_diffs_compatible(user_diff, &combined)
+			&& meets_minimum_output(user_diff, &combined)
+		{
+			return Some(fills);
+		}
+	}
+	None
+}
+
+/// `quotes` with every asset a solver sends reduced by its transfer fee,
+/// i.e. as what the receiving user actually nets.
+fn net_of_transfer_fees(
+	quotes: &BTreeMap<IntentId, Vec<Quote>>,
+	fee_bps: &BTreeMap<AssetId, u16>,
+) -> BTreeMap<IntentId, Vec<Quote>> {
+	let mut netted = quotes.clone();
+	for quote in netted.values_mut().flatten() {
+		for (asset, amount) in &mut quote.solver_token_diff {
+			if let Some(&bps) = fee_bps.get(asset)
+				&& *amount < 0
+			{
+				*amount -= bps_of(*amount, bps);
+			}
+		}
+	}
+	netted
+}
+
+/// Turn the net amounts winning solvers send in `solvers` (one flow per
+/// winner, matched on netted quotes) back into what they must send
+/// gross, never more than they quoted. Returns the transfer fees taken
+/// on the way, or `None` on overflow.
+fn gross_up(
+	solvers: &mut [TokenDiff],
+	winners: &[&Quote],
+	gross_quotes: &[Quote],
+	fee_bps: &BTreeMap<AssetId, u16>,
+) -> Option<TokenDiff> {
+	let mut taken = TokenDiff::new();
+	if fee_bps.is_empty() {
+		return Some(taken);
+	}
+	for (flow, winner) in solvers.iter_mut().zip(winners) {
+		let quoted = gross_quotes
+			.iter()
+			.find(|q| q.quote_hash == winner.quote_hash)?;
+		for (asset, amount) in flow.iter_mut() {
+			let Some(&bps) = fee_bps.get(asset) else {
+				continue;
+			};
+			if *amount >= 0 {
+				continue;
+			}
+			let net = amount.checked_neg()?;
+			let quoted_gross = quoted
+				.solver_token_diff
+				.get(asset)
+				.copied()
+				.unwrap_or(0)
+				.checked_neg()?;
+			let gross = if net == quoted_gross - bps_of(quoted_gross, bps) {
+				quoted_gross
+			} else {
+				gross_of(net, bps)?.min(quoted_gross).max(net)
+			};
+			*amount = gross.checked_neg()?;
+			let fee = taken.entry(asset.clone()).or_default();
+			*fee = fee.checked_add(gross - net)?;
+		}
+	}
+	taken.retain(|_, &mut fee| fee != 0);
+	Some(taken)
+}
+
+/// The smallest gross transfer that nets at least `net` after a `bps`
+/// transfer fee.
+fn gross_of(net: i128, bps: u16) -> Option<i128> {
+	let keep = 10_000 - i128::from(bps.min(9_999));
+	net.checked_mul(10_000)?
+		.checked_add(keep - 1)
+		.map(|scaled| scaled / keep)
+}
+
+/// Fill part of a single-pair fungible intent: combine quotes, highest
+/// `amount_out` first, as long as together they take no more input than
+/// the user offers and pay at least the user's limit price for what they
+/// take. Returns the quotes with the user's pro-rata share of the intent,
+/// or `None` if no quote helps.
+fn partial_fill<'a>(
+	user_diff: &TokenDiff,
+	quotes: &[&'a Quote],
+) -> Option<(Vec<&'a Quote>, TokenDiff)> {
+	if user_diff.keys().any(AssetId::is_non_fungible) {
+		return None;
+	}
+	let mut sent = user_diff.iter().filter(|&(_, &amount)| amount < 0);
+	let mut received = user_diff.iter().filter(|&(_, &amount)| amount > 0);
+	let ((asset_in, offered), None) = (sent.next()?, sent.next()) else {
+		return None;
+	};
+	let ((asset_out, wanted), None) = (received.next()?, received.next())
+	else {
+		return None;
+	};
+	let (offered, wanted) = (offered.unsigned_abs(), wanted.unsigned_abs());
+
+	let mut candidates: Vec<_> = quotes
+		.iter()
+		.copied()
+		.filter(|q| {
+			can_partially_fill(user_diff, &q.solver_token_diff)
+				&& attests_destination(user_diff, q)
+		})
+		.collect();
+	// Stable sort: ties keep arrival order.
+	candidates.sort_by(|a, b| b.amount_out.cmp(&a.amount_out));
+
+	let mut combined = TokenDiff::new();
+	let mut fills = Vec::new();
+	for quote in candidates {
+		let Some(next) =
+			aggregate_token_flow(&combined, &quote.solver_token_diff)
+		else {
+			continue;
+		};
+		let taken = next.get(asset_in).copied().unwrap_or(0).unsigned_abs();
+		let given = next.get(asset_out).copied().unwrap_or(0).unsigned_abs();
+		// given / taken must be at least the user's wanted / offered.
+		let fair = given
+			.checked_mul(offered)
+			.zip(taken.checked_mul(wanted))
+			.is_some_and(|(paid, owed)| paid >= owed);
+		if taken == 0 || taken > offered || !fair {
+			continue;
+		}
+		combined = next;
+		fills.push(quote);
+	}
+
+	let taken = combined.get(asset_in)?.unsigned_abs();
+	let share = taken.checked_mul(wanted)? / offered;
+	let diff = TokenDiff::from([
+		(asset_in.clone(), -to_i128(taken)?),
+		(asset_out.clone(), to_i128(share)?),
+	]);
+	(!fills.is_empty() && share > 0).then_some((fills, diff))
+}
+
+/// Whether a quote could be part of a split fill: it only touches the
+/// user's assets, provides some of the output, and takes no more than
+/// the user offers of any input.
+fn can_partially_fill(user_diff: &TokenDiff, solver_diff: &TokenDiff) -> bool {
+	let provides_output = solver_diff.iter().any(|(asset, &amount)| {
+		amount < 0 && user_diff.get(asset).is_some_and(|&wanted| wanted > 0)
+	});
+	provides_output
+		&& solver_diff.iter().all(|(asset, &amount)| {
+			match user_diff.get(asset) {
+				Some(&wanted) if wanted > 0 => amount < 0,
+				Some(&offered) => {
+					amount > 0
+						&& amount.unsigned_abs() <= offered.unsigned_abs()
+				}
+				None => false,
+			}
+		})
+}
+
+/// Compute the aggregate token flow for a user+solver pair, or the sum of
+/// any two flows. Returns `None` if any asset's sum overflows i128.
+fn aggregate_token_flow(
+	user_diff: &TokenDiff,
+	solver_diff: &TokenDiff,
+) -> Option<TokenDiff> {
+	let mut flow = user_diff.clone();
+	for (asset, &amount) in solver_diff {
+		let total = flow.entry(asset.clone()).or_insert(0);
+		*total = total.checked_add(amount)?;
+	}
+	Some(flow)
+}
+
+impl StateMachine for AuctionStateMachine {
+	/// Ends in [`PROTOCOL_VERSION`].
+	const ID: UniqueId = unique_id!(
+		"6e656172696e74656e74732d61756374696f6e2d763100000000000000000003"
+	);
+
+	type Command = VersionedCommand;
+	type Query = AuctionQuery;
+	type QueryResult = AuctionQueryResult;
+
+	fn reset(&mut self) {
+		self.pending_intents.clear();
+		self.intent_status.clear();
+		self.current_round = 0;
+		self.quotes.clear();
+		self.atomic_bundles.clear();
+		self.round_results.clear();
+		self.sealed_rounds.clear();
+		self.filled_intents.clear();
+		self.intents_by_signer.clear();
+		self.rate_limited_intents = 0;
+		self.round_deadline_ms = None;
+		self.late_quotes = 0;
+		self.volume.clear();
+		self.rejections.clear();
+		self.solver_bonds.clear();
+		self.operator_nonces.clear();
+		self.solver_records.clear();
+		self.solver_last_seen.clear();
+		self.paused = false;
+		self.next_unpublished_round = 0;
+		self.expiries.clear();
+		self.round_events.clear();
+		self.next_unannounced_round = 0;
+		self.intent_groups.clear();
+		self.seen_intents.clear();
+		self.seen_intent_set.clear();
+		self.request_ids.clear();
+		self.request_id_set.clear();
+		self.terminal_intents.clear();
+		self.signer_sequences.clear();
+		self.account_keys.clear();
+		self.amendment_nonces.clear();
+		self.unfilled.clear();
+		self.received_ms.clear();
+		self.latency_counts = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+		self.max_latency_ms = 0;
+		self.intents_submitted = 0;
+		self.intents_settled = 0;
+		self.quotes_submitted = 0;
+		self.quotes_rejected = 0;
+		self.rounds_cleared = 0;
+		self.expiries_dropped = 0;
+	}
+
+	fn apply(&mut self, command: Self::Command) {
+		let VersionedCommand { version, command } = command;
+		if version != PROTOCOL_VERSION {
+			tracing::warn!(
+				version,
+				expected = PROTOCOL_VERSION,
+				command = command.kind(),
+				"ignoring command of another protocol version",
+			);
+			return;
+		}
+		let _span = tracing::debug_span!(
+			"apply",
+			command = command.kind(),
+			round = self.current_round,
+		)
+		.entered();
+
+		match command {
+			AuctionCommand::SubmitIntent { intent, now_ms } => {
+				self.intents_submitted += 1;
+
+				if self.paused {
+					self.reject(intent.id, None, RejectionReason::Paused);
+					return;
+				}
+				if intent.token_diff().is_some_and(|diff| self.too_large(diff)) {
+					self.reject(intent.id, None, RejectionReason::TooLarge);
+					return;
+				}
+				if intent.token_diff().is_some_and(|diff| self.disallowed(diff))
+				{
+					let reason = RejectionReason::DisallowedAsset;
+					self.reject(intent.id, None, reason);
+					return;
+				}
+
+				// Only intents authorized by their signer may enter the book.
+				if !intent.verify_signature() {
+					self.reject(intent.id, None, RejectionReason::InvalidSignature);
+					return;
+				}
+				if self
+					.account_keys
+					.get(&intent.signer_id)
+					.is_some_and(|key| *key != intent.public_key)
+				{
+					let reason = RejectionReason::UnregisteredKey;
+					self.reject(intent.id, None, reason);
+					return;
+				}
+
+				if intent.token_diff().is_some_and(|diff| !is_well_formed(diff)) {
+					self.reject(intent.id, None, RejectionReason::Malformed);
+					return;
+				}
+				if self.closes_cycle(&intent) {
+					let reason = RejectionReason::DependencyCycle;
+					self.reject(intent.id, None, reason);
+					return;
+				}
+
+				// A stream may redeliver an intent, possibly under another
+				// id, so duplicates are caught by signer and sequence too.
+				let seen = (intent.signer_id.clone(), intent.sequence);
+				if self.intent_status.contains_key(&intent.id)
+					|| self.seen_intent_set.contains(&seen)
+				{
+					self.reject(intent.id, None, RejectionReason::Duplicate);
+					return;
+				}
+
+				// Each signer's intents form a strictly increasing sequence,
+				// so an old signed intent can't be replayed.
+				if self
+					.signer_sequences
+					.get(&intent.signer_id)
+					.is_some_and(|&last| intent.sequence <= last)
+				{
+					self.reject(intent.id, None, RejectionReason::StaleNonce);
+					return;
+				}
+
+				let submitted = self
+					.intents_by_signer
+					.entry(intent.signer_id.clone())
+					.or_default();
+				if let Some(max) = self.config.max_intents_per_signer_per_round
+					&& *submitted >= max as u64
+				{
+					self.rate_limited_intents += 1;
+					self.reject(intent.id, None, RejectionReason::RateLimited);
+					return;
+				}
+				*submitted += 1;
+
+				let id = intent.id;
+				self.signer_sequences
+					.insert(intent.signer_id.clone(), intent.sequence);
+				if self.seen_intents.len() >= SEEN_INTENT_CAPACITY
+					&& let Some(oldest) = self.seen_intents.pop_front()
+				{
+					self.seen_intent_set.remove(&oldest);
+				}
+				self.seen_intent_set.insert(seen.clone());
+				self.seen_intents.push_back(seen);
+				if let Some(group_id) = &intent.group_id {
+					self.intent_groups
+						.entry(group_id.clone())
 						.or_default()
-						.push(quote);
+						.insert(id);
+				}
+				self.intent_status.insert(id, IntentStatus::Pending);
+				self.pending_intents.insert(id, intent);
+				self.received_ms.insert(id, now_ms);
+			}
+			AuctionCommand::SubmitQuote { quote, now_ms } => {
+				let id = quote.intent_id;
+				if self.submit_quote(quote, now_ms)
+					&& self.config.clearing_mode == ClearingMode::Continuous
+				{
+					self.settle_on_arrival(id, now_ms);
+				}
+			}
+			AuctionCommand::SubmitQuoteBundle {
+				solver_id,
+				quotes,
+				atomic,
+				now_ms,
+			} => {
+				// A bundle speaks for a single solver.
+				if quotes.iter().any(|quote| quote.solver_id != solver_id) {
+					let reason = RejectionReason::SignerMismatch;
+					for quote in &quotes {
+						self.quotes_submitted += 1;
+						self.reject_quote(quote, reason);
+					}
+					return;
+				}
+				let bundle = quotes
+					.iter()
+					.map(|quote| (solver_id.clone(), quote.intent_id))
+					.collect::<BTreeSet<_>>();
+				let ids = quotes
+					.iter()
+					.map(|quote| quote.intent_id)
+					.collect::<Vec<_>>();
+				for quote in quotes {
+					self.submit_quote(quote, now_ms);
+				}
+
+				// Atomic bundles are only weighed as a whole, at `ClearRound`.
+				let continuous =
+					self.config.clearing_mode == ClearingMode::Continuous;
+				if atomic {
+					self.atomic_bundles.push(bundle);
+				} else if continuous {
+					for id in ids {
+						self.settle_on_arrival(id, now_ms);
+					}
+				}
+			}
+			AuctionCommand::SetPaused { paused, auth } => {
+				if !self.authorize(&auth, &("set_paused", paused)) {
+					tracing::warn!(paused, "ignoring unauthorized pause");
+					return;
+				}
+				self.paused = paused;
+				tracing::info!(paused, "pause set");
+			}
+			AuctionCommand::OpenRound { deadline_ms } => {
+				let round = self.current_round;
+				if let Some(open) = self.round_deadline_ms {
+					tracing::warn!(
+						round,
+						deadline_ms = open,
+						"ignoring open of a round already open",
+					);
+					return;
+				}
+				let opened = Outcome::RoundOpened { round, deadline_ms };
+				self.log_round_event(opened);
+				self.round_deadline_ms = Some(deadline_ms);
+			}
+			AuctionCommand::ClearRound { now_ms } => {
+				self.clear_round(now_ms, None);
+			}
+			AuctionCommand::ClearRoundFiltered {
+				now_ms,
+				asset,
+				signer_prefix,
+			} => {
+				let only = self.filtered_intents(
+					asset.as_ref(),
+					signer_prefix.as_deref(),
+				);
+				self.clear_round(now_ms, Some(&only));
+			}
+			AuctionCommand::RevealRound { round, now_ms } => {
+				let Some(&cleared_ms) = self.sealed_rounds.get(&round) else {
+					return;
+				};
+				let delay = self.config.reveal_delay_ms.unwrap_or(0);
+				if now_ms < cleared_ms.saturating_add(delay) {
+					tracing::warn!(round, "reveal before the delay passed");
+					return;
+				}
+				self.sealed_rounds.remove(&round);
+				let revealed = self
+					.round_results
+					.iter()
+					.find(|s| s.round == round && !s.failed)
+					.cloned();
+				// A round that failed while sealed moved nothing, so there
+				// is nothing to count or journal.
+				let Some(settlement) = revealed else {
+					tracing::info!(round, "failed round revealed");
+					return;
+				};
+				self.add_volume(&settlement);
+				self.journal(round);
+				tracing::info!(round, "round revealed");
+			}
+			AuctionCommand::Drain { now_ms } => {
+				self.clear_round(now_ms, None);
+				let remaining =
+					self.pending_intents.keys().copied().collect::<Vec<_>>();
+				for id in remaining {
+					self.expire(id);
+				}
+			}
+			AuctionCommand::FailSettlement { round, now_ms } => {
+				let Some(settlement) = self
+					.round_results
+					.iter_mut()
+					.find(|s| s.round == round && !s.failed)
+				else {
+					return;
+				};
+				settlement.failed = true;
+				let settlement = settlement.clone();
+				let sealed = self.sealed_rounds.contains_key(&round);
+				tracing::warn!(
+					round,
+					intents = settlement.settlements.len(),
+					"settlement failed",
+				);
+
+				// The winning solvers failed to deliver; each loses part of
+				// its bond once per failed round.
+				let penalty = self.config.reputation.fail_penalty;
+				for solver_id in settlement_solvers(&settlement) {
+					if let Some(bond) = self.solver_bonds.get_mut(solver_id) {
+						*bond = bond.saturating_sub(self.config.bond_slash_amount);
+					}
+					if let Some(record) =
+						self.solver_records.get_mut(solver_id)
+					{
+						record.failed = record.failed.saturating_add(1);
+						record.reputation =
+							record.reputation.saturating_sub(penalty);
+					}
+				}
+
+				// Nothing moved on-chain, so take the round out of the volume,
+				// unless it was still sealed and so never counted.
+				if !sealed {
+					for (asset, amount) in &settlement.volume {
+						if let Some(total) = self.volume.get_mut(asset) {
+							*total = total.saturating_sub(*amount);
+						}
+					}
+				}
+
+				// Fills that never happened are owed again, and no more.
+				for settled in &settlement.settlements {
+					let id = settled.intent_id;
+					if let Some(filled) = self.filled_intents.get_mut(&id) {
+						filled.filled = deduct(&filled.filled, &settled.flow);
+					}
+					if !settlement.partial_fills.contains(&id) {
+						continue;
+					}
+					let Some(left) = self.unfilled.get_mut(&id) else {
+						continue;
+					};
+					for (asset, &amount) in &settled.flow {
+						if let Some(wanted) = left.get_mut(asset) {
+							*wanted = wanted.saturating_add(amount);
+						}
+					}
+				}
+
+				// Re-queue settled intents that can still be auctioned,
+				// owing what their successful fills didn't cover; the rest
+				// have missed their deadline and are no longer valid.
+				// Partly filled ones never left the book.
+				for id in settlement.settled_intents() {
+					let Some(FilledIntent { intent, filled, .. }) =
+						self.forget_fill(id, round)
+					else {
+						continue;
+					};
+					let status = self.intent_status.get(&id).copied();
+					if status != Some(IntentStatus::Settled) {
+						continue;
+					}
+					if now_ms <= intent.deadline_ms {
+						if let Some(signed) = intent.token_diff()
+							&& filled.values().any(|&amount| amount != 0)
+						{
+							self.unfilled.insert(id, deduct(signed, &filled));
+						}
+						self.intent_status.insert(id, IntentStatus::Pending);
+						self.pending_intents.insert(id, intent);
+						self.received_ms.insert(id, now_ms);
+					} else {
+						self.intent_status.insert(id, IntentStatus::Expired);
+						self.retire(intent);
+						self.log_expiry(id);
+					}
+				}
+			}
+			AuctionCommand::ExpireStale { now_ms } => {
+				let stale = self
+					.pending_intents
+					.values()
+					.filter(|intent| intent.deadline_ms < now_ms)
+					.map(|intent| intent.id)
+					.collect::<Vec<_>>();
+				for id in stale {
+					self.expire(id);
+				}
+			}
+			AuctionCommand::DepositBond {
+				solver_id,
+				amount,
+				auth,
+			} => {
+				if !self.authorize(&auth, &("deposit_bond", &solver_id, amount))
+				{
+					tracing::warn!(solver_id, "ignoring unauthorized bond");
+					return;
+				}
+				let bond = self.solver_bonds.entry(solver_id).or_default();
+				*bond = bond.saturating_add(amount);
+			}
+			AuctionCommand::RegisterKey {
+				signer_id,
+				public_key,
+				auth,
+			} => {
+				let fields = ("register_key", &signer_id, &public_key);
+				if !self.authorize(&auth, &fields) {
+					tracing::warn!(signer_id, "ignoring unauthorized key");
+					return;
+				}
+				self.account_keys.insert(signer_id, public_key);
+			}
+			AuctionCommand::SolverHeartbeat { solver_id, now_ms } => {
+				let seen = self.solver_last_seen.entry(solver_id).or_default();
+				*seen = (*seen).max(now_ms);
+			}
+			AuctionCommand::AmendIntent {
+				id,
+				signer_id,
+				new_token_diff,
+				new_deadline_ms,
+				nonce,
+				now_ms,
+				signature,
+				authorization,
+			} => {
+				if self.paused {
+					self.reject(id, None, RejectionReason::Paused);
+					return;
+				}
+				let Some(intent) = self.pending_intents.get(&id) else {
+					self.reject(id, None, RejectionReason::UnknownIntent);
+					return;
+				};
+				if intent.signer_id != signer_id {
+					self.reject(id, None, RejectionReason::SignerMismatch);
+					return;
+				}
+				let last = self.amendment_nonces.get(&id).copied();
+				if last.is_some_and(|last| nonce <= last) {
+					self.reject(id, None, RejectionReason::StaleNonce);
+					return;
+				}
+				if new_deadline_ms <= now_ms {
+					self.reject(id, None, RejectionReason::Expired);
+					return;
+				}
+				if self.too_large(&new_token_diff) {
+					self.reject(id, None, RejectionReason::TooLarge);
+					return;
+				}
+				if self.disallowed(&new_token_diff) {
+					self.reject(id, None, RejectionReason::DisallowedAsset);
+					return;
+				}
+				if !is_well_formed(&new_token_diff) {
+					self.reject(id, None, RejectionReason::Malformed);
+					return;
+				}
+
+				let amended = Intent {
+					action: IntentAction::TokenDiff {
+						diff: new_token_diff,
+					},
+					deadline_ms: new_deadline_ms,
+					signature,
+					..intent.clone()
+				};
+				let payload = amendment_payload(id, nonce, &amended.signature);
+				if !amended.verify_signature()
+					|| !verify_ed25519(
+						&amended.public_key,
+						&authorization,
+						&payload,
+					) {
+					self.reject(id, None, RejectionReason::InvalidSignature);
+					return;
+				}
+
+				// Old quotes may not fit the new terms.
+				self.quotes.remove(&id);
+				self.amendment_nonces.insert(id, nonce);
+				self.pending_intents.insert(id, amended);
+			}
+			AuctionCommand::Reconfigure {
+				fee_bps,
+				max_settlements_per_round,
+				surplus_policy,
+				auth,
+			} => {
+				let fields = (
+					"reconfigure",
+					fee_bps,
+					max_settlements_per_round,
+					surplus_policy,
+				);
+				if !self.authorize(&auth, &fields) {
+					tracing::warn!("ignoring unauthorized reconfiguration");
+					return;
+				}
+				let split_bps = match surplus_policy {
+					SurplusPolicy::SplitBps(bps) => bps,
+					_ => 0,
+				};
+				let valid = fee_bps <= 10_000
+					&& split_bps <= 10_000
+					&& max_settlements_per_round != Some(0);
+				if !valid {
+					tracing::warn!(
+						fee_bps,
+						?max_settlements_per_round,
+						?surplus_policy,
+						"rejected invalid configuration",
+					);
+					return;
+				}
+				let config = &mut self.config;
+				config.fee_bps = fee_bps;
+				config.max_settlements_per_round = max_settlements_per_round;
+				config.surplus_policy = surplus_policy;
+			}
+			AuctionCommand::Request {
+				request_id,
+				command,
+			} => {
+				// Keeps recursion bounded however the request was built.
+				if matches!(*command, AuctionCommand::Request { .. }) {
+					tracing::warn!(request_id, "ignoring nested request");
+					return;
+				}
+				if self.request_id_set.contains(&request_id) {
+					tracing::debug!(request_id, "ignoring repeated request");
+					return;
+				}
+				if self.request_ids.len() >= REQUEST_ID_CAPACITY
+					&& let Some(oldest) = self.request_ids.pop_front()
+				{
+					self.request_id_set.remove(&oldest);
+				}
+				self.request_ids.push_back(request_id);
+				self.request_id_set.insert(request_id);
+				self.apply(*command);
+			}
+			AuctionCommand::Batch(commands) => {
+				for command in commands {
+					// Keeps recursion one level deep however the batch was
+					// built, looking through a request wrapping the command.
+					let unwrapped = match &command {
+						AuctionCommand::Request { command, .. } => {
+							command.as_ref()
+						}
+						command => command,
+					};
+					if matches!(unwrapped, AuctionCommand::Batch(_)) {
+						tracing::warn!("ignoring nested batch");
+						continue;
+					}
+					self.apply(command);
+				}
+			}
+			AuctionCommand::MarkPublished { round, auth } => {
+				if !self.authorize(&auth, &("mark_published", round)) {
+					tracing::warn!(round, "ignoring unauthorized publish mark");
+					return;
+				}
+				// Marking a round that can't have been published yet would
+				// hide it from every relay.
+				if round >= self.publishable_until() {
+					tracing::warn!(
+						round,
+						"ignoring publish mark of a later round"
+					);
+					return;
+				}
+				self.next_unpublished_round =
+					self.next_unpublished_round.max(round.saturating_add(1));
+				let next = self.next_unpublished_round;
+				self.expiries.retain(|&(round, _)| round >= next);
+				self.round_events.retain(|&(round, _)| round >= next);
+			}
+			AuctionCommand::MarkOpenPublished { round, auth } => {
+				if !self.authorize(&auth, &("mark_open_published", round)) {
+					tracing::warn!(round, "ignoring unauthorized publish mark");
+					return;
+				}
+				if round > self.current_round {
+					tracing::warn!(
+						round,
+						"ignoring publish mark of a later round"
+					);
+					return;
+				}
+				self.next_unannounced_round =
+					self.next_unannounced_round.max(round.saturating_add(1));
+			}
+		}
+	}
+
+	fn query(&self, query: Self::Query) -> Self::QueryResult {
+		let _span = tracing::trace_span!(
+			"query",
+			query = ?query,
+			round = self.current_round,
+		)
+		.entered();
+
+		match query {
+			AuctionQuery::PendingIntents => AuctionQueryResult::Intents(
+				self.pending_intents.values().cloned().collect(),
+			),
+			AuctionQuery::IntentsByStatus(IntentStatus::Pending) => {
+				AuctionQueryResult::Intents(
+					self.pending_intents.values().cloned().collect(),
+				)
+			}
+			AuctionQuery::IntentsByStatus(status) => {
+				// An intent retired twice (settled, failed, then expired)
+				// is listed once, under its current status.
+				let intents = self
+					.terminal_intents
+					.iter()
+					.filter(|intent| {
+						self.intent_status.get(&intent.id) == Some(&status)
+					})
+					.map(|intent| (intent.id, intent.clone()))
+					.collect::<BTreeMap<_, _>>();
+				AuctionQueryResult::Intents(intents.into_values().collect())
+			}
+			AuctionQuery::RoundResult(round) => {
+				let result =
+					self.round_results.iter().find(|s| s.round == round);
+				match result {
+					Some(s) if self.sealed_rounds.contains_key(&round) => {
+						AuctionQueryResult::Sealed(s.commitment)
+					}
+					result => AuctionQueryResult::Round(result.cloned()),
+				}
+			}
+			AuctionQuery::CurrentRound => {
+				AuctionQueryResult::RoundNumber(self.current_round)
+			}
+			AuctionQuery::QuoteWindow => {
+				AuctionQueryResult::Deadline(self.round_deadline_ms)
+			}
+			AuctionQuery::IntentStatus(id) => {
+				let status = self
+					.intent_status
+					.get(&id)
+					.copied()
+					.unwrap_or(IntentStatus::NotFoundOrNotValid);
+				AuctionQueryResult::Status(status)
+			}
+			AuctionQuery::QuotesForIntent(id) => {
+				let quotes = self
+					.quotes
+					.get(&id)
+					.cloned()
+					.unwrap_or_default();
+				AuctionQueryResult::Quotes(quotes)
+			}
+			AuctionQuery::IntentsBySigner(signer_id) => AuctionQueryResult::Count(
+				self.intents_by_signer.get(&signer_id).copied().unwrap_or(0),
+			),
+			AuctionQuery::RateLimitedIntents => {
+				AuctionQueryResult::Count(self.rate_limited_intents)
+			}
+			AuctionQuery::LateQuotes => {
+				AuctionQueryResult::Count(self.late_quotes)
+			}
+			AuctionQuery::SurplusForRound(round) => {
+				let surplus = self
+					.revealed_results()
+					.find(|s| s.round == round)
+					.map(|s| s.surplus.clone());
+				AuctionQueryResult::Surplus(surplus)
+			}
+			AuctionQuery::RoundBalance(round) => {
+				let residual = self
+					.revealed_results()
+					.find(|s| s.round == round)
+					.map(Settlement::residual);
+				AuctionQueryResult::Balance(residual)
+			}
+			AuctionQuery::RoundHistory { start, limit } => {
+				let end = start.saturating_add(limit as u64);
+				// round_results is appended in round order.
+				// Stopping at a sealed round keeps followers from skipping it.
+				let end = end.min(self.publishable_until());
+				let rounds = self
+					.round_results
+					.iter()
+					.skip_while(|s| s.round < start)
+					.take_while(|s| s.round < end)
+					.cloned()
+					.collect();
+				AuctionQueryResult::Rounds(rounds)
+			}
+			AuctionQuery::AssetVolume(asset) => AuctionQueryResult::Volume(
+				self.volume.get(&asset).copied().unwrap_or(0),
+			),
+			AuctionQuery::AllVolumes => {
+				AuctionQueryResult::Volumes(self.volume.clone())
+			}
+			AuctionQuery::Twap {
+				asset_in,
+				asset_out,
+				last_n_rounds,
+			} => AuctionQueryResult::Price(self.average_price(
+				&asset_in,
+				&asset_out,
+				last_n_rounds,
+			)),
+			AuctionQuery::RecentRejections { limit } => {
+				let skip = self.rejections.len().saturating_sub(limit);
+				AuctionQueryResult::Rejections(
+					self.rejections.iter().skip(skip).cloned().collect(),
+				)
+			}
+			AuctionQuery::SolverBond(solver_id) => AuctionQueryResult::Bond(
+				self.solver_bonds.get(&solver_id).copied().unwrap_or(0),
+			),
+			AuctionQuery::LiveSolvers { now_ms } => AuctionQueryResult::Solvers(
+				self.solver_last_seen
+					.keys()
+					.filter(|id| self.solver_live(id, now_ms))
+					.cloned()
+					.collect(),
+			),
+			AuctionQuery::Metrics => AuctionQueryResult::Metrics(self.metrics()),
+			AuctionQuery::RankedQuotes(id) => {
+				let winners = self.current_winners(&id);
+				let mut ranked = self
+					.quotes
+					.get(&id)
+					.into_iter()
+					.flatten()
+					.map(|quote| RankedQuote {
+						quote: quote.clone(),
+						winning: winners.iter().any(|w| std::ptr::eq(*w, quote)),
+					})
+					.collect::<Vec<_>>();
+				// Stable sort: ties keep arrival order.
+				ranked.sort_by_key(|r| Reverse(r.quote.amount_out));
+				AuctionQueryResult::RankedQuotes(ranked)
+			}
+			AuctionQuery::AssetMeta(asset) => AuctionQueryResult::AssetMeta(
+				self.config.asset_metadata.get(&asset).cloned(),
+			),
+			AuctionQuery::BestQuote(id) => AuctionQueryResult::Quote(
+				self.current_winners(&id).first().map(|&q| q.clone()),
+			),
+			AuctionQuery::Receipt(id) => {
+				let status = self
+					.intent_status
+					.get(&id)
+					.copied()
+					.unwrap_or(IntentStatus::NotFoundOrNotValid);
+				if status == IntentStatus::Settled
+					&& let Some(receipt) = self.receipt(id)
+				{
+					AuctionQueryResult::Receipt(receipt)
+				} else {
+					AuctionQueryResult::Status(status)
+				}
+			}
+			AuctionQuery::GroupStatus(group_id) => {
+				let statuses = self
+					.intent_groups
+					.get(&group_id)
+					.into_iter()
+					.flatten()
+					.map(|&id| {
+						let status = self
+							.intent_status
+							.get(&id)
+							.copied()
+							.unwrap_or(IntentStatus::NotFoundOrNotValid);
+						(id, status)
+					})
+					.collect();
+				AuctionQueryResult::Statuses(statuses)
+			}
+			AuctionQuery::CheckCompatible {
+				intent_id,
+				candidate_diff,
+			} => {
+				let user_diff = self.unfilled.get(&intent_id).or_else(|| {
+					self.pending_intents.get(&intent_id)?.token_diff()
+				});
+				AuctionQueryResult::Compatibility(match user_diff {
+					Some(user_diff) => {
+						check_compatible(user_diff, &candidate_diff)
+					}
+					None => Err(Incompatibility::UnknownIntent),
+				})
+			}
+			AuctionQuery::FillHistory(id) => AuctionQueryResult::Receipts(
+				self.revealed_results()
+					.filter(|s| !s.failed)
+					.flat_map(|s| {
+						s.settlements
+							.iter()
+							.filter(move |settled| settled.intent_id == id)
+							.map(|settled| {
+								SettlementReceipt::new(s.round, settled)
+							})
+					})
+					.collect(),
+			),
+			AuctionQuery::SignerNonce(signer_id) => AuctionQueryResult::Nonce(
+				self.signer_sequences.get(&signer_id).copied(),
+			),
+			AuctionQuery::OperatorNonce(public_key) => {
+				AuctionQueryResult::Nonce(
+					self.operator_nonces.get(&public_key).copied(),
+				)
+			}
+			AuctionQuery::LatencyStats => AuctionQueryResult::Latency(
+				LatencyStats::new(&self.latency_counts, self.max_latency_ms),
+			),
+			AuctionQuery::IsPreferred(solver_id) => AuctionQueryResult::Flag(
+				self.config.preferred_solvers.contains(&solver_id),
+			),
+			AuctionQuery::IsPaused => AuctionQueryResult::Flag(self.paused),
+			AuctionQuery::DueReveals { now_ms } => {
+				let delay = self.config.reveal_delay_ms.unwrap_or(0);
+				AuctionQueryResult::RoundNumbers(
+					self.sealed_rounds
+						.iter()
+						.filter(|&(_, &cleared_ms)| {
+							now_ms >= cleared_ms.saturating_add(delay)
+						})
+						.map(|(&round, _)| round)
+						.collect(),
+				)
+			}
+			AuctionQuery::ActivePairs => {
+				let mut pairs = BTreeMap::<(AssetId, AssetId), u64>::new();
+				for intent in self.pending_intents.values() {
+					let Some(diff) = self
+						.unfilled
+						.get(&intent.id)
+						.or_else(|| intent.token_diff())
+					else {
+						continue;
+					};
+					let sent = diff.iter().filter(|&(_, &amount)| amount < 0);
+					for (asset_in, _) in sent {
+						let received =
+							diff.iter().filter(|&(_, &amount)| amount > 0);
+						for (asset_out, _) in received {
+							let pair = (asset_in.clone(), asset_out.clone());
+							*pairs.entry(pair).or_default() += 1;
+						}
+					}
+				}
+				AuctionQueryResult::Pairs(pairs)
+			}
+			AuctionQuery::Reputation(solver_id) => AuctionQueryResult::Score(
+				self.solver_records
+					.get(&solver_id)
+					.map_or(0, |record| record.reputation),
+			),
+			AuctionQuery::CompetitionLevel(id) => {
+				AuctionQueryResult::Count(self.competition(&id))
+			}
+			AuctionQuery::OrderBook => AuctionQueryResult::Book(OrderBook {
+				round: self.current_round,
+				entries: self
+					.pending_intents
+					.values()
+					.map(|intent| BookEntry {
+						intent: intent.clone(),
+						best_quote: self
+							.current_winners(&intent.id)
+							.first()
+							.map(|&q| q.clone()),
+						competition_level: self.competition(&intent.id),
+					})
+					.collect(),
+			}),
+			AuctionQuery::PreviewRound { now_ms } => {
+				let settlement = self.match_round(now_ms).settlement;
+				AuctionQueryResult::Round(
+					(!settlement.settlements.is_empty()).then_some(settlement),
+				)
+			}
+			AuctionQuery::WhyUnsettled { intent_id, now_ms } => {
+				let reason = self.why_unsettled(intent_id, now_ms);
+				AuctionQueryResult::Unsettled(reason)
+			}
+			AuctionQuery::UnpublishedRounds => AuctionQueryResult::Rounds(
+				self.round_results
+					.iter()
+					.skip_while(|s| s.round < self.next_unpublished_round)
+					.take_while(|s| s.round < self.publishable_until())
+					.cloned()
+					.collect(),
+			),
+			AuctionQuery::UnpublishedOutcomes => {
+				let unpublished =
+					self.next_unpublished_round..self.publishable_until();
+				let expired = self
+					.expiries
+					.iter()
+					.filter(|(round, _)| unpublished.contains(round))
+					.map(|&(round, intent_id)| {
+						(round, 1, Outcome::Expired { intent_id })
+					});
+				let settled = self
+					.round_results
+					.iter()
+					.filter(|s| unpublished.contains(&s.round))
+					.map(|s| (s.round, 2, Outcome::Settled(s.clone())));
+				// The open round's start is published before it closes.
+				let boundaries = self
+					.round_events
+					.iter()
+					.filter(|(round, outcome)| match outcome {
+						Outcome::RoundOpened { .. } => {
+							*round >= self.next_unannounced_round
+								&& *round >= self.next_unpublished_round
+						}
+						_ => unpublished.contains(round),
+					})
+					.map(|(round, outcome)| {
+						let order = match outcome {
+							Outcome::RoundOpened { .. } => 0,
+							_ => 3,
+						};
+						(*round, order, outcome.clone())
+					});
+				let mut outcomes = expired
+					.chain(settled)
+					.chain(boundaries)
+					.collect::<Vec<_>>();
+				outcomes.sort_by_key(|&(round, order, _)| (round, order));
+				AuctionQueryResult::Outcomes(
+					outcomes
+						.into_iter()
+						.map(|(round, _, outcome)| (round, outcome))
+						.collect(),
+				)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, ed25519_dalek::SigningKey, proptest::prelude::*};
+
+	/// When each test's intents are submitted.
+	const T0: u64 = 1_000_000;
+
+	/// Deadline of every test intent.
+	const DEADLINE_MS: u64 = T0 + 60_000;
+
+	fn key(name: &str) -> SigningKey {
+		let seed: [u8; 32] = Sha256::digest(name.as_bytes()).into();
+		SigningKey::from_bytes(&seed)
+	}
+
+	fn operator() -> SigningKey {
+		key("operator")
+	}
+
+	/// The default config, with `operator()` as its operator.
+	fn config() -> AuctionConfig {
+		let operator = operator().verifying_key().to_bytes().to_vec();
+		AuctionConfig {
+			operator_keys: BTreeSet::from([operator]),
+			..AuctionConfig::default()
+		}
+	}
+
+	/// The operator's next nonce.
+	fn next_nonce(machine: &AuctionStateMachine) -> u64 {
+		let operator = operator().verifying_key().to_bytes().to_vec();
+		machine
+			.operator_nonces
+			.get(&operator)
+			.map_or(1, |last| last + 1)
+	}
+
+	fn deposit(machine: &mut AuctionStateMachine, solver: &str, amount: u128) {
+		let nonce = next_nonce(machine);
+		machine.apply(AuctionCommand::deposit_bond(
+			&operator(),
+			nonce,
+			solver,
+			amount,
+		));
+	}
+
+	fn bond(machine: &AuctionStateMachine, solver: &str) -> u128 {
+		match machine.query(AuctionQuery::SolverBond(solver.into())) {
+			AuctionQueryResult::Bond(bond) => bond,
+			other => panic!("unexpected result {other:?}"),
+		}
+	}
+
+	/// Why the quote with `quote_hash` was rejected, oldest first.
+	fn rejections(
+		machine: &AuctionStateMachine,
+		quote_hash: &str,
+	) -> Vec<RejectionReason> {
+		machine
+			.rejections
+			.iter()
+			.filter(|r| r.quote_hash.as_deref() == Some(quote_hash))
+			.map(|r| r.reason)
+			.collect()
+	}
+
+	fn usdc() -> AssetId {
+		AssetId::Nep141("usdc.near".into())
+	}
+
+	fn wnear() -> AssetId {
+		AssetId::Nep141("wrap.near".into())
+	}
+
+	/// `signer`'s intent number `seq`, selling `sell` USDC for at least
+	/// `buy` wNEAR.
+	fn intent(signer: &str, seq: u64, sell: i128, buy: i128) -> Intent {
+		let mut intent = Intent {
+			id: IntentId::derive(signer, seq),
+			signer_id: signer.into(),
+			sequence: seq,
+			action: IntentAction::TokenDiff {
+				diff: TokenDiff::from([(usdc(), -sell), (wnear(), buy)]),
+			},
+			verifying_contract: "intents.near".into(),
+			deadline_ms: DEADLINE_MS,
+			min_quote_deadline_ms: 0,
+			priority_fee: 0,
+			group_id: None,
+			limit_price: None,
+			depends_on: None,
+			signature: Vec::new(),
+			public_key: Vec::new(),
+		};
+		intent.sign(&key(signer));
+		intent
+	}
+
+	/// `solver`'s quote taking `take` USDC from `intent` for `give` wNEAR.
+	fn partial_quote(
+		solver: &str,
+		intent: &Intent,
+		take: i128,
+		give: i128,
+	) -> Quote {
+		let mut quote = Quote {
+			intent_id: intent.id,
+			quote_hash: format!("{solver}-{}", intent.id),
+			solver_id: solver.into(),
+			amount_out: to_u128(give).expect("positive"),
+			solver_token_diff: TokenDiff::from([
+				(usdc(), take),
+				(wnear(), -give),
+			]),
+			expiration_ms: DEADLINE_MS,
+			destination_chain: None,
+			signature: Vec::new(),
+			public_key: Vec::new(),
+		};
+		quote.sign(&key(solver));
+		quote
+	}
+
+	/// `solver`'s quote taking all of `intent`'s USDC for `give` wNEAR.
+	fn quote(solver: &str, intent: &Intent, give: i128) -> Quote {
+		let offered = intent.token_diff().expect("token diff")[&usdc()];
+		partial_quote(solver, intent, -offered, give)
+	}
+
+	fn submit(machine: &mut AuctionStateMachine, intent: &Intent) {
+		machine.apply(AuctionCommand::SubmitIntent {
+			intent: intent.clone(),
+			now_ms: T0,
+		});
+	}
+
+	/// Open a round, submit `quotes` and clear it, all at `now_ms`.
+	fn run_round(
+		machine: &mut AuctionStateMachine,
+		quotes: impl IntoIterator<Item = Quote>,
+		now_ms: u64,
+	) {
+		machine.apply(AuctionCommand::OpenRound {
+			deadline_ms: now_ms,
+		});
+		for quote in quotes {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms });
+		}
+		machine.apply(AuctionCommand::ClearRound { now_ms });
+	}
+
+	fn status(machine: &AuctionStateMachine, id: IntentId) -> IntentStatus {
+		match machine.query(AuctionQuery::IntentStatus(id)) {
+			AuctionQueryResult::Status(status) => status,
+			other => panic!("unexpected result {other:?}"),
+		}
+	}
+
+	fn quotes_for(machine: &AuctionStateMachine, id: IntentId) -> Vec<Quote> {
+		match machine.query(AuctionQuery::QuotesForIntent(id)) {
+			AuctionQueryResult::Quotes(quotes) => quotes,
+			other => panic!("unexpected result {other:?}"),
+		}
+	}
+
+	fn round(machine: &AuctionStateMachine, round: u64) -> Option<Settlement> {
+		match machine.query(AuctionQuery::RoundResult(round)) {
+			AuctionQueryResult::Round(settlement) => settlement,
+			other => panic!("unexpected result {other:?}"),
+		}
+	}
+
+	/// The answer to a query that counts something.
+	fn count(machine: &AuctionStateMachine, query: AuctionQuery) -> u64 {
+		match machine.query(query) {
+			AuctionQueryResult::Count(count) => count,
+			other => panic!("unexpected result {other:?}"),
+		}
+	}
+
+	#[test]
+	fn failed_settlement_requeues_intent_within_deadline() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 0,
+			now_ms: T0 + 1,
+		});
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+		assert!(round(&machine, 0).is_some_and(|s| s.failed));
+
+		// It can settle again, in a later round.
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0 + 2);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		assert!(round(&machine, 1).is_some());
+	}
+
+	#[test]
+	fn failed_settlement_expires_intent_past_deadline() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 0,
+			now_ms: DEADLINE_MS + 1,
+		});
+		assert_eq!(status(&machine, alice.id), IntentStatus::Expired);
+		assert!(machine.pending_intents.is_empty());
+		assert!(machine.filled_intents.is_empty());
+	}
+
+	#[test]
+	fn filled_intents_are_pruned_with_their_rounds() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_retained_rounds: Some(1),
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &bob, 950)], T0 + 1);
+
+		assert!(round(&machine, 0).is_none());
+		assert_eq!(
+			machine.filled_intents.keys().copied().collect::<Vec<_>>(),
+			[bob.id],
+		);
+
+		// A pruned round can no longer fail.
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 0,
+			now_ms: T0 + 2,
+		});
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+	}
+
+	/// A quote for `settled_rounds_are_zero_sum`: the solver's index, the
+	/// percentage of the intent's minimum it gives and of its offer it
+	/// takes, its expiration relative to `T0`, and whether it pays out
+	/// an asset the intent didn't ask for.
+	fn quote_spec() -> impl Strategy<Value = (u8, i128, i128, i64, bool)> {
+		(
+			0..3u8,
+			0..200i128,
+			1..=100i128,
+			-1_000..1_000i64,
+			any::<bool>(),
+		)
+	}
+
+	proptest! {
+		#[test]
+		fn settled_rounds_are_zero_sum(
+			intents in prop::collection::vec(
+				(
+					1..10_000i128,
+					1..=100i128,
+					prop::collection::vec(quote_spec(), 0..4),
+				),
+				1..4,
+			),
+		) {
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				allow_split_fills: true,
+				..AuctionConfig::default()
+			});
+			let mut quotes = Vec::new();
+			for (seq, (sell, buy_pct, specs)) in (1..).zip(intents) {
+				let buy = (sell * buy_pct / 100).max(1);
+				let signer = format!("user{seq}");
+				let intent = intent(&signer, seq, sell, buy);
+				submit(&mut machine, &intent);
+				for (i, spec) in specs.into_iter().enumerate() {
+					let (solver, give_pct, take_pct, expires, wrong) = spec;
+					let give = buy * give_pct / 100;
+					let paid = if wrong {
+						AssetId::Nep141("other.near".into())
+					} else {
+						wnear()
+					};
+					let solver = format!("solver{solver}");
+					let mut quote = Quote {
+						intent_id: intent.id,
+						quote_hash: format!("{solver}-{seq}-{i}"),
+						solver_id: solver.clone(),
+						amount_out: give.unsigned_abs(),
+						solver_token_diff: TokenDiff::from([
+							(usdc(), sell * take_pct / 100),
+							(paid, -give),
+						]),
+						expiration_ms: T0.saturating_add_signed(expires),
+						destination_chain: None,
+						signature: Vec::new(),
+						public_key: Vec::new(),
+					};
+					quote.sign(&key(&solver));
+					quotes.push(quote);
+				}
+			}
+			let submitted = quotes
+				.iter()
+				.map(|q| (q.quote_hash.clone(), q.clone()))
+				.collect::<BTreeMap<_, _>>();
+			run_round(&mut machine, quotes, T0);
+
+			let Some(settlement) = round(&machine, 0) else {
+				return Ok(());
+			};
+			prop_assert!(settlement.aggregate_flow.values().all(|&a| a == 0));
+			for settled in &settlement.settlements {
+				let mut net = settled.flow.clone();
+				let fills = settled.fills.iter().map(|fill| &fill.flow);
+				for flow in fills.chain([&settled.fee]) {
+					net = aggregate_token_flow(&net, flow)
+						.expect("settled flows don't overflow");
+				}
+				prop_assert!(net.values().all(|&amount| amount == 0));
+
+				for fill in &settled.fills {
+					let quote = &submitted[&fill.quote_hash];
+					prop_assert!(quote.expiration_ms >= T0);
+					prop_assert!(!quote.solver_token_diff.contains_key(
+						&AssetId::Nep141("other.near".into())
+					));
+				}
+			}
+		}
+	}
+
+	/// A diff over a handful of assets, so two of them often overlap. The
+	/// last asset is non-fungible.
+	fn small_diff() -> impl Strategy<Value = TokenDiff> {
+		prop::collection::vec((0..4u8, -3i128..4), 0..5).prop_map(|entries| {
+			entries
+				.into_iter()
+				.map(|(index, amount)| {
+					let asset = match index {
+						3 => AssetId::Nep171 {
+							contract: "nft.near".into(),
+							token_id: "1".into(),
+						},
+						i => AssetId::Nep141(format!("token{i}.near")),
+					};
+					(asset, amount)
+				})
+				.collect()
+		})
+	}
+
+	proptest! {
+		/// Mirrors the fuzz target in `fuzz/`.
+		#[test]
+		fn compatible_diffs_deliver_and_take_only_what_was_offered(
+			user in small_diff(),
+			solver in small_diff(),
+		) {
+			if !token_diffs_compatible(&user, &solver) {
+				return Ok(());
+			}
+			for (asset, &amount) in &solver {
+				let offered = user.get(asset).copied();
+				prop_assert!(offered.is_some());
+				if amount > 0 {
+					let offered = offered.unwrap_or(0);
+					prop_assert!(offered < 0);
+					let taken = amount.unsigned_abs();
+					prop_assert!(taken <= offered.unsigned_abs());
+				}
+			}
+			for (asset, &wanted) in user.iter().filter(|&(_, &a)| a > 0) {
+				let sent = solver.get(asset).copied().unwrap_or(0);
+				prop_assert!(sent < 0);
+				prop_assert!(sent.unsigned_abs() >= wanted.unsigned_abs());
+			}
+		}
+	}
+
+	#[test]
+	fn under_delivering_quote_leaves_intent_pending() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		// Advertises the minimum but only sends 850.
+		let mut short = quote("solver", &alice, 850);
+		short.amount_out = 900;
+		short.sign(&key("solver"));
+		run_round(&mut machine, [short], T0);
+
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+		assert!(round(&machine, 0).is_none());
+	}
+
+	#[test]
+	fn inflated_amount_out_does_not_block_valid_quote() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		let mut inflated = quote("inflated", &alice, 850);
+		inflated.amount_out = 2_000;
+		inflated.sign(&key("inflated"));
+		let honest = quote("honest", &alice, 950);
+		run_round(&mut machine, [inflated, honest], T0);
+
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		let fills = &settlement.settlements[0].fills;
+		assert_eq!(fills.len(), 1);
+		assert_eq!(fills[0].solver_id, "honest");
+	}
+
+	#[test]
+	fn reopening_an_open_round_keeps_its_window() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound {
+			deadline_ms: T0 + 100,
+		});
+		machine.apply(AuctionCommand::OpenRound {
+			deadline_ms: T0 + 10_000,
+		});
+		assert_eq!(machine.round_deadline_ms, Some(T0 + 100));
+
+		// Inside the original window the quote is accepted, after it not.
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("early", &alice, 950),
+			now_ms: T0 + 100,
+		});
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("late", &alice, 960),
+			now_ms: T0 + 101,
+		});
+		let quotes = quotes_for(&machine, alice.id);
+		assert_eq!(quotes.len(), 1);
+		assert_eq!(quotes[0].solver_id, "early");
+		assert_eq!(machine.late_quotes, 1);
+	}
+
+	fn volume(machine: &AuctionStateMachine, asset: AssetId) -> u128 {
+		match machine.query(AuctionQuery::AssetVolume(asset)) {
+			AuctionQueryResult::Volume(volume) => volume,
+			other => panic!("unexpected result {other:?}"),
+		}
+	}
+
+	#[test]
+	fn volume_sums_rounds_including_pruned_ones() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_retained_rounds: Some(1),
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 2_000, 1_800);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &bob, 1_900)], T0 + 1);
+
+		// Round 0 is pruned, but still counts.
+		assert!(round(&machine, 0).is_none());
+		assert_eq!(volume(&machine, usdc()), 3_000);
+		// The solver keeps the surplus, so users receive their minimums.
+		assert_eq!(volume(&machine, wnear()), 2_700);
+
+		// A failed round no longer counts.
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 1,
+			now_ms: T0 + 2,
+		});
+		assert_eq!(volume(&machine, usdc()), 1_000);
+		assert_eq!(volume(&machine, wnear()), 900);
+	}
+
+	#[test]
+	fn split_fill_covers_intent_no_quote_covers_alone() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let user_diff = alice.token_diff().expect("token diff");
+		let first = partial_quote("first", &alice, 500, 460);
+		let second = partial_quote("second", &alice, 500, 460);
+		let short =
+			|q: &Quote| !meets_minimum_output(user_diff, &q.solver_token_diff);
+		assert!(short(&first) && short(&second));
+
+		let fills = split_fill(user_diff, &[&first, &second]).expect("fill");
+		assert_eq!(fills.len(), 2);
+		let flows = settlement_flows(
+			user_diff,
+			&fills,
+			SurplusPolicy::KeepSolver,
+			SurplusRounding::Solver,
+			0,
+		)
+		.expect("balanced flows");
+		assert_eq!(flows.user, *user_diff);
+		assert_eq!(
+			flows.solvers,
+			[
+				TokenDiff::from([(usdc(), 500), (wnear(), -460)]),
+				TokenDiff::from([(usdc(), 500), (wnear(), -440)]),
+			],
+		);
+	}
+
+	#[test]
+	fn split_fill_never_gives_a_quote_more_than_it_asked() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let user_diff = alice.token_diff().expect("token diff");
+		let first = partial_quote("first", &alice, 400, 460);
+		let second = partial_quote("second", &alice, 400, 460);
+
+		let fills = split_fill(user_diff, &[&first, &second]).expect("fill");
+		let flows = settlement_flows(
+			user_diff,
+			&fills,
+			SurplusPolicy::KeepSolver,
+			SurplusRounding::Solver,
+			0,
+		)
+		.expect("balanced flows");
+		// The user keeps what neither quote asked for.
+		assert_eq!(
+			flows.user,
+			TokenDiff::from([(usdc(), -800), (wnear(), 900)]),
+		);
+		assert!(flows.solvers.iter().all(|flow| flow[&usdc()] == 400));
+	}
+
+	#[test]
+	fn quotes_need_a_bond_which_failure_slashes() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			min_solver_bond: 1_000,
+			bond_slash_amount: 300,
+			..config()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		deposit(&mut machine, "poor", 999);
+		deposit(&mut machine, "bonded", 1_000);
+		let poor = quote("poor", &alice, 990);
+		let hash = poor.quote_hash.clone();
+		run_round(&mut machine, [poor, quote("bonded", &alice, 950)], T0);
+
+		assert_eq!(
+			rejections(&machine, &hash),
+			[RejectionReason::InsufficientBond],
+		);
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.settlements[0].fills[0].solver_id, "bonded");
+
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 0,
+			now_ms: T0 + 1,
+		});
+		assert_eq!(bond(&machine, "bonded"), 700);
+		assert_eq!(bond(&machine, "poor"), 999);
+	}
+
+	#[test]
+	fn bonds_need_a_fresh_operator_signature() {
+		let mut machine = AuctionStateMachine::new(config());
+		let forged =
+			AuctionCommand::deposit_bond(&key("mallory"), 1, "solver", 1_000);
+		machine.apply(forged);
+		assert_eq!(bond(&machine, "solver"), 0);
+
+		let deposit =
+			AuctionCommand::deposit_bond(&operator(), 1, "solver", 1_000);
+		machine.apply(deposit.clone());
+		machine.apply(deposit);
+		assert_eq!(bond(&machine, "solver"), 1_000);
+
+		// The signature covers the amount.
+		let AuctionCommand::DepositBond {
+			solver_id, auth, ..
+		} = AuctionCommand::deposit_bond(&operator(), 2, "solver", 1)
+		else {
+			unreachable!();
+		};
+		machine.apply(AuctionCommand::DepositBond {
+			solver_id,
+			amount: 1_000_000,
+			auth,
+		});
+		assert_eq!(bond(&machine, "solver"), 1_000);
+	}
+
+	#[test]
+	fn reconfigured_fee_applies_from_the_next_round() {
+		let mut machine = AuctionStateMachine::new(config());
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 1_000)], T0);
+
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::reconfigure(
+			&operator(),
+			nonce,
+			100,
+			None,
+			SurplusPolicy::default(),
+		));
+		assert_eq!(machine.config.fee_bps, 100);
+
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &bob, 1_000)], T0 + 1);
+		let before = round(&machine, 0).expect("round 0 settled");
+		let after = round(&machine, 1).expect("round 1 settled");
+		assert!(before.fees.values().all(|&fee| fee == 0));
+		assert!(after.fees.values().any(|&fee| fee > 0));
+	}
+
+	#[test]
+	fn reconfigure_needs_an_operator_signature() {
+		let mut machine = AuctionStateMachine::new(config());
+		let policy = SurplusPolicy::default();
+		let forged =
+			AuctionCommand::reconfigure(&key("mallory"), 1, 100, None, policy);
+		machine.apply(forged);
+		assert_eq!(machine.config.fee_bps, 0);
+
+		// The signature covers the values.
+		let AuctionCommand::Reconfigure { auth, .. } =
+			AuctionCommand::reconfigure(&operator(), 1, 1, None, policy)
+		else {
+			unreachable!();
+		};
+		machine.apply(AuctionCommand::Reconfigure {
+			fee_bps: 100,
+			max_settlements_per_round: None,
+			surplus_policy: policy,
+			auth,
+		});
+		assert_eq!(machine.config.fee_bps, 0);
+	}
+
+	#[test]
+	fn repeated_request_is_applied_once() {
+		let mut machine = AuctionStateMachine::default();
+		let clear = AuctionCommand::Request {
+			request_id: 7,
+			command: Box::new(AuctionCommand::ClearRound { now_ms: T0 }),
+		};
+		machine.apply(clear.clone());
+		machine.apply(clear);
+		assert_eq!(machine.current_round, 1);
+
+		machine.apply(AuctionCommand::Request {
+			request_id: 8,
+			command: Box::new(AuctionCommand::ClearRound { now_ms: T0 }),
+		});
+		assert_eq!(machine.current_round, 2);
+	}
+
+	#[test]
+	fn compute_settlement_picks_the_best_quote() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let pending = BTreeMap::from([(alice.id, alice.clone())]);
+		let quotes = BTreeMap::from([(
+			alice.id,
+			vec![quote("low", &alice, 920), quote("high", &alice, 950)],
+		)]);
+		let policy = AuctionConfig::default().match_policy();
+		let outcome =
+			compute_settlement(3, &pending, &quotes, &[], T0, &policy);
+
+		let settlement = outcome.settlement;
+		assert_eq!(settlement.round, 3);
+		assert_eq!(settlement.settled_intents(), [alice.id]);
+		let winner = format!("high-{}", alice.id);
+		assert_eq!(settlement.winning_quotes(), [winner.as_str()]);
+		assert!(outcome.expired.is_empty());
+	}
+
+	#[test]
+	fn compute_settlement_reports_but_skips_expired_intents() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let pending = BTreeMap::from([(alice.id, alice.clone())]);
+		let quotes =
+			BTreeMap::from([(alice.id, vec![quote("solver", &alice, 950)])]);
+		let policy = AuctionConfig::default().match_policy();
+		let now_ms = DEADLINE_MS + 1;
+		let outcome =
+			compute_settlement(0, &pending, &quotes, &[], now_ms, &policy);
+
+		assert!(outcome.settlement.settlements.is_empty());
+		assert_eq!(outcome.expired, [alice.id]);
+	}
+
+	#[test]
+	fn compute_settlement_stops_at_the_settlement_cap() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		let pending =
+			BTreeMap::from([(alice.id, alice.clone()), (bob.id, bob.clone())]);
+		let quotes = BTreeMap::from([
+			(alice.id, vec![quote("solver", &alice, 950)]),
+			(bob.id, vec![quote("solver", &bob, 950)]),
+		]);
+		let policy = AuctionConfig {
+			max_settlements_per_round: Some(1),
+			..AuctionConfig::default()
+		}
+		.match_policy();
+		let outcome =
+			compute_settlement(0, &pending, &quotes, &[], T0, &policy);
+
+		assert_eq!(outcome.settlement.settlements.len(), 1);
+		assert!(outcome.expired.is_empty());
+	}
+
+	#[test]
+	fn clear_round_leaves_expired_intents_for_expire_stale() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [], DEADLINE_MS + 1);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+
+		machine.apply(AuctionCommand::ExpireStale {
+			now_ms: DEADLINE_MS + 1,
+		});
+		assert_eq!(status(&machine, alice.id), IntentStatus::Expired);
+	}
+
+	#[test]
+	#[tracing_test::traced_test]
+	fn settled_event_carries_the_intent_id() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+
+		let id = format!("intent_id={}", alice.id);
+		logs_assert(|lines: &[&str]| {
+			let settled = lines
+				.iter()
+				.filter(|line| line.contains(": settled "))
+				.collect::<Vec<_>>();
+			match settled.as_slice() {
+				[line] if line.contains(&id) && line.contains("round=0") => {
+					Ok(())
 				}
+				other => Err(format!("unexpected settled events {other:?}")),
+			}
+		});
+	}
+
+	/// The signer's amendment `nonce` of `intent`, asking for `buy` wNEAR
+	/// instead.
+	fn amendment(intent: &Intent, nonce: u64, buy: i128) -> AuctionCommand {
+		let offered = intent.token_diff().expect("token diff")[&usdc()];
+		let diff = TokenDiff::from([(usdc(), offered), (wnear(), buy)]);
+		AuctionCommand::amend_intent(
+			&key(&intent.signer_id),
+			intent,
+			nonce,
+			diff,
+			DEADLINE_MS,
+			T0,
+		)
+	}
+
+	#[test]
+	fn amendment_replaces_terms_and_drops_stale_quotes() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &alice, 920),
+			now_ms: T0,
+		});
+		assert_eq!(quotes_for(&machine, alice.id).len(), 1);
+
+		machine.apply(amendment(&alice, 1, 950));
+		assert!(quotes_for(&machine, alice.id).is_empty());
+		let amended = &machine.pending_intents[&alice.id];
+		assert_eq!(amended.token_diff().expect("token diff")[&wnear()], 950);
+		assert!(amended.verify_signature());
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn amendment_needs_the_signer_a_fresh_nonce_and_a_live_deadline() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		let buy = |machine: &AuctionStateMachine| {
+			machine.pending_intents[&alice.id]
+				.token_diff()
+				.expect("token diff")[&wnear()]
+		};
+
+		// Signed by someone else, or claimed by another signer.
+		let forged = AuctionCommand::amend_intent(
+			&key("mallory"),
+			&alice,
+			1,
+			TokenDiff::from([(usdc(), -1_000), (wnear(), 1)]),
+			DEADLINE_MS,
+			T0,
+		);
+		machine.apply(forged);
+		let AuctionCommand::AmendIntent {
+			id,
+			new_token_diff,
+			new_deadline_ms,
+			nonce,
+			now_ms,
+			signature,
+			authorization,
+			..
+		} = amendment(&alice, 1, 1)
+		else {
+			unreachable!();
+		};
+		machine.apply(AuctionCommand::AmendIntent {
+			id,
+			signer_id: "mallory".into(),
+			new_token_diff,
+			new_deadline_ms,
+			nonce,
+			now_ms,
+			signature,
+			authorization,
+		});
+		assert_eq!(buy(&machine), 900);
+		let reasons = machine.rejections.iter().map(|r| r.reason);
+		assert_eq!(
+			reasons.collect::<Vec<_>>(),
+			[
+				RejectionReason::InvalidSignature,
+				RejectionReason::SignerMismatch,
+			],
+		);
+
+		// A replay of an earlier amendment.
+		let first = amendment(&alice, 1, 950);
+		machine.apply(first.clone());
+		machine.apply(amendment(&alice, 2, 960));
+		machine.apply(first);
+		assert_eq!(buy(&machine), 960);
+		let last = machine.rejections.back().map(|r| r.reason);
+		assert_eq!(last, Some(RejectionReason::StaleNonce));
+
+		// A deadline already past.
+		machine.apply(AuctionCommand::amend_intent(
+			&key("alice"),
+			&alice,
+			3,
+			TokenDiff::from([(usdc(), -1_000), (wnear(), 970)]),
+			T0,
+			T0,
+		));
+		assert_eq!(buy(&machine), 960);
+		let last = machine.rejections.back().map(|r| r.reason);
+		assert_eq!(last, Some(RejectionReason::Expired));
+	}
+
+	#[test]
+	fn quote_without_spread_is_rejected_under_the_strict_policy() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			reference_rates: BTreeMap::from([((usdc(), wnear()), (95, 100))]),
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+
+		// Paying the reference rate gains the solver nothing.
+		for (solver, give) in [("market", 950), ("keeps-spread", 940)] {
+			machine.apply(AuctionCommand::SubmitQuote {
+				quote: quote(solver, &alice, give),
+				now_ms: T0,
+			});
+		}
+		assert_eq!(
+			rejections(&machine, &format!("market-{}", alice.id)),
+			[RejectionReason::ZeroSpread]
+		);
+		let quotes = quotes_for(&machine, alice.id);
+		assert_eq!(quotes.len(), 1);
+		assert_eq!(quotes[0].solver_id, "keeps-spread");
+
+		// At exactly the user's minimum the solver keeps the most.
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("at-minimum", &alice, 900),
+			now_ms: T0,
+		});
+		assert_eq!(quotes_for(&machine, alice.id).len(), 2);
+	}
+
+	#[test]
+	fn group_settles_only_with_every_known_member() {
+		let mut machine = AuctionStateMachine::default();
+		let mut alice = intent("alice", 1, 1_000, 900);
+		alice.group_id = Some("pair".into());
+		alice.sign(&key("alice"));
+		let mut bob = intent("bob", 1, 1_000, 900);
+		bob.group_id = Some("pair".into());
+		bob.deadline_ms = T0 + 10;
+		bob.sign(&key("bob"));
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+
+		// Bob has no quote, so neither settles.
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+		assert_eq!(status(&machine, bob.id), IntentStatus::Pending);
+
+		// Once bob has expired the group can never settle whole.
+		machine.apply(AuctionCommand::ExpireStale { now_ms: T0 + 20 });
+		assert_eq!(status(&machine, bob.id), IntentStatus::Expired);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0 + 30);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn signer_sequences_block_replays_under_the_registered_key() {
+		let mut machine = AuctionStateMachine::new(config());
+		let last_reason = |machine: &AuctionStateMachine| {
+			machine.rejections.back().map(|r| r.reason)
+		};
+		let signed = |seq: u64, sequence: u64, signer: &str| {
+			let mut intent = intent("alice", seq, 1_000, 900 + seq as i128);
+			intent.sequence = sequence;
+			intent.sign(&key(signer));
+			intent
+		};
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::register_key(
+			&operator(),
+			nonce,
+			"alice",
+			key("alice").verifying_key().to_bytes().to_vec(),
+		));
+
+		// An intent held back and replayed after a later one.
+		let second = signed(2, 2, "alice");
+		submit(&mut machine, &second);
+		submit(&mut machine, &signed(1, 1, "alice"));
+		assert_eq!(last_reason(&machine), Some(RejectionReason::StaleNonce));
+		// Under a fresh id, the same sequence is still a replay.
+		submit(&mut machine, &signed(3, 2, "alice"));
+		assert_eq!(last_reason(&machine), Some(RejectionReason::StaleNonce));
+
+		// The registration bound alice's key.
+		let forged = signed(4, 4, "mallory");
+		submit(&mut machine, &forged);
+		assert_eq!(
+			last_reason(&machine),
+			Some(RejectionReason::UnregisteredKey)
+		);
+		assert_eq!(
+			status(&machine, forged.id),
+			IntentStatus::NotFoundOrNotValid
+		);
+
+		// Only an operator can rebind it.
+		let rotated = key("alice-rotated").verifying_key().to_bytes().to_vec();
+		machine.apply(AuctionCommand::register_key(
+			&key("mallory"),
+			1,
+			"alice",
+			rotated.clone(),
+		));
+		let accepted = signed(5, 5, "alice");
+		submit(&mut machine, &accepted);
+		assert_eq!(status(&machine, accepted.id), IntentStatus::Pending);
+
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::register_key(
+			&operator(),
+			nonce,
+			"alice",
+			rotated,
+		));
+		submit(&mut machine, &signed(6, 6, "alice"));
+		assert_eq!(
+			last_reason(&machine),
+			Some(RejectionReason::UnregisteredKey)
+		);
+		let rotated = signed(7, 7, "alice-rotated");
+		submit(&mut machine, &rotated);
+		assert_eq!(status(&machine, rotated.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn failed_round_restores_only_its_own_fill() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			allow_carryover: true,
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		let settled =
+			|machine: &AuctionStateMachine| machine.metrics().intents_settled;
+
+		// Round 0 fills 400 of the 1_000 USDC, which isn't a settlement.
+		run_round(&mut machine, [partial_quote("a", &alice, 400, 400)], T0);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+		assert_eq!(settled(&machine), 0);
+		let first = round(&machine, 0).expect("partial fill");
+		let first = first.settlements[0].flow.clone();
+
+		// Round 1 fills the rest.
+		run_round(&mut machine, [partial_quote("b", &alice, 600, 600)], T0);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		assert_eq!(settled(&machine), 1);
+
+		// Only round 1's share is owed again.
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 1,
+			now_ms: T0 + 1,
+		});
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+		let signed = alice.token_diff().expect("token diff");
+		let owed = deduct(signed, &first);
+		assert_eq!(owed[&usdc()], -600);
+		assert_eq!(machine.unfilled.get(&alice.id), Some(&owed));
+
+		// Failing round 0 too puts back the rest.
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 0,
+			now_ms: T0 + 1,
+		});
+		assert_eq!(machine.unfilled.get(&alice.id), Some(signed));
+	}
+
+	#[test]
+	fn same_signer_and_sequence_under_another_id_is_a_duplicate() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+
+		let mut redelivered = intent("alice", 1, 1_000, 950);
+		redelivered.id = IntentId::derive("alice", 99);
+		redelivered.sign(&key("alice"));
+		submit(&mut machine, &redelivered);
+		let reasons = machine.rejections.iter().map(|r| r.reason);
+		assert_eq!(reasons.collect::<Vec<_>>(), [RejectionReason::Duplicate]);
+		assert_eq!(
+			status(&machine, redelivered.id),
+			IntentStatus::NotFoundOrNotValid
+		);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+
+		// The same terms under the next sequence are a new intent.
+		let mut again = alice.clone();
+		again.id = IntentId::derive("alice", 2);
+		again.sequence = 2;
+		again.sign(&key("alice"));
+		submit(&mut machine, &again);
+		assert_eq!(status(&machine, again.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn atomic_bundle_losing_one_intent_forfeits_all() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		machine.apply(AuctionCommand::SubmitQuoteBundle {
+			solver_id: "bundler".into(),
+			quotes: vec![
+				quote("bundler", &alice, 950),
+				quote("bundler", &bob, 950),
+			],
+			atomic: true,
+			now_ms: T0,
+		});
+		// Replacing a bundled quote keeps the intent in the bundle.
+		let mut replaced = quote("bundler", &alice, 960);
+		replaced.quote_hash = "bundler-replaced".into();
+		replaced.sign(&key("bundler"));
+		let outbid = quote("rival", &bob, 970);
+		let fallback = quote("rival", &alice, 920);
+		for quote in [replaced, outbid, fallback] {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 });
+
+		// The bundle won alice but lost bob, so rival takes both.
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.settlements.len(), 2);
+		assert!(settlement.settlements.iter().all(|settled| {
+			settled.fills.iter().all(|fill| fill.solver_id == "rival")
+		}));
+		assert_eq!(
+			rejections(&machine, "bundler-replaced"),
+			[RejectionReason::BundleForfeited],
+		);
+		assert_eq!(
+			rejections(&machine, &format!("bundler-{}", bob.id)),
+			[RejectionReason::BundleForfeited],
+		);
+	}
+
+	#[test]
+	fn split_fill_quotes_below_the_floor_settle_together() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			allow_split_fills: true,
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		// Each quote alone is below the floor of 900.
+		run_round(
+			&mut machine,
+			[
+				partial_quote("first", &alice, 500, 460),
+				partial_quote("second", &alice, 500, 460),
+				partial_quote("first", &bob, 500, 440),
+				partial_quote("second", &bob, 500, 440),
+			],
+			T0,
+		);
+
+		// Together alice's winners send 920, bob's only 880.
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		assert_eq!(status(&machine, bob.id), IntentStatus::Pending);
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.settlements.len(), 1);
+		assert_eq!(settlement.settlements[0].fills.len(), 2);
+		let hash = format!("first-{}", bob.id);
+		let incompatible = RejectionReason::Incompatible;
+		assert!(!rejections(&machine, &hash).contains(&incompatible));
+	}
+
+	#[test]
+	fn per_intent_cap_keeps_the_quotes_delivering_most() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_quotes_per_intent: Some(2),
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		// Advertises the most but sends the least.
+		let mut inflated = quote("inflated", &alice, 910);
+		inflated.amount_out = 2_000;
+		inflated.sign(&key("inflated"));
+		let quotes = [
+			quote("first", &alice, 950),
+			inflated,
+			quote("second", &alice, 960),
+			quote("third", &alice, 920),
+		];
+		for quote in quotes {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		let held = quotes_for(&machine, alice.id)
+			.into_iter()
+			.map(|q| q.solver_id)
+			.collect::<Vec<_>>();
+		assert_eq!(held, ["first", "second"]);
+		for solver in ["inflated", "third"] {
+			assert_eq!(
+				rejections(&machine, &format!("{solver}-{}", alice.id)),
+				[RejectionReason::Backpressure],
+			);
+		}
+	}
+
+	#[test]
+	fn global_cap_rejects_new_quotes_but_not_revisions() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_total_quotes: Some(2),
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		let quotes = [
+			quote("first", &alice, 950),
+			quote("first", &bob, 950),
+			quote("second", &bob, 960),
+			quote("first", &alice, 970),
+		];
+		for quote in quotes {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		assert_eq!(
+			rejections(&machine, &format!("second-{}", bob.id)),
+			[RejectionReason::Backpressure],
+		);
+		let held = quotes_for(&machine, alice.id);
+		assert_eq!(held.len(), 1);
+		assert_eq!(held[0].amount_out, 970);
+		assert_eq!(quotes_for(&machine, bob.id).len(), 1);
+	}
+
+	fn outcomes(machine: &AuctionStateMachine) -> Vec<(u64, Outcome)> {
+		match machine.query(AuctionQuery::UnpublishedOutcomes) {
+			AuctionQueryResult::Outcomes(outcomes) => outcomes,
+			other => panic!("unexpected result {other:?}"),
+		}
+	}
+
+	#[test]
+	fn expired_and_matched_intents_publish_their_outcomes() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let mut bob = intent("bob", 1, 1_000, 900);
+		bob.deadline_ms = T0 + 1;
+		bob.sign(&key("bob"));
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		machine.apply(AuctionCommand::ExpireStale { now_ms: T0 + 2 });
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0 + 2);
+
+		let outcomes = outcomes(&machine);
+		assert!(outcomes.iter().any(|(_, outcome)| matches!(
+			outcome,
+			Outcome::Expired { intent_id } if *intent_id == bob.id
+		)));
+		assert!(outcomes.iter().any(|(_, outcome)| matches!(
+			outcome,
+			Outcome::Settled(settlement)
+				if settlement.settled_intents() == [alice.id]
+		)));
+		assert_eq!(machine.metrics().expiries_dropped, 0);
+	}
+
+	#[test]
+	fn amendments_wait_for_resume() {
+		let mut machine = AuctionStateMachine::new(config());
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		let buy = |machine: &AuctionStateMachine| {
+			machine.pending_intents[&alice.id]
+				.token_diff()
+				.expect("token diff")[&wnear()]
+		};
+
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::set_paused(&operator(), nonce, true));
+		machine.apply(amendment(&alice, 1, 950));
+		assert_eq!(buy(&machine), 900);
+		assert!(machine.rejections.iter().any(|r| {
+			r.intent_id == alice.id && r.reason == RejectionReason::Paused
+		}));
+
+		// New intents and quotes are turned away too.
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &bob);
+		assert_eq!(status(&machine, bob.id), IntentStatus::NotFoundOrNotValid);
+		let late = quote("solver", &alice, 950);
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: late.clone(),
+			now_ms: T0,
+		});
+		assert_eq!(
+			rejections(&machine, &late.quote_hash),
+			[RejectionReason::Paused]
+		);
+		assert!(quotes_for(&machine, alice.id).is_empty());
+
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::set_paused(&operator(), nonce, false));
+		machine.apply(amendment(&alice, 1, 950));
+		assert_eq!(buy(&machine), 950);
+	}
+
+	#[test]
+	fn pause_needs_an_operator_signature() {
+		let mut machine = AuctionStateMachine::new(config());
+		machine.apply(AuctionCommand::set_paused(&key("mallory"), 1, true));
+		assert!(!machine.paused);
+
+		// The signature covers the value.
+		let AuctionCommand::SetPaused { auth, .. } =
+			AuctionCommand::set_paused(&operator(), 1, false)
+		else {
+			unreachable!();
+		};
+		machine.apply(AuctionCommand::SetPaused { paused: true, auth });
+		assert!(!machine.paused);
+
+		machine.apply(AuctionCommand::set_paused(&operator(), 1, true));
+		assert!(machine.paused);
+	}
+
+	/// Keeps the round of each settlement it is handed.
+	#[derive(Debug, Default, Clone)]
+	struct MemorySink(Arc<std::sync::Mutex<Vec<u64>>>);
+
+	impl SettlementSink for MemorySink {
+		fn record(&self, settlement: &Settlement) {
+			self.0.lock().expect("sink lock").push(settlement.round);
+		}
+	}
+
+	#[test]
+	fn leader_journals_each_settled_round_once() {
+		let sink = MemorySink::default();
+		let leading = Arc::new(AtomicBool::new(true));
+		let mut machine = AuctionStateMachine::default()
+			.with_sink(sink.clone())
+			.with_leadership(leading.clone());
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		let carol = intent("carol", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		run_round(&mut machine, [], T0 + 1);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &bob, 950)], T0 + 2);
+		assert_eq!(*sink.0.lock().expect("sink lock"), [0, 2]);
+
+		// A follower applies the same rounds without journaling them.
+		leading.store(false, Ordering::Relaxed);
+		submit(&mut machine, &carol);
+		run_round(&mut machine, [quote("solver", &carol, 950)], T0 + 3);
+		assert_eq!(status(&machine, carol.id), IntentStatus::Settled);
+		assert_eq!(*sink.0.lock().expect("sink lock"), [0, 2]);
+	}
+
+	#[test]
+	fn newer_snapshots_and_commands_are_refused() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+
+		let mut snapshot = machine.snapshot();
+		snapshot[..4].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+		let mut restored = AuctionStateMachine::default();
+		assert!(restored.restore(&snapshot).is_err());
+		assert!(restored.pending_intents.is_empty());
+		restored.restore(&machine.snapshot()).expect("same version");
+		assert_eq!(status(&restored, alice.id), IntentStatus::Pending);
+
+		let bob = intent("bob", 1, 1_000, 900);
+		let newer = VersionedCommand {
+			version: PROTOCOL_VERSION + 1,
+			command: AuctionCommand::SubmitIntent {
+				intent: bob.clone(),
+				now_ms: T0,
+			},
+		};
+		StateMachine::apply(&mut machine, newer);
+		assert!(!machine.pending_intents.contains_key(&bob.id));
+		submit(&mut machine, &bob);
+		assert!(machine.pending_intents.contains_key(&bob.id));
+	}
+
+	#[test]
+	fn quotes_verify_against_their_solvers_key() {
+		let mut machine = AuctionStateMachine::new(config());
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		let registered = key("registered").verifying_key().to_bytes().to_vec();
+		machine.apply(AuctionCommand::register_key(
+			&operator(),
+			next_nonce(&machine),
+			"solver",
+			registered,
+		));
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		let mut submit_quote = |quote: Quote| {
+			let hash = quote.quote_hash.clone();
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+			rejections(&machine, &hash)
+		};
+
+		// Signed by a key other than the registered one.
+		let wrong = quote("solver", &alice, 950);
+		assert_eq!(submit_quote(wrong), [RejectionReason::UnregisteredKey]);
+
+		let mut valid = quote("solver", &alice, 950);
+		valid.quote_hash = "valid".into();
+		valid.sign(&key("registered"));
+		assert!(submit_quote(valid).is_empty());
+
+		let mut tampered = quote("solver", &alice, 950);
+		tampered.quote_hash = "tampered".into();
+		tampered.sign(&key("registered"));
+		tampered.amount_out = 2_000;
+		let reason = RejectionReason::InvalidSignature;
+		assert_eq!(submit_quote(tampered), [reason]);
+
+		let held = quotes_for(&machine, alice.id);
+		assert_eq!(held.len(), 1);
+		assert_eq!(held[0].quote_hash, "valid");
+	}
+
+	#[test]
+	fn sealed_round_shows_its_commitment_until_revealed() {
+		let sink = MemorySink::default();
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			reveal_delay_ms: Some(1_000),
+			..AuctionConfig::default()
+		})
+		.with_sink(sink.clone());
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		let result = |machine: &AuctionStateMachine| {
+			machine.query(AuctionQuery::RoundResult(0))
+		};
+		let due = |machine: &AuctionStateMachine, now_ms| match machine
+			.query(AuctionQuery::DueReveals { now_ms })
+		{
+			AuctionQueryResult::RoundNumbers(rounds) => rounds,
+			other => panic!("unexpected result {other:?}"),
+		};
+
+		let commitment = match result(&machine) {
+			AuctionQueryResult::Sealed(commitment) => commitment,
+			other => panic!("unexpected result {other:?}"),
+		};
+		assert_eq!(volume(&machine, usdc()), 0);
+		assert!(sink.0.lock().expect("sink lock").is_empty());
+		assert!(due(&machine, T0 + 999).is_empty());
+		assert_eq!(due(&machine, T0 + 1_000), [0]);
+
+		// Too early, then once the delay has passed.
+		machine.apply(AuctionCommand::RevealRound {
+			round: 0,
+			now_ms: T0 + 999,
+		});
+		assert!(matches!(result(&machine), AuctionQueryResult::Sealed(_)));
+		machine.apply(AuctionCommand::RevealRound {
+			round: 0,
+			now_ms: T0 + 1_000,
+		});
+		let AuctionQueryResult::Round(Some(settlement)) = result(&machine)
+		else {
+			panic!("round 0 not revealed");
+		};
+		assert_eq!(settlement.commitment, commitment);
+		assert_eq!(settlement.settled_intents(), [alice.id]);
+		assert_eq!(volume(&machine, usdc()), 1_000);
+		assert_eq!(*sink.0.lock().expect("sink lock"), [0]);
+	}
+
+	#[test]
+	fn sealed_rounds_outlive_the_retention_window_until_revealed() {
+		let sink = MemorySink::default();
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_retained_rounds: Some(1),
+			reveal_delay_ms: Some(1_000),
+			..AuctionConfig::default()
+		})
+		.with_sink(sink.clone());
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &bob, 950)], T0 + 1);
+		for n in [0, 1] {
+			let result = machine.query(AuctionQuery::RoundResult(n));
+			assert!(matches!(result, AuctionQueryResult::Sealed(_)));
+		}
+
+		machine.apply(AuctionCommand::RevealRound {
+			round: 0,
+			now_ms: T0 + 1_000,
+		});
+		let settlement = round(&machine, 0).expect("round 0 revealed");
+		assert_eq!(settlement.settled_intents(), [alice.id]);
+		assert_eq!(volume(&machine, usdc()), 1_000);
+		assert_eq!(*sink.0.lock().expect("sink lock"), [0]);
+	}
+
+	#[test]
+	fn round_failed_while_sealed_is_neither_counted_nor_journaled() {
+		let sink = MemorySink::default();
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			reveal_delay_ms: Some(1_000),
+			..AuctionConfig::default()
+		})
+		.with_sink(sink.clone());
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+
+		machine.apply(AuctionCommand::FailSettlement {
+			round: 0,
+			now_ms: T0 + 1,
+		});
+		machine.apply(AuctionCommand::RevealRound {
+			round: 0,
+			now_ms: T0 + 1_000,
+		});
+		assert!(round(&machine, 0).is_some_and(|s| s.failed));
+		assert_eq!(volume(&machine, usdc()), 0);
+		assert!(sink.0.lock().expect("sink lock").is_empty());
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn nested_requests_are_ignored() {
+		let mut machine = AuctionStateMachine::default();
+		let mut command = AuctionCommand::ClearRound { now_ms: T0 };
+		for request_id in 0..1_000 {
+			command = AuctionCommand::Request {
+				request_id,
+				command: Box::new(command),
+			};
+		}
+		machine.apply(command);
+		assert_eq!(machine.current_round, 0);
+
+		// The outer id isn't spent, so a well-formed retry still applies.
+		machine.apply(AuctionCommand::Request {
+			request_id: 999,
+			command: Box::new(AuctionCommand::ClearRound { now_ms: T0 }),
+		});
+		assert_eq!(machine.current_round, 1);
+	}
+
+	#[test]
+	fn batch_is_equivalent_to_its_commands_one_by_one() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 500, 450);
+		let commands = vec![
+			AuctionCommand::SubmitIntent {
+				intent: alice.clone(),
+				now_ms: T0,
+			},
+			AuctionCommand::SubmitIntent {
+				intent: bob.clone(),
+				now_ms: T0,
+			},
+			AuctionCommand::OpenRound { deadline_ms: T0 },
+			AuctionCommand::SubmitQuote {
+				quote: quote("solver", &alice, 950),
+				now_ms: T0,
+			},
+			// Rejected, as below the intent's minimum.
+			AuctionCommand::SubmitQuote {
+				quote: quote("solver", &bob, 400),
+				now_ms: T0,
+			},
+			AuctionCommand::ClearRound { now_ms: T0 },
+		];
+
+		let mut batched = AuctionStateMachine::default();
+		batched.apply(AuctionCommand::Batch(commands.clone()));
+		let mut one_by_one = AuctionStateMachine::default();
+		for command in commands {
+			one_by_one.apply(command);
+		}
+
+		assert_eq!(batched.snapshot(), one_by_one.snapshot());
+		assert_eq!(status(&batched, alice.id), IntentStatus::Settled);
+		assert_eq!(status(&batched, bob.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn batch_wrapped_in_a_request_is_not_nested() {
+		let mut machine = AuctionStateMachine::default();
+		let inner = AuctionCommand::Batch(vec![AuctionCommand::ClearRound {
+			now_ms: T0,
+		}]);
+		machine.apply(AuctionCommand::Batch(vec![AuctionCommand::Request {
+			request_id: 1,
+			command: Box::new(inner.clone()),
+		}]));
+		assert_eq!(machine.current_round, 0);
+
+		// On its own, the request applies its batch.
+		machine.apply(AuctionCommand::Request {
+			request_id: 1,
+			command: Box::new(inner),
+		});
+		assert_eq!(machine.current_round, 1);
+	}
+
+	#[test]
+	fn publish_marks_need_an_operator_signature_and_a_published_round() {
+		let mut machine = AuctionStateMachine::new(config());
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+
+		machine.apply(AuctionCommand::mark_published(&key("mallory"), 1, 0));
+		machine.apply(AuctionCommand::mark_open_published(
+			&key("mallory"),
+			2,
+			0,
+		));
+		assert_eq!(machine.next_unpublished_round, 0);
+		assert_eq!(machine.next_unannounced_round, 0);
+
+		// Rounds that can't have been published yet aren't marked, or
+		// every later outcome would be withheld.
+		let nonce = next_nonce(&machine);
+		let command = AuctionCommand::mark_published(&operator(), nonce, 1);
+		machine.apply(command);
+		let nonce = next_nonce(&machine);
+		let command =
+			AuctionCommand::mark_open_published(&operator(), nonce, u64::MAX);
+		machine.apply(command);
+		assert_eq!(machine.next_unpublished_round, 0);
+		assert_eq!(machine.next_unannounced_round, 0);
+
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::mark_published(&operator(), nonce, 0));
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::mark_open_published(
+			&operator(),
+			nonce,
+			1,
+		));
+		assert_eq!(machine.next_unpublished_round, 1);
+		assert_eq!(machine.next_unannounced_round, 2);
+		let operator = operator().verifying_key().to_bytes().to_vec();
+		assert!(matches!(
+			machine.query(AuctionQuery::OperatorNonce(operator)),
+			AuctionQueryResult::Nonce(Some(4))
+		));
+	}
+
+	#[test]
+	fn accounts_cant_be_squatted_before_their_first_use() {
+		let mut machine = AuctionStateMachine::new(config());
+
+		// Mallory gets in first under bob's and the solver's names.
+		let mut squatted = intent("bob", 1, 1_000, 900);
+		squatted.sign(&key("mallory"));
+		submit(&mut machine, &squatted);
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		let mut squatter = quote("solver", &alice, 950);
+		squatter.quote_hash = "squatter".into();
+		squatter.sign(&key("mallory"));
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: squatter,
+			now_ms: T0,
+		});
+		assert!(machine.account_keys.is_empty());
+
+		// Neither account is locked to mallory's key.
+		let bob = intent("bob", 2, 1_000, 900);
+		submit(&mut machine, &bob);
+		assert_eq!(status(&machine, bob.id), IntentStatus::Pending);
+		let solver = quote("solver", &alice, 960);
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: solver.clone(),
+			now_ms: T0,
+		});
+		assert!(rejections(&machine, &solver.quote_hash).is_empty());
+		let held = quotes_for(&machine, alice.id);
+		assert_eq!(held.len(), 1);
+		assert_eq!(held[0].quote_hash, solver.quote_hash);
+	}
+
+	#[test]
+	fn nft_goes_to_the_earliest_expiring_compatible_quote() {
+		let mut machine = AuctionStateMachine::default();
+		let nft = AssetId::Nep171 {
+			contract: "nft.near".into(),
+			token_id: "1".into(),
+		};
+		let mut alice = intent("alice", 1, 0, 0);
+		alice.action = IntentAction::TokenDiff {
+			diff: TokenDiff::from([(nft.clone(), -1), (wnear(), 900)]),
+		};
+		alice.sign(&key("alice"));
+		submit(&mut machine, &alice);
+		let bid = |solver: &str, give: i128, expiration_ms: u64| {
+			let mut quote = Quote {
+				intent_id: alice.id,
+				quote_hash: format!("{solver}-{}", alice.id),
+				solver_id: solver.into(),
+				amount_out: to_u128(give).expect("positive"),
+				solver_token_diff: TokenDiff::from([
+					(nft.clone(), 1),
+					(wnear(), -give),
+				]),
+				expiration_ms,
+				destination_chain: None,
+				signature: Vec::new(),
+				public_key: Vec::new(),
+			};
+			quote.sign(&key(solver));
+			quote
+		};
+
+		// The higher bid expires later, so the earlier one wins.
+		let quotes =
+			[bid("high", 1_000, DEADLINE_MS), bid("low", 950, T0 + 10)];
+		run_round(&mut machine, quotes, T0);
+		let settlement = round(&machine, 0).expect("settled");
+		let [settled] = settlement.settlements.as_slice() else {
+			panic!("expected one settled intent");
+		};
+		assert_eq!(settled.fills.len(), 1);
+		assert_eq!(settled.fills[0].solver_id, "low");
+		assert_eq!(settled.flow[&nft], -1);
+	}
+
+	#[test]
+	fn nft_must_move_exactly_one_unit() {
+		let nft = AssetId::Nep171 {
+			contract: "nft.near".into(),
+			token_id: "1".into(),
+		};
+		let user = TokenDiff::from([(nft.clone(), -1), (wnear(), 900)]);
+		let solver = TokenDiff::from([(nft.clone(), 1), (wnear(), -900)]);
+		assert!(token_diffs_compatible(&user, &solver));
+
+		// Two of a unique token can't change hands, either way round.
+		let user_two = TokenDiff::from([(nft.clone(), -2), (wnear(), 900)]);
+		let solver_two = TokenDiff::from([(nft.clone(), 2), (wnear(), -900)]);
+		assert!(!token_diffs_compatible(&user_two, &solver_two));
+		assert!(!token_diffs_compatible(&user, &solver_two));
+		assert!(!token_diffs_compatible(&user_two, &solver));
+
+		// Nor can another token of the same contract stand in.
+		let other = AssetId::Nep171 {
+			contract: "nft.near".into(),
+			token_id: "2".into(),
+		};
+		let swapped = TokenDiff::from([(other, 1), (wnear(), -900)]);
+		assert!(!token_diffs_compatible(&user, &swapped));
+	}
+
+	#[test]
+	fn restored_snapshot_answers_every_query_the_same() {
+		let mut machine = AuctionStateMachine::new(config());
+		deposit(&mut machine, "solver", 1_000);
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		machine.apply(AuctionCommand::SolverHeartbeat {
+			solver_id: "solver".into(),
+			now_ms: T0,
+		});
+		machine.apply(AuctionCommand::OpenRound {
+			deadline_ms: T0 + 10,
+		});
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &bob, 800),
+			now_ms: T0 + 1,
+		});
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &bob, 920),
+			now_ms: T0 + 20,
+		});
+
+		let mut restored = AuctionStateMachine::default();
+		restored.restore(&machine.snapshot()).expect("same version");
+
+		let now_ms = T0 + 5;
+		let queries = [
+			AuctionQuery::PendingIntents,
+			AuctionQuery::RoundResult(0),
+			AuctionQuery::CurrentRound,
+			AuctionQuery::QuoteWindow,
+			AuctionQuery::IntentStatus(alice.id),
+			AuctionQuery::IntentStatus(bob.id),
+			AuctionQuery::QuotesForIntent(bob.id),
+			AuctionQuery::IntentsByStatus(IntentStatus::Settled),
+			AuctionQuery::IntentsBySigner("bob".into()),
+			AuctionQuery::RateLimitedIntents,
+			AuctionQuery::LateQuotes,
+			AuctionQuery::SurplusForRound(0),
+			AuctionQuery::RoundBalance(0),
+			AuctionQuery::RoundHistory {
+				start: 0,
+				limit: 10,
+			},
+			AuctionQuery::AssetVolume(usdc()),
+			AuctionQuery::AllVolumes,
+			AuctionQuery::Twap {
+				asset_in: usdc(),
+				asset_out: wnear(),
+				last_n_rounds: 1,
+			},
+			AuctionQuery::RecentRejections { limit: 10 },
+			AuctionQuery::SolverBond("solver".into()),
+			AuctionQuery::LiveSolvers { now_ms },
+			AuctionQuery::UnpublishedRounds,
+			AuctionQuery::UnpublishedOutcomes,
+			AuctionQuery::PreviewRound { now_ms },
+			AuctionQuery::WhyUnsettled {
+				intent_id: bob.id,
+				now_ms,
+			},
+			AuctionQuery::Metrics,
+			AuctionQuery::CompetitionLevel(bob.id),
+			AuctionQuery::RankedQuotes(bob.id),
+			AuctionQuery::AssetMeta(usdc()),
+			AuctionQuery::BestQuote(bob.id),
+			AuctionQuery::Receipt(alice.id),
+			AuctionQuery::GroupStatus("group".into()),
+			AuctionQuery::IsPreferred("solver".into()),
+			AuctionQuery::IsPaused,
+			AuctionQuery::FillHistory(alice.id),
+			AuctionQuery::CheckCompatible {
+				intent_id: bob.id,
+				candidate_diff: quote("other", &bob, 900).solver_token_diff,
+			},
+			AuctionQuery::LatencyStats,
+			AuctionQuery::SignerNonce("alice".into()),
+			AuctionQuery::Reputation("solver".into()),
+			AuctionQuery::ActivePairs,
+			AuctionQuery::OrderBook,
+			AuctionQuery::DueReveals { now_ms },
+			AuctionQuery::OperatorNonce(
+				operator().verifying_key().to_bytes().to_vec(),
+			),
+		];
+		for query in queries {
+			let expected = format!("{:?}", machine.query(query.clone()));
+			assert_eq!(
+				format!("{:?}", restored.query(query.clone())),
+				expected,
+				"{query:?}"
+			);
+		}
+		assert_eq!(restored.snapshot(), machine.snapshot());
+	}
+
+	#[test]
+	fn preview_is_the_settlement_clear_round_produces() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		let carol = intent("carol", 1, 500, 450);
+		for intent in [&alice, &bob, &carol] {
+			submit(&mut machine, intent);
+		}
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		for quote in [
+			quote("solver", &alice, 950),
+			quote("rival", &alice, 990),
+			quote("solver", &bob, 920),
+			// Too little for carol, who stays pending.
+			quote("solver", &carol, 400),
+		] {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		let before = machine.snapshot();
+		let preview =
+			match machine.query(AuctionQuery::PreviewRound { now_ms: T0 }) {
+				AuctionQueryResult::Round(Some(settlement)) => settlement,
+				other => panic!("unexpected result {other:?}"),
+			};
+		assert_eq!(machine.snapshot(), before);
+		assert_eq!(preview.settlements.len(), 2);
+
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 });
+		let cleared = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(
+			bincode::serialize(&preview).expect("serializable"),
+			bincode::serialize(&cleared).expect("serializable"),
+		);
+		assert_eq!(status(&machine, carol.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn higher_priority_fee_settles_first_under_the_settlement_cap() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_settlements_per_round: Some(1),
+			..AuctionConfig::default()
+		});
+		// Give the fee to the intent that would lose on id order alone.
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		let (low, mut high) = if alice.id < bob.id {
+			(alice, bob)
+		} else {
+			(bob, alice)
+		};
+		high.priority_fee = 10;
+		high.sign(&key(&high.signer_id));
+		submit(&mut machine, &low);
+		submit(&mut machine, &high);
+
+		let quotes =
+			|| [quote("solver", &low, 950), quote("solver", &high, 950)];
+		run_round(&mut machine, quotes(), T0);
+		assert_eq!(status(&machine, high.id), IntentStatus::Settled);
+		assert_eq!(status(&machine, low.id), IntentStatus::Pending);
+
+		run_round(&mut machine, quotes(), T0 + 1);
+		assert_eq!(status(&machine, low.id), IntentStatus::Settled);
+	}
+
+	#[test]
+	fn signer_rate_limit_drops_only_the_excess() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_intents_per_signer_per_round: Some(2),
+			..AuctionConfig::default()
+		});
+		let alice = (1..=4)
+			.map(|seq| intent("alice", seq, 1_000, 900))
+			.collect::<Vec<_>>();
+		let bob = intent("bob", 1, 1_000, 900);
+		for intent in &alice[..3] {
+			submit(&mut machine, intent);
+		}
+		submit(&mut machine, &bob);
+
+		assert_eq!(status(&machine, alice[1].id), IntentStatus::Pending);
+		assert_eq!(
+			status(&machine, alice[2].id),
+			IntentStatus::NotFoundOrNotValid,
+		);
+		assert_eq!(status(&machine, bob.id), IntentStatus::Pending);
+		let alice_count = AuctionQuery::IntentsBySigner("alice".into());
+		assert_eq!(count(&machine, alice_count.clone()), 2);
+		assert_eq!(
+			count(&machine, AuctionQuery::IntentsBySigner("bob".into())),
+			1
+		);
+		assert_eq!(count(&machine, AuctionQuery::RateLimitedIntents), 1);
+		let last = machine.rejections.back().expect("rejected");
+		assert_eq!(
+			(last.intent_id, last.reason),
+			(alice[2].id, RejectionReason::RateLimited),
+		);
+
+		// The limit is per round.
+		run_round(&mut machine, [], T0);
+		assert_eq!(count(&machine, alice_count), 0);
+		submit(&mut machine, &alice[3]);
+		assert_eq!(status(&machine, alice[3].id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn intents_need_a_valid_signature_over_their_terms() {
+		let mut machine = AuctionStateMachine::default();
+		let signed = intent("alice", 1, 1_000, 900);
+		let mut tampered = intent("bob", 1, 1_000, 900);
+		if let IntentAction::TokenDiff { diff } = &mut tampered.action {
+			diff.insert(wnear(), 2_000);
+		}
+		let mut wrong_key = intent("carol", 1, 1_000, 900);
+		wrong_key.public_key =
+			key("mallory").verifying_key().to_bytes().to_vec();
+		for intent in [&signed, &tampered, &wrong_key] {
+			submit(&mut machine, intent);
+		}
+
+		assert_eq!(status(&machine, signed.id), IntentStatus::Pending);
+		let reasons = machine
+			.rejections
+			.iter()
+			.map(|r| (r.intent_id, r.reason))
+			.collect::<Vec<_>>();
+		let reason = RejectionReason::InvalidSignature;
+		assert_eq!(reasons, [(tampered.id, reason), (wrong_key.id, reason)]);
+		assert_eq!(machine.pending_intents.len(), 1);
+	}
+
+	#[test]
+	fn over_delivery_is_reported_as_surplus() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 975)], T0);
+
+		let surplus =
+			|round| match machine.query(AuctionQuery::SurplusForRound(round)) {
+				AuctionQueryResult::Surplus(surplus) => surplus,
+				other => panic!("unexpected result {other:?}"),
+			};
+		assert_eq!(surplus(0), Some(TokenDiff::from([(wnear(), 75)])));
+		assert_eq!(surplus(1), None);
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.settlements[0].surplus, settlement.surplus);
+	}
+
+	#[test]
+	fn surplus_policies_share_the_excess_and_stay_balanced() {
+		// Alice asks for 900 wNEAR and is quoted 1000.
+		for (policy, rounding, user_gets) in [
+			(SurplusPolicy::KeepSolver, SurplusRounding::Solver, 900),
+			(SurplusPolicy::ReturnUser, SurplusRounding::Solver, 1_000),
+			(SurplusPolicy::SplitBps(2_500), SurplusRounding::Solver, 925),
+			(SurplusPolicy::SplitBps(3_333), SurplusRounding::Solver, 933),
+			(SurplusPolicy::SplitBps(3_333), SurplusRounding::User, 934),
+		] {
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				surplus_policy: policy,
+				surplus_rounding: rounding,
+				..AuctionConfig::default()
+			});
+			let alice = intent("alice", 1, 1_000, 900);
+			submit(&mut machine, &alice);
+			run_round(&mut machine, [quote("solver", &alice, 1_000)], T0);
+
+			let settlement = round(&machine, 0).expect("round 0 settled");
+			let settled = &settlement.settlements[0];
+			assert_eq!(
+				settled.flow,
+				TokenDiff::from([(usdc(), -1_000), (wnear(), user_gets)]),
+				"{policy:?}",
+			);
+			assert_eq!(settled.fills[0].flow[&wnear()], -user_gets);
+			assert_eq!(settlement.surplus, TokenDiff::from([(wnear(), 100)]));
+			assert!(settlement.residual().values().all(|&sum| sum == 0));
+		}
+	}
+
+	#[test]
+	fn round_history_pages_only_the_retained_window() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_retained_rounds: Some(2),
+			..AuctionConfig::default()
+		});
+		for (seq, now_ms) in (1..=4).zip(T0..) {
+			let alice = intent("alice", seq, 1_000, 900);
+			submit(&mut machine, &alice);
+			run_round(&mut machine, [quote("solver", &alice, 950)], now_ms);
+		}
+
+		let history = |start, limit| match machine
+			.query(AuctionQuery::RoundHistory { start, limit })
+		{
+			AuctionQueryResult::Rounds(rounds) => {
+				rounds.iter().map(|s| s.round).collect::<Vec<_>>()
 			}
-			AuctionCommand::ClearRound => {
-				let mut settled_intents = Vec::new();
-				let mut winning_quotes = Vec::new();
-				let mut aggregate_flow = TokenDiff::new();
+			other => panic!("unexpected result {other:?}"),
+		};
+		assert_eq!(history(0, 10), [2, 3]);
+		assert!(history(0, 2).is_empty());
+		assert_eq!(history(1, 2), [2]);
+		assert_eq!(history(3, 1), [3]);
+		assert!(history(4, 10).is_empty());
+		assert!(history(u64::MAX, usize::MAX).is_empty());
+	}
 
-				for (&intent_id, intent) in &self.pending_intents {
-					// Only TokenDiff intents participate in the auction.
-					let Some(user_diff) = intent.token_diff() else {
-						continue;
-					};
+	#[test]
+	fn quote_for_an_unknown_intent_is_recorded() {
+		let mut machine = AuctionStateMachine::default();
+		let ghost = intent("ghost", 1, 1_000, 900);
+		let stray = quote("solver", &ghost, 950);
+		let hash = stray.quote_hash.clone();
+		run_round(&mut machine, [stray], T0);
 
-					let Some(quotes) = self.quotes.get(&intent_id) else {
-						continue;
-					};
+		assert_eq!(
+			rejections(&machine, &hash),
+			[RejectionReason::UnknownIntent]
+		);
+		let AuctionQueryResult::Rejections(recent) =
+			machine.query(AuctionQuery::RecentRejections { limit: 10 })
+		else {
+			panic!("unexpected result");
+		};
+		assert_eq!(recent.len(), 1);
+		assert_eq!(recent[0].intent_id, ghost.id);
+		assert_eq!(recent[0].quote_hash.as_deref(), Some(hash.as_str()));
+	}
 
-					// Pick the best quote: highest amount_out that is also
-					// compatible with the user's token_diff.
-					let best = quotes
-						.iter()
-						.filter(|q| {
-							token_diffs_compatible(
-								user_diff,
-								&q.solver_token_diff,
-							)
-						})
-						.max_by_key(|q| q.amount_out);
-
-					if let Some(best) = best {
-						settled_intents.push(intent_id);
-						winning_quotes.push(best.quote_hash.clone());
-
-						// Accumulate the aggregate flow
-						let flow = aggregate_token_flow(
-							user_diff,
-							&best.solver_token_diff,
-						);
-						for (asset, amount) in flow {
-							*aggregate_flow.entry(asset).or_insert(0) += amount;
-						}
-					}
-				}
+	#[test]
+	fn each_settled_intent_carries_its_own_fills() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			allow_split_fills: true,
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		let carol = intent("carol", 1, 1_000, 900);
+		for intent in [&alice, &bob, &carol] {
+			submit(&mut machine, intent);
+		}
+		run_round(
+			&mut machine,
+			[
+				quote("solver", &alice, 950),
+				partial_quote("first", &bob, 500, 460),
+				partial_quote("second", &bob, 500, 460),
+				// Too little for carol, who stays pending.
+				quote("solver", &carol, 800),
+			],
+			T0,
+		);
 
-				// Update statuses and remove settled intents
-				for &id in &settled_intents {
-					self.pending_intents.remove(&id);
-					self.intent_status.insert(id, IntentStatus::Settled);
-				}
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		let settled = settlement
+			.settlements
+			.iter()
+			.map(|s| (s.intent_id, s.fills.len()))
+			.collect::<BTreeMap<_, _>>();
+		assert_eq!(settled, BTreeMap::from([(alice.id, 1), (bob.id, 2)]));
+		for settled in &settlement.settlements {
+			let intent_id = settled.intent_id.to_string();
+			assert!(
+				settled
+					.fills
+					.iter()
+					.all(|fill| fill.quote_hash.ends_with(&intent_id))
+			);
+			// Each entry balances on its own.
+			let net = settled
+				.fills
+				.iter()
+				.try_fold(settled.flow.clone(), |net, fill| {
+					aggregate_token_flow(&net, &fill.flow)
+				})
+				.expect("no overflow");
+			assert!(net.values().all(|&sum| sum == 0));
+		}
+	}
 
-				if !settled_intents.is_empty() {
-					self.round_results.push(Settlement {
-						round: self.current_round,
-						settled_intents,
-						winning_quotes,
-						aggregate_flow,
-					});
+	#[test]
+	fn near_max_amounts_are_rejected_not_wrapped() {
+		let huge = TokenDiff::from([(wnear(), i128::MAX)]);
+		assert!(aggregate_token_flow(&huge, &huge).is_none());
+
+		// Each quote's surplus is near `i128::MAX`, so the round's total
+		// can only hold one of them.
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 1);
+		let bob = intent("bob", 1, 1_000, 1);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		let (first, second) = if alice.id < bob.id {
+			(&alice, &bob)
+		} else {
+			(&bob, &alice)
+		};
+		let overflowing = quote("solver", second, i128::MAX);
+		let hash = overflowing.quote_hash.clone();
+		run_round(
+			&mut machine,
+			[quote("solver", first, i128::MAX), overflowing],
+			T0,
+		);
+
+		assert_eq!(status(&machine, first.id), IntentStatus::Settled);
+		assert_eq!(status(&machine, second.id), IntentStatus::Pending);
+		assert_eq!(rejections(&machine, &hash), [RejectionReason::Overflow]);
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.surplus[&wnear()], i128::MAX - 1);
+		assert!(settlement.residual().values().all(|&sum| sum == 0));
+	}
+
+	#[test]
+	fn metrics_count_the_commands_applied() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		let mut forged = intent("carol", 1, 1_000, 900);
+		forged.public_key = key("mallory").verifying_key().to_bytes().to_vec();
+		for intent in [&alice, &bob, &forged] {
+			submit(&mut machine, intent);
+		}
+		let ghost = intent("ghost", 1, 1_000, 900);
+		run_round(
+			&mut machine,
+			[quote("solver", &alice, 950), quote("solver", &ghost, 950)],
+			T0,
+		);
+		run_round(&mut machine, [], T0 + 1);
+
+		let expected = AuctionMetrics {
+			intents_submitted: 3,
+			intents_settled: 1,
+			quotes_submitted: 2,
+			quotes_rejected: 1,
+			rounds_cleared: 2,
+			expiries_dropped: 0,
+			pending_count: 1,
+			volume: BTreeMap::from([(usdc(), 1_000), (wnear(), 900)]),
+		};
+		assert_eq!(machine.metrics(), expected);
+		let AuctionQueryResult::Metrics(queried) =
+			machine.query(AuctionQuery::Metrics)
+		else {
+			panic!("unexpected result");
+		};
+		assert_eq!(queried, expected);
+
+		let mut restored = AuctionStateMachine::default();
+		restored.restore(&machine.snapshot()).expect("same version");
+		assert_eq!(restored.metrics(), expected);
+	}
+
+	#[test]
+	fn lone_quote_stays_pending_under_a_competition_threshold() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			min_quotes_to_settle: 2,
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &alice, 950),
+			now_ms: T0,
+		});
+
+		let level = AuctionQuery::CompetitionLevel(alice.id);
+		assert_eq!(count(&machine, level.clone()), 1);
+		let why = machine.query(AuctionQuery::WhyUnsettled {
+			intent_id: alice.id,
+			now_ms: T0,
+		});
+		assert!(matches!(
+			why,
+			AuctionQueryResult::Unsettled(Some(
+				UnsettledReason::BelowCompetitionThreshold
+			))
+		));
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 });
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+
+		machine.apply(AuctionCommand::OpenRound {
+			deadline_ms: T0 + 1,
+		});
+		for solver in ["solver", "rival"] {
+			machine.apply(AuctionCommand::SubmitQuote {
+				quote: quote(solver, &alice, 950),
+				now_ms: T0 + 1,
+			});
+		}
+		assert_eq!(count(&machine, level), 2);
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 + 1 });
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+	}
+
+	#[test]
+	fn basket_quote_missing_an_output_is_rejected_naming_it() {
+		let usdt = AssetId::Nep141("usdt.near".into());
+		let mut alice = intent("alice", 1, 1_000, 450);
+		if let IntentAction::TokenDiff { diff } = &mut alice.action {
+			diff.insert(usdt.clone(), 450);
+		}
+		alice.sign(&key("alice"));
+		let mut machine = AuctionStateMachine::default();
+		submit(&mut machine, &alice);
+
+		let partial = partial_quote("partial", &alice, 1_000, 500);
+		let hash = partial.quote_hash.clone();
+		let AuctionQueryResult::Compatibility(check) =
+			machine.query(AuctionQuery::CheckCompatible {
+				intent_id: alice.id,
+				candidate_diff: partial.solver_token_diff.clone(),
+			})
+		else {
+			panic!("unexpected result");
+		};
+		assert_eq!(check, Err(Incompatibility::MissingOutput(usdt.clone())));
+
+		run_round(&mut machine, [partial], T0);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+		let record = machine
+			.rejections
+			.iter()
+			.find(|r| r.quote_hash.as_deref() == Some(hash.as_str()))
+			.expect("rejected");
+		assert_eq!(record.reason, RejectionReason::Incompatible);
+		assert_eq!(record.missing_assets, [usdt.clone()]);
+
+		let mut full = partial_quote("full", &alice, 1_000, 450);
+		full.solver_token_diff.insert(usdt, -450);
+		full.sign(&key("full"));
+		run_round(&mut machine, [full], T0 + 1);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+	}
+
+	#[test]
+	fn best_quote_is_the_one_delivering_most() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		for quote in [quote("low", &alice, 950), quote("high", &alice, 990)] {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		let AuctionQueryResult::Quote(Some(best)) =
+			machine.query(AuctionQuery::BestQuote(alice.id))
+		else {
+			panic!("no best quote");
+		};
+		assert_eq!(best.solver_id, "high");
+		let AuctionQueryResult::RankedQuotes(ranked) =
+			machine.query(AuctionQuery::RankedQuotes(alice.id))
+		else {
+			panic!("unexpected result");
+		};
+		let ranked = ranked
+			.iter()
+			.map(|r| (r.quote.solver_id.as_str(), r.winning))
+			.collect::<Vec<_>>();
+		assert_eq!(ranked, [("high", true), ("low", false)]);
+	}
+
+	#[test]
+	fn bridged_output_needs_a_quote_attesting_its_chain() {
+		let weth = AssetId::Bridged {
+			asset: Box::new(AssetId::Nep141("aurora.weth.near".into())),
+			chain: "eth".into(),
+		};
+		let mut alice = intent("alice", 1, 1_000, 900);
+		alice.action = IntentAction::TokenDiff {
+			diff: TokenDiff::from([(usdc(), -1_000), (weth.clone(), 900)]),
+		};
+		alice.sign(&key("alice"));
+		let bridge_quote = |solver: &str, give: i128, chain: Option<&str>| {
+			let mut quote = partial_quote(solver, &alice, 1_000, give);
+			quote.solver_token_diff =
+				TokenDiff::from([(usdc(), 1_000), (weth.clone(), -give)]);
+			quote.destination_chain = chain.map(String::from);
+			quote.sign(&key(solver));
+			quote
+		};
+
+		let mut machine = AuctionStateMachine::default();
+		submit(&mut machine, &alice);
+		run_round(
+			&mut machine,
+			[
+				bridge_quote("unattested", 990, None),
+				bridge_quote("wrong", 980, Some("arb")),
+			],
+			T0,
+		);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+
+		run_round(
+			&mut machine,
+			[bridge_quote("right", 950, Some("eth"))],
+			T0 + 1,
+		);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		let settlement = round(&machine, 1).expect("round 1 settled");
+		assert_eq!(settlement.settlements[0].fills[0].solver_id, "right");
+	}
+
+	#[test]
+	fn quote_after_the_intent_settled_is_late() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("fast", &alice, 950)], T0);
+
+		let slow = quote("slow", &alice, 990);
+		let hash = slow.quote_hash.clone();
+		run_round(&mut machine, [slow], T0 + 1);
+		assert_eq!(rejections(&machine, &hash), [RejectionReason::LateQuote]);
+		let record = machine.rejections.back().expect("rejected");
+		assert_eq!(record.missed_round, Some(0));
+		assert_eq!(record.round, 1);
+		// The round it missed is unaffected.
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.settlements[0].fills[0].solver_id, "fast");
+	}
+
+	#[test]
+	fn asset_metadata_never_changes_matching() {
+		let usdc_meta = AssetMetadata {
+			decimals: 6,
+			symbol: "USDC".into(),
+		};
+		let mut settled = Vec::new();
+		for asset_metadata in [
+			AssetRegistry::new(),
+			AssetRegistry::from([(usdc(), usdc_meta.clone())]),
+		] {
+			let registered = !asset_metadata.is_empty();
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				asset_metadata,
+				..AuctionConfig::default()
+			});
+			let alice = intent("alice", 1, 1_000, 900);
+			submit(&mut machine, &alice);
+			run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+			let settlement = round(&machine, 0).expect("round 0 settled");
+			settled
+				.push(bincode::serialize(&settlement).expect("serializable"));
+
+			let meta = match machine.query(AuctionQuery::AssetMeta(usdc())) {
+				AuctionQueryResult::AssetMeta(meta) => meta,
+				other => panic!("unexpected result {other:?}"),
+			};
+			assert_eq!(meta, registered.then(|| usdc_meta.clone()));
+		}
+		assert_eq!(settled[0], settled[1]);
+	}
+
+	#[test]
+	fn malformed_token_diffs_are_rejected() {
+		let mut machine = AuctionStateMachine::default();
+		let with_diff = |seq, diff| {
+			let mut intent = intent("alice", seq, 1_000, 900);
+			intent.action = IntentAction::TokenDiff { diff };
+			intent.sign(&key("alice"));
+			intent
+		};
+		let malformed = [
+			with_diff(1, TokenDiff::from([(usdc(), -1_000)])),
+			with_diff(2, TokenDiff::from([(wnear(), 900)])),
+			with_diff(3, TokenDiff::from([(usdc(), -1_000), (wnear(), 0)])),
+			with_diff(4, TokenDiff::new()),
+		];
+		for intent in &malformed {
+			submit(&mut machine, intent);
+			assert_eq!(
+				status(&machine, intent.id),
+				IntentStatus::NotFoundOrNotValid,
+			);
+			let last = machine.rejections.back().expect("rejected");
+			assert_eq!(
+				(last.intent_id, last.reason),
+				(intent.id, RejectionReason::Malformed),
+			);
+		}
+
+		let valid =
+			with_diff(5, TokenDiff::from([(usdc(), -1_000), (wnear(), 900)]));
+		submit(&mut machine, &valid);
+		assert_eq!(status(&machine, valid.id), IntentStatus::Pending);
+	}
+
+	#[test]
+	fn continuous_mode_settles_on_the_first_acceptable_quote() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let first = quote("first", &alice, 950);
+		let second = quote("second", &alice, 990);
+		let late = second.quote_hash.clone();
+
+		let mut outcomes = Vec::new();
+		for mode in [ClearingMode::Batch, ClearingMode::Continuous] {
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				clearing_mode: mode,
+				..AuctionConfig::default()
+			});
+			submit(&mut machine, &alice);
+			machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+			machine.apply(AuctionCommand::SubmitQuote {
+				quote: first.clone(),
+				now_ms: T0,
+			});
+			let after_first = status(&machine, alice.id);
+			machine.apply(AuctionCommand::SubmitQuote {
+				quote: second.clone(),
+				now_ms: T0,
+			});
+			machine.apply(AuctionCommand::ClearRound { now_ms: T0 });
+
+			let settlement = round(&machine, 0).expect("round 0 settled");
+			let winner = settlement.settlements[0].fills[0].solver_id.clone();
+			outcomes.push((after_first, winner, rejections(&machine, &late)));
+			assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		}
+
+		// Batch waits for the clear and takes the best quote; continuous
+		// settles on arrival, in a round of its own, and the second quote
+		// is late.
+		assert_eq!(
+			outcomes,
+			[
+				(IntentStatus::Pending, "second".to_string(), vec![]),
+				(
+					IntentStatus::Settled,
+					"first".to_string(),
+					vec![RejectionReason::LateQuote],
+				),
+			],
+		);
+	}
+
+	#[test]
+	fn expire_stale_sweeps_only_intents_past_their_deadline() {
+		let mut machine = AuctionStateMachine::default();
+		let stale = intent("alice", 1, 1_000, 900);
+		let mut live = intent("bob", 1, 1_000, 900);
+		live.deadline_ms = DEADLINE_MS + 60_000;
+		live.sign(&key("bob"));
+		submit(&mut machine, &stale);
+		submit(&mut machine, &live);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		for quote in [quote("solver", &stale, 950), quote("solver", &live, 950)]
+		{
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		machine.apply(AuctionCommand::ExpireStale {
+			now_ms: DEADLINE_MS + 1,
+		});
+		assert_eq!(status(&machine, stale.id), IntentStatus::Expired);
+		assert!(quotes_for(&machine, stale.id).is_empty());
+		assert_eq!(status(&machine, live.id), IntentStatus::Pending);
+		assert_eq!(quotes_for(&machine, live.id).len(), 1);
+		assert_eq!(
+			machine.pending_intents.keys().copied().collect::<Vec<_>>(),
+			[live.id],
+		);
+		// Sweeping clears no round.
+		assert_eq!(machine.current_round, 0);
+	}
+
+	#[test]
+	fn receipt_reconciles_with_the_round() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			fee_bps: 100,
+			surplus_policy: SurplusPolicy::SplitBps(5_000),
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &alice, 1_000)], T0);
+
+		let AuctionQueryResult::Receipt(receipt) =
+			machine.query(AuctionQuery::Receipt(alice.id))
+		else {
+			panic!("alice settled");
+		};
+		// Of the 100 surplus, the 9 fee comes first; the user gets half
+		// the remaining 91, rounded down, and the solver the rest.
+		assert_eq!((receipt.intent_id, receipt.round), (alice.id, 0));
+		assert_eq!(receipt.amount_in, TokenDiff::from([(usdc(), -1_000)]));
+		assert_eq!(receipt.amount_out, TokenDiff::from([(wnear(), 945)]));
+		assert_eq!(receipt.surplus, TokenDiff::from([(wnear(), 100)]));
+		assert_eq!(receipt.fee, TokenDiff::from([(wnear(), 9)]));
+		assert_eq!(receipt.fills.len(), 1);
+		assert_eq!(
+			receipt.fills[0].flow,
+			TokenDiff::from([(usdc(), 1_000), (wnear(), -954)]),
+		);
+
+		// Together with its fills the receipt nets to zero, and so to the
+		// round's aggregate flow.
+		let net = [&receipt.amount_in, &receipt.amount_out, &receipt.fee]
+			.into_iter()
+			.chain(receipt.fills.iter().map(|fill| &fill.flow))
+			.try_fold(TokenDiff::new(), |net, flow| {
+				aggregate_token_flow(&net, flow)
+			})
+			.expect("no overflow");
+		assert!(net.values().all(|&sum| sum == 0));
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert!(settlement.aggregate_flow.values().all(|&sum| sum == 0));
+		assert_eq!(settlement.fees, receipt.fee);
+
+		let unsettled = machine.query(AuctionQuery::Receipt(bob.id));
+		assert!(matches!(
+			unsettled,
+			AuctionQueryResult::Status(IntentStatus::Pending)
+		));
+	}
+
+	#[test]
+	fn resubmitted_quote_replaces_the_solvers_last() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		for quote in [
+			quote("solver", &alice, 950),
+			quote("rival", &alice, 960),
+			quote("solver", &alice, 990),
+		] {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		let quotes = quotes_for(&machine, alice.id)
+			.into_iter()
+			.map(|q| (q.solver_id, q.amount_out))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			quotes,
+			[("solver".to_string(), 990), ("rival".to_string(), 960)],
+		);
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 });
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.settlements[0].fills[0].solver_id, "solver");
+	}
+
+	#[test]
+	fn random_tie_break_rotates_by_round_but_agrees_across_replicas() {
+		let alice = intent("alice", 1, 1_000, 900);
+		let (a, b) = (quote("a", &alice, 950), quote("b", &alice, 950));
+		let config = AuctionConfig {
+			tie_break: TieBreak::RandomTieBreak { seed: 7 },
+			..AuctionConfig::default()
+		};
+
+		// Under seed 7, "a" ranks higher in round 0 and "b" in round 1.
+		for (round_number, expected) in [(0, "a"), (1, "b")] {
+			// Replicas see the tied quotes in either order.
+			for quotes in [[a.clone(), b.clone()], [b.clone(), a.clone()]] {
+				let mut machine = AuctionStateMachine::new(config.clone());
+				for empty in 0..round_number {
+					run_round(&mut machine, [], T0 + empty);
 				}
+				submit(&mut machine, &alice);
+				run_round(&mut machine, quotes, T0 + round_number);
 
-				self.quotes.clear();
-				self.current_round += 1;
+				let settlement =
+					round(&machine, round_number).expect("alice settled");
+				assert_eq!(
+					settlement.settlements[0].fills[0].solver_id,
+					expected,
+				);
 			}
 		}
 	}
 
-	fn query(&self, query: Self::Query) -> Self::QueryResult {
-		match query {
-			AuctionQuery::PendingIntents => AuctionQueryResult::Intents(
-				self.pending_intents.values().cloned().collect(),
+	#[test]
+	fn estimated_transfers_count_every_nonzero_flow() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			allow_split_fills: true,
+			gas_per_transfer: 100,
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		run_round(
+			&mut machine,
+			[
+				quote("solver", &alice, 950),
+				partial_quote("first", &bob, 500, 460),
+				partial_quote("second", &bob, 500, 460),
+			],
+			T0,
+		);
+
+		// Alice and her solver each move two assets; bob and his two
+		// solvers move two assets each.
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.estimated_transfers, 4 + 6);
+		assert_eq!(settlement.estimated_gas, 1_000);
+	}
+
+	#[test]
+	fn rejections_map_to_their_rpc_errors() {
+		use AuctionError as E;
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_intents_per_signer_per_round: Some(1),
+			..config()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &intent("alice", 2, 1_000, 900));
+		let mut forged = intent("carol", 1, 1_000, 900);
+		forged.public_key = key("mallory").verifying_key().to_bytes().to_vec();
+		submit(&mut machine, &forged);
+		submit(&mut machine, &alice);
+
+		let mut expired = quote("expired", &alice, 950);
+		expired.expiration_ms = T0 - 1;
+		expired.sign(&key("expired"));
+		let ghost = intent("ghost", 1, 1_000, 900);
+		run_round(
+			&mut machine,
+			[
+				quote("solver", &ghost, 950),
+				expired,
+				quote("short", &alice, 800),
+			],
+			T0,
+		);
+
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::set_paused(&operator(), nonce, true));
+		submit(&mut machine, &intent("dave", 1, 1_000, 900));
+
+		let errors = machine
+			.rejections
+			.iter()
+			.map(RejectionRecord::error)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			errors,
+			[
+				E::RateLimited,
+				E::Unauthorized,
+				E::Malformed,
+				E::UnknownIntent,
+				E::Expired,
+				E::Incompatible,
+				E::Paused,
+			],
+		);
+		assert_eq!(E::from(RejectionReason::Overflow), E::Overflow);
+		assert!(
+			errors
+				.iter()
+				.all(|error| (-32099..=-32000).contains(&error.code()))
+		);
+	}
+
+	#[test]
+	fn preferred_solver_wins_only_near_ties() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			preferred_solvers: BTreeSet::from(["trusted".to_string()]),
+			preferred_tolerance_bps: 100,
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		// Within 1% of 950 is 941 or more.
+		run_round(
+			&mut machine,
+			[
+				quote("trusted", &alice, 941),
+				quote("rival", &alice, 950),
+				quote("trusted", &bob, 940),
+				quote("rival", &bob, 950),
+			],
+			T0,
+		);
+
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		let winners = settlement
+			.settlements
+			.iter()
+			.map(|s| (s.intent_id, s.fills[0].solver_id.as_str()))
+			.collect::<BTreeMap<_, _>>();
+		assert_eq!(winners[&alice.id], "trusted");
+		assert_eq!(winners[&bob.id], "rival");
+
+		let preferred = |solver: &str| match machine
+			.query(AuctionQuery::IsPreferred(solver.into()))
+		{
+			AuctionQueryResult::Flag(flag) => flag,
+			other => panic!("unexpected result {other:?}"),
+		};
+		assert!(preferred("trusted"));
+		assert!(!preferred("rival"));
+	}
+
+	#[test]
+	fn settlement_latency_lands_in_its_bucket() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		// Both were received at T0.
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0 + 50);
+		run_round(&mut machine, [quote("solver", &bob, 950)], T0 + 700);
+
+		let latency = |machine: &AuctionStateMachine| match machine
+			.query(AuctionQuery::LatencyStats)
+		{
+			AuctionQueryResult::Latency(stats) => stats,
+			other => panic!("unexpected result {other:?}"),
+		};
+		let stats = latency(&machine);
+		// 50ms falls in the first bucket, up to 100ms, and 700ms in the
+		// fourth, up to 1s.
+		let mut counts = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+		counts[0] = 1;
+		counts[3] = 1;
+		assert_eq!(
+			stats,
+			LatencyStats {
+				counts,
+				p50_ms: Some(100),
+				p95_ms: Some(1_000),
+			},
+		);
+
+		let mut restored = AuctionStateMachine::default();
+		restored.restore(&machine.snapshot()).expect("same version");
+		assert_eq!(latency(&restored), stats);
+	}
+
+	#[test]
+	fn drain_clears_then_expires_everything_left() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &alice, 950),
+			now_ms: T0,
+		});
+
+		machine.apply(AuctionCommand::Drain { now_ms: T0 });
+		let AuctionQueryResult::Intents(pending) =
+			machine.query(AuctionQuery::PendingIntents)
+		else {
+			panic!("unexpected result");
+		};
+		assert!(pending.is_empty());
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		assert_eq!(status(&machine, bob.id), IntentStatus::Expired);
+		assert!(round(&machine, 0).is_some());
+	}
+
+	#[test]
+	fn check_compatible_names_the_first_misfit() {
+		use Incompatibility as I;
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		let usdt = AssetId::Nep141("usdt.near".into());
+
+		let cases = [
+			(TokenDiff::from([(usdc(), 1_000), (wnear(), -900)]), Ok(())),
+			(
+				TokenDiff::from([(usdc(), 1_000), (usdt.clone(), -900)]),
+				Err(I::UnexpectedAsset(usdt)),
 			),
-			AuctionQuery::RoundResult(round) => {
-				let result = self
-					.round_results
-					.iter()
-					.find(|s| s.round == round)
-					.cloned();
-				AuctionQueryResult::Round(result)
+			(
+				TokenDiff::from([(usdc(), 1_000)]),
+				Err(I::MissingOutput(wnear())),
+			),
+			(
+				TokenDiff::from([(usdc(), -1), (wnear(), -900)]),
+				Err(I::WrongSign(usdc())),
+			),
+			(
+				TokenDiff::from([(usdc(), 1_001), (wnear(), -900)]),
+				Err(I::ExceedsOffer(usdc())),
+			),
+			(
+				TokenDiff::from([(usdc(), 1_000), (wnear(), -899)]),
+				Err(I::InsufficientAmount(wnear())),
+			),
+		];
+		let check = |intent_id, candidate_diff| match machine.query(
+			AuctionQuery::CheckCompatible {
+				intent_id,
+				candidate_diff,
+			},
+		) {
+			AuctionQueryResult::Compatibility(result) => result,
+			other => panic!("unexpected result {other:?}"),
+		};
+		for (candidate_diff, expected) in cases {
+			assert_eq!(check(alice.id, candidate_diff.clone()), expected);
+		}
+		let ghost = IntentId::derive("ghost", 1);
+		assert_eq!(check(ghost, TokenDiff::new()), Err(I::UnknownIntent));
+	}
+
+	#[test]
+	fn fee_on_transfer_output_needs_a_grossed_up_quote() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			transfer_fee_bps: BTreeMap::from([(wnear(), 10)]),
+			..AuctionConfig::default()
+		});
+		let alice = intent("alice", 1, 100_000, 100_000);
+		submit(&mut machine, &alice);
+
+		// Sending exactly the minimum nets the user 99_900.
+		run_round(&mut machine, [quote("exact", &alice, 100_000)], T0);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+
+		run_round(&mut machine, [quote("grossed", &alice, 100_100)], T0 + 1);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		let settlement = round(&machine, 1).expect("round 1 settled");
+		let settled = &settlement.settlements[0];
+		assert_eq!(settled.flow[&wnear()], 100_000);
+		assert_eq!(settled.fills[0].flow[&wnear()], -100_100);
+		assert_eq!(settlement.transfer_fees, TokenDiff::from([(wnear(), 100)]));
+		assert!(settlement.residual().values().all(|&sum| sum == 0));
+	}
+
+	#[test]
+	fn commitments_differ_by_round_and_survive_serialization() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+		run_round(&mut machine, [quote("solver", &bob, 950)], T0 + 1);
+
+		let first = round(&machine, 0).expect("round 0 settled");
+		let second = round(&machine, 1).expect("round 1 settled");
+		assert_ne!(first.commitment, second.commitment);
+		for settlement in [first, second] {
+			assert_eq!(settlement.commitment, settlement.commitment());
+			let bytes = bincode::serialize(&settlement).expect("serializable");
+			let decoded: Settlement =
+				bincode::deserialize(&bytes).expect("deserializable");
+			assert_eq!(decoded.commitment, settlement.commitment);
+			assert_eq!(decoded.commitment(), settlement.commitment);
+		}
+	}
+
+	#[test]
+	fn intents_by_status_splits_settled_from_pending() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &alice, 950)], T0);
+
+		let ids = |status| match machine
+			.query(AuctionQuery::IntentsByStatus(status))
+		{
+			AuctionQueryResult::Intents(intents) => {
+				intents.iter().map(|intent| intent.id).collect::<Vec<_>>()
 			}
-			AuctionQuery::CurrentRound => {
-				AuctionQueryResult::RoundNumber(self.current_round)
+			other => panic!("unexpected result {other:?}"),
+		};
+		assert_eq!(ids(IntentStatus::Settled), [alice.id]);
+		assert_eq!(ids(IntentStatus::Pending), [bob.id]);
+		assert!(ids(IntentStatus::Expired).is_empty());
+	}
+
+	#[test]
+	fn reliable_solver_wins_under_a_reliability_heavy_weighting() {
+		let weighted = QuoteSelection::Weighted {
+			price_weight: 1,
+			reliability_weight: 10,
+			validity_weight: 0,
+			reputation_weight: 0,
+		};
+		let mut winners = Vec::new();
+		for quote_selection in [QuoteSelection::BestPrice, weighted] {
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				quote_selection,
+				..AuctionConfig::default()
+			});
+			// "flaky" wins a round that then fails on-chain; "steady" wins
+			// one that settles.
+			let alice = intent("alice", 1, 1_000, 900);
+			let bob = intent("bob", 1, 1_000, 900);
+			let carol = intent("carol", 1, 1_000, 900);
+			for intent in [&alice, &bob, &carol] {
+				submit(&mut machine, intent);
 			}
-			AuctionQuery::IntentStatus(id) => {
-				let status = self
-					.intent_status
-					.get(&id)
-					.copied()
-					.unwrap_or(IntentStatus::NotFoundOrNotValid);
-				AuctionQueryResult::Status(status)
+			run_round(&mut machine, [quote("flaky", &alice, 950)], T0);
+			machine.apply(AuctionCommand::FailSettlement {
+				round: 0,
+				now_ms: T0 + 1,
+			});
+			run_round(&mut machine, [quote("steady", &bob, 950)], T0 + 2);
+
+			// "flaky" now bids 4% more for carol.
+			run_round(
+				&mut machine,
+				[quote("flaky", &carol, 990), quote("steady", &carol, 950)],
+				T0 + 3,
+			);
+			let settlement = round(&machine, 2).expect("round 2 settled");
+			winners.push(settlement.settlements[0].fills[0].solver_id.clone());
+		}
+		assert_eq!(winners, ["flaky", "steady"]);
+	}
+
+	#[test]
+	fn diffs_naming_too_many_assets_are_rejected() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			max_assets_per_diff: Some(2),
+			..AuctionConfig::default()
+		});
+		let usdt = AssetId::Nep141("usdt.near".into());
+
+		let mut basket = intent("bob", 1, 1_000, 900);
+		basket.action = IntentAction::TokenDiff {
+			diff: TokenDiff::from([
+				(usdc(), -1_000),
+				(wnear(), 900),
+				(usdt.clone(), 900),
+			]),
+		};
+		basket.sign(&key("bob"));
+		submit(&mut machine, &basket);
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		assert_eq!(
+			status(&machine, basket.id),
+			IntentStatus::NotFoundOrNotValid
+		);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+
+		let mut bloated = quote("bloated", &alice, 990);
+		bloated.solver_token_diff.insert(usdt, -1);
+		bloated.sign(&key("bloated"));
+		run_round(&mut machine, [bloated, quote("solver", &alice, 950)], T0);
+
+		let reasons = machine
+			.rejections
+			.iter()
+			.map(|r| (r.intent_id, r.quote_hash.is_some(), r.reason))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			reasons,
+			[
+				(basket.id, false, RejectionReason::TooLarge),
+				(alice.id, true, RejectionReason::TooLarge),
+			],
+		);
+		let settlement = round(&machine, 0).expect("alice settled");
+		assert_eq!(settlement.settlements[0].fills[0].solver_id, "solver");
+	}
+
+	proptest! {
+		#[test]
+		fn round_balance_is_always_zero(
+			fee_bps in 0..=1_000u16,
+			split_bps in 0..=10_000u16,
+			transfer_fee_bps in 0..=500u16,
+			intents in prop::collection::vec(
+				(1..10_000i128, 1..=100i128, prop::collection::vec(
+					(0..3u8, 50..200i128, 1..=100i128),
+					0..4,
+				)),
+				1..4,
+			),
+		) {
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				allow_split_fills: true,
+				fee_bps,
+				surplus_policy: SurplusPolicy::SplitBps(split_bps),
+				transfer_fee_bps: BTreeMap::from([(wnear(), transfer_fee_bps)]),
+				..AuctionConfig::default()
+			});
+			let mut quotes = Vec::new();
+			for (seq, (sell, buy_pct, specs)) in (1..).zip(intents) {
+				let buy = (sell * buy_pct / 100).max(1);
+				let intent = intent(&format!("user{seq}"), seq, sell, buy);
+				submit(&mut machine, &intent);
+				for (solver, give_pct, take_pct) in specs {
+					let solver = format!("solver{solver}");
+					let take = (sell * take_pct / 100).max(1);
+					let give = (buy * give_pct / 100).max(1);
+					quotes.push(partial_quote(&solver, &intent, take, give));
+				}
 			}
-			AuctionQuery::QuotesForIntent(id) => {
-				let quotes = self
-					.quotes
-					.get(&id)
-					.cloned()
-					.unwrap_or_default();
-				AuctionQueryResult::Quotes(quotes)
+			run_round(&mut machine, quotes, T0);
+
+			// A round that settled nothing isn't recorded.
+			match machine.query(AuctionQuery::RoundBalance(0)) {
+				AuctionQueryResult::Balance(Some(residual)) => {
+					prop_assert!(residual.values().all(|&amount| amount == 0));
+				}
+				AuctionQueryResult::Balance(None) => {
+					prop_assert!(round(&machine, 0).is_none());
+				}
+				other => panic!("unexpected result {other:?}"),
+			}
+		}
+	}
+
+	#[test]
+	fn only_allowed_assets_may_be_traded() {
+		let usdt = AssetId::Nep141("usdt.near".into());
+		let dai = AssetId::Nep141("dai.near".into());
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			// The denylist wins over the allowlist.
+			allowed_assets: Some(BTreeSet::from([
+				usdc(),
+				wnear(),
+				usdt.clone(),
+			])),
+			denied_assets: BTreeSet::from([usdt.clone()]),
+			..AuctionConfig::default()
+		});
+		let buying = |signer: &str, asset: &AssetId| {
+			let mut intent = intent(signer, 1, 1_000, 900);
+			intent.action = IntentAction::TokenDiff {
+				diff: TokenDiff::from([(usdc(), -1_000), (asset.clone(), 900)]),
+			};
+			intent.sign(&key(signer));
+			intent
+		};
+		let alice = intent("alice", 1, 1_000, 900);
+		let denied = buying("bob", &usdt);
+		let unlisted = buying("carol", &dai);
+		for intent in [&alice, &denied, &unlisted] {
+			submit(&mut machine, intent);
+		}
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+
+		let mut sneaky = quote("sneaky", &alice, 990);
+		sneaky.solver_token_diff.insert(usdt, -1);
+		sneaky.sign(&key("sneaky"));
+		run_round(&mut machine, [sneaky, quote("solver", &alice, 950)], T0);
+
+		let rejected = machine
+			.rejections
+			.iter()
+			.map(|r| (r.intent_id, r.reason))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			rejected,
+			[
+				(denied.id, RejectionReason::DisallowedAsset),
+				(unlisted.id, RejectionReason::DisallowedAsset),
+				(alice.id, RejectionReason::DisallowedAsset),
+			],
+		);
+		assert_eq!(
+			rejections(&machine, &format!("sneaky-{}", alice.id)),
+			[RejectionReason::DisallowedAsset],
+		);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+	}
+
+	#[test]
+	fn why_unsettled_explains_each_stuck_intent() {
+		use UnsettledReason as U;
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			min_quotes_to_settle: 2,
+			..AuctionConfig::default()
+		});
+		let [lonely, stale, mismatched, short, lone, contested] = [
+			"lonely",
+			"stale",
+			"mismatched",
+			"short",
+			"lone",
+			"contested",
+		]
+		.map(|signer| intent(signer, 1, 1_000, 900));
+		for intent in [&lonely, &stale, &mismatched, &short, &lone, &contested]
+		{
+			submit(&mut machine, intent);
+		}
+
+		let mut expiring = quote("solver", &stale, 950);
+		expiring.expiration_ms = T0 + 10;
+		expiring.sign(&key("solver"));
+		let mut wrong_asset = quote("solver", &mismatched, 950);
+		wrong_asset.solver_token_diff.remove(&wnear());
+		wrong_asset
+			.solver_token_diff
+			.insert(AssetId::Nep141("usdt.near".into()), -950);
+		wrong_asset.sign(&key("solver"));
+		machine.apply(AuctionCommand::OpenRound {
+			deadline_ms: T0 + 20,
+		});
+		for quote in [
+			expiring,
+			wrong_asset,
+			quote("solver", &short, 800),
+			quote("solver", &lone, 950),
+			quote("a", &contested, 950),
+			quote("b", &contested, 940),
+		] {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		let why = |intent_id| match machine.query(AuctionQuery::WhyUnsettled {
+			intent_id,
+			now_ms: T0 + 20,
+		}) {
+			AuctionQueryResult::Unsettled(reason) => reason,
+			other => panic!("unexpected result {other:?}"),
+		};
+		assert_eq!(why(lonely.id), Some(U::NoQuotes));
+		assert_eq!(why(stale.id), Some(U::AllExpired));
+		assert_eq!(why(mismatched.id), Some(U::AllIncompatible));
+		assert_eq!(why(short.id), Some(U::BelowMinimum));
+		assert_eq!(why(lone.id), Some(U::BelowCompetitionThreshold));
+		assert_eq!(why(contested.id), None);
+		assert_eq!(why(IntentId::derive("ghost", 1)), Some(U::NotPending));
+
+		// The explanations agree with what the clear then does.
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 + 20 });
+		let settlement = round(&machine, 0).expect("contested settled");
+		assert_eq!(settlement.settled_intents(), [contested.id]);
+	}
+
+	#[test]
+	fn stale_solvers_lose_to_a_live_one() {
+		let mut machine = AuctionStateMachine::new(AuctionConfig {
+			solver_liveness_ms: Some(1_000),
+			..AuctionConfig::default()
+		});
+		let heartbeat =
+			|machine: &mut AuctionStateMachine, solver: &str, at| {
+				machine.apply(AuctionCommand::SolverHeartbeat {
+					solver_id: solver.into(),
+					now_ms: at,
+				});
+			};
+		heartbeat(&mut machine, "gone", T0 - 5_000);
+		heartbeat(&mut machine, "live", T0 - 100);
+		// A delayed, older heartbeat doesn't make "live" look stale.
+		heartbeat(&mut machine, "live", T0 - 3_000);
+		let live_at = |machine: &AuctionStateMachine, now_ms| match machine
+			.query(AuctionQuery::LiveSolvers { now_ms })
+		{
+			AuctionQueryResult::Solvers(solvers) => solvers,
+			other => panic!("unexpected result {other:?}"),
+		};
+		assert_eq!(live_at(&machine, T0), ["live"]);
+		assert!(live_at(&machine, T0 + 1_000).is_empty());
+
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(
+			&mut machine,
+			[
+				quote("gone", &alice, 990),
+				quote("silent", &alice, 980),
+				quote("live", &alice, 950),
+			],
+			T0,
+		);
+
+		let settlement = round(&machine, 0).expect("alice settled");
+		assert_eq!(settlement.settlements[0].fills[0].solver_id, "live");
+		for solver in ["gone", "silent"] {
+			assert_eq!(
+				rejections(&machine, &format!("{solver}-{}", alice.id)),
+				[RejectionReason::SolverStale],
+			);
+		}
+	}
+
+	#[test]
+	fn odd_surplus_splits_evenly_with_its_remainder_placed() {
+		use SurplusRounding as R;
+		// Alice asks for 900 wNEAR and is quoted 951: 51 to split 50/50.
+		for (rounding, user, protocol) in
+			[(R::Solver, 25, 0), (R::User, 26, 0), (R::Protocol, 25, 1)]
+		{
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				surplus_policy: SurplusPolicy::SplitBps(5_000),
+				surplus_rounding: rounding,
+				..AuctionConfig::default()
+			});
+			let alice = intent("alice", 1, 1_000, 900);
+			submit(&mut machine, &alice);
+			run_round(&mut machine, [quote("solver", &alice, 951)], T0);
+
+			let settlement = round(&machine, 0).expect("round 0 settled");
+			let settled = &settlement.settlements[0];
+			let sent = -settled.fills[0].flow[&wnear()];
+			let fee = settled.fee.get(&wnear()).copied().unwrap_or(0);
+			assert_eq!(settled.flow[&wnear()], 900 + user, "{rounding:?}");
+			assert_eq!(fee, protocol, "{rounding:?}");
+			// Nothing is created or destroyed: the user's share, what the
+			// solver keeps and the protocol's unit are the whole surplus.
+			let kept = 951 - sent;
+			assert_eq!(user + kept + fee, 51, "{rounding:?}");
+			assert!(settlement.residual().values().all(|&sum| sum == 0));
+		}
+	}
+
+	#[test]
+	fn twap_weights_each_trade_by_its_volume() {
+		let mut machine = AuctionStateMachine::default();
+		// Alice sells at 0.9 wNEAR per USDC, then Bob three times as much
+		// at 1.1.
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		run_round(&mut machine, [quote("solver", &alice, 900)], T0);
+		let bob = intent("bob", 1, 3_000, 3_300);
+		submit(&mut machine, &bob);
+		run_round(&mut machine, [quote("solver", &bob, 3_300)], T0 + 1);
+
+		let twap = |asset_in, asset_out, last_n_rounds| match machine.query(
+			AuctionQuery::Twap {
+				asset_in,
+				asset_out,
+				last_n_rounds,
+			},
+		) {
+			AuctionQueryResult::Price(price) => price,
+			other => panic!("unexpected result {other:?}"),
+		};
+		let both = twap(usdc(), wnear(), 2).expect("two trades");
+		assert_eq!((both.amount_out, both.amount_in), (4_200, 4_000));
+		assert_eq!(both.to_fixed(4), Some(10_500));
+		let last = twap(usdc(), wnear(), 1).expect("Bob's trade");
+		assert_eq!(last.to_fixed(4), Some(11_000));
+		assert!(twap(usdc(), wnear(), 0).is_none());
+		assert!(twap(wnear(), usdc(), 2).is_none());
+	}
+
+	#[test]
+	fn quote_meeting_the_amount_but_not_the_limit_rate_is_rejected() {
+		use crate::types::LIMIT_PRICE_DECIMALS;
+		let mut machine = AuctionStateMachine::default();
+		// Both want at least 900 wNEAR for 1000 USDC; Alice also wants a
+		// rate of at least 0.95.
+		let mut alice = intent("alice", 1, 1_000, 900);
+		alice.limit_price = Some(LimitPrice {
+			asset_in: usdc(),
+			asset_out: wnear(),
+			min_out_per_in: 95 * 10u128.pow(LIMIT_PRICE_DECIMALS - 2),
+		});
+		alice.sign(&key("alice"));
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+
+		let below = quote("below", &alice, 920);
+		run_round(&mut machine, [below, quote("below", &bob, 920)], T0);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Pending);
+		assert_eq!(status(&machine, bob.id), IntentStatus::Settled);
+		assert_eq!(
+			rejections(&machine, &format!("below-{}", alice.id)),
+			[RejectionReason::BelowLimitPrice],
+		);
+
+		run_round(&mut machine, [quote("at", &alice, 950)], T0 + 1);
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+	}
+
+	#[test]
+	fn filtered_clear_settles_only_matching_intents() {
+		let mut machine = AuctionStateMachine::default();
+		let stnear = AssetId::Nep141("meta-pool.near".into());
+		let alice = intent("alice", 1, 1_000, 900);
+		// Carol swaps wNEAR for stNEAR, without touching USDC.
+		let mut carol = intent("carol", 1, 1_000, 900);
+		carol.action = IntentAction::TokenDiff {
+			diff: TokenDiff::from([(wnear(), -1_000), (stnear.clone(), 900)]),
+		};
+		carol.sign(&key("carol"));
+		let carol_quote = || {
+			let mut quote = partial_quote("solver", &carol, 0, 950);
+			quote.solver_token_diff =
+				TokenDiff::from([(wnear(), 1_000), (stnear.clone(), -950)]);
+			quote.sign(&key("solver"));
+			quote
+		};
+		submit(&mut machine, &alice);
+		submit(&mut machine, &carol);
+
+		let clear = |machine: &mut AuctionStateMachine, asset, prefix| {
+			machine.apply(AuctionCommand::ClearRoundFiltered {
+				now_ms: T0,
+				asset,
+				signer_prefix: prefix,
+			});
+		};
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		for quote in [quote("solver", &alice, 950), carol_quote()] {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+		clear(&mut machine, Some(usdc()), None);
+
+		assert_eq!(status(&machine, alice.id), IntentStatus::Settled);
+		assert_eq!(status(&machine, carol.id), IntentStatus::Pending);
+		assert!(quotes_for(&machine, carol.id).is_empty());
+		assert_eq!(machine.current_round, 1);
+
+		// A later round can settle Carol by her signer.
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: carol_quote(),
+			now_ms: T0,
+		});
+		clear(&mut machine, None, Some("bob".into()));
+		assert_eq!(status(&machine, carol.id), IntentStatus::Pending);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: carol_quote(),
+			now_ms: T0,
+		});
+		clear(&mut machine, Some(wnear()), Some("car".into()));
+		assert_eq!(status(&machine, carol.id), IntentStatus::Settled);
+		let settlement = round(&machine, 2).expect("carol settled");
+		assert_eq!(settlement.settled_intents(), [carol.id]);
+	}
+
+	#[test]
+	fn chained_intent_settles_after_its_dependency_or_not_at_all() {
+		let mut machine = AuctionStateMachine::default();
+		let chained = |signer: &str, on: &Intent| {
+			let mut intent = intent(signer, 1, 1_000, 900);
+			intent.depends_on = Some(on.id);
+			intent.sign(&key(signer));
+			intent
+		};
+		// Bob's id sorts before Alice's, but his intent spends what hers
+		// brings in.
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = chained("bob", &alice);
+		assert!(bob.id < alice.id);
+		// Dave depends on Carol, whose only quote falls short.
+		let carol = intent("carol", 1, 1_000, 900);
+		let dave = chained("dave", &carol);
+		for intent in [&bob, &alice, &dave, &carol] {
+			submit(&mut machine, intent);
+		}
+		run_round(
+			&mut machine,
+			[
+				quote("solver", &alice, 950),
+				quote("solver", &bob, 950),
+				quote("solver", &carol, 800),
+				quote("solver", &dave, 950),
+			],
+			T0,
+		);
+
+		let settlement = round(&machine, 0).expect("round 0 settled");
+		assert_eq!(settlement.settled_intents(), [alice.id, bob.id]);
+		assert_eq!(status(&machine, carol.id), IntentStatus::Pending);
+		assert_eq!(status(&machine, dave.id), IntentStatus::Pending);
+
+		// Erin depends on Frank before he submits; his intent depending on
+		// hers would close a cycle.
+		let frank = intent("frank", 1, 1_000, 900);
+		let erin = chained("erin", &frank);
+		let frank = chained("frank", &erin);
+		submit(&mut machine, &erin);
+		submit(&mut machine, &frank);
+		assert_eq!(status(&machine, erin.id), IntentStatus::Pending);
+		let last = machine.rejections.back().expect("frank rejected");
+		assert_eq!(
+			(last.intent_id, last.reason),
+			(frank.id, RejectionReason::DependencyCycle),
+		);
+	}
+
+	#[test]
+	fn order_book_shows_each_intents_best_quote() {
+		let mut machine = AuctionStateMachine::default();
+		let alice = intent("alice", 1, 1_000, 900);
+		let bob = intent("bob", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		submit(&mut machine, &bob);
+		machine.apply(AuctionCommand::OpenRound { deadline_ms: T0 });
+		for quote in [
+			quote("a", &alice, 950),
+			quote("b", &alice, 960),
+			quote("c", &bob, 920),
+		] {
+			machine.apply(AuctionCommand::SubmitQuote { quote, now_ms: T0 });
+		}
+
+		let AuctionQueryResult::Book(book) =
+			machine.query(AuctionQuery::OrderBook)
+		else {
+			panic!("unexpected result");
+		};
+		assert_eq!(book.round, 0);
+		let entries = book
+			.entries
+			.iter()
+			.map(|entry| {
+				let best =
+					entry.best_quote.as_ref().map(|q| q.quote_hash.clone());
+				(entry.intent.id, best, entry.competition_level)
+			})
+			.collect::<Vec<_>>();
+		// By intent id, so Bob's comes first.
+		assert_eq!(
+			entries,
+			[
+				(bob.id, Some(format!("c-{}", bob.id)), 1),
+				(alice.id, Some(format!("b-{}", alice.id)), 2),
+			],
+		);
+	}
+
+	#[test]
+	fn reputation_rises_with_wins_and_decays_while_idle() {
+		let mut machine = AuctionStateMachine::default();
+		let reputation = |machine: &AuctionStateMachine| match machine
+			.query(AuctionQuery::Reputation("solver".into()))
+		{
+			AuctionQueryResult::Score(score) => score,
+			other => panic!("unexpected result {other:?}"),
+		};
+		assert_eq!(reputation(&machine), 0);
+
+		// Under the default params each win adds 1000 after a 0.9 decay.
+		let mut scores = Vec::new();
+		for (round, signer) in (0..).zip(["alice", "bob"]) {
+			let intent = intent(signer, 1, 1_000, 900);
+			submit(&mut machine, &intent);
+			run_round(
+				&mut machine,
+				[quote("solver", &intent, 950)],
+				T0 + round,
+			);
+			scores.push(reputation(&machine));
+		}
+		assert_eq!(scores, [1_000, 1_900]);
+
+		// Idle rounds shrink it every time, down to nothing.
+		let mut last = 1_900;
+		for round in 2..100 {
+			run_round(&mut machine, [], T0 + round);
+			let score = reputation(&machine);
+			assert!(score < last || score == 0, "round {round}: {score}");
+			last = score;
+		}
+		assert_eq!(last, 0);
+	}
+
+	#[test]
+	fn round_boundaries_bracket_its_outcomes_once() {
+		let mut machine = AuctionStateMachine::new(config());
+		let events = |machine: &AuctionStateMachine| {
+			outcomes(machine)
+				.into_iter()
+				.map(|(round, outcome)| match outcome {
+					Outcome::RoundOpened { deadline_ms, .. } => {
+						(round, "opened", deadline_ms)
+					}
+					Outcome::Settled(settlement) => {
+						(round, "settled", settlement.settlements.len() as u64)
+					}
+					Outcome::Expired { .. } => (round, "expired", 0),
+					Outcome::RoundClosed { settled_count, .. } => {
+						(round, "closed", settled_count as u64)
+					}
+				})
+				.collect::<Vec<_>>()
+		};
+		let alice = intent("alice", 1, 1_000, 900);
+		submit(&mut machine, &alice);
+		machine.apply(AuctionCommand::OpenRound {
+			deadline_ms: T0 + 10,
+		});
+		machine.apply(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &alice, 950),
+			now_ms: T0,
+		});
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 + 10 });
+		// Opening an open round again announces nothing new.
+		for _ in 0..2 {
+			machine.apply(AuctionCommand::OpenRound {
+				deadline_ms: T0 + 20,
+			});
+		}
+		assert_eq!(
+			events(&machine),
+			[
+				(0, "opened", T0 + 10),
+				(0, "settled", 1),
+				(0, "closed", 1),
+				(1, "opened", T0 + 20),
+			],
+		);
+
+		// Once marked, nothing is published again.
+		for mark in [
+			AuctionCommand::mark_open_published,
+			AuctionCommand::mark_published,
+		] {
+			let nonce = next_nonce(&machine);
+			machine.apply(mark(&operator(), nonce, 0));
+		}
+		let nonce = next_nonce(&machine);
+		machine.apply(AuctionCommand::mark_open_published(
+			&operator(),
+			nonce,
+			1,
+		));
+		assert!(events(&machine).is_empty());
+
+		machine.apply(AuctionCommand::ClearRound { now_ms: T0 + 20 });
+		assert_eq!(events(&machine), [(1, "closed", 0)]);
+	}
+
+	#[test]
+	fn active_pairs_count_pending_intents_per_direction() {
+		let mut machine = AuctionStateMachine::default();
+		// Dave's intent settles, so only the others stay active.
+		let dave = intent("dave", 1, 1_000, 900);
+		submit(&mut machine, &dave);
+		run_round(&mut machine, [quote("solver", &dave, 950)], T0);
+
+		let mut carol = intent("carol", 1, 1_000, 900);
+		carol.action = IntentAction::TokenDiff {
+			diff: TokenDiff::from([(wnear(), -1_000), (usdc(), 900)]),
+		};
+		carol.sign(&key("carol"));
+		for intent in [
+			intent("alice", 1, 1_000, 900),
+			intent("bob", 1, 500, 450),
+			carol,
+		] {
+			submit(&mut machine, &intent);
+		}
+
+		let AuctionQueryResult::Pairs(pairs) =
+			machine.query(AuctionQuery::ActivePairs)
+		else {
+			panic!("unexpected result");
+		};
+		assert_eq!(
+			pairs,
+			BTreeMap::from([((usdc(), wnear()), 2), ((wnear(), usdc()), 1)]),
+		);
+	}
+
+	#[test]
+	fn fair_ordering_ignores_the_ids_intents_pick() {
+		// The signers settled in round 0, in order, when Alice, Bob and
+		// Carol pick `ids`.
+		let settled_order = |intent_ordering, ids: [u64; 3]| {
+			let mut machine = AuctionStateMachine::new(AuctionConfig {
+				intent_ordering,
+				..AuctionConfig::default()
+			});
+			let mut quotes = Vec::new();
+			for (signer, id) in ["alice", "bob", "carol"].into_iter().zip(ids) {
+				let mut intent = intent(signer, 1, 1_000, 900);
+				intent.id = IntentId::from(id);
+				intent.sign(&key(signer));
+				submit(&mut machine, &intent);
+				quotes.push(quote("solver", &intent, 950));
 			}
+			run_round(&mut machine, quotes, T0);
+			let settlement = round(&machine, 0).expect("round 0 settled");
+			settlement
+				.settlements
+				.iter()
+				.map(|settled| settled.signer_id.clone())
+				.collect::<Vec<_>>()
+		};
+
+		// By id, whoever picks the lowest goes first.
+		let by_id = IntentOrdering::ById;
+		assert_eq!(settled_order(by_id, [1, 2, 3]), ["alice", "bob", "carol"]);
+		assert_eq!(settled_order(by_id, [3, 1, 2]), ["bob", "carol", "alice"]);
+
+		let fair = settled_order(IntentOrdering::FairOrdering, [1, 2, 3]);
+		for ids in [[3, 1, 2], [2, 3, 1], [3, 2, 1], [1, u64::MAX, 0]] {
+			let order = settled_order(IntentOrdering::FairOrdering, ids);
+			assert_eq!(order, fair, "{ids:?}");
 		}
 	}
 }
@@ -0,0 +1,77 @@
+//! Clearing rounds on a timer instead of by hand.
+
+use {
+	crate::{
+		auction::{
+			AuctionCommand, AuctionQuery, AuctionQueryResult,
+			AuctionStateMachine,
+		},
+		now_ms,
+	},
+	mosaik::{Consistency, PeerId, groups::Group},
+	std::{sync::Arc, time::Duration},
+	tokio::{
+		task::JoinHandle,
+		time::{Instant, MissedTickBehavior, interval_at},
+	},
+};
+
+/// Spawn a task that commits `ClearRound` every `interval_ms` while
+/// `local_id` leads `group`, then `RevealRound` for every round sealed
+/// under `reveal_delay_ms` whose delay has passed.
+///
+/// Every auctioneer can run one: followers skip their ticks, so only the
+/// current leader clears and a deposed leader stops on its next tick.
+/// Each clear is waited on until committed before the next tick, so a
+/// slow log never queues up clears. Failed clears (e.g. mid leader
+/// change) are logged and retried on the next tick.
+pub fn spawn_auto_clearer(
+	group: Arc<Group<AuctionStateMachine>>,
+	local_id: PeerId,
+	interval_ms: u64,
+) -> JoinHandle<()> {
+	tokio::spawn(async move {
+		let interval = Duration::from_millis(interval_ms);
+		let mut ticker = interval_at(Instant::now() + interval, interval);
+		ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+		loop {
+			ticker.tick().await;
+			if group.leader() != Some(local_id) {
+				continue;
+			}
+			let now_ms = now_ms();
+			let clear = AuctionCommand::ClearRound { now_ms };
+			match group.execute(clear.into()).await {
+				Ok(index) => {
+					group.when().committed().reaches(index).await;
+					tracing::debug!("auto-cleared round at {now_ms}");
+				}
+				Err(err) => tracing::warn!("auto-clear failed: {err}"),
+			}
+			if let Err(err) = reveal_due(&group, now_ms).await {
+				tracing::warn!("auto-reveal failed: {err}");
+			}
+		}
+	})
+}
+
+/// Commit `RevealRound` for every sealed round of `group` due by
+/// `now_ms`, waiting for each to commit.
+pub async fn reveal_due(
+	group: &Group<AuctionStateMachine>,
+	now_ms: u64,
+) -> anyhow::Result<()> {
+	let due = AuctionQuery::DueReveals { now_ms };
+	let AuctionQueryResult::RoundNumbers(rounds) =
+		group.query(due, Consistency::Strong).await?
+	else {
+		return Ok(());
+	};
+	for round in rounds {
+		let reveal = AuctionCommand::RevealRound { round, now_ms };
+		let index = group.execute(reveal.into()).await?;
+		group.when().committed().reaches(index).await;
+		tracing::debug!("revealed round {round}");
+	}
+	Ok(())
+}
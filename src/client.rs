@@ -0,0 +1,149 @@
+//! A failover-aware client for the auctioneer group.
+//!
+//! Reads default to `Consistency::Weak`: they are answered by the local
+//! replica, which may lag the leader and not have applied the latest
+//! `ClearRound` yet. That is fine for dashboards. Anything acting on
+//! settlements, like a relayer that must neither miss nor double-relay
+//! one, should read them with [`query_settled`] and
+//! `Consistency::Strong`.
+
+use {
+	crate::{
+		auction::{
+			AuctionCommand, AuctionQuery, AuctionQueryResult,
+			AuctionStateMachine,
+		},
+		types::Settlement,
+	},
+	anyhow::anyhow,
+	mosaik::{Consistency, groups::Group},
+	std::sync::{
+		Arc, Mutex,
+		atomic::{AtomicUsize, Ordering},
+	},
+};
+
+/// Talks to the auctioneer group through any of its members.
+///
+/// Requests go to the handle that last succeeded and fall over to the
+/// next one when it errors or has lost track of the leader, so a dead
+/// auctioneer doesn't stall the auction. Commands already committed to
+/// the log survive the failover, since every replica applies them.
+///
+/// A command whose reply is lost may be retried after it committed.
+/// Commands that aren't idempotent, like `ClearRound`, are sent wrapped
+/// in an `AuctionCommand::Request` with a fresh id, so the state machine
+/// applies a retried one only once.
+pub struct AuctionClient {
+	groups: Vec<Arc<Group<AuctionStateMachine>>>,
+
+	/// Index into `groups` of the handle that last succeeded.
+	preferred: AtomicUsize,
+
+	/// Last leader seen, for logging leader changes.
+	leader: Mutex<Option<String>>,
+}
+
+impl AuctionClient {
+	pub fn new(
+		groups: impl IntoIterator<Item = Arc<Group<AuctionStateMachine>>>,
+	) -> Self {
+		Self {
+			groups: groups.into_iter().collect(),
+			preferred: AtomicUsize::new(0),
+			leader: Mutex::new(None),
+		}
+	}
+
+	/// Execute a command and wait until it is committed.
+	pub async fn execute(&self, command: AuctionCommand) -> anyhow::Result<()> {
+		let command = if command.is_idempotent() {
+			command
+		} else {
+			AuctionCommand::Request {
+				request_id: rand::random(),
+				command: Box::new(command),
+			}
+		};
+		let mut last_err = None;
+		for (i, group) in self.candidates() {
+			match group.execute(command.clone().into()).await {
+				Ok(index) => {
+					group.when().committed().reaches(index).await;
+					self.preferred.store(i, Ordering::Relaxed);
+					return Ok(());
+				}
+				Err(err) => {
+					tracing::warn!("auctioneer {i} failed to execute: {err}");
+					last_err = Some(err.into());
+				}
+			}
+		}
+		Err(last_err.unwrap_or_else(|| anyhow!("no auctioneer has a leader")))
+	}
+
+	/// Run a weakly consistent query against the first auctioneer that
+	/// answers.
+	pub async fn query(
+		&self,
+		query: AuctionQuery,
+	) -> anyhow::Result<AuctionQueryResult> {
+		let mut last_err = None;
+		for (i, group) in self.candidates() {
+			match group.query(query.clone(), Consistency::Weak).await {
+				Ok(result) => {
+					self.preferred.store(i, Ordering::Relaxed);
+					return Ok(result);
+				}
+				Err(err) => {
+					tracing::warn!("auctioneer {i} failed to query: {err}");
+					last_err = Some(err.into());
+				}
+			}
+		}
+		Err(last_err.unwrap_or_else(|| anyhow!("no auctioneer has a leader")))
+	}
+
+	/// Handles to try in order, starting from the preferred one. Handles
+	/// that currently see no leader are skipped.
+	fn candidates(
+		&self,
+	) -> impl Iterator<Item = (usize, &Group<AuctionStateMachine>)> {
+		let start = self.preferred.load(Ordering::Relaxed);
+		(0..self.groups.len())
+			.map(move |offset| (start + offset) % self.groups.len())
+			.filter_map(|i| {
+				let leader = self.groups[i].leader()?.to_string();
+				self.note_leader(leader);
+				Some((i, &*self.groups[i]))
+			})
+	}
+
+	fn note_leader(&self, leader: String) {
+		let mut known = self.leader.lock().expect("leader lock poisoned");
+		if known.as_ref().is_some_and(|known| *known != leader) {
+			tracing::info!("auctioneer leader changed to {leader}");
+		}
+		*known = Some(leader);
+	}
+}
+
+/// Fetch the settlement produced for `round`, if any. A round sealed
+/// under `reveal_delay_ms` reads as `None` until it is revealed.
+///
+/// With `Consistency::Weak`, `None` may only mean the replica hasn't
+/// applied that round's `ClearRound` yet. With `Consistency::Strong` the
+/// read goes through the leader and reflects every command committed
+/// before the call, so once an `execute(ClearRound)` has returned its
+/// settlement is always visible.
+pub async fn query_settled(
+	group: &Group<AuctionStateMachine>,
+	round: u64,
+	consistency: Consistency,
+) -> anyhow::Result<Option<Settlement>> {
+	match group.query(AuctionQuery::RoundResult(round), consistency).await? {
+		AuctionQueryResult::Round(settlement) => Ok(settlement),
+		AuctionQueryResult::Sealed(_) => Ok(None),
+		other => Err(anyhow!("unexpected result for RoundResult: {other:?}")),
+	}
+}
@@ -0,0 +1,375 @@
+//! Canonical NEAR Defuse JSON encoding for intents.
+//!
+//! The Verifier contract expects an intent message of the form:
+//!
+//! ```json
+//! {
+//!   "signer_id": "alice.near",
+//!   "verifying_contract": "intents.near",
+//!   "deadline_ms": "1700000000000",
+//!   "nonce": "0000000000000000000000000000000000000000000000000000000000000001",
+//!   "intents": [
+//!     {
+//!       "intent": "token_diff",
+//!       "diff": { "nep141:usdc.near": "-1000", "nep141:wrap.near": "950" }
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! All amounts are decimal strings rather than JSON numbers so i128/u128
+//! values survive JSON parsers that only support f64 precision. Messages
+//! come from untrusted clients, so amounts are parsed strictly: ASCII
+//! digits with an optional leading `-` for signed amounts, and an error
+//! rather than a wrap or panic when a value is out of its type's range.
+//!
+//! The signature is carried in the outer signed envelope, not in the
+//! message, so `from_defuse_json` returns an unsigned intent.
+
+use {
+	crate::types::{AssetId, Intent, IntentAction},
+	anyhow::{Context, anyhow, bail},
+	serde_json::{Map, Value, json},
+	std::{collections::BTreeMap, str::FromStr},
+};
+
+/// `min_quote_deadline_ms` is a relay parameter, not part of the signed
+/// message; intents decoded from JSON get the protocol default.
+const DEFAULT_MIN_QUOTE_DEADLINE_MS: u64 = 60_000;
+
+impl Intent {
+	/// Encode this intent as a Defuse Verifier JSON message.
+	pub fn to_defuse_json(&self) -> Value {
+		let intent = match &self.action {
+			IntentAction::TokenDiff { diff } => json!({
+				"intent": "token_diff",
+				"diff": amounts_to_json(diff),
+			}),
+			IntentAction::Transfer {
+				receiver_id,
+				tokens,
+			} => json!({
+				"intent": "transfer",
+				"receiver_id": receiver_id,
+				"tokens": amounts_to_json(tokens),
+			}),
+			IntentAction::FtWithdraw {
+				token,
+				receiver_id,
+				amount,
+			} => json!({
+				"intent": "ft_withdraw",
+				"token": token,
+				"receiver_id": receiver_id,
+				"amount": amount.to_string(),
+			}),
+			IntentAction::NativeWithdraw {
+				receiver_id,
+				amount,
+			} => json!({
+				"intent": "native_withdraw",
+				"receiver_id": receiver_id,
+				"amount": amount.to_string(),
+			}),
+		};
+
+		json!({
+			"signer_id": self.signer_id,
+			"verifying_contract": self.verifying_contract,
+			"deadline_ms": self.deadline_ms.to_string(),
+			"nonce": self.id.to_string(),
+			"intents": [intent],
+		})
+	}
+
+	/// Decode an intent from a Defuse Verifier JSON message.
+	///
+	/// The message must carry exactly one entry in `intents`.
+	pub fn from_defuse_json(v: &Value) -> anyhow::Result<Intent> {
+		let intents = v
+			.get("intents")
+			.and_then(Value::as_array)
+			.ok_or_else(|| anyhow!("missing `intents` array"))?;
+		let [intent] = intents.as_slice() else {
+			bail!("expected exactly one intent, got {}", intents.len());
+		};
+
+		let action = match str_field(intent, "intent")? {
+			"token_diff" => IntentAction::TokenDiff {
+				diff: amounts_from_json(intent, "diff")?,
+			},
+			"transfer" => IntentAction::Transfer {
+				receiver_id: str_field(intent, "receiver_id")?.into(),
+				tokens: amounts_from_json(intent, "tokens")?,
+			},
+			"ft_withdraw" => IntentAction::FtWithdraw {
+				token: parsed_field(intent, "token")?,
+				receiver_id: str_field(intent, "receiver_id")?.into(),
+				amount: amount_field(intent, "amount")?,
+			},
+			"native_withdraw" => IntentAction::NativeWithdraw {
+				receiver_id: str_field(intent, "receiver_id")?.into(),
+				amount: amount_field(intent, "amount")?,
+			},
+			other => bail!("unsupported intent type `{other}`"),
+		};
+
+		Ok(Intent {
+			id: parsed_field(v, "nonce")?,
+			signer_id: str_field(v, "signer_id")?.into(),
+			sequence: 0,
+			action,
+			verifying_contract: str_field(v, "verifying_contract")?.into(),
+			deadline_ms: parsed_field(v, "deadline_ms")?,
+			min_quote_deadline_ms: DEFAULT_MIN_QUOTE_DEADLINE_MS,
+			priority_fee: 0,
+			group_id: None,
+			limit_price: None,
+			depends_on: None,
+			signature: Vec::new(),
+			public_key: Vec::new(),
+		})
+	}
+}
+
+/// Render an asset -> amount map with decimal-string amounts.
+fn amounts_to_json<T: ToString>(amounts: &BTreeMap<AssetId, T>) -> Value {
+	Value::Object(
+		amounts
+			.iter()
+			.map(|(asset, amount)| (asset.to_string(), amount.to_string().into()))
+			.collect::<Map<_, _>>(),
+	)
+}
+
+/// Parse an asset -> decimal-string amount map stored under `key`.
+fn amounts_from_json<T: Amount>(
+	v: &Value,
+	key: &str,
+) -> anyhow::Result<BTreeMap<AssetId, T>> {
+	let map = v
+		.get(key)
+		.and_then(Value::as_object)
+		.ok_or_else(|| anyhow!("missing `{key}` object"))?;
+	map.iter()
+		.map(|(asset, amount)| -> anyhow::Result<(AssetId, T)> {
+			let asset_id = asset
+				.parse()
+				.with_context(|| format!("invalid asset id `{asset}`"))?;
+			let Some(amount) = amount.as_str() else {
+				bail!("amount for `{asset}` is not a string");
+			};
+			let amount = parse_amount(amount)
+				.with_context(|| format!("invalid amount for `{asset}`"))?;
+			Ok((asset_id, amount))
+		})
+		.collect()
+}
+
+/// An integer type amounts are decoded into.
+trait Amount: Sized {
+	const NAME: &'static str;
+
+	/// The value with sign `negative` and absolute value `magnitude`, or
+	/// `None` if it is out of range.
+	fn from_parts(negative: bool, magnitude: u128) -> Option<Self>;
+}
+
+impl Amount for i128 {
+	const NAME: &'static str = "i128";
+
+	fn from_parts(negative: bool, magnitude: u128) -> Option<Self> {
+		if negative {
+			0i128.checked_sub_unsigned(magnitude)
+		} else {
+			i128::try_from(magnitude).ok()
+		}
+	}
+}
+
+impl Amount for u64 {
+	const NAME: &'static str = "u64";
+
+	fn from_parts(negative: bool, magnitude: u128) -> Option<Self> {
+		if negative {
+			None
+		} else {
+			u64::try_from(magnitude).ok()
+		}
+	}
+}
+
+/// Parse a decimal amount: ASCII digits, with a leading `-` if `T` is
+/// signed. No `+`, whitespace, separators or exponent.
+fn parse_amount<T: Amount>(s: &str) -> anyhow::Result<T> {
+	let (negative, digits) = match s.strip_prefix('-') {
+		Some(digits) => (true, digits),
+		None => (false, s),
+	};
+	if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+		bail!("`{s}` is not a decimal integer");
+	}
+	let overflow = || anyhow!("`{s}` is out of range for {}", T::NAME);
+	let magnitude = digits.bytes().try_fold(0u128, |acc, digit| {
+		acc.checked_mul(10)?.checked_add(u128::from(digit - b'0'))
+	});
+	let magnitude = magnitude.ok_or_else(overflow)?;
+	if negative && T::from_parts(true, 0).is_none() {
+		bail!("`{s}` is negative, which {} can't be", T::NAME);
+	}
+	T::from_parts(negative, magnitude).ok_or_else(overflow)
+}
+
+/// Parse an amount string field with [`parse_amount`].
+fn amount_field<T: Amount>(v: &Value, key: &str) -> anyhow::Result<T> {
+	parse_amount(str_field(v, key)?)
+		.with_context(|| format!("invalid value for `{key}`"))
+}
+
+fn str_field<'a>(v: &'a Value, key: &str) -> anyhow::Result<&'a str> {
+	v.get(key)
+		.and_then(Value::as_str)
+		.ok_or_else(|| anyhow!("missing string field `{key}`"))
+}
+
+/// Parse a string field via `FromStr` (nonces, deadlines, asset ids).
+fn parsed_field<T>(v: &Value, key: &str) -> anyhow::Result<T>
+where
+	T: FromStr,
+	T::Err: std::error::Error + Send + Sync + 'static,
+{
+	str_field(v, key)?
+		.parse()
+		.with_context(|| format!("invalid value for `{key}`"))
+}
+
+#[cfg(test)]
+mod tests {
+	use {
+		super::*,
+		crate::types::{IntentId, TokenDiff},
+	};
+
+	/// A Verifier message as a client sends it, as in the module docs.
+	const FIXTURE: &str = r#"{
+		"signer_id": "alice.near",
+		"verifying_contract": "intents.near",
+		"deadline_ms": "1700000000000",
+		"nonce": "0000000000000000000000000000000000000000000000000000000000000001",
+		"intents": [
+			{
+				"intent": "token_diff",
+				"diff": {
+					"nep141:usdc.near": "-1000",
+					"nep141:wrap.near": "950"
+				}
+			}
+		]
+	}"#;
+
+	/// Encode `intent`, print the JSON as text and decode it again.
+	fn round_trip(intent: &Intent) -> anyhow::Result<Intent> {
+		let text = serde_json::to_string(&intent.to_defuse_json())?;
+		Intent::from_defuse_json(&serde_json::from_str(&text)?)
+	}
+
+	#[test]
+	fn fixture_round_trips_through_an_intent() -> anyhow::Result<()> {
+		let fixture = serde_json::from_str::<Value>(FIXTURE)?;
+		let intent = Intent::from_defuse_json(&fixture)?;
+		assert_eq!(intent.id, IntentId::from(1));
+		assert_eq!(intent.signer_id, "alice.near");
+		assert_eq!(intent.verifying_contract, "intents.near");
+		assert_eq!(intent.deadline_ms, 1_700_000_000_000);
+		let diff = TokenDiff::from([
+			("nep141:usdc.near".parse()?, -1_000),
+			("nep141:wrap.near".parse()?, 950),
+		]);
+		assert_eq!(intent.token_diff(), Some(&diff));
+
+		assert_eq!(intent.to_defuse_json(), fixture);
+		let decoded = round_trip(&intent)?;
+		assert_eq!(decoded.to_defuse_json(), fixture);
+		assert_eq!(decoded.id, intent.id);
+		assert_eq!(decoded.token_diff(), Some(&diff));
+		Ok(())
+	}
+
+	#[test]
+	fn every_action_and_extreme_amount_round_trips() -> anyhow::Result<()> {
+		let fixture = serde_json::from_str::<Value>(FIXTURE)?;
+		let intent = Intent::from_defuse_json(&fixture)?;
+		let usdc: AssetId = "nep141:usdc.near".parse()?;
+		let actions = [
+			IntentAction::TokenDiff {
+				diff: TokenDiff::from([(usdc.clone(), i128::MIN)]),
+			},
+			IntentAction::Transfer {
+				receiver_id: "bob.near".into(),
+				tokens: BTreeMap::from([(usdc.clone(), u64::MAX)]),
+			},
+			IntentAction::FtWithdraw {
+				token: usdc,
+				receiver_id: "bob.near".into(),
+				amount: u64::MAX,
+			},
+			IntentAction::NativeWithdraw {
+				receiver_id: "bob.near".into(),
+				amount: 1,
+			},
+		];
+		for action in actions {
+			let intent = Intent {
+				action,
+				..intent.clone()
+			};
+			let json = intent.to_defuse_json();
+			assert_eq!(round_trip(&intent)?.to_defuse_json(), json);
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn numeric_and_out_of_range_amounts_are_rejected() -> anyhow::Result<()> {
+		let mut fixture = serde_json::from_str::<Value>(FIXTURE)?;
+		let diff = &mut fixture["intents"][0]["diff"];
+		diff["nep141:usdc.near"] = json!(-1000);
+		assert!(Intent::from_defuse_json(&fixture).is_err());
+		let diff = &mut fixture["intents"][0]["diff"];
+		diff["nep141:usdc.near"] =
+			json!("-170141183460469231731687303715884105729");
+		assert!(Intent::from_defuse_json(&fixture).is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn amounts_parse_exactly_to_their_bounds() -> anyhow::Result<()> {
+		assert_eq!(parse_amount::<i128>("-1000")?, -1_000);
+		assert_eq!(parse_amount::<i128>("0")?, 0);
+		assert_eq!(
+			parse_amount::<i128>("170141183460469231731687303715884105727")?,
+			i128::MAX,
+		);
+		assert_eq!(
+			parse_amount::<i128>("-170141183460469231731687303715884105728")?,
+			i128::MIN,
+		);
+		assert_eq!(parse_amount::<u64>("18446744073709551615")?, u64::MAX);
+
+		for overflowing in [
+			"170141183460469231731687303715884105728",
+			"-170141183460469231731687303715884105729",
+			"999999999999999999999999999999999999999999",
+		] {
+			let error = parse_amount::<i128>(overflowing).unwrap_err();
+			assert!(error.to_string().contains("out of range"), "{error}");
+		}
+		assert!(parse_amount::<u64>("18446744073709551616").is_err());
+		assert!(parse_amount::<u64>("-1").is_err());
+		for malformed in ["", "-", "+1", " 1", "1_000", "1e3", "--1", "0x10"] {
+			let error = parse_amount::<i128>(malformed).unwrap_err();
+			assert!(error.to_string().contains("not a decimal"), "{error}");
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,218 @@
+//! An append-only journal of finalized settlements, kept alongside the
+//! Raft log for audits that shouldn't depend on it.
+//!
+//! The state machine hands every round it clears to its
+//! [`SettlementSink`] while applying the log, on the group's leader only
+//! once [`spawn_leader_watch`] tracks leadership. Each replica has its
+//! own sink, so attach a [`FileSink`] on the nodes that should keep a
+//! journal and leave the rest with the default [`NullSink`].
+
+use {
+	crate::{auction::AuctionStateMachine, types::Settlement},
+	mosaik::{PeerId, groups::Group},
+	serde_json::json,
+	std::{
+		fmt,
+		fs::{File, OpenOptions},
+		io::{self, Read, Write},
+		path::{Path, PathBuf},
+		sync::{
+			Arc, Mutex, PoisonError,
+			atomic::{AtomicBool, Ordering},
+		},
+		time::Duration,
+	},
+	tokio::task::JoinHandle,
+};
+
+/// How often [`spawn_leader_watch`] checks the group's leader.
+const LEADER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Receives each settlement the state machine finalizes.
+///
+/// Called synchronously from `apply` once a round's clear completes, so
+/// implementations should be quick and must not panic. Replaying the
+/// log, e.g. after a restart, hands the same rounds over again.
+pub trait SettlementSink: fmt::Debug + Send + Sync {
+	/// Record `settlement`.
+	fn record(&self, settlement: &Settlement);
+}
+
+/// Discards every settlement. The default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullSink;
+
+impl SettlementSink for NullSink {
+	fn record(&self, _: &Settlement) {}
+}
+
+/// Appends each settlement to a file as a JSON line with its round and
+/// hex commitment.
+///
+/// Rounds at or below the last one already in the file are skipped, so a
+/// replayed log journals every round exactly once. Write errors are
+/// logged rather than propagated, since they must not stall consensus.
+/// A last line torn by a crash mid-write is dropped on open, so its round
+/// is journaled again.
+#[derive(Debug)]
+pub struct FileSink {
+	path: PathBuf,
+	state: Mutex<FileState>,
+}
+
+#[derive(Debug)]
+struct FileState {
+	file: File,
+
+	/// Highest round in the file.
+	last_round: Option<u64>,
+}
+
+impl FileSink {
+	/// Open or create the journal at `path`, resuming after its last
+	/// complete round.
+	pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		let mut file = OpenOptions::new()
+			.create(true)
+			.read(true)
+			.append(true)
+			.open(&path)?;
+		let mut contents = Vec::new();
+		file.read_to_end(&mut contents)?;
+
+		// Every entry ends in a newline, so anything after the last one
+		// is a torn write.
+		let complete = contents
+			.iter()
+			.rposition(|&byte| byte == b'\n')
+			.map_or(0, |end| end + 1);
+		if complete < contents.len() {
+			tracing::warn!(
+				path = %path.display(),
+				"dropping a torn last journal line",
+			);
+			file.set_len(complete as u64)?;
+		}
+
+		let mut last_round = None;
+		for line in contents[..complete].split(|&byte| byte == b'\n') {
+			if line.is_empty() {
+				continue;
+			}
+			let entry = serde_json::from_slice::<serde_json::Value>(line)?;
+			let round = entry["round"].as_u64();
+			last_round = last_round.max(round);
+		}
+		Ok(Self {
+			path,
+			state: Mutex::new(FileState { file, last_round }),
+		})
+	}
+}
+
+impl SettlementSink for FileSink {
+	fn record(&self, settlement: &Settlement) {
+		// Nothing panics while holding the lock, and `last_round` only
+		// moves after a complete write, so a poisoned state is still
+		// sound.
+		let mut state =
+			self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		if state
+			.last_round
+			.is_some_and(|last| settlement.round <= last)
+		{
+			return;
+		}
+		let commitment = settlement
+			.commitment
+			.iter()
+			.map(|byte| format!("{byte:02x}"))
+			.collect::<String>();
+		let entry = json!({
+			"round": settlement.round,
+			"commitment": commitment,
+			"settlement": settlement,
+		});
+		let written = writeln!(state.file, "{entry}")
+			.and_then(|()| state.file.sync_data());
+		match written {
+			Ok(()) => state.last_round = Some(settlement.round),
+			Err(err) => tracing::error!(
+				path = %self.path.display(),
+				round = settlement.round,
+				"failed to journal settlement: {err}",
+			),
+		}
+	}
+}
+
+/// Spawn a task keeping `leading` set exactly while `local_id` leads
+/// `group`, for [`AuctionStateMachine::with_leadership`]. Leadership is
+/// polled, so a new leader may miss journaling the rounds of its first
+/// moments.
+pub fn spawn_leader_watch(
+	group: Arc<Group<AuctionStateMachine>>,
+	local_id: PeerId,
+	leading: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+	tokio::spawn(async move {
+		let mut ticker = tokio::time::interval(LEADER_POLL_INTERVAL);
+		loop {
+			ticker.tick().await;
+			let leads = group.leader() == Some(local_id);
+			leading.store(leads, Ordering::Relaxed);
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, std::fs};
+
+	fn settlement(round: u64) -> Settlement {
+		Settlement {
+			round,
+			settlements: Vec::new(),
+			aggregate_flow: Default::default(),
+			surplus: Default::default(),
+			volume: Default::default(),
+			failed: false,
+			fees: Default::default(),
+			estimated_transfers: 0,
+			estimated_gas: 0,
+			partial_fills: Vec::new(),
+			transfer_fees: Default::default(),
+			commitment: [0; 32],
+		}
+	}
+
+	#[test]
+	fn torn_last_line_is_dropped_and_rewritten() -> io::Result<()> {
+		let path = std::env::temp_dir()
+			.join(format!("near-intents-journal-{}", std::process::id()));
+		let sink = FileSink::open(&path)?;
+		sink.record(&settlement(0));
+		sink.record(&settlement(1));
+		drop(sink);
+
+		// A crash mid-write leaves round 1 without its newline.
+		let journal = fs::read(&path)?;
+		fs::write(&path, &journal[..journal.len() - 10])?;
+
+		let sink = FileSink::open(&path)?;
+		sink.record(&settlement(0));
+		sink.record(&settlement(1));
+		let journal = fs::read_to_string(&path)?;
+		fs::remove_file(&path)?;
+		let rounds = journal
+			.lines()
+			.map(|line| {
+				let entry = serde_json::from_str::<serde_json::Value>(line)?;
+				Ok(entry["round"].as_u64())
+			})
+			.collect::<io::Result<Vec<_>>>()?;
+		assert_eq!(rounds, [Some(0), Some(1)]);
+		Ok(())
+	}
+}
@@ -0,0 +1,24 @@
+//! NEAR Intents protocol types and the Raft-replicated auction state
+//! machine. The demo binary in `main.rs` wires these into a Mosaik
+//! topology of users, solvers and auctioneers.
+
+pub mod auction;
+pub mod clearer;
+pub mod client;
+pub mod defuse;
+pub mod journal;
+pub mod relay;
+pub mod shard;
+pub mod sim;
+pub mod solver;
+pub mod types;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current unix time in milliseconds, or zero if the clock is set
+/// before the epoch.
+pub fn now_ms() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
@@ -2,45 +2,113 @@
 //!
 //! Demonstrates the NEAR Intents / Defuse protocol architecture:
 //! - token_diff intents (declarative balance changes)
-//! - RFQ-based solver competition with quote responses
+//! - RFQ-based solver competition: the auctioneer broadcasts a
+//!   `QuoteRequest` per intent and solvers answer with quotes
 //! - Atomic settlement via Raft-replicated auction state machine
 //! - Settlement stream output for on-chain relay
 //!
 //! Topology:
-//!   User (Intent stream) -> Solvers (consume intents, produce quotes)
+//!   User (Intent stream) -> Auctioneer (QuoteRequest stream)
+//!   -> Solvers (consume RFQs, produce quotes)
 //!   -> Auctioneer Group (Raft RSM, consumes quotes) -> Settlement stream
+//!
+//! Without a subcommand the whole topology runs in this process. The
+//! `auctioneer`, `solver`, `user` and `relayer` subcommands run a single
+//! role each, see `roles.rs`.
 
 #![allow(clippy::too_many_lines)]
 
-mod auction;
-mod types;
+mod roles;
 
 use {
-	auction::{
-		AuctionCommand, AuctionQuery, AuctionQueryResult, AuctionStateMachine,
-	},
+	anyhow::Context,
+	clap::{Parser, Subcommand},
+	ed25519_dalek::SigningKey,
 	futures::{SinkExt, StreamExt},
 	mosaik::{discovery, primitives::Tag, *},
-	std::collections::BTreeMap,
-	types::{Intent, IntentAction, Quote, Settlement},
+	near_intents::{
+		auction::{
+			AuctionCommand, AuctionConfig, AuctionQuery, AuctionQueryResult,
+			AuctionStateMachine, ClearingMode, IntentOrdering, QuoteSelection,
+			ReputationParams, SurplusPolicy, SurplusRounding, TieBreak,
+		},
+		types::{
+			AssetId, AssetMetadata, AssetRegistry, Intent, IntentAction,
+			IntentId, Outcome, Quote, QuoteRequest, ToHuman,
+		},
+		client::{AuctionClient, query_settled},
+		now_ms,
+		relay::{SettlementStream, settlements, spawn_outcome_relay},
+		shard::shard_for,
+		solver::{ConstantSpread, PricingStrategy, build_quote},
+	},
+	std::{
+		collections::{BTreeMap, BTreeSet},
+		sync::Arc,
+	},
 };
 
+/// How long the auctioneer collects quotes for a round, matching the
+/// Solver Relay's 3000ms RFQ window.
+const QUOTE_WINDOW_MS: u64 = 3_000;
+
+/// How many auctioneer groups the demo shards intents across, see
+/// `shard.rs`. The last one is the overflow shard.
+const DEMO_SHARDS: usize = 3;
+
+/// NEAR Intents on Mosaik.
+#[derive(Debug, Parser)]
+struct Cli {
+	#[command(subcommand)]
+	role: Option<Role>,
+}
+
+/// A node role to run in this process.
+#[derive(Debug, Subcommand)]
+enum Role {
+	/// Run the full demo topology in one process (the default).
+	Demo,
+	/// Replicate the auction state, and coordinate rounds with
+	/// `--coordinator`.
+	Auctioneer(roles::AuctioneerArgs),
+	/// Answer RFQs with quotes.
+	Solver(roles::SolverArgs),
+	/// Submit one intent and wait for it to settle.
+	User(roles::UserArgs),
+	/// Follow published settlements for on-chain submission.
+	Relayer(roles::RelayerArgs),
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
 	tracing_subscriber::fmt()
 		.with_env_filter(
 			tracing_subscriber::EnvFilter::try_from_default_env()
-				.unwrap_or_else(|_| "info,mosaik=info".parse().unwrap()),
+				.unwrap_or_else(|_| {
+					tracing_subscriber::EnvFilter::new("info,mosaik=info")
+				}),
 		)
 		.init();
 
+	match Cli::parse().role {
+		None | Some(Role::Demo) => demo().await,
+		Some(Role::Auctioneer(args)) => roles::auctioneer(args).await,
+		Some(Role::Solver(args)) => roles::solver(args).await,
+		Some(Role::User(args)) => roles::user(args).await,
+		Some(Role::Relayer(args)) => roles::relayer(args).await,
+	}
+}
+
+/// Run every role in this process against a fresh network.
+async fn demo() -> anyhow::Result<()> {
 	let network_id = NetworkId::random();
-	let group_key = GroupKey::random();
+	let group_keys =
+		(0..DEMO_SHARDS).map(|_| GroupKey::random()).collect::<Vec<_>>();
 
 	// --- 1. Create network nodes ---
 	tracing::info!("creating network nodes...");
 
-	// 3 auctioneer nodes (Raft group for settlement consensus)
+	// 3 auctioneer nodes (a Raft group per shard for settlement consensus)
 	let auctioneer0 = Network::new(network_id).await?;
 	let auctioneer1 = Network::new(network_id).await?;
 	let auctioneer2 = Network::new(network_id).await?;
@@ -75,55 +143,74 @@ async fn main() -> anyhow::Result<()> {
 
 	tracing::info!("all nodes discovered and tagged");
 
-	// --- 2. Auctioneers join a Raft group with AuctionStateMachine ---
-	let g0 = auctioneer0
-		.groups()
-		.with_key(group_key)
-		.with_state_machine(AuctionStateMachine::new())
-		.join();
-
-	let g1 = auctioneer1
-		.groups()
-		.with_key(group_key)
-		.with_state_machine(AuctionStateMachine::new())
-		.join();
-
-	let g2 = auctioneer2
-		.groups()
-		.with_key(group_key)
-		.with_state_machine(AuctionStateMachine::new())
-		.join();
-
-	// --- 3. Wait for group online ---
-	tracing::info!("waiting for auctioneer group to come online...");
-	g0.when().online().await;
-	g1.when().online().await;
-	g2.when().online().await;
-
-	let leader = g0
-		.leader()
-		.expect("leader should be elected after online");
-	tracing::info!("auctioneer group online, leader: {leader}");
-
-	// --- 4. User produces Stream<Intent> ---
+	// --- 2. Auctioneers join a Raft group per shard ---
+	// All replicas must share the same config. Each auctioneer node is a
+	// member of every shard's group. The operator key posts solver bonds;
+	// the relay key marks outcomes published.
+	let operator_key = SigningKey::from_bytes(&rand::random());
+	let relay_key = SigningKey::from_bytes(&rand::random());
+	let config = AuctionConfig {
+		operator_keys: [&operator_key, &relay_key]
+			.map(|key| key.verifying_key().to_bytes().to_vec())
+			.into(),
+		..demo_config()
+	};
+
+	// Handles are shared with the clients and the settlement relay task.
+	let shards = group_keys
+		.iter()
+		.map(|&key| {
+			[&auctioneer0, &auctioneer1, &auctioneer2].map(|node| {
+				Arc::new(
+					node.groups()
+						.with_key(key)
+						.with_state_machine(AuctionStateMachine::new(
+							config.clone(),
+						))
+						.join(),
+				)
+			})
+		})
+		.collect::<Vec<_>>();
+
+	// --- 3. Wait for the groups online ---
+	tracing::info!("waiting for auctioneer groups to come online...");
+	for (shard, groups) in shards.iter().enumerate() {
+		for group in groups {
+			group.when().online().await;
+		}
+		let leader = groups[0]
+			.leader()
+			.context("no leader elected after the group came online")?;
+		tracing::info!("auctioneer shard {shard} online, leader: {leader}");
+	}
+
+	// Commands and queries fail over to another auctioneer if one dies.
+	let clients = shards
+		.iter()
+		.map(|groups| AuctionClient::new(groups.iter().cloned()))
+		.collect::<Vec<_>>();
+
+	// --- 4. User produces Stream<Intent>, auctioneer produces RFQs ---
 	let mut intent_producer = user_node.streams().produce::<Intent>();
+	let mut rfq_producer = auctioneer0.streams().produce::<QuoteRequest>();
 
-	// --- 5. Solvers consume intents and produce quotes ---
-	let user_tag = Tag::from("user");
-	let mut solver0_intent_consumer = solver0
+	// --- 5. Solvers consume RFQs and produce quotes ---
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut solver0_rfq_consumer = solver0
 		.streams()
-		.consumer::<Intent>()
+		.consumer::<QuoteRequest>()
 		.subscribe_if(move |peer: &discovery::PeerEntry| {
-			peer.tags().contains(&user_tag)
+			peer.tags().contains(&auctioneer_tag)
 		})
 		.build();
 
-	let user_tag = Tag::from("user");
-	let mut solver1_intent_consumer = solver1
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut solver1_rfq_consumer = solver1
 		.streams()
-		.consumer::<Intent>()
+		.consumer::<QuoteRequest>()
 		.subscribe_if(move |peer: &discovery::PeerEntry| {
-			peer.tags().contains(&user_tag)
+			peer.tags().contains(&auctioneer_tag)
 		})
 		.build();
 
@@ -145,9 +232,19 @@ async fn main() -> anyhow::Result<()> {
 		.await?;
 	tracing::info!("auctioneer re-synced with solvers and user");
 
-	solver0_intent_consumer.when().subscribed().await;
-	solver1_intent_consumer.when().subscribed().await;
-	tracing::info!("solvers subscribed to user intent stream");
+	// Solvers re-sync with the auctioneer to discover its RFQ stream.
+	solver0
+		.discovery()
+		.sync_with(auctioneer0.local().addr())
+		.await?;
+	solver1
+		.discovery()
+		.sync_with(auctioneer0.local().addr())
+		.await?;
+
+	solver0_rfq_consumer.when().subscribed().await;
+	solver1_rfq_consumer.when().subscribed().await;
+	tracing::info!("solvers subscribed to auctioneer RFQ stream");
 
 	// Auctioneer0 consumes quotes and intents
 	let solver_tag = Tag::from("solver");
@@ -172,100 +269,127 @@ async fn main() -> anyhow::Result<()> {
 	intent_consumer.when().subscribed().await;
 	tracing::info!("auctioneer subscribed to solver and user streams");
 
+	// Shard 0's outcomes are relayed to users from whichever replica
+	// leads it. The stream carries no shard id, so the other shards are
+	// followed with `SettlementStream` below instead.
+	let auctioneer_nodes = [&auctioneer0, &auctioneer1, &auctioneer2];
+	for (node, group) in auctioneer_nodes.iter().zip(&shards[0]) {
+		let producer = node.streams().produce::<Outcome>();
+		spawn_outcome_relay(
+			group.clone(),
+			node.local().id(),
+			relay_key.clone(),
+			producer,
+		);
+	}
+	for node in auctioneer_nodes {
+		user_node.discovery().sync_with(node.local().addr()).await?;
+	}
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut outcome_consumer = user_node
+		.streams()
+		.consumer::<Outcome>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&auctioneer_tag)
+		})
+		.build();
+	outcome_consumer
+		.when()
+		.subscribed()
+		.minimum_of(auctioneer_nodes.len())
+		.await;
+	tracing::info!("user subscribed to shard 0 outcome relays");
+
 	// --- 6. Spawn solver tasks ---
 	// Solver0: "ref-finance" AMM solver - provides NEAR/USDC liquidity
+	let solver0_pricing = ConstantSpread {
+		rates: demo_rates(),
+		bps: 0,
+	};
+	let solver0_key = SigningKey::from_bytes(&rand::random());
 	let solver0_task = tokio::spawn(async move {
-		let mut count = 0u32;
-		while let Some(intent) = solver0_intent_consumer.next().await {
+		// One RFQ per demo intent.
+		for count in 0..3u32 {
+			let Some(request) = solver0_rfq_consumer.next().await else {
+				break;
+			};
 			tracing::info!(
-				"solver0 received intent id={} from {}",
-				intent.id,
-				intent.signer_id
+				"solver0 received RFQ for intent {}: {} {} -> {}",
+				request.intent_id,
+				request.exact_amount_in,
+				request.asset_in,
+				request.asset_out,
 			);
 
-			// Only handle TokenDiff intents
-			let Some(user_diff) = intent.token_diff() else {
-				tracing::warn!("solver0: skipping non-TokenDiff intent {}", intent.id);
+			let Some(amount_out) = solver0_pricing.quote_for(&request) else {
+				tracing::warn!("solver0: no price for intent {}", request.intent_id);
 				continue;
 			};
-
-			// Build a counter token_diff: opposite signs from the user's diff
-			let mut solver_diff = BTreeMap::new();
-			for (asset, &amount) in user_diff {
-				solver_diff.insert(asset.clone(), -amount);
-			}
-
-			// Compute the amount_out (what user receives)
-			let amount_out: u128 = user_diff
-				.values()
-				.filter(|&&v| v > 0)
-				.map(|&v| v as u128)
-				.sum();
-
-			let quote = Quote {
-				intent_id: intent.id,
-				quote_hash: format!("ref-finance-{}-{count}", intent.id),
-				solver_id: "solver0:ref-finance".into(),
-				amount_out: amount_out + u128::from(count) * 5,
-				solver_token_diff: solver_diff,
-				expiration_ms: intent.deadline_ms,
+			let Some(mut quote) = build_quote(
+				&request,
+				"solver0:ref-finance",
+				format!("ref-finance-{}-{count}", request.intent_id),
+				amount_out,
+				now_ms() + request.min_deadline_ms,
+			) else {
+				tracing::info!(
+					"solver0: can't meet the floor for intent {}",
+					request.intent_id,
+				);
+				continue;
 			};
+			quote.sign(&solver0_key);
 
 			if let Err(e) = solver0_quote_producer.send(quote).await {
 				tracing::warn!("solver0 failed to send quote: {e}");
 			}
-			count += 1;
-			if count >= 3 {
-				break;
-			}
 		}
 		tracing::info!("solver0 finished");
 	});
 
 	// Solver1: "jumbo-exchange" solver - provides multi-hop routing
+	// Slightly worse pricing than solver0
+	let solver1_pricing = ConstantSpread {
+		rates: demo_rates(),
+		bps: 100,
+	};
+	let solver1_key = SigningKey::from_bytes(&rand::random());
 	let solver1_task = tokio::spawn(async move {
-		let mut count = 0u32;
-		while let Some(intent) = solver1_intent_consumer.next().await {
+		// One RFQ per demo intent.
+		for count in 0..3u32 {
+			let Some(request) = solver1_rfq_consumer.next().await else {
+				break;
+			};
 			tracing::info!(
-				"solver1 received intent id={} from {}",
-				intent.id,
-				intent.signer_id
+				"solver1 received RFQ for intent {}: {} {} -> {}",
+				request.intent_id,
+				request.exact_amount_in,
+				request.asset_in,
+				request.asset_out,
 			);
 
-			// Only handle TokenDiff intents
-			let Some(user_diff) = intent.token_diff() else {
-				tracing::warn!("solver1: skipping non-TokenDiff intent {}", intent.id);
+			let Some(amount_out) = solver1_pricing.quote_for(&request) else {
+				tracing::warn!("solver1: no price for intent {}", request.intent_id);
 				continue;
 			};
-
-			let mut solver_diff = BTreeMap::new();
-			for (asset, &amount) in user_diff {
-				solver_diff.insert(asset.clone(), -amount);
-			}
-
-			let amount_out: u128 = user_diff
-				.values()
-				.filter(|&&v| v > 0)
-				.map(|&v| v as u128)
-				.sum();
-
-			let quote = Quote {
-				intent_id: intent.id,
-				quote_hash: format!("jumbo-{}-{count}", intent.id),
-				solver_id: "solver1:jumbo-exchange".into(),
-				// Slightly worse pricing than solver0
-				amount_out: amount_out.saturating_sub(10) + u128::from(count) * 3,
-				solver_token_diff: solver_diff,
-				expiration_ms: intent.deadline_ms,
+			let Some(mut quote) = build_quote(
+				&request,
+				"solver1:jumbo-exchange",
+				format!("jumbo-{}-{count}", request.intent_id),
+				amount_out,
+				now_ms() + request.min_deadline_ms,
+			) else {
+				tracing::info!(
+					"solver1: can't meet the floor for intent {}",
+					request.intent_id,
+				);
+				continue;
 			};
+			quote.sign(&solver1_key);
 
 			if let Err(e) = solver1_quote_producer.send(quote).await {
 				tracing::warn!("solver1 failed to send quote: {e}");
 			}
-			count += 1;
-			if count >= 3 {
-				break;
-			}
 		}
 		tracing::info!("solver1 finished");
 	});
@@ -273,188 +397,301 @@ async fn main() -> anyhow::Result<()> {
 	// --- 7. User submits token_diff intents ---
 	tracing::info!("submitting intents...");
 
-	let now_ms = std::time::SystemTime::now()
-		.duration_since(std::time::UNIX_EPOCH)
-		.unwrap()
-		.as_millis() as u64;
+	let now = now_ms();
+
+	// Each user signs their intents with their own ed25519 key.
+	let alice_key = SigningKey::from_bytes(&rand::random());
+	let bob_key = SigningKey::from_bytes(&rand::random());
+	let charlie_key = SigningKey::from_bytes(&rand::random());
 
 	// Intent 1: Swap 1000 USDC for NEAR (min 950 NEAR)
-	intent_producer
-		.send(Intent {
-			id: 1,
-			signer_id: "alice.near".into(),
-			action: IntentAction::TokenDiff {
-				diff: BTreeMap::from([
-					("nep141:usdc.near".into(), -1000),
-					("nep141:wrap.near".into(), 950),
-				]),
-			},
-			verifying_contract: "intents.near".into(),
-			deadline_ms: now_ms + 120_000,
-			min_quote_deadline_ms: 60_000,
-		})
-		.await?;
+	let mut intent = Intent {
+		id: IntentId::from(1),
+		signer_id: "alice.near".into(),
+		sequence: 1,
+		action: IntentAction::TokenDiff {
+			diff: BTreeMap::from([
+				(AssetId::Nep141("usdc.near".into()), -1000),
+				(AssetId::Nep141("wrap.near".into()), 950),
+			]),
+		},
+		verifying_contract: "intents.near".into(),
+		deadline_ms: now + 120_000,
+		min_quote_deadline_ms: 60_000,
+		priority_fee: 0,
+		group_id: None,
+		limit_price: None,
+		depends_on: None,
+		signature: Vec::new(),
+		public_key: Vec::new(),
+	};
+	intent.sign(&alice_key);
+	intent_producer.send(intent).await?;
 
 	// Intent 2: Swap 500 USDC for wETH (cross-chain bridge intent)
-	intent_producer
-		.send(Intent {
-			id: 2,
-			signer_id: "bob.near".into(),
-			action: IntentAction::TokenDiff {
-				diff: BTreeMap::from([
-					("nep141:usdc.near".into(), -500),
-					("nep141:aurora.weth.near".into(), 15),
-				]),
-			},
-			verifying_contract: "intents.near".into(),
-			deadline_ms: now_ms + 180_000,
-			min_quote_deadline_ms: 60_000,
-		})
-		.await?;
+	let mut intent = Intent {
+		id: IntentId::from(2),
+		signer_id: "bob.near".into(),
+		sequence: 1,
+		action: IntentAction::TokenDiff {
+			diff: BTreeMap::from([
+				(AssetId::Nep141("usdc.near".into()), -500),
+				// wETH delivered on Ethereum through the bridge.
+				("nep141:aurora.weth.near@eth".parse()?, 15),
+			]),
+		},
+		verifying_contract: "intents.near".into(),
+		deadline_ms: now + 180_000,
+		min_quote_deadline_ms: 60_000,
+		priority_fee: 0,
+		group_id: None,
+		limit_price: None,
+		depends_on: None,
+		signature: Vec::new(),
+		public_key: Vec::new(),
+	};
+	intent.sign(&bob_key);
+	intent_producer.send(intent).await?;
 
 	// Intent 3: Swap 2000 NEAR for stNEAR (liquid staking)
-	intent_producer
-		.send(Intent {
-			id: 3,
-			signer_id: "charlie.near".into(),
-			action: IntentAction::TokenDiff {
-				diff: BTreeMap::from([
-					("nep141:wrap.near".into(), -2000),
-					("nep141:meta-pool.near".into(), 1900),
-				]),
-			},
-			verifying_contract: "intents.near".into(),
-			deadline_ms: now_ms + 150_000,
-			min_quote_deadline_ms: 60_000,
-		})
-		.await?;
+	let mut intent = Intent {
+		id: IntentId::from(3),
+		signer_id: "charlie.near".into(),
+		sequence: 1,
+		action: IntentAction::TokenDiff {
+			diff: BTreeMap::from([
+				(AssetId::Nep141("wrap.near".into()), -2000),
+				(AssetId::Nep141("meta-pool.near".into()), 1900),
+			]),
+		},
+		verifying_contract: "intents.near".into(),
+		deadline_ms: now + 150_000,
+		min_quote_deadline_ms: 60_000,
+		priority_fee: 0,
+		group_id: None,
+		limit_price: None,
+		depends_on: None,
+		signature: Vec::new(),
+		public_key: Vec::new(),
+	};
+	intent.sign(&charlie_key);
+	intent_producer.send(intent).await?;
 
 	tracing::info!("all intents submitted");
 
-	// --- 8. Auctioneer consumes intents and feeds to Raft group ---
+	// --- 8. Auctioneer routes intents to their shard and broadcasts RFQs ---
+	// Solvers post their bonds on every shard so their quotes are
+	// accepted wherever the intent lands.
+	for client in &clients {
+		let solvers = ["solver0:ref-finance", "solver1:jumbo-exchange"];
+		for (nonce, solver_id) in (1..).zip(solvers) {
+			client
+				.execute(AuctionCommand::deposit_bond(
+					&operator_key,
+					nonce,
+					solver_id,
+					10_000,
+				))
+				.await?;
+		}
+	}
+
+	// Open the round's quote window before solvers see any RFQ.
+	let window = std::time::Duration::from_millis(QUOTE_WINDOW_MS);
+	let window_closes = tokio::time::Instant::now() + window;
+	for client in &clients {
+		client
+			.execute(AuctionCommand::OpenRound {
+				deadline_ms: now_ms() + QUOTE_WINDOW_MS,
+			})
+			.await?;
+	}
+
+	// Quotes follow their intent to its shard.
+	let mut routes = BTreeMap::new();
+
 	for _ in 0..3 {
 		let intent = intent_consumer
 			.next()
 			.await
-			.expect("expected intent from stream");
+			.context("intent stream closed")?;
 		tracing::info!(
 			"auctioneer received intent id={} from {}: {:?}",
 			intent.id,
 			intent.signer_id,
 			intent.action,
 		);
-		g0.execute(AuctionCommand::SubmitIntent(intent)).await?;
-	}
-	tracing::info!("all intents submitted to auction");
+		let request = QuoteRequest::from_intent(&intent);
+		let shard = intent
+			.token_diff()
+			.map_or(0, |diff| shard_for(diff, DEMO_SHARDS));
+		tracing::info!("intent {} routed to shard {shard}", intent.id);
+		routes.insert(intent.id, shard);
+		clients[shard]
+			.execute(AuctionCommand::SubmitIntent {
+				intent,
+				now_ms: now_ms(),
+			})
+			.await?;
 
-	// Auctioneer collects quotes (2 solvers x 3 intents = 6 quotes)
-	for _ in 0..6 {
-		let quote = quote_consumer
-			.next()
-			.await
-			.expect("expected quote from stream");
+		match request {
+			Some(request) => rfq_producer.send(request).await?,
+			None => tracing::warn!("intent does not fit a single-pair RFQ"),
+		}
+	}
+	tracing::info!("all intents submitted to auction and broadcast as RFQs");
+
+	// Auctioneer collects quotes until the window closes and submits
+	// them in a single Raft proposal per shard. Solvers that can't meet an
+	// intent's floor don't quote it.
+	let mut quotes = vec![Vec::new(); DEMO_SHARDS];
+	while let Ok(next) =
+		tokio::time::timeout_at(window_closes, quote_consumer.next()).await
+	{
+		let quote = next.context("quote stream closed")?;
 		tracing::info!(
 			"auctioneer received quote from {} for intent {}: amount_out={}",
 			quote.solver_id,
 			quote.intent_id,
 			quote.amount_out,
 		);
-		g0.execute(AuctionCommand::SubmitQuote(quote)).await?;
+		let Some(&shard) = routes.get(&quote.intent_id) else {
+			tracing::warn!("quote for unknown intent {}", quote.intent_id);
+			continue;
+		};
+		quotes[shard].push(AuctionCommand::SubmitQuote {
+			quote,
+			now_ms: now_ms(),
+		});
+	}
+	for (client, quotes) in clients.iter().zip(quotes) {
+		client.execute(AuctionCommand::Batch(quotes)).await?;
 	}
 	tracing::info!("all quotes submitted to auction");
 
 	let _ = tokio::join!(solver0_task, solver1_task);
 	tracing::info!("solvers finished processing");
 
-	// --- 9. Execute ClearRound (batch settlement) ---
-	let clear_index = g0.execute(AuctionCommand::ClearRound).await?;
-	tracing::info!("round cleared at index {clear_index}");
+	// --- 9. Execute ClearRound (batch settlement) on every shard ---
+	let clear_at = now_ms();
+	for (shard, client) in clients.iter().enumerate() {
+		let preview = client
+			.query(AuctionQuery::PreviewRound { now_ms: clear_at })
+			.await?;
+		if let AuctionQueryResult::Round(Some(settlement)) = &preview {
+			tracing::info!(
+				"preview: shard {shard} round would settle {:?}",
+				settlement.settled_intents(),
+			);
+		}
 
-	g0.when().committed().reaches(clear_index).await;
+		client
+			.execute(AuctionCommand::ClearRound { now_ms: clear_at })
+			.await?;
+	}
+	tracing::info!("round cleared on every shard");
 
 	// --- 10. Query round results ---
-	let result = g0
-		.query(AuctionQuery::RoundResult(0), Consistency::Weak)
-		.await?;
+	let mut settled_shards = Vec::new();
+	for (shard, client) in clients.iter().enumerate() {
+		let result = client.query(AuctionQuery::RoundResult(0)).await?;
 
-	if let AuctionQueryResult::Round(Some(settlement)) = &result {
-		tracing::info!(
-			"round 0 settlement: settled={:?}, winners={:?}",
-			settlement.settled_intents,
-			settlement.winning_quotes,
-		);
-		tracing::info!(
-			"  aggregate token flow: {:?}",
-			settlement.aggregate_flow,
-		);
-	}
+		if let AuctionQueryResult::Round(Some(settlement)) = &result {
+			settled_shards.push(shard);
+			tracing::info!(
+				"shard {shard} round 0 settlement: settled={:?}, winners={:?}",
+				settlement.settled_intents(),
+				settlement.winning_quotes(),
+			);
+			tracing::info!(
+				"  aggregate token flow: {:?}",
+				settlement.aggregate_flow,
+			);
+			for settled in &settlement.settlements {
+				tracing::info!(
+					"  intent {} flow: {:?}",
+					settled.intent_id,
+					settled.flow.to_human(&config.asset_metadata),
+				);
+			}
+		}
 
-	let result = g0
-		.query(AuctionQuery::PendingIntents, Consistency::Weak)
-		.await?;
+		let result = client.query(AuctionQuery::PendingIntents).await?;
 
-	if let AuctionQueryResult::Intents(intents) = &result {
-		tracing::info!("{} intents still pending after round", intents.len());
+		if let AuctionQueryResult::Intents(intents) = &result {
+			tracing::info!(
+				"{} intents still pending on shard {shard} after round",
+				intents.len(),
+			);
+		}
+
+		if let AuctionQueryResult::Metrics(metrics) =
+			client.query(AuctionQuery::Metrics).await?
+		{
+			tracing::info!(
+				"shard {shard} metrics: intents submitted={} settled={} \
+				 pending={}, quotes submitted={} rejected={}, rounds \
+				 cleared={}",
+				metrics.intents_submitted,
+				metrics.intents_settled,
+				metrics.pending_count,
+				metrics.quotes_submitted,
+				metrics.quotes_rejected,
+				metrics.rounds_cleared,
+			);
+			tracing::info!("  volume: {:?}", metrics.volume);
+		}
 	}
 
-	// Query individual intent statuses
-	for id in 1..=3u64 {
-		let result = g0
-			.query(AuctionQuery::IntentStatus(id), Consistency::Weak)
-			.await?;
+	// Query individual intent statuses from the shard each was routed to
+	for (id, &shard) in &routes {
+		let result =
+			clients[shard].query(AuctionQuery::IntentStatus(*id)).await?;
 		if let AuctionQueryResult::Status(status) = &result {
 			tracing::info!("  intent {id} status: {status:?}");
 		}
 	}
 
-	let result = g0
-		.query(AuctionQuery::CurrentRound, Consistency::Weak)
-		.await?;
-
-	if let AuctionQueryResult::RoundNumber(round) = &result {
-		tracing::info!("current round: {round}");
-	}
-
-	// --- 11. Produce settlements on a Stream<Settlement> ---
-	let mut settlement_producer =
-		auctioneer0.streams().produce::<Settlement>();
-
-	// Sync user_node with auctioneer0 so it discovers the Settlement stream
-	user_node
-		.discovery()
-		.sync_with(auctioneer0.local().addr())
-		.await?;
-
-	// Create consumer before sending, so it's subscribed when data arrives
-	let mut relayer_consumer = user_node.streams().consume::<Settlement>();
-	relayer_consumer.when().subscribed().await;
-	tracing::info!("relayer subscribed to settlement stream");
+	// --- 11. Verify replication to followers ---
+	for (shard, [g0, g1, _]) in shards.iter().enumerate() {
+		g1.when().committed().reaches(g0.committed()).await;
 
-	// Verify replication to followers
-	g1.when().committed().reaches(g0.committed()).await;
-	tracing::info!("follower caught up with leader");
-
-	let result = g1
-		.query(AuctionQuery::RoundResult(0), Consistency::Weak)
-		.await?;
-	if let AuctionQueryResult::Round(Some(settlement)) = result {
-		tracing::info!(
-			"follower confirms round 0: settled={:?}",
-			settlement.settled_intents,
-		);
-		settlement_producer.send(settlement).await?;
+		// A strong read can't miss the clear that was already committed.
+		if let Some(settlement) =
+			query_settled(g1, 0, Consistency::Strong).await?
+		{
+			tracing::info!(
+				"shard {shard} follower confirms round 0: settled={:?}",
+				settlement.settled_intents(),
+			);
+		}
 	}
-
-	// --- 12. Relayer: user node receives settlements for on-chain relay ---
-	if let Some(settlement) = relayer_consumer.next().await {
-		tracing::info!(
-			"relayer received settlement: round={}, settled={:?}",
-			settlement.round,
-			settlement.settled_intents,
-		);
-		tracing::info!(
-			"  ready for on-chain settlement via Verifier contract"
-		);
+	tracing::info!("followers caught up with leaders");
+
+	// --- 12. Relayer: receives settlements for on-chain relay ---
+	// Shard 0 arrives through its outcome relay, deduplicated across
+	// leader changes; every other shard gets its own follower stream.
+	// Shards that settled nothing are skipped since their stream would
+	// never yield.
+	let mut relayed = settlements(&mut outcome_consumer);
+	for shard in settled_shards {
+		let next = if shard == 0 {
+			relayed.next().await
+		} else {
+			let relay_group = shards[shard][2].clone();
+			SettlementStream::new(relay_group, 0).next().await
+		};
+		if let Some(settlement) = next {
+			tracing::info!(
+				"relayer received shard {shard} settlement: round={}, \
+				 settled={:?}",
+				settlement.round,
+				settlement.settled_intents(),
+			);
+			tracing::info!(
+				"  ready for on-chain settlement via Verifier contract"
+			);
+		}
 	}
 
 	// --- 13. Done ---
@@ -462,6 +699,78 @@ async fn main() -> anyhow::Result<()> {
 	Ok(())
 }
 
+/// The auction config every demo auctioneer runs with.
+fn demo_config() -> AuctionConfig {
+	AuctionConfig {
+		max_retained_rounds: Some(1_000),
+		max_intents_per_signer_per_round: Some(16),
+		max_assets_per_diff: Some(16),
+		max_quotes_per_intent: Some(8),
+		max_total_quotes: Some(10_000),
+		allowed_assets: None,
+		denied_assets: BTreeSet::new(),
+		surplus_policy: SurplusPolicy::KeepSolver,
+		surplus_rounding: SurplusRounding::Solver,
+		fee_bps: 0,
+		allow_split_fills: false,
+		min_solver_bond: 1_000,
+		solver_liveness_ms: None,
+		bond_slash_amount: 100,
+		max_settlements_per_round: Some(100),
+		min_quotes_to_settle: 1,
+		asset_metadata: demo_asset_metadata(),
+		operator_keys: BTreeSet::new(),
+		reference_rates: BTreeMap::new(),
+		allow_zero_spread: false,
+		gas_per_transfer: 5_000_000_000_000,
+		quote_selection: QuoteSelection::BestPrice,
+		tie_break: TieBreak::ArrivalOrder,
+		intent_ordering: IntentOrdering::ById,
+		preferred_solvers: BTreeSet::new(),
+		preferred_tolerance_bps: 0,
+		allow_carryover: false,
+		transfer_fee_bps: BTreeMap::new(),
+		clearing_mode: ClearingMode::Batch,
+		reputation: ReputationParams::default(),
+		reveal_delay_ms: None,
+	}
+}
+
+/// Display metadata for the demo's assets.
+fn demo_asset_metadata() -> AssetRegistry {
+	[
+		("nep141:usdc.near", 6, "USDC"),
+		("nep141:wrap.near", 24, "NEAR"),
+		("nep141:aurora.weth.near@eth", 18, "wETH"),
+		("nep141:meta-pool.near", 24, "stNEAR"),
+	]
+	.into_iter()
+	.map(|(asset, decimals, symbol)| {
+		let asset = asset.parse().expect("demo asset ids are valid");
+		(asset, AssetMetadata {
+			decimals,
+			symbol: symbol.into(),
+		})
+	})
+	.collect()
+}
+
+/// Demo price feed: the rates the demo intents ask for, per
+/// `(asset_in, asset_out)` pair.
+fn demo_rates() -> BTreeMap<(AssetId, AssetId), (u128, u128)> {
+	let usdc = AssetId::Nep141("usdc.near".into());
+	let wnear = AssetId::Nep141("wrap.near".into());
+	let weth = "nep141:aurora.weth.near@eth"
+		.parse::<AssetId>()
+		.expect("demo asset ids are valid");
+	let stnear = AssetId::Nep141("meta-pool.near".into());
+	BTreeMap::from([
+		((usdc.clone(), wnear.clone()), (95, 100)),
+		((usdc, weth), (3, 100)),
+		((wnear, stnear), (95, 100)),
+	])
+}
+
 /// Tag a network node and broadcast the signed entry to all other nodes.
 fn broadcast_tag(
 	network: &Network,
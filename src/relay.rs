@@ -0,0 +1,292 @@
+//! Publishing and following cleared settlements, and expirations, from
+//! the auctioneer group, and publishing RFQs for the intents it accepts.
+
+use {
+	crate::{
+		auction::{
+			AuctionCommand, AuctionQuery, AuctionQueryResult,
+			AuctionStateMachine,
+		},
+		types::{IntentId, Outcome, QuoteRequest, Settlement},
+	},
+	ed25519_dalek::SigningKey,
+	futures::{
+		Sink, SinkExt, Stream, StreamExt, future,
+		stream::{self, BoxStream},
+	},
+	mosaik::{Consistency, PeerId, groups::Group},
+	std::{
+		collections::{BTreeSet, VecDeque},
+		pin::Pin,
+		sync::{
+			Arc,
+			atomic::{AtomicU64, Ordering},
+		},
+		task::{Context, Poll},
+		time::Duration,
+	},
+	tokio::task::JoinHandle,
+};
+
+/// How often the relay checks the group for newly cleared rounds.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Most settlements a `SettlementStream` fetches per query.
+const PAGE_SIZE: usize = 64;
+
+/// Spawn a task that forwards every settlement the group clears, and
+/// every intent that expires, to `producer` as an [`Outcome`] once its
+/// round closes, so users get a terminal signal either way. Each round
+/// is bracketed by `RoundOpened`, sent as soon as the round opens, and
+/// `RoundClosed`.
+///
+/// Every auctioneer can run one: only the relay on `local_id` while it
+/// leads `group` publishes, so a new leader's relay takes over after a
+/// leader change. After sending a round's outcomes, the relay commits
+/// `MarkPublished` (or `MarkOpenPublished` for a round's start) and only
+/// then moves on. The cursor is part of the replicated state, so the
+/// next leader resumes after the last published round. Reads are
+/// strongly consistent so a lagging replica can't hide a cleared round.
+/// Marks are signed with `operator`, which must be one of the config's
+/// `operator_keys`.
+///
+/// A round sent just before a crash or leader change, but not yet
+/// marked, is sent again by the next leader. Read settlements through
+/// [`settlements`] to get each round exactly once.
+pub fn spawn_outcome_relay<P>(
+	group: Arc<Group<AuctionStateMachine>>,
+	local_id: PeerId,
+	operator: SigningKey,
+	mut producer: P,
+) -> JoinHandle<anyhow::Result<()>>
+where
+	P: Sink<Outcome> + Unpin + Send + 'static,
+	P::Error: std::error::Error + Send + Sync + 'static,
+{
+	tokio::spawn(async move {
+		let public_key = operator.verifying_key().to_bytes().to_vec();
+		let mut ticker = tokio::time::interval(POLL_INTERVAL);
+		loop {
+			ticker.tick().await;
+			if group.leader() != Some(local_id) {
+				continue;
+			}
+			let AuctionQueryResult::Outcomes(outcomes) = group
+				.query(AuctionQuery::UnpublishedOutcomes, Consistency::Strong)
+				.await?
+			else {
+				continue;
+			};
+			// Marks commit one at a time, so the nonce read once per poll
+			// stays ahead. If another holder of the key interleaves, a
+			// mark is ignored and its round sent again on the next poll.
+			let query = AuctionQuery::OperatorNonce(public_key.clone());
+			let AuctionQueryResult::Nonce(last_nonce) =
+				group.query(query, Consistency::Strong).await?
+			else {
+				continue;
+			};
+			let mut nonce = last_nonce.unwrap_or(0);
+
+			let mut outcomes = outcomes.into_iter().peekable();
+			while let Some((round, outcome)) = outcomes.next() {
+				// A round's start is marked on its own, since the round may
+				// still be open.
+				let opened = matches!(outcome, Outcome::RoundOpened { .. });
+				producer.send(outcome).await?;
+				let command = if opened {
+					AuctionCommand::mark_open_published(
+						&operator,
+						nonce + 1,
+						round,
+					)
+				} else if outcomes
+					.peek()
+					.is_some_and(|(next, _)| *next == round)
+				{
+					continue;
+				} else {
+					AuctionCommand::mark_published(&operator, nonce + 1, round)
+				};
+				nonce += 1;
+				let index = group.execute(command.into()).await?;
+				group.when().committed().reaches(index).await;
+				tracing::debug!("published outcomes for round {round}");
+			}
+		}
+	})
+}
+
+/// The settlements in `outcomes`, each round once.
+///
+/// The relay publishes rounds in order and repeats only ones it may not
+/// have marked published, so a settlement at or below the last round
+/// yielded is a repeat and is skipped.
+pub fn settlements<S>(outcomes: S) -> impl Stream<Item = Settlement>
+where
+	S: Stream<Item = Outcome>,
+{
+	let mut next_round = 0;
+	outcomes.filter_map(move |outcome| {
+		let settlement = match outcome {
+			Outcome::Settled(settlement) if settlement.round >= next_round => {
+				next_round = settlement.round + 1;
+				Some(settlement)
+			}
+			_ => None,
+		};
+		future::ready(settlement)
+	})
+}
+
+/// Publishes a [`QuoteRequest`] for every intent the group commits to
+/// its book, so solvers hear about exactly the intents that can settle.
+///
+/// Each request carries the current quote window's deadline in
+/// `respond_by_ms`. An intent is requested once while it stays pending;
+/// one re-queued after a failed settlement is requested again. The sent
+/// set lives in the broadcaster, so a restarted one repeats requests for
+/// intents still pending, which is harmless since a solver's new quote
+/// replaces its old one. Run a single broadcaster per group.
+pub struct RfqBroadcaster {
+	group: Arc<Group<AuctionStateMachine>>,
+
+	/// Pending intents already requested.
+	sent: BTreeSet<IntentId>,
+}
+
+impl RfqBroadcaster {
+	pub fn new(group: Arc<Group<AuctionStateMachine>>) -> Self {
+		Self {
+			group,
+			sent: BTreeSet::new(),
+		}
+	}
+
+	/// Spawn a task polling the group and sending new requests to
+	/// `producer`, until a send or query fails.
+	pub fn spawn<P>(mut self, mut producer: P) -> JoinHandle<anyhow::Result<()>>
+	where
+		P: Sink<QuoteRequest> + Unpin + Send + 'static,
+		P::Error: std::error::Error + Send + Sync + 'static,
+	{
+		tokio::spawn(async move {
+			let mut ticker = tokio::time::interval(POLL_INTERVAL);
+			loop {
+				ticker.tick().await;
+				for request in self.poll().await? {
+					let id = request.intent_id;
+					tracing::debug!("requesting quotes for intent {id}");
+					producer.send(request).await?;
+				}
+			}
+		})
+	}
+
+	/// Requests for intents pending since the last poll. Reads are
+	/// strongly consistent so only committed intents are requested.
+	async fn poll(&mut self) -> anyhow::Result<Vec<QuoteRequest>> {
+		let AuctionQueryResult::Intents(pending) = self
+			.group
+			.query(AuctionQuery::PendingIntents, Consistency::Strong)
+			.await?
+		else {
+			return Ok(Vec::new());
+		};
+		let respond_by_ms = match self
+			.group
+			.query(AuctionQuery::QuoteWindow, Consistency::Strong)
+			.await?
+		{
+			AuctionQueryResult::Deadline(deadline) => deadline,
+			_ => None,
+		};
+
+		// Forget intents that left the book, so a re-queued one is
+		// requested again.
+		let pending_ids =
+			pending.iter().map(|intent| intent.id).collect::<BTreeSet<_>>();
+		self.sent.retain(|id| pending_ids.contains(id));
+		let requests = pending
+			.iter()
+			.filter(|intent| self.sent.insert(intent.id))
+			.filter_map(QuoteRequest::from_intent)
+			.map(|request| QuoteRequest {
+				respond_by_ms,
+				..request
+			})
+			.collect();
+		Ok(requests)
+	}
+}
+
+/// The settlements a group clears, in round order, as they commit.
+///
+/// Rounds are read with `Consistency::Strong`, so a lagging replica can't
+/// skip one, and query errors (e.g. during a leader change) are retried
+/// rather than ending the stream. Rounds that settled nothing are never
+/// yielded. To resume after a restart without gaps or duplicates, build
+/// a new stream from the last [`next_round`](Self::next_round).
+pub struct SettlementStream {
+	inner: BoxStream<'static, Settlement>,
+
+	/// First round not yet yielded.
+	next_round: Arc<AtomicU64>,
+}
+
+impl SettlementStream {
+	/// Follow `group` starting at round `from_round`.
+	pub fn new(
+		group: Arc<Group<AuctionStateMachine>>,
+		from_round: u64,
+	) -> Self {
+		let next_round = Arc::new(AtomicU64::new(from_round));
+		let cursor = Arc::clone(&next_round);
+		let inner = stream::unfold(VecDeque::new(), move |mut buffered| {
+			let group = Arc::clone(&group);
+			let cursor = Arc::clone(&cursor);
+			async move {
+				loop {
+					if let Some(settlement) = buffered.pop_front() {
+						cursor.store(settlement.round + 1, Ordering::Release);
+						return Some((settlement, buffered));
+					}
+					let query = AuctionQuery::RoundHistory {
+						start: cursor.load(Ordering::Acquire),
+						limit: PAGE_SIZE,
+					};
+					match group.query(query, Consistency::Strong).await {
+						Ok(AuctionQueryResult::Rounds(rounds))
+							if !rounds.is_empty() =>
+						{
+							buffered.extend(rounds);
+						}
+						Ok(_) => tokio::time::sleep(POLL_INTERVAL).await,
+						Err(err) => {
+							tracing::warn!("settlement stream query: {err}");
+							tokio::time::sleep(POLL_INTERVAL).await;
+						}
+					}
+				}
+			}
+		})
+		.boxed();
+		Self { inner, next_round }
+	}
+
+	/// The round to resume from: one past the last settlement yielded.
+	pub fn next_round(&self) -> u64 {
+		self.next_round.load(Ordering::Acquire)
+	}
+}
+
+impl Stream for SettlementStream {
+	type Item = Settlement;
+
+	fn poll_next(
+		mut self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Settlement>> {
+		self.inner.poll_next_unpin(cx)
+	}
+}
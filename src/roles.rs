@@ -0,0 +1,498 @@
+//! Single-role nodes, so each part of the topology can run as its own
+//! process.
+//!
+//! Nodes find each other through `--peer` addresses. Every node logs its
+//! network id and address as JSON when it comes up; pass them to the
+//! next node with `--network-id` and `--peer`. A typical setup:
+//!
+//! ```text
+//! near-intents auctioneer --coordinator
+//! near-intents solver --network-id <ID> --peer <AUCTIONEER>
+//! near-intents relayer --network-id <ID> --peer <AUCTIONEER>
+//! near-intents user --network-id <ID> --peer <AUCTIONEER>
+//! ```
+
+use {
+	crate::{QUOTE_WINDOW_MS, broadcast_tag, demo_config, demo_rates, now_ms},
+	anyhow::Context,
+	clap::Args,
+	ed25519_dalek::SigningKey,
+	futures::{SinkExt, StreamExt},
+	mosaik::{discovery, primitives::Tag, *},
+	near_intents::{
+		auction::{
+			AuctionCommand, AuctionConfig, AuctionQuery, AuctionQueryResult,
+			AuctionStateMachine,
+		},
+		client::AuctionClient,
+		journal::{FileSink, spawn_leader_watch},
+		relay::{RfqBroadcaster, settlements, spawn_outcome_relay},
+		solver::{ConstantSpread, PricingStrategy, build_quote},
+		types::{
+			AssetId, Intent, IntentAction, IntentId, Outcome, Quote,
+			QuoteRequest,
+		},
+	},
+	sha2::{Digest, Sha256},
+	std::{
+		collections::{BTreeMap, BTreeSet},
+		path::PathBuf,
+		sync::{Arc, atomic::AtomicBool},
+		time::Duration,
+	},
+	tokio::time::{Instant, interval_at},
+};
+
+/// How a node joins the network.
+#[derive(Debug, Args)]
+pub struct NodeArgs {
+	/// Network to join, as logged by a running node. Omit to start a new
+	/// network.
+	#[arg(long)]
+	network_id: Option<String>,
+
+	/// Address of a running node to discover the network through, as it
+	/// logged it. May be repeated.
+	#[arg(long = "peer")]
+	peers: Vec<String>,
+}
+
+impl NodeArgs {
+	/// Bring up a node tagged with its role.
+	async fn join(&self, tag: &str) -> anyhow::Result<Network> {
+		let network_id = match &self.network_id {
+			Some(id) => {
+				serde_json::from_str(id).context("invalid --network-id")?
+			}
+			None => NetworkId::random(),
+		};
+		let network = Network::new(network_id).await?;
+		broadcast_tag(&network, tag, &[&network])?;
+		tracing::info!(
+			network_id = %serde_json::to_string(&network_id)?,
+			addr = %serde_json::to_string(&network.local().addr())?,
+			"{tag} node up",
+		);
+		Ok(network)
+	}
+
+	/// Sync with every `--peer`. Call once the node's streams exist, so
+	/// peers learn about them.
+	async fn sync(&self, network: &Network) -> anyhow::Result<()> {
+		for peer in &self.peers {
+			let addr = serde_json::from_str(peer).context("invalid --peer")?;
+			network.discovery().sync_with(addr).await?;
+		}
+		Ok(())
+	}
+}
+
+#[derive(Debug, Args)]
+pub struct AuctioneerArgs {
+	#[command(flatten)]
+	node: NodeArgs,
+
+	/// Auctioneer group to join, as logged by its first member. Omit to
+	/// start a new group.
+	#[arg(long)]
+	group_key: Option<String>,
+
+	/// Consume intents and quotes, broadcast RFQs, clear rounds and
+	/// publish outcomes. Run exactly one coordinator per group.
+	#[arg(long)]
+	coordinator: bool,
+
+	/// How long each round collects quotes before it is cleared.
+	#[arg(long, default_value_t = QUOTE_WINDOW_MS)]
+	window_ms: u64,
+
+	/// Append every settlement cleared while this replica leads to a
+	/// JSON-lines file.
+	#[arg(long)]
+	journal: Option<PathBuf>,
+
+	/// Seed of the operator key relays mark outcomes published with.
+	/// Every replica of a group must use the same.
+	#[arg(long, default_value = "relay")]
+	relay_seed: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SolverArgs {
+	#[command(flatten)]
+	node: NodeArgs,
+
+	/// Id the solver quotes under.
+	#[arg(long, default_value = "solver0:ref-finance")]
+	solver_id: String,
+
+	/// Spread kept on the demo price feed, in basis points.
+	#[arg(long, default_value_t = 0)]
+	spread_bps: u16,
+}
+
+#[derive(Debug, Args)]
+pub struct UserArgs {
+	#[command(flatten)]
+	node: NodeArgs,
+
+	/// Account signing the intent. Its demo key is derived from the
+	/// name, so every run signs with the same key, as one registered for
+	/// the account requires.
+	#[arg(long, default_value = "alice.near")]
+	signer_id: String,
+
+	/// Intent id.
+	#[arg(long, default_value_t = 1)]
+	id: u64,
+
+	/// Must exceed the signer's previous intents' sequences.
+	#[arg(long, default_value_t = 1)]
+	sequence: u64,
+
+	/// Asset to sell.
+	#[arg(long, default_value = "nep141:usdc.near")]
+	sell: AssetId,
+
+	/// Exact amount to sell.
+	#[arg(long, default_value_t = 1_000)]
+	amount_in: i128,
+
+	/// Asset to buy.
+	#[arg(long, default_value = "nep141:wrap.near")]
+	buy: AssetId,
+
+	/// Least amount to receive.
+	#[arg(long, default_value_t = 950)]
+	min_out: i128,
+
+	/// How long the intent stays valid.
+	#[arg(long, default_value_t = 120_000)]
+	ttl_ms: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct RelayerArgs {
+	#[command(flatten)]
+	node: NodeArgs,
+}
+
+/// Config for auctioneers started from the CLI. Bonds are posted out of
+/// band, so none is required, and `relay_key` is the only operator.
+fn auctioneer_config(relay_key: &SigningKey) -> AuctionConfig {
+	AuctionConfig {
+		min_solver_bond: 0,
+		operator_keys: BTreeSet::from([relay_key
+			.verifying_key()
+			.to_bytes()
+			.to_vec()]),
+		..demo_config()
+	}
+}
+
+/// Run an auctioneer replica until the process is stopped.
+pub async fn auctioneer(args: AuctioneerArgs) -> anyhow::Result<()> {
+	let network = args.node.join("auctioneer").await?;
+	let group_key = match &args.group_key {
+		Some(key) => serde_json::from_str(key).context("invalid --group-key")?,
+		None => GroupKey::random(),
+	};
+	tracing::info!(
+		group_key = %serde_json::to_string(&group_key)?,
+		"joining auctioneer group",
+	);
+	// Only the leader journals, so its replicas don't each write one.
+	let leading = Arc::new(AtomicBool::new(false));
+	let seed: [u8; 32] = Sha256::digest(args.relay_seed.as_bytes()).into();
+	let relay_key = SigningKey::from_bytes(&seed);
+	let mut machine = AuctionStateMachine::new(auctioneer_config(&relay_key))
+		.with_leadership(leading.clone());
+	if let Some(path) = &args.journal {
+		let sink = FileSink::open(path).context("can't open the journal")?;
+		machine = machine.with_sink(sink);
+	}
+	let group = Arc::new(
+		network
+			.groups()
+			.with_key(group_key)
+			.with_state_machine(machine)
+			.join(),
+	);
+	spawn_leader_watch(group.clone(), network.local().id(), leading);
+
+	// Every replica runs a relay, so publishing survives a leader change;
+	// only the leader's sends.
+	let outcome_producer = network.streams().produce::<Outcome>();
+	if !args.coordinator {
+		args.node.sync(&network).await?;
+		group.when().online().await;
+		tracing::info!("auctioneer group online, replicating");
+		let relay = spawn_outcome_relay(
+			group.clone(),
+			network.local().id(),
+			relay_key,
+			outcome_producer,
+		);
+		return relay.await?.context("settlement relay stopped");
+	}
+
+	let rfq_producer = network.streams().produce::<QuoteRequest>();
+	let solver_tag = Tag::from("solver");
+	let mut quotes = network
+		.streams()
+		.consumer::<Quote>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&solver_tag)
+		})
+		.build();
+	let user_tag = Tag::from("user");
+	let mut intents = network
+		.streams()
+		.consumer::<Intent>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&user_tag)
+		})
+		.build();
+	args.node.sync(&network).await?;
+
+	group.when().online().await;
+	tracing::info!("auctioneer group online, coordinating rounds");
+	let relay = spawn_outcome_relay(
+		group.clone(),
+		network.local().id(),
+		relay_key,
+		outcome_producer,
+	);
+	// Solvers are asked to quote only once an intent is committed.
+	let rfqs = RfqBroadcaster::new(group.clone()).spawn(rfq_producer);
+	let client = AuctionClient::new([group]);
+
+	// Errors are logged rather than fatal, so a leader change doesn't
+	// take the coordinator down.
+	let execute = async |command: AuctionCommand| {
+		let kind = command.kind();
+		if let Err(e) = client.execute(command).await {
+			tracing::warn!("{kind} failed: {e}");
+		}
+	};
+
+	let window = Duration::from_millis(args.window_ms);
+	let mut rounds = interval_at(Instant::now() + window, window);
+	execute(AuctionCommand::OpenRound {
+		deadline_ms: now_ms() + args.window_ms,
+	})
+	.await;
+	loop {
+		tokio::select! {
+			Some(intent) = intents.next() => {
+				tracing::info!(
+					"received intent {} from {}",
+					intent.id,
+					intent.signer_id,
+				);
+				execute(AuctionCommand::SubmitIntent {
+					intent,
+					now_ms: now_ms(),
+				})
+				.await;
+			}
+			Some(quote) = quotes.next() => {
+				tracing::info!(
+					"received quote from {} for intent {}",
+					quote.solver_id,
+					quote.intent_id,
+				);
+				execute(AuctionCommand::SubmitQuote {
+					quote,
+					now_ms: now_ms(),
+				})
+				.await;
+			}
+			_ = rounds.tick() => {
+				let now = now_ms();
+				// Clearing leaves expired intents in the book; sweep them
+				// so their users hear about it.
+				execute(AuctionCommand::ExpireStale { now_ms: now }).await;
+				execute(AuctionCommand::ClearRound { now_ms: now }).await;
+				// Rounds sealed under `reveal_delay_ms` are published once
+				// their delay has passed.
+				let due = AuctionQuery::DueReveals { now_ms: now };
+				if let Ok(AuctionQueryResult::RoundNumbers(rounds)) =
+					client.query(due).await
+				{
+					for round in rounds {
+						execute(AuctionCommand::RevealRound {
+							round,
+							now_ms: now,
+						})
+						.await;
+					}
+				}
+				execute(AuctionCommand::OpenRound {
+					deadline_ms: now + args.window_ms,
+				})
+				.await;
+			}
+		}
+
+		if relay.is_finished() {
+			return relay.await?.context("settlement relay stopped");
+		}
+		if rfqs.is_finished() {
+			return rfqs.await?.context("RFQ broadcaster stopped");
+		}
+	}
+}
+
+/// Quote every RFQ off the demo price feed until the process is stopped.
+pub async fn solver(args: SolverArgs) -> anyhow::Result<()> {
+	let network = args.node.join("solver").await?;
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut requests = network
+		.streams()
+		.consumer::<QuoteRequest>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&auctioneer_tag)
+		})
+		.build();
+	let mut quote_producer = network.streams().produce::<Quote>();
+	args.node.sync(&network).await?;
+
+	requests.when().subscribed().await;
+	tracing::info!("{} subscribed to RFQs", args.solver_id);
+
+	let pricing = ConstantSpread {
+		rates: demo_rates(),
+		bps: args.spread_bps,
+	};
+	// Quotes are signed so the Verifier can attribute them to us.
+	let key = SigningKey::from_bytes(&rand::random());
+	let mut count = 0u64;
+	while let Some(request) = requests.next().await {
+		// The window closed before the request reached us.
+		if request.respond_by_ms.is_some_and(|by| now_ms() > by) {
+			tracing::info!("RFQ for intent {} arrived late", request.intent_id);
+			continue;
+		}
+		let Some(amount_out) = pricing.quote_for(&request) else {
+			tracing::warn!("no price for intent {}", request.intent_id);
+			continue;
+		};
+		let Some(mut quote) = build_quote(
+			&request,
+			&args.solver_id,
+			format!("{}-{}-{count}", args.solver_id, request.intent_id),
+			amount_out,
+			now_ms() + request.min_deadline_ms,
+		) else {
+			continue;
+		};
+		quote.sign(&key);
+		tracing::info!(
+			"quoting {amount_out} {} for intent {}",
+			request.asset_out,
+			request.intent_id,
+		);
+		quote_producer.send(quote).await?;
+		count += 1;
+	}
+	Ok(())
+}
+
+/// Sign and submit one intent, then wait for it to settle or expire.
+pub async fn user(args: UserArgs) -> anyhow::Result<()> {
+	let network = args.node.join("user").await?;
+	let mut intent_producer = network.streams().produce::<Intent>();
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut outcomes = network
+		.streams()
+		.consumer::<Outcome>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&auctioneer_tag)
+		})
+		.build();
+	args.node.sync(&network).await?;
+
+	let mut intent = Intent {
+		id: IntentId::from(args.id),
+		signer_id: args.signer_id,
+		sequence: args.sequence,
+		action: IntentAction::TokenDiff {
+			diff: BTreeMap::from([
+				(args.sell, -args.amount_in),
+				(args.buy, args.min_out),
+			]),
+		},
+		verifying_contract: "intents.near".into(),
+		deadline_ms: now_ms() + args.ttl_ms,
+		min_quote_deadline_ms: 60_000,
+		priority_fee: 0,
+		group_id: None,
+		limit_price: None,
+		depends_on: None,
+		signature: Vec::new(),
+		public_key: Vec::new(),
+	};
+	let seed: [u8; 32] = Sha256::digest(intent.signer_id.as_bytes()).into();
+	intent.sign(&SigningKey::from_bytes(&seed));
+	let id = intent.id;
+
+	outcomes.when().subscribed().await;
+	intent_producer.when().subscribed().await;
+	intent_producer.send(intent).await?;
+	tracing::info!("submitted intent {id}");
+
+	while let Some(outcome) = outcomes.next().await {
+		match outcome {
+			Outcome::Settled(settlement) => {
+				let Some(settled) =
+					settlement.settlements.iter().find(|s| s.intent_id == id)
+				else {
+					continue;
+				};
+				tracing::info!(
+					"intent {id} settled in round {}: {:?}",
+					settlement.round,
+					settled.flow,
+				);
+				return Ok(());
+			}
+			Outcome::Expired { intent_id } if intent_id == id => {
+				anyhow::bail!("intent {id} expired without settling")
+			}
+			Outcome::Expired { .. }
+			| Outcome::RoundOpened { .. }
+			| Outcome::RoundClosed { .. } => {}
+		}
+	}
+	anyhow::bail!("outcome stream closed before intent {id} settled")
+}
+
+/// Log every published settlement, ready for on-chain submission.
+pub async fn relayer(args: RelayerArgs) -> anyhow::Result<()> {
+	let network = args.node.join("relayer").await?;
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut outcomes = network
+		.streams()
+		.consumer::<Outcome>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&auctioneer_tag)
+		})
+		.build();
+	args.node.sync(&network).await?;
+
+	outcomes.when().subscribed().await;
+	tracing::info!("relayer following settlements");
+	// Expirations never reach the chain, and a round a relay repeats
+	// after a leader change is submitted once.
+	let mut settlements = settlements(&mut outcomes);
+	while let Some(settlement) = settlements.next().await {
+		tracing::info!(
+			"round {}: settled={:?}, {} borsh bytes ready for the Verifier",
+			settlement.round,
+			settlement.settled_intents(),
+			settlement.to_borsh().len(),
+		);
+	}
+	Ok(())
+}
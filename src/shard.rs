@@ -0,0 +1,115 @@
+//! Sharding auctions by asset pair across several auctioneer groups.
+//!
+//! One Raft group orders every command, so it caps throughput. Intents
+//! trading unrelated pairs don't interact, so they can be auctioned by
+//! independent groups, each running its own `AuctionStateMachine`.
+//! Every node routes with [`shard_for`], so all of them send an intent,
+//! and the quotes for it, to the same group.
+
+use {
+	crate::types::{AssetId, TokenDiff},
+	sha2::{Digest, Sha256},
+	std::collections::BTreeSet,
+};
+
+/// The shard that takes intents no single pair shard owns: the last one.
+///
+/// # Panics
+///
+/// If `num_shards` is zero.
+pub fn overflow_shard(num_shards: usize) -> usize {
+	assert!(num_shards > 0, "at least one shard is required");
+	num_shards - 1
+}
+
+/// The shard, out of `num_shards`, that auctions an intent with `diff`.
+///
+/// Each pair of assets is owned by one of the first `num_shards - 1`
+/// shards, picked by a hash of the pair's sorted asset ids, so the result
+/// doesn't depend on the process or the map's insertion order. An intent
+/// goes to the shard owning all of its pairs. Baskets whose pairs are
+/// owned by different shards, and diffs moving fewer than two assets, go
+/// to the [`overflow_shard`]. With a single shard everything goes to it.
+///
+/// # Panics
+///
+/// If `num_shards` is zero.
+pub fn shard_for(diff: &TokenDiff, num_shards: usize) -> usize {
+	let overflow = overflow_shard(num_shards);
+	if overflow == 0 {
+		return 0;
+	}
+
+	let assets = diff.keys().collect::<Vec<_>>();
+	let owners = assets
+		.iter()
+		.enumerate()
+		.flat_map(|(i, a)| assets[i + 1..].iter().map(move |b| (*a, *b)))
+		.map(|(a, b)| pair_shard(a, b, overflow))
+		.collect::<BTreeSet<_>>();
+	match owners.len() {
+		1 => owners.into_iter().next().unwrap_or(overflow),
+		_ => overflow,
+	}
+}
+
+/// Which of the `pair_shards` pair shards owns `(a, b)`, with `a < b`.
+fn pair_shard(a: &AssetId, b: &AssetId, pair_shards: usize) -> usize {
+	let digest = Sha256::new()
+		.chain_update(a.to_string())
+		.chain_update([0])
+		.chain_update(b.to_string())
+		.finalize();
+	let mut prefix = [0u8; 8];
+	prefix.copy_from_slice(&digest[..8]);
+	(u64::from_be_bytes(prefix) % pair_shards as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn nep141(id: &str) -> AssetId {
+		AssetId::Nep141(id.into())
+	}
+
+	/// Selling 1_000 of `sell` for 900 of `buy`.
+	fn swap(sell: &str, buy: &str) -> TokenDiff {
+		TokenDiff::from([(nep141(sell), -1_000), (nep141(buy), 900)])
+	}
+
+	#[test]
+	fn a_pair_has_one_shard_whichever_way_it_trades() {
+		let shard = shard_for(&swap("usdc.near", "wrap.near"), 4);
+		assert!(shard < overflow_shard(4));
+		assert_eq!(shard_for(&swap("usdc.near", "wrap.near"), 4), shard);
+		assert_eq!(shard_for(&swap("wrap.near", "usdc.near"), 4), shard);
+	}
+
+	#[test]
+	fn split_baskets_and_single_assets_overflow() {
+		// Its pairs belong to shards 0, 1 and 2.
+		let basket = TokenDiff::from([
+			(nep141("usdc.near"), -1_000),
+			(nep141("usdt.near"), -1_000),
+			(nep141("wrap.near"), 1_800),
+		]);
+		assert_eq!(shard_for(&basket, 4), 3);
+
+		let single = TokenDiff::from([(nep141("usdc.near"), -1_000)]);
+		assert_eq!(shard_for(&single, 4), 3);
+		assert_eq!(shard_for(&TokenDiff::new(), 4), 3);
+	}
+
+	#[test]
+	fn one_shard_takes_everything() {
+		let basket = TokenDiff::from([
+			(nep141("usdc.near"), -1_000),
+			(nep141("usdt.near"), -1_000),
+			(nep141("wrap.near"), 1_800),
+		]);
+		for diff in [swap("usdc.near", "wrap.near"), basket, TokenDiff::new()] {
+			assert_eq!(shard_for(&diff, 1), 0);
+		}
+	}
+}
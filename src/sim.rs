@@ -0,0 +1,268 @@
+//! Offline backtesting of solver strategies.
+//!
+//! A [`Simulator`] drives an [`AuctionStateMachine`] directly, without
+//! Mosaik or Raft: each round it opens a quote window, submits the
+//! round's intents, has every solver's [`PricingStrategy`] quote the
+//! pending book and clears. Matching is the state machine's own, so a
+//! strategy wins exactly the rounds it would win against the same
+//! competition on a live auctioneer.
+
+use {
+	crate::{
+		auction::{
+			AuctionCommand, AuctionConfig, AuctionMetrics, AuctionQuery,
+			AuctionQueryResult, AuctionStateMachine,
+		},
+		solver::{PricingStrategy, build_quote},
+		types::{Intent, QuoteRequest, Settlement, TokenDiff},
+	},
+	ed25519_dalek::SigningKey,
+	mosaik::groups::StateMachine,
+	sha2::{Digest, Sha256},
+	std::collections::BTreeMap,
+};
+
+/// How long each simulated round's quote window lasts.
+const SIM_WINDOW_MS: u64 = 3_000;
+
+/// Feeds intents and solver quotes through an [`AuctionStateMachine`]
+/// round by round, keeping score per solver.
+///
+/// Intents must be signed like live ones. Solvers are given
+/// `min_solver_bond` and a heartbeat every round, and sign their quotes
+/// with a key derived from their id, so runs are reproducible. Bonds are
+/// posted under a simulator operator key added to the config.
+pub struct Simulator {
+	machine: AuctionStateMachine,
+	solvers: Vec<SimSolver>,
+	operator: SigningKey,
+
+	/// Last nonce the operator signed with.
+	operator_nonce: u64,
+
+	/// Bond deposited for each solver: the config's `min_solver_bond`.
+	bond: u128,
+
+	/// The simulated clock, in unix milliseconds.
+	now_ms: u64,
+
+	/// Rounds run so far.
+	rounds: u64,
+}
+
+struct SimSolver {
+	solver_id: String,
+	strategy: Box<dyn PricingStrategy>,
+	key: SigningKey,
+	stats: SolverStats,
+}
+
+/// How one solver fared over a simulation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SolverStats {
+	/// Quotes the solver's strategy offered.
+	pub quotes: u64,
+
+	/// Fills the solver won. A split intent counts once per solver.
+	pub wins: u64,
+
+	/// Net token flow of the solver's fills: what it received (positive)
+	/// less what it delivered (negative), per asset. Value it at
+	/// whatever reference prices the backtest assumes.
+	pub pnl: TokenDiff,
+}
+
+impl SolverStats {
+	/// Share of the solver's quotes that won, in basis points.
+	pub fn win_rate_bps(&self) -> u64 {
+		self.wins.saturating_mul(10_000).checked_div(self.quotes).unwrap_or(0)
+	}
+}
+
+/// The outcome of a simulation so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimReport {
+	pub rounds: u64,
+
+	/// The state machine's counters, including settled intents and
+	/// volume.
+	pub metrics: AuctionMetrics,
+
+	/// Per solver, by id.
+	pub solvers: BTreeMap<String, SolverStats>,
+}
+
+impl Simulator {
+	/// A simulator running an auction with `config`, its clock starting
+	/// at `start_ms`.
+	pub fn new(mut config: AuctionConfig, start_ms: u64) -> Self {
+		let operator = derive_key("simulator-operator");
+		let operator_key = operator.verifying_key().to_bytes().to_vec();
+		config.operator_keys.insert(operator_key);
+		Self {
+			bond: config.min_solver_bond,
+			machine: AuctionStateMachine::new(config),
+			solvers: Vec::new(),
+			operator,
+			operator_nonce: 0,
+			now_ms: start_ms,
+			rounds: 0,
+		}
+	}
+
+	/// Add a solver quoting with `strategy`.
+	pub fn with_solver(
+		mut self,
+		solver_id: impl Into<String>,
+		strategy: impl PricingStrategy + 'static,
+	) -> Self {
+		let solver_id = solver_id.into();
+		self.operator_nonce += 1;
+		self.machine.apply(AuctionCommand::deposit_bond(
+			&self.operator,
+			self.operator_nonce,
+			solver_id.clone(),
+			self.bond,
+		));
+		self.solvers.push(SimSolver {
+			key: derive_key(&solver_id),
+			solver_id,
+			strategy: Box::new(strategy),
+			stats: SolverStats::default(),
+		});
+		self
+	}
+
+	/// The simulated clock, in unix milliseconds.
+	pub fn now_ms(&self) -> u64 {
+		self.now_ms
+	}
+
+	/// The state machine being driven, for any further queries.
+	pub fn machine(&self) -> &AuctionStateMachine {
+		&self.machine
+	}
+
+	/// Run one round: submit `intents`, let every solver quote every
+	/// pending intent, advance the clock past the quote window and clear.
+	/// Returns the round's settlement, `None` if nothing settled.
+	pub fn run_round(
+		&mut self,
+		intents: impl IntoIterator<Item = Intent>,
+	) -> Option<Settlement> {
+		let AuctionQueryResult::RoundNumber(round) =
+			self.machine.query(AuctionQuery::CurrentRound)
+		else {
+			return None;
+		};
+		let now_ms = self.now_ms;
+		let deadline_ms = now_ms + SIM_WINDOW_MS;
+		self.machine.apply(AuctionCommand::OpenRound { deadline_ms });
+		for solver in &self.solvers {
+			self.machine.apply(AuctionCommand::SolverHeartbeat {
+				solver_id: solver.solver_id.clone(),
+				now_ms,
+			});
+		}
+		for intent in intents {
+			self.machine
+				.apply(AuctionCommand::SubmitIntent { intent, now_ms });
+		}
+
+		let pending = match self.machine.query(AuctionQuery::PendingIntents) {
+			AuctionQueryResult::Intents(pending) => pending,
+			_ => Vec::new(),
+		};
+		let requests = pending
+			.iter()
+			.filter_map(QuoteRequest::from_intent)
+			.collect::<Vec<_>>();
+		for request in &requests {
+			for solver in &mut self.solvers {
+				let Some(amount_out) = solver.strategy.quote_for(request)
+				else {
+					continue;
+				};
+				let quote_hash = format!(
+					"{}-{}-{round}",
+					solver.solver_id, request.intent_id
+				);
+				let Some(mut quote) = build_quote(
+					request,
+					&solver.solver_id,
+					quote_hash,
+					amount_out,
+					now_ms + request.min_deadline_ms,
+				) else {
+					continue;
+				};
+				quote.sign(&solver.key);
+				solver.stats.quotes += 1;
+				self.machine
+					.apply(AuctionCommand::SubmitQuote { quote, now_ms });
+			}
+		}
+
+		self.now_ms = deadline_ms;
+		self.machine.apply(AuctionCommand::ClearRound {
+			now_ms: deadline_ms,
+		});
+		self.rounds += 1;
+
+		// A round sealed under `reveal_delay_ms` isn't scored.
+		let AuctionQueryResult::Round(settlement) =
+			self.machine.query(AuctionQuery::RoundResult(round))
+		else {
+			return None;
+		};
+		let settlement = settlement?;
+		for settled in &settlement.settlements {
+			for fill in &settled.fills {
+				let Some(solver) = self
+					.solvers
+					.iter_mut()
+					.find(|s| s.solver_id == fill.solver_id)
+				else {
+					continue;
+				};
+				solver.stats.wins += 1;
+				for (asset, &amount) in &fill.flow {
+					let pnl = solver.stats.pnl.entry(asset.clone());
+					let pnl = pnl.or_default();
+					*pnl = pnl.saturating_add(amount);
+				}
+			}
+		}
+		Some(settlement)
+	}
+
+	/// Run a round per batch of `intents`, then report.
+	pub fn run<I>(&mut self, rounds: impl IntoIterator<Item = I>) -> SimReport
+	where
+		I: IntoIterator<Item = Intent>,
+	{
+		for intents in rounds {
+			self.run_round(intents);
+		}
+		self.report()
+	}
+
+	/// Totals over every round run so far.
+	pub fn report(&self) -> SimReport {
+		SimReport {
+			rounds: self.rounds,
+			metrics: self.machine.metrics(),
+			solvers: self
+				.solvers
+				.iter()
+				.map(|s| (s.solver_id.clone(), s.stats.clone()))
+				.collect(),
+		}
+	}
+}
+
+/// A signing key derived from `name`, so simulations are reproducible.
+fn derive_key(name: &str) -> SigningKey {
+	let seed: [u8; 32] = Sha256::digest(name.as_bytes()).into();
+	SigningKey::from_bytes(&seed)
+}
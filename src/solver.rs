@@ -0,0 +1,275 @@
+//! Solver-side pricing of RFQs.
+//!
+//! A solver answers each [`QuoteRequest`] with the `amount_out` its
+//! [`PricingStrategy`] offers, wrapped into a [`Quote`] by
+//! [`build_quote`].
+
+use {
+	crate::types::{AssetId, Quote, QuoteRequest, TokenDiff, to_i128},
+	std::collections::BTreeMap,
+};
+
+/// Prices RFQs for a solver.
+pub trait PricingStrategy {
+	/// The `amount_out` of `request.asset_out` offered for the request's
+	/// `exact_amount_in`, or `None` if the pair can't be priced.
+	fn quote_for(&self, request: &QuoteRequest) -> Option<u128>;
+}
+
+/// Quotes a fixed exchange rate per pair, less a spread.
+#[derive(Debug, Clone, Default)]
+pub struct ConstantSpread {
+	/// Reference rate per `(asset_in, asset_out)` pair, as a
+	/// `(numerator, denominator)` fraction of `asset_in`.
+	pub rates: BTreeMap<(AssetId, AssetId), (u128, u128)>,
+
+	/// Spread kept by the solver, in basis points of the reference
+	/// output (capped at 10_000).
+	pub bps: u16,
+}
+
+impl PricingStrategy for ConstantSpread {
+	fn quote_for(&self, request: &QuoteRequest) -> Option<u128> {
+		let pair = (request.asset_in.clone(), request.asset_out.clone());
+		let &(num, den) = self.rates.get(&pair)?;
+		let reference =
+			request.exact_amount_in.checked_mul(num)?.checked_div(den)?;
+		let keep = 10_000 - u128::from(self.bps.min(10_000));
+		Some(reference.checked_mul(keep)? / 10_000)
+	}
+}
+
+/// Prices against the solver's own inventory as a constant-product pool:
+/// selling `in` into reserves `(r_in, r_out)` yields
+/// `r_out * in / (r_in + in)`, so larger trades get worse rates.
+#[derive(Debug, Clone, Default)]
+pub struct DepthBased {
+	/// Inventory the solver can trade per asset.
+	pub reserves: BTreeMap<AssetId, u128>,
+}
+
+impl PricingStrategy for DepthBased {
+	fn quote_for(&self, request: &QuoteRequest) -> Option<u128> {
+		let reserve_in = *self.reserves.get(&request.asset_in)?;
+		let reserve_out = *self.reserves.get(&request.asset_out)?;
+		let amount_in = request.exact_amount_in;
+		let amount_out = reserve_out
+			.checked_mul(amount_in)?
+			.checked_div(reserve_in.checked_add(amount_in)?)?;
+		(amount_out > 0).then_some(amount_out)
+	}
+}
+
+/// Wraps another strategy so it only quotes what the solver's holdings
+/// can deliver: no quote whose `amount_out` exceeds the inventory of
+/// `asset_out`, since the solver couldn't settle it.
+#[derive(Debug, Clone, Default)]
+pub struct InventoryLimited<S> {
+	/// Prices requests before the inventory check.
+	pub strategy: S,
+
+	/// What the solver holds per asset. Assets it doesn't list are
+	/// treated as a zero balance.
+	pub holdings: BTreeMap<AssetId, u128>,
+}
+
+impl<S> InventoryLimited<S> {
+	/// Apply a won quote for `request` at `amount_out` to the holdings:
+	/// the solver receives `exact_amount_in` of `asset_in` and delivers
+	/// `amount_out` of `asset_out`. `None`, leaving the holdings
+	/// untouched, if it can't deliver or the balance would overflow.
+	pub fn record_win(
+		&mut self,
+		request: &QuoteRequest,
+		amount_out: u128,
+	) -> Option<()> {
+		let out = self.holdings.get(&request.asset_out).copied().unwrap_or(0);
+		let out = out.checked_sub(amount_out)?;
+		let held = self.holdings.get(&request.asset_in).copied().unwrap_or(0);
+		let held = held.checked_add(request.exact_amount_in)?;
+		self.holdings.insert(request.asset_out.clone(), out);
+		self.holdings.insert(request.asset_in.clone(), held);
+		Some(())
+	}
+}
+
+impl<S: PricingStrategy> PricingStrategy for InventoryLimited<S> {
+	fn quote_for(&self, request: &QuoteRequest) -> Option<u128> {
+		let amount_out = self.strategy.quote_for(request)?;
+		let held = self.holdings.get(&request.asset_out).copied();
+		(held.unwrap_or(0) >= amount_out).then_some(amount_out)
+	}
+}
+
+/// Build the quote a solver sends for `request` when offering
+/// `amount_out`: it receives the user's `exact_amount_in` of `asset_in`
+/// and sends `amount_out` of `asset_out`. `None` if `amount_out` is below
+/// the request's `min_amount_out`: taking all of the input, such a quote
+/// can't be split with another, so it could never win.
+///
+/// The quote is unsigned; the solver signs it with [`Quote::sign`]
+/// before sending.
+pub fn build_quote(
+	request: &QuoteRequest,
+	solver_id: &str,
+	quote_hash: String,
+	amount_out: u128,
+	expiration_ms: u64,
+) -> Option<Quote> {
+	if amount_out < request.min_amount_out {
+		return None;
+	}
+	Some(Quote {
+		intent_id: request.intent_id,
+		quote_hash,
+		solver_id: solver_id.into(),
+		amount_out,
+		solver_token_diff: counter_diff(request, amount_out)?,
+		expiration_ms,
+		destination_chain: request.asset_out.chain().map(Into::into),
+		signature: Vec::new(),
+		public_key: Vec::new(),
+	})
+}
+
+/// A solver's counter token_diff for an RFQ.
+fn counter_diff(request: &QuoteRequest, amount_out: u128) -> Option<TokenDiff> {
+	Some(BTreeMap::from([
+		(request.asset_in.clone(), to_i128(request.exact_amount_in)?),
+		(request.asset_out.clone(), -to_i128(amount_out)?),
+	]))
+}
+
+#[cfg(test)]
+mod tests {
+	use {super::*, crate::types::IntentId};
+
+	fn usdc() -> AssetId {
+		AssetId::Nep141("usdc.near".into())
+	}
+
+	fn wnear() -> AssetId {
+		AssetId::Nep141("wrap.near".into())
+	}
+
+	/// An RFQ selling `amount_in` USDC for at least `min_out` wNEAR.
+	fn request(amount_in: u128, min_out: u128) -> QuoteRequest {
+		QuoteRequest {
+			intent_id: IntentId::from(1),
+			asset_in: usdc(),
+			asset_out: wnear(),
+			exact_amount_in: amount_in,
+			min_amount_out: min_out,
+			min_deadline_ms: 60_000,
+			respond_by_ms: None,
+		}
+	}
+
+	#[test]
+	fn constant_spread_keeps_its_bps_of_the_reference_rate() {
+		let mut pricing = ConstantSpread {
+			rates: BTreeMap::from([((usdc(), wnear()), (3, 2))]),
+			bps: 100,
+		};
+		// 1_000 USDC at 3/2 is 1_500 wNEAR, less 1%.
+		assert_eq!(pricing.quote_for(&request(1_000, 0)), Some(1_485));
+
+		// The spread is capped at everything.
+		pricing.bps = u16::MAX;
+		assert_eq!(pricing.quote_for(&request(1_000, 0)), Some(0));
+
+		// Only listed pairs, in their direction, are priced.
+		let reversed = QuoteRequest {
+			asset_in: wnear(),
+			asset_out: usdc(),
+			..request(1_000, 0)
+		};
+		assert_eq!(pricing.quote_for(&reversed), None);
+		assert_eq!(pricing.quote_for(&request(u128::MAX, 0)), None);
+	}
+
+	#[test]
+	fn depth_based_rates_worsen_with_size() {
+		let pricing = DepthBased {
+			reserves: BTreeMap::from([(usdc(), 10_000), (wnear(), 10_000)]),
+		};
+		// 10_000 * 1_000 / 11_000, rounded down, and 10_000 * 10_000 / 20_000.
+		assert_eq!(pricing.quote_for(&request(1_000, 0)), Some(909));
+		assert_eq!(pricing.quote_for(&request(10_000, 0)), Some(5_000));
+
+		// Nothing to quote for dust or an unlisted asset.
+		assert_eq!(pricing.quote_for(&request(0, 0)), None);
+		let unlisted = QuoteRequest {
+			asset_out: AssetId::Nep141("other.near".into()),
+			..request(1_000, 0)
+		};
+		assert_eq!(pricing.quote_for(&unlisted), None);
+	}
+
+	#[test]
+	fn inventory_limited_quotes_and_records_only_what_it_holds() {
+		let mut pricing = InventoryLimited {
+			strategy: ConstantSpread {
+				rates: BTreeMap::from([((usdc(), wnear()), (1, 1))]),
+				bps: 0,
+			},
+			holdings: BTreeMap::from([(wnear(), 1_500)]),
+		};
+		assert_eq!(pricing.quote_for(&request(1_000, 0)), Some(1_000));
+		assert_eq!(pricing.quote_for(&request(2_000, 0)), None);
+
+		pricing.record_win(&request(1_000, 0), 1_000).expect("held");
+		assert_eq!(pricing.holdings[&usdc()], 1_000);
+		assert_eq!(pricing.holdings[&wnear()], 500);
+		assert_eq!(pricing.quote_for(&request(1_000, 0)), None);
+
+		// A win it couldn't deliver leaves the holdings untouched.
+		let before = pricing.holdings.clone();
+		assert_eq!(pricing.record_win(&request(1_000, 0), 501), None);
+		assert_eq!(pricing.holdings, before);
+	}
+
+	#[test]
+	fn build_quote_needs_the_minimum_out() {
+		let request = request(1_000, 900);
+		assert!(build_quote(&request, "solver", "h".into(), 899, 0).is_none());
+
+		let quote = build_quote(&request, "solver", "h".into(), 900, 5)
+			.expect("meets the minimum");
+		assert_eq!(quote.intent_id, request.intent_id);
+		assert_eq!(quote.solver_id, "solver");
+		assert_eq!((quote.amount_out, quote.expiration_ms), (900, 5));
+		assert_eq!(
+			quote.solver_token_diff,
+			TokenDiff::from([(usdc(), 1_000), (wnear(), -900)])
+		);
+		assert_eq!(quote.destination_chain, None);
+	}
+
+	#[test]
+	fn inventory_limited_declines_until_a_win_funds_the_trade() {
+		let mut pricing = InventoryLimited {
+			strategy: ConstantSpread {
+				rates: BTreeMap::from([
+					((usdc(), wnear()), (1, 1)),
+					((wnear(), usdc()), (1, 1)),
+				]),
+				bps: 0,
+			},
+			holdings: BTreeMap::from([(wnear(), 1_000)]),
+		};
+		// Selling wNEAR for USDC needs USDC the solver doesn't have.
+		let reverse = QuoteRequest {
+			asset_in: wnear(),
+			asset_out: usdc(),
+			..request(800, 0)
+		};
+		assert_eq!(pricing.quote_for(&reverse), None);
+		assert_eq!(pricing.quote_for(&request(1_000, 0)), Some(1_000));
+
+		// Winning that trade brings in the USDC.
+		pricing.record_win(&request(1_000, 0), 1_000).expect("held");
+		assert_eq!(pricing.quote_for(&reverse), Some(800));
+		assert_eq!(pricing.quote_for(&request(1, 0)), None);
+	}
+}
@@ -1,15 +1,257 @@
 use {
-	serde::{Deserialize, Serialize},
-	std::collections::BTreeMap,
+	borsh::{BorshDeserialize, BorshSerialize},
+	ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey},
+	serde::{Deserialize, Deserializer, Serialize, Serializer},
+	sha2::{Digest, Sha256},
+	std::{cmp::Reverse, collections::BTreeMap, fmt, str::FromStr},
 };
 
-/// A Defuse-style asset identifier.
+/// A Defuse-style multi-token asset identifier.
 ///
-/// Format: `<standard>:<account>` e.g. `nep141:usdc.near`, `nep141:wrap.near`
+/// Renders and parses as `<standard>:<...>`:
+/// - `nep141:<contract>` for fungible tokens, e.g. `nep141:usdc.near`
+/// - `nep171:<contract>:<token_id>` for non-fungible tokens
+/// - `nep245:<contract>:<token_id>` for multi-tokens
+/// - `<asset>@<chain>` for any of the above bridged to another chain,
+///   e.g. `nep141:aurora.weth.near@eth`
 ///
-/// In the real protocol, the multi-token standard on NEAR uses identifiers
-/// like `nep141:usdc.near` for NEP-141 fungible tokens.
-pub type AssetId = String;
+/// Serialized in its string form, so it can key JSON maps. Borsh encodes
+/// the same string, as the Verifier contract keys tokens by it.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AssetId {
+	/// NEP-141 fungible token.
+	Nep141(String),
+	/// NEP-171 non-fungible token.
+	Nep171 { contract: String, token_id: String },
+	/// NEP-245 multi-token.
+	Nep245 { contract: String, token_id: String },
+	/// A NEAR asset delivered on another chain through a bridge.
+	Bridged { asset: Box<AssetId>, chain: String },
+}
+
+impl AssetId {
+	/// Whether this is a unique, non-fungible (NEP-171) token.
+	pub fn is_non_fungible(&self) -> bool {
+		match self {
+			Self::Nep171 { .. } => true,
+			Self::Bridged { asset, .. } => asset.is_non_fungible(),
+			_ => false,
+		}
+	}
+
+	/// The chain a bridged asset is delivered on, `None` for NEAR.
+	pub fn chain(&self) -> Option<&str> {
+		match self {
+			Self::Bridged { chain, .. } => Some(chain),
+			_ => None,
+		}
+	}
+}
+
+/// Error returned when parsing an [`AssetId`] from a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseAssetIdError {
+	/// The string has no `<standard>:` prefix.
+	MissingStandard,
+	/// The standard prefix is not a supported token standard.
+	UnknownStandard(String),
+	/// The contract, token id or chain is missing or empty.
+	MissingField,
+}
+
+impl fmt::Display for ParseAssetIdError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::MissingStandard => write!(f, "missing token standard prefix"),
+			Self::UnknownStandard(standard) => {
+				write!(f, "unknown token standard `{standard}`")
+			}
+			Self::MissingField => {
+				write!(f, "missing contract, token id or chain")
+			}
+		}
+	}
+}
+
+impl std::error::Error for ParseAssetIdError {}
+
+impl fmt::Display for AssetId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Nep141(contract) => write!(f, "nep141:{contract}"),
+			Self::Nep171 { contract, token_id } => {
+				write!(f, "nep171:{contract}:{token_id}")
+			}
+			Self::Nep245 { contract, token_id } => {
+				write!(f, "nep245:{contract}:{token_id}")
+			}
+			Self::Bridged { asset, chain } => write!(f, "{asset}@{chain}"),
+		}
+	}
+}
+
+/// Debug-prints as the quoted string form, which keeps logged token_diffs
+/// readable (`{"nep141:usdc.near": -1000}`).
+impl fmt::Debug for AssetId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		fmt::Debug::fmt(&self.to_string(), f)
+	}
+}
+
+impl FromStr for AssetId {
+	type Err = ParseAssetIdError;
+
+	/// The last `@` splits off the chain of a bridged asset, so token ids
+	/// may contain `@` only when bridged.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.rsplit_once('@') {
+			Some((_, "")) => Err(ParseAssetIdError::MissingField),
+			Some((asset, chain)) => Ok(Self::Bridged {
+				asset: Box::new(Self::parse_near(asset)?),
+				chain: chain.to_string(),
+			}),
+			None => Self::parse_near(s),
+		}
+	}
+}
+
+impl AssetId {
+	/// Parse an asset on NEAR itself (no `@<chain>` suffix).
+	fn parse_near(s: &str) -> Result<Self, ParseAssetIdError> {
+		let (standard, rest) =
+			s.split_once(':').ok_or(ParseAssetIdError::MissingStandard)?;
+
+		// Account ids can't contain `:`, so the first separator after the
+		// contract splits off the token id (which may itself contain `:`).
+		let contract_and_token = || match rest.split_once(':') {
+			Some((contract, token_id))
+				if !contract.is_empty() && !token_id.is_empty() =>
+			{
+				Ok((contract.to_string(), token_id.to_string()))
+			}
+			_ => Err(ParseAssetIdError::MissingField),
+		};
+
+		match standard {
+			"nep141" if !rest.is_empty() && !rest.contains(':') => {
+				Ok(Self::Nep141(rest.to_string()))
+			}
+			"nep141" => Err(ParseAssetIdError::MissingField),
+			"nep171" => {
+				let (contract, token_id) = contract_and_token()?;
+				Ok(Self::Nep171 { contract, token_id })
+			}
+			"nep245" => {
+				let (contract, token_id) = contract_and_token()?;
+				Ok(Self::Nep245 { contract, token_id })
+			}
+			other => Err(ParseAssetIdError::UnknownStandard(other.to_string())),
+		}
+	}
+}
+
+impl Serialize for AssetId {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for AssetId {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		<String as Deserialize>::deserialize(deserializer)?
+			.parse()
+			.map_err(serde::de::Error::custom)
+	}
+}
+
+impl BorshSerialize for AssetId {
+	fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+		BorshSerialize::serialize(&self.to_string(), writer)
+	}
+}
+
+impl BorshDeserialize for AssetId {
+	fn deserialize_reader<R: borsh::io::Read>(
+		reader: &mut R,
+	) -> borsh::io::Result<Self> {
+		String::deserialize_reader(reader)?.parse().map_err(|err| {
+			borsh::io::Error::new(borsh::io::ErrorKind::InvalidData, err)
+		})
+	}
+}
+
+/// A 256-bit intent nonce, as used by the real protocol.
+///
+/// Displays and parses as 64 lowercase hex characters.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
+pub struct IntentId(pub [u8; 32]);
+
+impl IntentId {
+	/// Derive a nonce from a signer's account and a per-signer sequence
+	/// number. Hashing the account in means two signers using the same
+	/// sequence never collide.
+	pub fn derive(signer_id: &str, sequence: u64) -> Self {
+		let mut hasher = Sha256::new();
+		hasher.update((signer_id.len() as u64).to_be_bytes());
+		hasher.update(signer_id.as_bytes());
+		hasher.update(sequence.to_be_bytes());
+		Self(hasher.finalize().into())
+	}
+}
+
+/// Places the value big-endian in the low bytes, so ids built from
+/// integers order numerically.
+impl From<u64> for IntentId {
+	fn from(value: u64) -> Self {
+		let mut bytes = [0u8; 32];
+		bytes[24..].copy_from_slice(&value.to_be_bytes());
+		Self(bytes)
+	}
+}
+
+impl fmt::Display for IntentId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for byte in self.0 {
+			write!(f, "{byte:02x}")?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for IntentId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "IntentId({self})")
+	}
+}
+
+/// Error returned when parsing an [`IntentId`] that is not 64 hex chars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseIntentIdError;
+
+impl fmt::Display for ParseIntentIdError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "intent id must be 64 hex characters")
+	}
+}
+
+impl std::error::Error for ParseIntentIdError {}
+
+impl FromStr for IntentId {
+	type Err = ParseIntentIdError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+			return Err(ParseIntentIdError);
+		}
+		let mut bytes = [0u8; 32];
+		for (i, byte) in bytes.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16)
+				.map_err(|_| ParseIntentIdError)?;
+		}
+		Ok(Self(bytes))
+	}
+}
 
 /// A token_diff intent: declares desired balance changes per asset.
 ///
@@ -24,6 +266,69 @@ pub type AssetId = String;
 /// by the Verifier contract.
 pub type TokenDiff = BTreeMap<AssetId, i128>;
 
+/// A token_diff amount as an unsigned amount, `None` if it is negative.
+pub fn to_u128(amount: i128) -> Option<u128> {
+	u128::try_from(amount).ok()
+}
+
+/// An unsigned amount as a token_diff amount, `None` above `i128::MAX`.
+pub fn to_i128(amount: u128) -> Option<i128> {
+	i128::try_from(amount).ok()
+}
+
+/// Display metadata for an asset. Presentational only: amounts stay raw
+/// integers everywhere else.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetMetadata {
+	/// How many of the raw amount's digits are fractional.
+	pub decimals: u8,
+
+	/// Ticker shown after rendered amounts, e.g. `USDC`.
+	pub symbol: String,
+}
+
+/// Metadata for the assets a deployment knows about.
+pub type AssetRegistry = BTreeMap<AssetId, AssetMetadata>;
+
+/// Human-readable rendering of raw amounts.
+pub trait ToHuman {
+	/// Render each amount scaled by its asset's decimals, e.g. a raw
+	/// `1_000_000` USDC at 6 decimals as `1.0 USDC`. Assets missing from
+	/// `registry` render as the raw integer.
+	fn to_human(&self, registry: &AssetRegistry) -> BTreeMap<AssetId, String>;
+}
+
+impl ToHuman for TokenDiff {
+	fn to_human(&self, registry: &AssetRegistry) -> BTreeMap<AssetId, String> {
+		self.iter()
+			.map(|(asset, &amount)| {
+				let rendered = match registry.get(asset) {
+					Some(meta) => format_amount(amount, meta),
+					None => amount.to_string(),
+				};
+				(asset.clone(), rendered)
+			})
+			.collect()
+	}
+}
+
+/// `amount` as a decimal with trailing zeros trimmed (keeping at least one
+/// fractional digit), followed by the symbol.
+fn format_amount(amount: i128, meta: &AssetMetadata) -> String {
+	let decimals = usize::from(meta.decimals);
+	let Some(scale) = 10u128.checked_pow(u32::from(meta.decimals)) else {
+		return format!("{amount} {}", meta.symbol);
+	};
+	let sign = if amount < 0 { "-" } else { "" };
+	let magnitude = amount.unsigned_abs();
+	let fraction = format!("{:0decimals$}", magnitude % scale);
+	let fraction = match fraction.trim_end_matches('0') {
+		"" => "0",
+		digits => digits,
+	};
+	format!("{sign}{}.{fraction} {}", magnitude / scale, meta.symbol)
+}
+
 /// The action type within an intent.
 ///
 /// The real protocol supports multiple intent types; `TokenDiff` is the
@@ -56,12 +361,18 @@ pub enum IntentAction {
 /// balance changes. Solvers compete to provide matching counter-diffs.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Intent {
-	/// Unique intent identifier (nonce in the real protocol is 256-bit).
-	pub id: u64,
+	/// Unique intent identifier (the protocol's 256-bit nonce).
+	pub id: IntentId,
 
 	/// The account that signed this intent.
 	pub signer_id: String,
 
+	/// Position in the signer's sequence of intents. Each must exceed
+	/// the last one accepted from the signer, so an old signed intent
+	/// can't be replayed.
+	#[serde(default)]
+	pub sequence: u64,
+
 	/// The intent action (token_diff, transfer, withdraw, etc.).
 	pub action: IntentAction,
 
@@ -77,9 +388,97 @@ pub struct Intent {
 	/// Solvers must provide quotes valid for at least this long.
 	/// Default in the real protocol is 60_000ms (1 minute).
 	pub min_quote_deadline_ms: u64,
+
+	/// Fee the signer offers for earlier settlement. `ClearRound` matches
+	/// intents in descending fee order, which decides who settles when a
+	/// round's capacity is limited.
+	#[serde(default)]
+	pub priority_fee: u128,
+
+	/// Intents sharing a group id settle all together in one round or
+	/// not at all, e.g. the legs of a multi-leg arbitrage.
+	#[serde(default)]
+	pub group_id: Option<String>,
+
+	/// Least rate the signer accepts, on top of the amounts in its
+	/// token_diff. Quotes whose rate is worse don't settle.
+	#[serde(default)]
+	pub limit_price: Option<LimitPrice>,
+
+	/// An intent this one is funded by: it only settles in a round that
+	/// also settles that one, and after it.
+	#[serde(default)]
+	pub depends_on: Option<IntentId>,
+
+	/// ed25519 signature over the intent's economic fields.
+	pub signature: Vec<u8>,
+
+	/// The ed25519 public key the signature verifies against. Binding
+	/// the key to `signer_id` is up to the Verifier's account registry.
+	pub public_key: Vec<u8>,
 }
 
 impl Intent {
+	/// Sign the intent with `secret_key`, filling in `signature` and
+	/// `public_key`.
+	pub fn sign(&mut self, secret_key: &SigningKey) {
+		self.public_key = secret_key.verifying_key().to_bytes().to_vec();
+		self.signature =
+			secret_key.sign(&self.signing_payload()).to_bytes().to_vec();
+	}
+
+	/// Check `signature` against `public_key` over the canonical payload.
+	/// Any change to the signed fields after signing fails verification.
+	pub fn verify_signature(&self) -> bool {
+		verify_ed25519(
+			&self.public_key,
+			&self.signature,
+			&self.signing_payload(),
+		)
+	}
+
+	/// Canonical serialization of the fields covered by the signature.
+	///
+	/// `sequence` is included so an old intent can't be renumbered past
+	/// the signer's replay check, `verifying_contract` so a signed intent
+	/// can't be replayed against a different Verifier, `priority_fee` so
+	/// a relayer can't raise what the signer pays, `group_id` so legs
+	/// can't be unlinked, and `limit_price` and `depends_on` so they
+	/// can't be dropped.
+	fn signing_payload(&self) -> Vec<u8> {
+		bincode::serialize(&(
+			self.id,
+			&self.signer_id,
+			self.sequence,
+			&self.action,
+			&self.verifying_contract,
+			self.deadline_ms,
+			self.priority_fee,
+			&self.group_id,
+			&self.limit_price,
+			self.depends_on,
+		))
+		.expect("intent fields are always serializable")
+	}
+
+	/// SHA-256 over the signed fields except `id`, so two intents with
+	/// the same terms hash alike whatever nonce they carry.
+	pub fn hash(&self) -> [u8; 32] {
+		let content = bincode::serialize(&(
+			&self.signer_id,
+			self.sequence,
+			&self.action,
+			&self.verifying_contract,
+			self.deadline_ms,
+			self.priority_fee,
+			&self.group_id,
+			&self.limit_price,
+			self.depends_on,
+		))
+		.expect("intent fields are always serializable");
+		Sha256::digest(content).into()
+	}
+
 	/// Extract the token_diff from this intent, if it is a TokenDiff action.
 	pub fn token_diff(&self) -> Option<&TokenDiff> {
 		match &self.action {
@@ -89,18 +488,71 @@ impl Intent {
 	}
 }
 
+/// Whether `signature` is a valid ed25519 signature of `payload` by
+/// `public_key`.
+pub(crate) fn verify_ed25519(public_key: &[u8], signature: &[u8], payload: &[u8]) -> bool {
+	let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+		return false;
+	};
+	let Ok(public_key) = VerifyingKey::from_bytes(&public_key) else {
+		return false;
+	};
+	let Ok(signature) = Signature::from_slice(signature) else {
+		return false;
+	};
+	public_key.verify_strict(payload, &signature).is_ok()
+}
+
+/// Fractional digits of [`LimitPrice::min_out_per_in`].
+pub const LIMIT_PRICE_DECIMALS: u32 = 18;
+
+/// A per-unit floor on an intent's rate: at least `min_out_per_in` of
+/// `asset_out` for each unit of `asset_in`, as a fixed-point number with
+/// [`LIMIT_PRICE_DECIMALS`] fractional digits. Unlike the token_diff's
+/// absolute minimum, it bounds the rate however much is filled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitPrice {
+	pub asset_in: AssetId,
+	pub asset_out: AssetId,
+	pub min_out_per_in: u128,
+}
+
+impl LimitPrice {
+	/// Whether trading `amount_in` for `amount_out` meets the limit.
+	/// Compared exactly, in 256 bits, so large amounts can't overflow.
+	pub fn admits(&self, amount_in: u128, amount_out: u128) -> bool {
+		let scale = 10u128.pow(LIMIT_PRICE_DECIMALS);
+		amount_in > 0
+			&& widening_mul(amount_out, scale)
+				>= widening_mul(self.min_out_per_in, amount_in)
+	}
+}
+
+/// The full product of `a` and `b` as `(high, low)` 128-bit halves,
+/// which compare like the 256-bit value.
+fn widening_mul(a: u128, b: u128) -> (u128, u128) {
+	const MASK: u128 = u64::MAX as u128;
+	let (a_hi, a_lo) = (a >> 64, a & MASK);
+	let (b_hi, b_lo) = (b >> 64, b & MASK);
+	let lo_lo = a_lo * b_lo;
+	let hi_lo = a_hi * b_lo;
+	let lo_hi = a_lo * b_hi;
+	let mid = (lo_lo >> 64) + (hi_lo & MASK) + (lo_hi & MASK);
+	let low = (lo_lo & MASK) | (mid << 64);
+	let high = a_hi * b_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+	(high, low)
+}
+
 /// An RFQ (Request for Quote) broadcast to solvers.
 ///
 /// In the real protocol, the Solver Relay broadcasts quote requests to all
-/// connected solvers and waits up to 3000ms for responses.
-///
-/// Not used directly in the demo flow (solvers derive quotes from intents),
-/// but included for protocol completeness.
+/// connected solvers and waits up to 3000ms for responses. Here the
+/// auctioneer derives one per intent with [`QuoteRequest::from_intent`]
+/// and publishes it on a `Stream<QuoteRequest>`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
 pub struct QuoteRequest {
 	/// The intent this quote request is for.
-	pub intent_id: u64,
+	pub intent_id: IntentId,
 
 	/// The asset the user is selling (negative in their token_diff).
 	pub asset_in: AssetId,
@@ -111,8 +563,50 @@ pub struct QuoteRequest {
 	/// The exact amount of asset_in the user wants to sell.
 	pub exact_amount_in: u128,
 
+	/// The least amount of asset_out the user accepts: the slippage floor.
+	/// An intent only settles if its winning quotes together send at
+	/// least this much.
+	#[serde(default)]
+	pub min_amount_out: u128,
+
 	/// Minimum deadline for the quote in milliseconds.
 	pub min_deadline_ms: u64,
+
+	/// When the round's quote window closes, as unix milliseconds. Quotes
+	/// arriving later are rejected. `None` if the publisher doesn't know.
+	#[serde(default)]
+	pub respond_by_ms: Option<u64>,
+}
+
+impl QuoteRequest {
+	/// Derive the RFQ for a single-in/single-out token_diff intent.
+	///
+	/// Returns `None` for non-TokenDiff intents and for baskets that don't
+	/// send exactly one asset and receive exactly one asset.
+	pub fn from_intent(intent: &Intent) -> Option<Self> {
+		let diff = intent.token_diff()?;
+		let mut sent = diff.iter().filter(|&(_, &amount)| amount < 0);
+		let mut received = diff.iter().filter(|&(_, &amount)| amount > 0);
+
+		let ((asset_in, amount_in), None) = (sent.next()?, sent.next()) else {
+			return None;
+		};
+		let ((asset_out, amount_out), None) =
+			(received.next()?, received.next())
+		else {
+			return None;
+		};
+
+		Some(Self {
+			intent_id: intent.id,
+			asset_in: asset_in.clone(),
+			asset_out: asset_out.clone(),
+			exact_amount_in: amount_in.unsigned_abs(),
+			min_amount_out: amount_out.unsigned_abs(),
+			min_deadline_ms: intent.min_quote_deadline_ms,
+			respond_by_ms: None,
+		})
+	}
 }
 
 /// A solver's quote response to an RFQ.
@@ -122,7 +616,7 @@ pub struct QuoteRequest {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Quote {
 	/// Links back to the original intent.
-	pub intent_id: u64,
+	pub intent_id: IntentId,
 
 	/// Unique quote identifier (hash in the real protocol).
 	pub quote_hash: String,
@@ -139,37 +633,1016 @@ pub struct Quote {
 
 	/// Expiration time for this quote (unix millis).
 	pub expiration_ms: u64,
+
+	/// The chain the solver attests it will deliver bridged outputs on.
+	/// Must match the chain of an intent's bridged outputs.
+	#[serde(default)]
+	pub destination_chain: Option<String>,
+
+	/// ed25519 signature over the quote's terms, so the Verifier can
+	/// attribute it to the solver.
+	#[serde(default)]
+	pub signature: Vec<u8>,
+
+	/// The ed25519 public key the signature verifies against. Binding
+	/// the key to `solver_id` is up to the Verifier's account registry.
+	#[serde(default)]
+	pub public_key: Vec<u8>,
+}
+
+impl Quote {
+	/// Sign the quote with `secret_key`, filling in `signature` and
+	/// `public_key`.
+	pub fn sign(&mut self, secret_key: &SigningKey) {
+		self.public_key = secret_key.verifying_key().to_bytes().to_vec();
+		self.signature =
+			secret_key.sign(&self.signing_payload()).to_bytes().to_vec();
+	}
+
+	/// Check `signature` against `public_key` over the canonical payload.
+	/// Any change to the signed fields after signing fails verification.
+	pub fn verify_signature(&self) -> bool {
+		verify_ed25519(
+			&self.public_key,
+			&self.signature,
+			&self.signing_payload(),
+		)
+	}
+
+	/// Canonical serialization of the fields covered by the signature.
+	fn signing_payload(&self) -> Vec<u8> {
+		bincode::serialize(&(
+			self.intent_id,
+			&self.quote_hash,
+			&self.solver_id,
+			self.amount_out,
+			&self.solver_token_diff,
+			self.expiration_ms,
+		))
+		.expect("quote fields are always serializable")
+	}
 }
 
 /// Intent lifecycle status, matching the real protocol.
+///
+/// Statuses change only as commands apply, never with the clock: an
+/// intent past its deadline stays `Pending` until an `ExpireStale` (or
+/// a `Drain`) moves it to `Expired`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IntentStatus {
-	/// Received but not yet settled.
+	/// Received but not yet settled. Also reported past the deadline
+	/// until the intent is expired.
 	Pending,
 	/// Settlement transaction has been broadcast.
 	TxBroadcasted,
 	/// Successfully settled on-chain.
 	Settled,
-	/// Intent was not valid or expired.
+	/// Intent was not valid.
 	NotFoundOrNotValid,
+	/// Intent's deadline passed before it settled.
+	Expired,
 }
 
 /// The result of a settlement round.
 ///
 /// In a batch auction, multiple intents can be settled atomically by
 /// combining compatible token_diffs from users and solvers.
+///
+/// The Borsh layout (see [`Settlement::to_borsh`]) is the field order
+/// below, which relayers hand to the Verifier contract as-is, so fields
+/// must only ever be appended.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(BorshSerialize, BorshDeserialize)]
 pub struct Settlement {
 	/// The auction round number.
 	pub round: u64,
 
-	/// Intent IDs that were settled in this round.
-	pub settled_intents: Vec<u64>,
-
-	/// The winning solver quote hashes.
-	pub winning_quotes: Vec<String>,
+	/// One entry per settled intent, in the order they were matched
+	/// (descending priority fee, then the configured intent ordering,
+	/// ascending intent id by default), except that an intent comes after
+	/// the one it `depends_on`. Each entry carries its own winning quotes,
+	/// so intents and quotes can never be mismatched.
+	pub settlements: Vec<SettledIntent>,
 
 	/// Aggregate token flow: the combined token_diffs for all participants.
 	/// In a valid settlement, the sum across all diffs for each asset is zero.
 	pub aggregate_flow: TokenDiff,
+
+	/// Price improvement: per asset, how much more the winning solvers
+	/// delivered than the users' requested minimums, summed over the round.
+	pub surplus: TokenDiff,
+
+	/// Gross amount of each asset moved between users and solvers.
+	pub volume: BTreeMap<AssetId, u128>,
+
+	/// Set when the on-chain settlement tx failed and the round's intents
+	/// were re-queued via `FailSettlement`.
+	pub failed: bool,
+
+	/// Protocol fees collected in the round, per asset.
+	#[serde(default)]
+	pub fees: TokenDiff,
+
+	/// Token transfers needed to settle the round on-chain: one per
+	/// nonzero entry in each user's, solver's and fee flow. Since
+	/// `aggregate_flow` nets to zero, it can't be counted from there.
+	#[serde(default)]
+	pub estimated_transfers: usize,
+
+	/// `estimated_transfers` times the configured gas per transfer, for
+	/// budgeting the relay transaction.
+	#[serde(default)]
+	pub estimated_gas: u64,
+
+	/// Intents in `settlements` that were only partly filled and stay
+	/// pending for the rest, in matching order.
+	#[serde(default)]
+	pub partial_fills: Vec<IntentId>,
+
+	/// Kept by fee-on-transfer token contracts, per asset. Part of the
+	/// zero sum: users, solvers, the protocol fee and these balance.
+	#[serde(default)]
+	pub transfer_fees: TokenDiff,
+
+	/// [`Settlement::commitment`] as of clearing. The Verifier rejects a
+	/// commitment it has already seen, so a relayer can't settle the same
+	/// round twice.
+	#[serde(default)]
+	pub commitment: [u8; 32],
+}
+
+impl Settlement {
+	/// Encode this settlement as Borsh for on-chain submission.
+	///
+	/// Amounts are fixed-width little-endian (i128/u128), maps are sorted
+	/// by key with a u32 length prefix, and asset ids are their string
+	/// form.
+	pub fn to_borsh(&self) -> Vec<u8> {
+		borsh::to_vec(self).expect("settlements are always borsh-encodable")
+	}
+
+	/// Decode a settlement produced by [`Settlement::to_borsh`].
+	pub fn from_borsh(bytes: &[u8]) -> borsh::io::Result<Settlement> {
+		borsh::from_slice(bytes)
+	}
+
+	/// SHA-256 over the Borsh encoding of the round, settled intents,
+	/// winning quotes and aggregate flow.
+	pub fn commitment(&self) -> [u8; 32] {
+		let content = borsh::to_vec(&(
+			self.round,
+			self.settled_intents(),
+			self.winning_quotes(),
+			&self.aggregate_flow,
+		))
+		.expect("settlements are always borsh-encodable");
+		Sha256::digest(content).into()
+	}
+
+	/// What is left when every settled intent's user flow, its fills'
+	/// flows, the protocol fee and the transfer fees are summed per
+	/// asset, recomputed from the entries rather than taken from
+	/// `aggregate_flow`. All zeros unless matching has a bug.
+	pub fn residual(&self) -> TokenDiff {
+		let flows = self.settlements.iter().flat_map(|settled| {
+			std::iter::once(&settled.flow)
+				.chain(settled.fills.iter().map(|fill| &fill.flow))
+				.chain(std::iter::once(&settled.fee))
+		});
+		let mut residual = TokenDiff::new();
+		for flow in flows.chain(std::iter::once(&self.transfer_fees)) {
+			for (asset, &amount) in flow {
+				let total = residual.entry(asset.clone()).or_default();
+				*total = total.saturating_add(amount);
+			}
+		}
+		residual
+	}
+
+	/// One `round,asset,amount,direction` CSV row per nonzero entry of
+	/// each settled intent's user flow, fill flows and fee, then the
+	/// round's transfer fees, in that order. `amount` is the signed
+	/// decimal amount and `direction` is `in` for what the party receives
+	/// and `out` for what it sends.
+	pub fn to_csv_rows(&self) -> Vec<String> {
+		let flows = self.settlements.iter().flat_map(|settled| {
+			std::iter::once(&settled.flow)
+				.chain(settled.fills.iter().map(|fill| &fill.flow))
+				.chain(std::iter::once(&settled.fee))
+		});
+		flows
+			.chain(std::iter::once(&self.transfer_fees))
+			.flatten()
+			.filter(|&(_, &amount)| amount != 0)
+			.map(|(asset, amount)| {
+				let direction = if *amount > 0 { "in" } else { "out" };
+				format!(
+					"{},{},{amount},{direction}",
+					self.round,
+					csv_field(&asset.to_string()),
+				)
+			})
+			.collect()
+	}
+
+	/// Ids of the intents settled in this round, in settlement order.
+	pub fn settled_intents(&self) -> Vec<IntentId> {
+		self.settlements.iter().map(|s| s.intent_id).collect()
+	}
+
+	/// Winning quote hashes in settlement order. An intent split across
+	/// several solvers contributes one hash per fill.
+	pub fn winning_quotes(&self) -> Vec<&str> {
+		self.settlements
+			.iter()
+			.flat_map(|s| &s.fills)
+			.map(|f| f.quote_hash.as_str())
+			.collect()
+	}
+}
+
+/// A definitive result for intents, or a round boundary, as published to
+/// users alongside each other in round order. A round's outcomes come
+/// between its `RoundOpened` and `RoundClosed`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Outcome {
+	/// A round settled the intents in the settlement.
+	Settled(Settlement),
+	/// The intent expired without settling and left the book.
+	Expired { intent_id: IntentId },
+	/// A round's quote window opened, closing at `deadline_ms`. Sent as
+	/// soon as it opens, so clients can count down to the clear.
+	RoundOpened { round: u64, deadline_ms: u64 },
+	/// A round closed, settling `settled_count` intents.
+	RoundClosed { round: u64, settled_count: usize },
+}
+
+/// Export `rounds` as CSV for off-chain reconciliation: a header, then
+/// [`Settlement::to_csv_rows`] for each round, one row per line.
+pub fn export_rounds_csv(rounds: &[Settlement]) -> String {
+	let mut csv = String::from("round,asset,amount,direction\n");
+	for row in rounds.iter().flat_map(Settlement::to_csv_rows) {
+		csv.push_str(&row);
+		csv.push('\n');
+	}
+	csv
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+	if field.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+/// Root of a binary SHA-256 Merkle tree over the settlements'
+/// [`Settlement::commitment`]s, in order, so a contract can check that
+/// one of many relayed settlements is included. A level with an odd
+/// number of nodes pairs its last node with [`MERKLE_PADDING`], which no
+/// leaf hashes to, so a proof for the last leaf of an odd level can't
+/// verify one index past the end. Leaves and inner nodes are hashed with
+/// distinct prefixes so neither can pass for the other. All zeros for no
+/// settlements.
+pub fn merkle_root(settlements: &[Settlement]) -> [u8; 32] {
+	let mut level = merkle_leaves(settlements);
+	if level.is_empty() {
+		return [0; 32];
+	}
+	while level.len() > 1 {
+		level = merkle_parents(&level);
+	}
+	level[0]
+}
+
+/// Sibling hashes from the leaf at `index` up to the root, for
+/// [`verify_proof`]. `None` if `index` is out of bounds.
+pub fn merkle_proof(
+	settlements: &[Settlement],
+	index: usize,
+) -> Option<Vec<[u8; 32]>> {
+	if index >= settlements.len() {
+		return None;
+	}
+	let mut level = merkle_leaves(settlements);
+	let mut index = index;
+	let mut proof = Vec::new();
+	while level.len() > 1 {
+		proof.push(level.get(index ^ 1).copied().unwrap_or(MERKLE_PADDING));
+		level = merkle_parents(&level);
+		index /= 2;
+	}
+	Some(proof)
+}
+
+/// Whether `proof` shows the settlement with `leaf_commitment` at
+/// `index` under `root`, as produced by [`merkle_proof`].
+pub fn verify_proof(
+	root: [u8; 32],
+	leaf_commitment: [u8; 32],
+	proof: &[[u8; 32]],
+	index: usize,
+) -> bool {
+	let mut index = index;
+	let mut hash = merkle_leaf(&leaf_commitment);
+	for sibling in proof {
+		hash = if index % 2 == 0 {
+			merkle_node(&hash, sibling)
+		} else {
+			merkle_node(sibling, &hash)
+		};
+		index /= 2;
+	}
+	index == 0 && hash == root
+}
+
+/// The sibling of a level's odd last node. It isn't a SHA-256 output
+/// anyone can produce, so it can't stand for a leaf.
+const MERKLE_PADDING: [u8; 32] = [0; 32];
+
+fn merkle_leaves(settlements: &[Settlement]) -> Vec<[u8; 32]> {
+	settlements
+		.iter()
+		.map(|settlement| merkle_leaf(&settlement.commitment()))
+		.collect()
+}
+
+/// The level above `level`, pairing an odd last node with
+/// [`MERKLE_PADDING`].
+fn merkle_parents(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+	level
+		.chunks(2)
+		.map(|pair| {
+			merkle_node(&pair[0], pair.get(1).unwrap_or(&MERKLE_PADDING))
+		})
+		.collect()
+}
+
+fn merkle_leaf(commitment: &[u8; 32]) -> [u8; 32] {
+	Sha256::new()
+		.chain_update([0])
+		.chain_update(commitment)
+		.finalize()
+		.into()
+}
+
+fn merkle_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+	Sha256::new()
+		.chain_update([1])
+		.chain_update(left)
+		.chain_update(right)
+		.finalize()
+		.into()
+}
+
+/// The account [`net_transfers`] pays protocol fees to.
+pub const FEE_ACCOUNT: &str = "protocol";
+
+/// The account [`net_transfers`] pays transfer fees to, standing in for
+/// the token contracts that keep them.
+pub const TRANSFER_FEE_ACCOUNT: &str = "transfer-fees";
+
+/// One token movement the relayer submits on-chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transfer {
+	pub asset: AssetId,
+	pub from: String,
+	pub to: String,
+	pub amount: u128,
+}
+
+/// Net a round's flows into the transfers that settle it.
+///
+/// Every user, solver, [`FEE_ACCOUNT`] and [`TRANSFER_FEE_ACCOUNT`] is
+/// netted per asset, so an account that both sends and receives an asset
+/// in the round moves only the difference. Then, per asset, the largest
+/// remaining payer pays the largest remaining receiver until both sides
+/// are exhausted, which takes at most one transfer fewer than there are
+/// accounts with a nonzero balance. Transfers are ordered by asset.
+pub fn net_transfers(settlement: &Settlement) -> Vec<Transfer> {
+	let mut balances = Balances::new();
+	for settled in &settlement.settlements {
+		credit(&mut balances, &settled.signer_id, &settled.flow);
+		for fill in &settled.fills {
+			credit(&mut balances, &fill.solver_id, &fill.flow);
+		}
+		credit(&mut balances, FEE_ACCOUNT, &settled.fee);
+	}
+	credit(&mut balances, TRANSFER_FEE_ACCOUNT, &settlement.transfer_fees);
+
+	let mut transfers = Vec::new();
+	for (asset, accounts) in balances {
+		// Stable sort: equal balances keep account order.
+		let side = |sign: i128| {
+			let mut side = accounts
+				.iter()
+				.filter(|&(_, balance)| balance.signum() == sign)
+				.map(|(&account, balance)| (account, balance.unsigned_abs()))
+				.collect::<Vec<_>>();
+			side.sort_by_key(|&(_, amount)| Reverse(amount));
+			side
+		};
+		let (mut payers, mut receivers) = (side(-1), side(1));
+		let (mut i, mut j) = (0, 0);
+		while i < payers.len() && j < receivers.len() {
+			let amount = payers[i].1.min(receivers[j].1);
+			transfers.push(Transfer {
+				asset: asset.clone(),
+				from: payers[i].0.to_string(),
+				to: receivers[j].0.to_string(),
+				amount,
+			});
+			payers[i].1 -= amount;
+			receivers[j].1 -= amount;
+			if payers[i].1 == 0 {
+				i += 1;
+			}
+			if receivers[j].1 == 0 {
+				j += 1;
+			}
+		}
+	}
+	transfers
+}
+
+/// Net balance per asset, then per account.
+type Balances<'a> = BTreeMap<&'a AssetId, BTreeMap<&'a str, i128>>;
+
+fn credit<'a>(
+	balances: &mut Balances<'a>,
+	account: &'a str,
+	flow: &'a TokenDiff,
+) {
+	for (asset, &amount) in flow {
+		let balance = balances.entry(asset).or_default();
+		let balance = balance.entry(account).or_default();
+		*balance = balance.saturating_add(amount);
+	}
+}
+
+/// A single intent settled in a round, bundled with the quotes that won it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct SettledIntent {
+	/// The settled intent.
+	pub intent_id: IntentId,
+
+	/// The account that signed the intent.
+	pub signer_id: String,
+
+	/// The winning quotes: a single fill unless the intent was split
+	/// across several solvers.
+	pub fills: Vec<Fill>,
+
+	/// The user's side of the settlement after the surplus policy: what
+	/// they send (negative) and receive (positive). Together with the
+	/// fills' flows, `fee` and any token transfer fees it sums to zero
+	/// per asset.
+	pub flow: TokenDiff,
+
+	/// Price improvement the winning quotes offered over the user's minimum.
+	pub surplus: TokenDiff,
+
+	/// Protocol fee taken out of the winning quotes' surplus.
+	#[serde(default)]
+	pub fee: TokenDiff,
+}
+
+impl SettledIntent {
+	/// Token transfers needed to settle this intent: one per nonzero
+	/// entry in the user's flow, each fill's flow and the fee.
+	pub fn transfer_count(&self) -> usize {
+		std::iter::once(&self.flow)
+			.chain(self.fills.iter().map(|fill| &fill.flow))
+			.chain(std::iter::once(&self.fee))
+			.flat_map(|flow| flow.values())
+			.filter(|&&amount| amount != 0)
+			.count()
+	}
+}
+
+/// One solver's part in settling an intent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Fill {
+	pub solver_id: String,
+
+	/// Hash of the quote being filled.
+	pub quote_hash: String,
+
+	/// The solver's side of the settlement: what it sends (negative) and
+	/// receives (positive).
+	pub flow: TokenDiff,
+}
+
+/// What a user paid and received for one settled intent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettlementReceipt {
+	pub intent_id: IntentId,
+
+	/// The round that settled the intent.
+	pub round: u64,
+
+	/// The winning quotes: a single fill unless the intent was split.
+	pub fills: Vec<Fill>,
+
+	/// What the user sent, as negative amounts.
+	pub amount_in: TokenDiff,
+
+	/// What the user received, as positive amounts.
+	pub amount_out: TokenDiff,
+
+	/// Price improvement the winning quotes offered over the user's minimum.
+	pub surplus: TokenDiff,
+
+	/// Protocol fee taken out of the surplus.
+	pub fee: TokenDiff,
+}
+
+impl SettlementReceipt {
+	/// The receipt for `settled`, cleared in `round`.
+	pub fn new(round: u64, settled: &SettledIntent) -> Self {
+		let (amount_in, amount_out) = settled
+			.flow
+			.iter()
+			.map(|(asset, &amount)| (asset.clone(), amount))
+			.partition(|&(_, amount)| amount < 0);
+		Self {
+			intent_id: settled.intent_id,
+			round,
+			fills: settled.fills.clone(),
+			amount_in,
+			amount_out,
+			surplus: settled.surplus.clone(),
+			fee: settled.fee.clone(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn settlement(round: u64) -> Settlement {
+		let mut settlement = Settlement {
+			round,
+			settlements: Vec::new(),
+			aggregate_flow: TokenDiff::new(),
+			surplus: TokenDiff::new(),
+			volume: BTreeMap::new(),
+			failed: false,
+			fees: TokenDiff::new(),
+			estimated_transfers: 0,
+			estimated_gas: 0,
+			partial_fills: Vec::new(),
+			transfer_fees: TokenDiff::new(),
+			commitment: [0; 32],
+		};
+		settlement.commitment = settlement.commitment();
+		settlement
+	}
+
+	#[test]
+	fn merkle_root_is_stable_and_commits_to_every_leaf() {
+		let rounds = (0..3).map(settlement).collect::<Vec<_>>();
+		let root = merkle_root(&rounds);
+		assert_eq!(root, merkle_root(&rounds.clone()));
+		assert_ne!(root, merkle_root(&rounds[..2]));
+		assert_ne!(root, merkle_root(&[&rounds[..], &rounds[2..]].concat()));
+		let mut swapped = rounds.clone();
+		swapped.swap(0, 1);
+		assert_ne!(root, merkle_root(&swapped));
+		assert_eq!(merkle_root(&[]), [0; 32]);
+	}
+
+	#[test]
+	fn merkle_proofs_verify_only_their_own_leaf() {
+		let rounds = (0..3).map(settlement).collect::<Vec<_>>();
+		let root = merkle_root(&rounds);
+		for (index, round) in rounds.iter().enumerate() {
+			let proof = merkle_proof(&rounds, index).expect("in bounds");
+			assert!(verify_proof(root, round.commitment, &proof, index));
+		}
+
+		let proof = merkle_proof(&rounds, 1).expect("in bounds");
+		let tampered = settlement(7).commitment;
+		assert!(!verify_proof(root, tampered, &proof, 1));
+		assert!(!verify_proof(root, rounds[0].commitment, &proof, 1));
+
+		// The odd last leaf can't pass for a fourth one.
+		let last = rounds[2].commitment;
+		let proof = merkle_proof(&rounds, 2).expect("in bounds");
+		assert!(!verify_proof(root, last, &proof, 3));
+		assert!(!verify_proof(root, last, &proof, 6));
+
+		assert_eq!(merkle_proof(&rounds, 3), None);
+		assert_eq!(merkle_proof(&[], 0), None);
+	}
+
+	#[test]
+	fn borsh_layout_is_pinned() {
+		let usdc = AssetId::Nep141("usdc.near".into());
+		let wnear = AssetId::Nep141("wrap.near".into());
+		let settlement = Settlement {
+			round: 7,
+			settlements: vec![SettledIntent {
+				intent_id: IntentId::from(1),
+				signer_id: "alice".into(),
+				fills: vec![Fill {
+					solver_id: "solver".into(),
+					quote_hash: "q".into(),
+					flow: TokenDiff::from([
+						(usdc.clone(), 1_000),
+						(wnear.clone(), -900),
+					]),
+				}],
+				flow: TokenDiff::from([(usdc.clone(), -1_000), (wnear, 900)]),
+				surplus: TokenDiff::new(),
+				fee: TokenDiff::new(),
+			}],
+			aggregate_flow: TokenDiff::new(),
+			surplus: TokenDiff::new(),
+			volume: BTreeMap::from([(usdc, 1_000)]),
+			failed: false,
+			fees: TokenDiff::new(),
+			estimated_transfers: 4,
+			estimated_gas: 20,
+			partial_fills: Vec::new(),
+			transfer_fees: TokenDiff::new(),
+			commitment: [0xab; 32],
+		};
+		let bytes = settlement.to_borsh();
+		let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+		let expected = concat!(
+			// Round 7.
+			"0700000000000000",
+			// One settled intent, id 1.
+			"01000000",
+			"0000000000000000000000000000000000000000000000000000000000000001",
+			// Signer "alice".
+			"05000000616c696365",
+			// One fill by "solver" for quote "q".
+			"0100000006000000736f6c7665720100000071",
+			// Its flow: +1000 usdc.near, -900 wrap.near, as i128.
+			"02000000",
+			"100000006e65703134313a757364632e6e656172",
+			"e8030000000000000000000000000000",
+			"100000006e65703134313a777261702e6e656172",
+			"7cfcffffffffffffffffffffffffffff",
+			// The user's flow: -1000 usdc.near, +900 wrap.near.
+			"02000000",
+			"100000006e65703134313a757364632e6e656172",
+			"18fcffffffffffffffffffffffffffff",
+			"100000006e65703134313a777261702e6e656172",
+			"84030000000000000000000000000000",
+			// No surplus or fee for the intent; no aggregate flow or
+			// surplus for the round.
+			"00000000000000000000000000000000",
+			// Volume: 1000 usdc.near, as u128.
+			"01000000100000006e65703134313a757364632e6e656172",
+			"e8030000000000000000000000000000",
+			// Not failed, no fees.
+			"0000000000",
+			// 4 transfers as a u64, 20 gas.
+			"04000000000000001400000000000000",
+			// No partial fills or transfer fees.
+			"0000000000000000",
+			// The commitment.
+			"abababababababababababababababababababababababababababababababab",
+		);
+		assert_eq!(hex, expected);
+		let decoded = Settlement::from_borsh(&bytes).expect("decodes");
+		assert_eq!(decoded.to_borsh(), bytes);
+	}
+
+	#[test]
+	fn asset_ids_round_trip_through_their_string_form() {
+		let nft = AssetId::Nep171 {
+			contract: "nft.near".into(),
+			token_id: "rare:1".into(),
+		};
+		let cases = [
+			("nep141:usdc.near", AssetId::Nep141("usdc.near".into())),
+			("nep171:nft.near:rare:1", nft.clone()),
+			(
+				"nep245:multi.near:gold",
+				AssetId::Nep245 {
+					contract: "multi.near".into(),
+					token_id: "gold".into(),
+				},
+			),
+			(
+				"nep171:nft.near:rare:1@eth",
+				AssetId::Bridged {
+					asset: Box::new(nft),
+					chain: "eth".into(),
+				},
+			),
+		];
+		for (text, asset) in cases {
+			assert_eq!(text.parse::<AssetId>(), Ok(asset.clone()));
+			assert_eq!(asset.to_string(), text);
+		}
+	}
+
+	#[test]
+	fn malformed_asset_ids_are_rejected() {
+		use ParseAssetIdError as E;
+		let cases = [
+			("usdc.near", E::MissingStandard),
+			("nep14:usdc.near", E::UnknownStandard("nep14".into())),
+			("nep141:", E::MissingField),
+			("nep141:usdc.near:1", E::MissingField),
+			("nep171:nft.near", E::MissingField),
+			("nep171::1", E::MissingField),
+			("nep245:multi.near:", E::MissingField),
+			("nep141:usdc.near@", E::MissingField),
+			("erc20:0xabc@eth", E::UnknownStandard("erc20".into())),
+		];
+		for (text, error) in cases {
+			assert_eq!(text.parse::<AssetId>(), Err(error), "{text}");
+		}
+	}
+
+	#[test]
+	fn derived_intent_ids_never_collide_across_signers() {
+		let alice = IntentId::derive("alice.near", 1);
+		assert_eq!(alice, IntentId::derive("alice.near", 1));
+		assert_ne!(alice, IntentId::derive("alice.near", 2));
+		assert_ne!(alice, IntentId::derive("bob.near", 1));
+		// The account's length is hashed in, so shifting bytes between
+		// the account and the sequence can't collide either.
+		assert_ne!(IntentId::derive("a", 0x62), IntentId::derive("ab", 0));
+		assert_ne!(alice, IntentId::from(1));
+	}
+
+	#[test]
+	fn intent_ids_round_trip_through_hex() {
+		let id = IntentId::from(0x0102);
+		let hex = id.to_string();
+		assert_eq!(hex, format!("{}0102", "0".repeat(60)));
+		assert_eq!(hex.parse::<IntentId>(), Ok(id));
+		// Integer ids order numerically.
+		assert!(IntentId::from(2) < IntentId::from(256));
+
+		let derived = IntentId::derive("alice.near", 7);
+		assert_eq!(derived.to_string().parse::<IntentId>(), Ok(derived));
+		assert_eq!(hex.to_uppercase().parse::<IntentId>(), Ok(id));
+		assert_eq!("01".parse::<IntentId>(), Err(ParseIntentIdError));
+		let not_hex = format!("{}zz", "0".repeat(62));
+		assert_eq!(not_hex.parse::<IntentId>(), Err(ParseIntentIdError));
+	}
+
+	#[test]
+	fn quote_requests_need_one_asset_in_and_one_out() {
+		let usdc = AssetId::Nep141("usdc.near".into());
+		let wnear = AssetId::Nep141("wrap.near".into());
+		let usdt = AssetId::Nep141("usdt.near".into());
+		let intent = |action| Intent {
+			id: IntentId::derive("alice.near", 1),
+			signer_id: "alice.near".into(),
+			sequence: 1,
+			action,
+			verifying_contract: "intents.near".into(),
+			deadline_ms: 60_000,
+			min_quote_deadline_ms: 3_000,
+			priority_fee: 0,
+			group_id: None,
+			limit_price: None,
+			depends_on: None,
+			signature: Vec::new(),
+			public_key: Vec::new(),
+		};
+		let swap = |diff: TokenDiff| intent(IntentAction::TokenDiff { diff });
+
+		let pair = swap(TokenDiff::from([
+			(usdc.clone(), -1_000),
+			(wnear.clone(), 900),
+		]));
+		let request = QuoteRequest::from_intent(&pair).expect("single pair");
+		assert_eq!(request.intent_id, pair.id);
+		assert_eq!(
+			(request.asset_in, request.asset_out),
+			(usdc.clone(), wnear.clone())
+		);
+		assert_eq!(
+			(request.exact_amount_in, request.min_amount_out),
+			(1_000, 900)
+		);
+		assert_eq!(request.min_deadline_ms, 3_000);
+		assert_eq!(request.respond_by_ms, None);
+
+		let baskets = [
+			TokenDiff::from([
+				(usdc.clone(), -1_000),
+				(wnear.clone(), 450),
+				(usdt.clone(), 450),
+			]),
+			TokenDiff::from([
+				(usdc.clone(), -500),
+				(usdt, -500),
+				(wnear.clone(), 900),
+			]),
+			TokenDiff::from([(usdc.clone(), -1_000)]),
+			TokenDiff::from([(wnear, 900)]),
+		];
+		for diff in baskets {
+			assert!(QuoteRequest::from_intent(&swap(diff)).is_none());
+		}
+		let transfer = intent(IntentAction::FtWithdraw {
+			token: usdc,
+			receiver_id: "bob.near".into(),
+			amount: 1_000,
+		});
+		assert!(QuoteRequest::from_intent(&transfer).is_none());
+	}
+
+	#[test]
+	fn amounts_render_scaled_by_their_decimals() {
+		let usdc = AssetId::Nep141("usdc.near".into());
+		let wnear = AssetId::Nep141("wrap.near".into());
+		let registry = AssetRegistry::from([(
+			usdc.clone(),
+			AssetMetadata {
+				decimals: 6,
+				symbol: "USDC".into(),
+			},
+		)]);
+		let render = |amount| {
+			TokenDiff::from([(usdc.clone(), amount)]).to_human(&registry)[&usdc]
+				.clone()
+		};
+		assert_eq!(render(1_000_000), "1.0 USDC");
+		assert_eq!(render(-1_500_000), "-1.5 USDC");
+		assert_eq!(render(1_000), "0.001 USDC");
+		assert_eq!(render(0), "0.0 USDC");
+		// Unregistered assets keep their raw amount.
+		let raw = TokenDiff::from([(wnear.clone(), 900)]).to_human(&registry);
+		assert_eq!(raw[&wnear], "900");
+	}
+
+	#[test]
+	fn rounds_export_to_csv_that_reads_back() {
+		let usdc = AssetId::Nep141("usdc.near".into());
+		let wnear = AssetId::Nep141("wrap.near".into());
+		let odd = AssetId::Nep141("odd,token.near".into());
+		let mut swap = settlement(3);
+		swap.settlements.push(SettledIntent {
+			intent_id: IntentId::derive("alice", 1),
+			signer_id: "alice".into(),
+			fills: vec![Fill {
+				solver_id: "solver".into(),
+				quote_hash: "q".into(),
+				flow: TokenDiff::from([
+					(usdc.clone(), 1_000),
+					(wnear.clone(), -955),
+				]),
+			}],
+			flow: TokenDiff::from([
+				(usdc.clone(), -1_000),
+				(wnear.clone(), 945),
+			]),
+			surplus: TokenDiff::from([(wnear.clone(), 45)]),
+			fee: TokenDiff::from([(usdc, 0), (wnear, 10)]),
+		});
+		let mut fees = settlement(4);
+		fees.transfer_fees = TokenDiff::from([(odd.clone(), i128::MAX)]);
+
+		let csv = export_rounds_csv(&[swap, fees]);
+		assert_eq!(
+			csv,
+			"round,asset,amount,direction\n\
+			 3,nep141:usdc.near,-1000,out\n\
+			 3,nep141:wrap.near,945,in\n\
+			 3,nep141:usdc.near,1000,in\n\
+			 3,nep141:wrap.near,-955,out\n\
+			 3,nep141:wrap.near,10,in\n\
+			 4,\"nep141:odd,token.near\",\
+			 170141183460469231731687303715884105727,in\n",
+		);
+
+		// Split a row on commas outside quotes.
+		let fields = |row: &str| {
+			let (mut fields, mut field, mut quoted) =
+				(Vec::new(), String::new(), false);
+			for c in row.chars() {
+				match c {
+					'"' => quoted = !quoted,
+					',' if !quoted => fields.push(std::mem::take(&mut field)),
+					c => field.push(c),
+				}
+			}
+			fields.push(field);
+			fields
+		};
+		let mut lines = csv.lines();
+		assert_eq!(
+			fields(lines.next().expect("header")),
+			["round", "asset", "amount", "direction"]
+		);
+		let rows = lines
+			.map(|row| match &fields(row)[..] {
+				[round, asset, amount, direction] => {
+					let amount = amount.parse::<i128>().expect("exact decimal");
+					assert_eq!(direction == "in", amount > 0);
+					let round = round.parse::<u64>().expect("round");
+					(round, asset.parse::<AssetId>().expect("asset"), amount)
+				}
+				other => panic!("unexpected row {other:?}"),
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(rows.len(), 6);
+		assert_eq!(rows[5], (4, odd, i128::MAX));
+	}
+
+	#[test]
+	fn amount_conversions_fail_out_of_range_instead_of_wrapping() {
+		let max = i128::MAX.unsigned_abs();
+		assert_eq!(to_u128(0), Some(0));
+		assert_eq!(to_u128(i128::MAX), Some(max));
+		assert_eq!(to_u128(-1), None);
+		assert_eq!(to_u128(i128::MIN), None);
+
+		assert_eq!(to_i128(0), Some(0));
+		assert_eq!(to_i128(max), Some(i128::MAX));
+		assert_eq!(to_i128(max + 1), None);
+		assert_eq!(to_i128(u128::MAX), None);
+	}
+
+	#[test]
+	fn net_transfers_reconcile_with_fewer_moves() {
+		let usdc = AssetId::Nep141("usdc.near".into());
+		let wnear = AssetId::Nep141("wrap.near".into());
+		// One solver fills Alice and Bob, each paying a protocol fee.
+		let settled =
+			|signer: &str, sell: i128, buy: i128, fee: i128| SettledIntent {
+				intent_id: IntentId::derive(signer, 1),
+				signer_id: signer.into(),
+				fills: vec![Fill {
+					solver_id: "solver".into(),
+					quote_hash: format!("solver-{signer}"),
+					flow: TokenDiff::from([
+						(usdc.clone(), sell),
+						(wnear.clone(), -buy - fee),
+					]),
+				}],
+				flow: TokenDiff::from([
+					(usdc.clone(), -sell),
+					(wnear.clone(), buy),
+				]),
+				surplus: TokenDiff::new(),
+				fee: TokenDiff::from([(wnear.clone(), fee)]),
+			};
+		let mut round = settlement(0);
+		round.settlements = vec![
+			settled("alice", 1_000, 945, 10),
+			settled("bob", 500, 470, 5),
+		];
+		assert!(round.residual().values().all(|&amount| amount == 0));
+
+		let transfers = net_transfers(&round);
+		let transfer =
+			|asset: &AssetId, from: &str, to: &str, amount| Transfer {
+				asset: asset.clone(),
+				from: from.into(),
+				to: to.into(),
+				amount,
+			};
+		assert_eq!(
+			transfers,
+			[
+				transfer(&usdc, "alice", "solver", 1_000),
+				transfer(&usdc, "bob", "solver", 500),
+				transfer(&wnear, "solver", "alice", 945),
+				transfer(&wnear, "solver", "bob", 470),
+				transfer(&wnear, "solver", FEE_ACCOUNT, 15),
+			],
+		);
+		// Settling the intents one by one takes ten transfers.
+		let separate = round
+			.settlements
+			.iter()
+			.map(SettledIntent::transfer_count)
+			.sum::<usize>();
+		assert!(transfers.len() < separate);
+
+		// Every account ends up where its flows put it.
+		let mut net = BTreeMap::<(&str, &AssetId), i128>::new();
+		for t in &transfers {
+			let amount = i128::try_from(t.amount).expect("small");
+			*net.entry((t.from.as_str(), &t.asset)).or_default() -= amount;
+			*net.entry((t.to.as_str(), &t.asset)).or_default() += amount;
+		}
+		let expected = BTreeMap::from([
+			(("alice", &usdc), -1_000),
+			(("alice", &wnear), 945),
+			(("bob", &usdc), -500),
+			(("bob", &wnear), 470),
+			((FEE_ACCOUNT, &wnear), 15),
+			(("solver", &usdc), 1_500),
+			(("solver", &wnear), -1_430),
+		]);
+		assert_eq!(net, expected);
+	}
 }
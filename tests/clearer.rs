@@ -0,0 +1,43 @@
+//! With an auto-clearer on every replica, only the leader's clears.
+
+mod common;
+
+use {
+	common::auctioneers,
+	mosaik::*,
+	near_intents::{
+		auction::{AuctionConfig, AuctionQuery, AuctionQueryResult},
+		clearer::spawn_auto_clearer,
+	},
+	std::time::Duration,
+};
+
+#[tokio::test]
+async fn three_replicas_clear_once_per_interval() -> anyhow::Result<()> {
+	const INTERVAL_MS: u64 = 1_000;
+	let network_id = NetworkId::random();
+	let (nodes, groups) =
+		auctioneers(network_id, 3, &AuctionConfig::default()).await?;
+	let clearers = nodes
+		.iter()
+		.zip(&groups)
+		.map(|(node, group)| {
+			spawn_auto_clearer(group.clone(), node.local().id(), INTERVAL_MS)
+		})
+		.collect::<Vec<_>>();
+
+	// Three intervals, and half of one so the third tick has committed.
+	tokio::time::sleep(Duration::from_millis(INTERVAL_MS * 7 / 2)).await;
+	for clearer in &clearers {
+		clearer.abort();
+	}
+
+	let metrics = groups[0]
+		.query(AuctionQuery::Metrics, Consistency::Strong)
+		.await?;
+	let AuctionQueryResult::Metrics(metrics) = metrics else {
+		panic!("unexpected result {metrics:?}");
+	};
+	assert_eq!(metrics.rounds_cleared, 3);
+	Ok(())
+}
@@ -0,0 +1,98 @@
+//! The single-role CLI: an auctioneer and a solver, each in its own
+//! process, settle a user's intent.
+
+use {
+	anyhow::Context,
+	std::{process::Stdio, time::Duration},
+	tokio::{
+		io::{AsyncBufReadExt, BufReader, Lines},
+		process::{Child, ChildStdout, Command},
+	},
+};
+
+/// How long a process may take to log what the test waits for.
+const STEP_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Log = Lines<BufReader<ChildStdout>>;
+
+/// Run the `near-intents` binary with `args`, its log piped back. The
+/// process is killed when the handle drops.
+fn spawn(args: &[&str]) -> anyhow::Result<(Child, Log)> {
+	let mut child = Command::new(env!("CARGO_BIN_EXE_near-intents"))
+		.args(args)
+		.env("RUST_LOG", "info")
+		.env("NO_COLOR", "1")
+		.stdout(Stdio::piped())
+		.kill_on_drop(true)
+		.spawn()?;
+	let stdout = child.stdout.take().context("stdout not piped")?;
+	Ok((child, BufReader::new(stdout).lines()))
+}
+
+/// Read `log` up to the first line containing `needle` and return it,
+/// without terminal styling.
+async fn wait_for(log: &mut Log, needle: &str) -> anyhow::Result<String> {
+	let find = async {
+		while let Some(line) = log.next_line().await? {
+			let line = strip_ansi(&line);
+			if line.contains(needle) {
+				return Ok(line);
+			}
+		}
+		anyhow::bail!("process exited before logging `{needle}`")
+	};
+	tokio::time::timeout(STEP_TIMEOUT, find).await?
+}
+
+/// Keep reading `log` so the process never blocks on a full pipe.
+fn drain(mut log: Log) {
+	tokio::spawn(
+		async move { while let Ok(Some(_)) = log.next_line().await {} },
+	);
+}
+
+/// The value logged for `name` in `line`. Nodes log their ids and
+/// addresses as compact JSON, so values hold no spaces.
+fn field(line: &str, name: &str) -> Option<String> {
+	let start = line.find(&format!(" {name}="))? + name.len() + 2;
+	let value = line[start..].split_whitespace().next()?;
+	Some(value.to_owned())
+}
+
+/// `line` without ANSI escape sequences.
+fn strip_ansi(line: &str) -> String {
+	let mut plain = String::with_capacity(line.len());
+	let mut chars = line.chars();
+	while let Some(c) = chars.next() {
+		if c == '\u{1b}' {
+			// Skip to the sequence's final letter.
+			chars.by_ref().find(char::is_ascii_alphabetic);
+		} else {
+			plain.push(c);
+		}
+	}
+	plain
+}
+
+#[tokio::test]
+async fn role_processes_settle_an_intent() -> anyhow::Result<()> {
+	let (_auctioneer, mut log) =
+		spawn(&["auctioneer", "--coordinator", "--window-ms", "1000"])?;
+	let up = wait_for(&mut log, "auctioneer node up").await?;
+	let network_id = field(&up, "network_id").context("no network id")?;
+	let addr = field(&up, "addr").context("no address")?;
+	drain(log);
+	let peer = ["--network-id", network_id.as_str(), "--peer", addr.as_str()];
+
+	// The intent is only sent once the solver listens for its RFQ.
+	let (_solver, mut log) = spawn(&[&["solver"][..], &peer].concat())?;
+	wait_for(&mut log, "subscribed to RFQs").await?;
+	drain(log);
+
+	// The user process exits successfully once its intent settles.
+	let (mut user, log) = spawn(&[&["user"][..], &peer].concat())?;
+	drain(log);
+	let status = tokio::time::timeout(STEP_TIMEOUT, user.wait()).await??;
+	assert!(status.success(), "user exited with {status}");
+	Ok(())
+}
@@ -0,0 +1,147 @@
+//! Helpers shared by the integration tests: bringing up a small Mosaik
+//! network and building signed intents and quotes.
+
+#![allow(dead_code)]
+
+use {
+	ed25519_dalek::SigningKey,
+	mosaik::{primitives::Tag, *},
+	near_intents::{
+		auction::{AuctionConfig, AuctionStateMachine},
+		types::{AssetId, Intent, IntentAction, IntentId, Quote, TokenDiff},
+	},
+	sha2::{Digest, Sha256},
+	std::sync::Arc,
+};
+
+pub use near_intents::now_ms;
+
+/// How long test intents and quotes stay valid.
+pub const TTL_MS: u64 = 120_000;
+
+/// Tag a network node and broadcast the signed entry to all other nodes.
+pub fn broadcast_tag(
+	network: &Network,
+	tag: &str,
+	all_nodes: &[&Network],
+) -> anyhow::Result<()> {
+	let me = network.discovery().me();
+	let entry = me.into_unsigned();
+	let updated = entry.add_tags(Tag::from(tag));
+	let signed = updated.sign(network.local().secret_key())?;
+	for node in all_nodes {
+		node.discovery().feed(signed.clone());
+	}
+	Ok(())
+}
+
+/// Cross-discover all networks with each other.
+pub async fn discover_all(
+	networks: impl IntoIterator<Item = &Network>,
+) -> anyhow::Result<()> {
+	let networks = networks.into_iter().collect::<Vec<_>>();
+	for (i, net_i) in networks.iter().enumerate() {
+		for (j, net_j) in networks.iter().enumerate() {
+			if i != j {
+				net_i.discovery().sync_with(net_j.local().addr()).await?;
+			}
+		}
+	}
+	Ok(())
+}
+
+/// `count` auctioneer nodes tagged "auctioneer", each joined to one
+/// group running `config`, with the group online.
+pub async fn auctioneers(
+	network_id: NetworkId,
+	count: usize,
+	config: &AuctionConfig,
+) -> anyhow::Result<(Vec<Network>, Vec<Arc<Group<AuctionStateMachine>>>)> {
+	let mut nodes = Vec::new();
+	for _ in 0..count {
+		nodes.push(Network::new(network_id).await?);
+	}
+	discover_all(&nodes).await?;
+	let all = nodes.iter().collect::<Vec<_>>();
+	for node in &nodes {
+		broadcast_tag(node, "auctioneer", &all)?;
+	}
+
+	let key = GroupKey::random();
+	let groups = nodes
+		.iter()
+		.map(|node| {
+			Arc::new(
+				node.groups()
+					.with_key(key)
+					.with_state_machine(AuctionStateMachine::new(
+						config.clone(),
+					))
+					.join(),
+			)
+		})
+		.collect::<Vec<_>>();
+	for group in &groups {
+		group.when().online().await;
+	}
+	Ok((nodes, groups))
+}
+
+/// A signing key derived from `name`.
+pub fn key(name: &str) -> SigningKey {
+	let seed: [u8; 32] = Sha256::digest(name.as_bytes()).into();
+	SigningKey::from_bytes(&seed)
+}
+
+pub fn usdc() -> AssetId {
+	AssetId::Nep141("usdc.near".into())
+}
+
+pub fn wnear() -> AssetId {
+	AssetId::Nep141("wrap.near".into())
+}
+
+/// `signer`'s intent number `seq`, selling `sell` USDC for at least
+/// `buy` wNEAR.
+pub fn intent(signer: &str, seq: u64, sell: i128, buy: i128) -> Intent {
+	let mut intent = Intent {
+		id: IntentId::derive(signer, seq),
+		signer_id: signer.into(),
+		sequence: seq,
+		action: IntentAction::TokenDiff {
+			diff: TokenDiff::from([(usdc(), -sell), (wnear(), buy)]),
+		},
+		verifying_contract: "intents.near".into(),
+		deadline_ms: now_ms() + TTL_MS,
+		min_quote_deadline_ms: 0,
+		priority_fee: 0,
+		group_id: None,
+		limit_price: None,
+		depends_on: None,
+		signature: Vec::new(),
+		public_key: Vec::new(),
+	};
+	intent.sign(&key(signer));
+	intent
+}
+
+/// `solver`'s quote taking all of `intent`'s USDC for `give` wNEAR.
+pub fn quote(solver: &str, intent: &Intent, give: i128) -> Quote {
+	let offered = intent.token_diff().expect("token diff")[&usdc()];
+	let mut quote = Quote {
+		intent_id: intent.id,
+		quote_hash: format!("{solver}-{}", intent.id),
+		solver_id: solver.into(),
+		amount_out: give.unsigned_abs(),
+		solver_token_diff: TokenDiff::from([
+			(usdc(), -offered),
+			(wnear(), -give),
+		]),
+		expiration_ms: now_ms() + TTL_MS,
+		destination_chain: None,
+		signature: Vec::new(),
+		public_key: Vec::new(),
+	};
+	quote.sign(&key(solver));
+	quote
+}
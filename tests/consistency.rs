@@ -0,0 +1,62 @@
+//! A strong read through any replica sees every clear that returned.
+
+mod common;
+
+use {
+	common::{auctioneers, intent, now_ms, quote},
+	mosaik::*,
+	near_intents::{
+		auction::{AuctionCommand, AuctionConfig},
+		client::{AuctionClient, query_settled},
+	},
+	std::sync::Arc,
+};
+
+#[tokio::test]
+async fn strong_reads_see_each_clear_once_it_returns() -> anyhow::Result<()> {
+	let network_id = NetworkId::random();
+	let (nodes, groups) =
+		auctioneers(network_id, 3, &AuctionConfig::default()).await?;
+
+	// Go through a follower, whose weak reads may lag the leader.
+	let leader = groups[0].leader().expect("group is online");
+	let follower = nodes
+		.iter()
+		.position(|node| node.local().id() != leader)
+		.expect("two of the auctioneers follow");
+	let group = &groups[follower];
+	let client = AuctionClient::new([Arc::clone(group)]);
+
+	for round in 0..5 {
+		let alice = intent("alice", round + 1, 1_000, 900);
+		let now = now_ms();
+		client
+			.execute(AuctionCommand::OpenRound {
+				deadline_ms: now + 1_000,
+			})
+			.await?;
+		client
+			.execute(AuctionCommand::SubmitIntent {
+				intent: alice.clone(),
+				now_ms: now,
+			})
+			.await?;
+		client
+			.execute(AuctionCommand::SubmitQuote {
+				quote: quote("solver", &alice, 950),
+				now_ms: now,
+			})
+			.await?;
+		client
+			.execute(AuctionCommand::ClearRound {
+				now_ms: now + 1_000,
+			})
+			.await?;
+
+		let settlement = query_settled(group, round, Consistency::Strong)
+			.await?
+			.expect("a strong read sees the clear");
+		assert_eq!(settlement.settled_intents(), [alice.id]);
+	}
+	Ok(())
+}
@@ -0,0 +1,77 @@
+//! Commands keep committing, exactly once, when the leader dies.
+
+mod common;
+
+use {
+	common::{auctioneers, intent, now_ms, quote},
+	mosaik::*,
+	near_intents::{
+		auction::{
+			AuctionCommand, AuctionConfig, AuctionQuery, AuctionQueryResult,
+		},
+		client::{AuctionClient, query_settled},
+	},
+	std::time::Duration,
+};
+
+#[tokio::test]
+async fn round_settles_after_the_leader_dies() -> anyhow::Result<()> {
+	let network_id = NetworkId::random();
+	let (mut nodes, mut groups) =
+		auctioneers(network_id, 3, &AuctionConfig::default()).await?;
+	let client = AuctionClient::new(groups.iter().cloned());
+
+	let alice = intent("alice", 1, 1_000, 900);
+	let id = alice.id;
+	let now = now_ms();
+	client
+		.execute(AuctionCommand::OpenRound {
+			deadline_ms: now + 1_000,
+		})
+		.await?;
+	client
+		.execute(AuctionCommand::SubmitIntent {
+			intent: alice.clone(),
+			now_ms: now,
+		})
+		.await?;
+	client
+		.execute(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &alice, 950),
+			now_ms: now,
+		})
+		.await?;
+
+	// Kill the leader between the quotes and the clear.
+	let leader = groups[0].leader().expect("group is online");
+	let dead = nodes
+		.iter()
+		.position(|node| node.local().id() == leader)
+		.expect("the leader is one of the auctioneers");
+	drop(nodes.remove(dead));
+	drop(groups.remove(dead));
+	let survivor = &groups[0];
+	tokio::time::timeout(Duration::from_secs(30), async {
+		while survivor.leader().is_none_or(|new| new == leader) {
+			tokio::time::sleep(Duration::from_millis(100)).await;
+		}
+	})
+	.await?;
+
+	client
+		.execute(AuctionCommand::ClearRound {
+			now_ms: now + 1_000,
+		})
+		.await?;
+	let settlement = query_settled(survivor, 0, Consistency::Strong)
+		.await?
+		.expect("round 0 settled");
+	assert_eq!(settlement.settled_intents(), [id]);
+
+	// Even if the clear was resent, it cleared one round.
+	let round = survivor
+		.query(AuctionQuery::CurrentRound, Consistency::Strong)
+		.await?;
+	assert!(matches!(round, AuctionQueryResult::RoundNumber(1)));
+	Ok(())
+}
@@ -0,0 +1,120 @@
+//! The outcome stream brackets each round's outcomes with its boundaries.
+
+mod common;
+
+use {
+	common::{
+		auctioneers, broadcast_tag, discover_all, intent, key, now_ms, quote,
+	},
+	futures::StreamExt,
+	mosaik::{discovery, primitives::Tag, *},
+	near_intents::{
+		auction::{AuctionCommand, AuctionConfig},
+		client::AuctionClient,
+		relay::spawn_outcome_relay,
+		types::Outcome,
+	},
+	std::{collections::BTreeSet, time::Duration},
+};
+
+#[tokio::test]
+async fn round_boundaries_bracket_its_settlement_once() -> anyhow::Result<()> {
+	let network_id = NetworkId::random();
+	let relay_key = key("relay");
+	let config = AuctionConfig {
+		operator_keys: BTreeSet::from([relay_key
+			.verifying_key()
+			.to_bytes()
+			.to_vec()]),
+		..AuctionConfig::default()
+	};
+	let (nodes, groups) = auctioneers(network_id, 3, &config).await?;
+	let user = Network::new(network_id).await?;
+	discover_all(nodes.iter().chain([&user])).await?;
+	let all = nodes.iter().chain([&user]).collect::<Vec<_>>();
+	for node in &nodes {
+		broadcast_tag(node, "auctioneer", &all)?;
+	}
+
+	// A relay on every replica; only the leader's publishes.
+	for (node, group) in nodes.iter().zip(&groups) {
+		let producer = node.streams().produce::<Outcome>();
+		spawn_outcome_relay(
+			group.clone(),
+			node.local().id(),
+			relay_key.clone(),
+			producer,
+		);
+	}
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut outcomes = user
+		.streams()
+		.consumer::<Outcome>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&auctioneer_tag)
+		})
+		.build();
+	outcomes.when().subscribed().minimum_of(nodes.len()).await;
+
+	let client = AuctionClient::new(groups.iter().cloned());
+	let alice = intent("alice", 1, 1_000, 900);
+	let now_ms = now_ms();
+	let deadline_ms = now_ms + 1_000;
+	client
+		.execute(AuctionCommand::OpenRound { deadline_ms })
+		.await?;
+	client
+		.execute(AuctionCommand::SubmitIntent {
+			intent: alice.clone(),
+			now_ms,
+		})
+		.await?;
+	client
+		.execute(AuctionCommand::SubmitQuote {
+			quote: quote("solver", &alice, 950),
+			now_ms,
+		})
+		.await?;
+	client
+		.execute(AuctionCommand::ClearRound {
+			now_ms: deadline_ms,
+		})
+		.await?;
+
+	let mut events = Vec::new();
+	for _ in 0..3 {
+		let next = tokio::time::timeout(Duration::from_secs(10), async {
+			outcomes.next().await
+		});
+		events.push(match next.await?.expect("outcome stream closed") {
+			Outcome::RoundOpened { round, deadline_ms } => {
+				format!("opened {round} until {deadline_ms}")
+			}
+			Outcome::Settled(settlement) => format!(
+				"settled {} with {:?}",
+				settlement.round,
+				settlement.settled_intents(),
+			),
+			Outcome::Expired { intent_id } => format!("expired {intent_id}"),
+			Outcome::RoundClosed {
+				round,
+				settled_count,
+			} => format!("closed {round} after {settled_count}"),
+		});
+	}
+	assert_eq!(
+		events,
+		[
+			format!("opened 0 until {deadline_ms}"),
+			format!("settled 0 with {:?}", [alice.id]),
+			"closed 0 after 1".to_string(),
+		],
+	);
+
+	// Each is sent once, by the leader alone.
+	let more = tokio::time::timeout(Duration::from_secs(1), async {
+		outcomes.next().await
+	});
+	assert!(more.await.is_err());
+	Ok(())
+}
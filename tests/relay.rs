@@ -0,0 +1,112 @@
+//! The outcome relay publishes each cleared round exactly once.
+
+mod common;
+
+use {
+	common::{
+		auctioneers, broadcast_tag, discover_all, intent, key, now_ms, quote,
+	},
+	futures::StreamExt,
+	mosaik::{discovery, primitives::Tag, *},
+	near_intents::{
+		auction::{
+			AuctionCommand, AuctionConfig, AuctionQuery, AuctionQueryResult,
+		},
+		client::AuctionClient,
+		relay::{settlements, spawn_outcome_relay},
+		types::Outcome,
+	},
+	std::{collections::BTreeSet, time::Duration},
+};
+
+#[tokio::test]
+async fn relayer_receives_each_cleared_round_once() -> anyhow::Result<()> {
+	let network_id = NetworkId::random();
+	let relay_key = key("relay");
+	let config = AuctionConfig {
+		operator_keys: BTreeSet::from([relay_key
+			.verifying_key()
+			.to_bytes()
+			.to_vec()]),
+		..AuctionConfig::default()
+	};
+	let (nodes, groups) = auctioneers(network_id, 3, &config).await?;
+	let relayer = Network::new(network_id).await?;
+	discover_all(nodes.iter().chain([&relayer])).await?;
+	let all = nodes.iter().chain([&relayer]).collect::<Vec<_>>();
+	for node in &nodes {
+		broadcast_tag(node, "auctioneer", &all)?;
+	}
+
+	// A relay on every replica; only the leader's publishes.
+	for (node, group) in nodes.iter().zip(&groups) {
+		let producer = node.streams().produce::<Outcome>();
+		spawn_outcome_relay(
+			group.clone(),
+			node.local().id(),
+			relay_key.clone(),
+			producer,
+		);
+	}
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut outcomes = relayer
+		.streams()
+		.consumer::<Outcome>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&auctioneer_tag)
+		})
+		.build();
+	outcomes.when().subscribed().minimum_of(nodes.len()).await;
+
+	let client = AuctionClient::new(groups.iter().cloned());
+	for signer in ["alice", "bob"] {
+		let intent = intent(signer, 1, 1_000, 900);
+		let now_ms = now_ms();
+		let quote = quote("solver", &intent, 950);
+		client
+			.execute(AuctionCommand::OpenRound {
+				deadline_ms: now_ms,
+			})
+			.await?;
+		client
+			.execute(AuctionCommand::SubmitIntent { intent, now_ms })
+			.await?;
+		client
+			.execute(AuctionCommand::SubmitQuote { quote, now_ms })
+			.await?;
+		client
+			.execute(AuctionCommand::ClearRound { now_ms })
+			.await?;
+	}
+
+	let mut settlements = settlements(&mut outcomes);
+	let mut rounds = Vec::new();
+	for _ in 0..2 {
+		let next = tokio::time::timeout(Duration::from_secs(10), async {
+			settlements.next().await
+		});
+		let settlement = next.await?.expect("outcome stream closed");
+		rounds.push(settlement.round);
+	}
+	assert_eq!(rounds, [0, 1]);
+
+	// Nothing more arrives, not even a repeat.
+	let more = tokio::time::timeout(Duration::from_secs(1), async {
+		settlements.next().await
+	});
+	assert!(more.await.is_err());
+
+	// The relay's signed marks were applied.
+	let marked = tokio::time::timeout(Duration::from_secs(10), async {
+		loop {
+			match client.query(AuctionQuery::UnpublishedRounds).await? {
+				AuctionQueryResult::Rounds(rounds) if rounds.is_empty() => {
+					return anyhow::Ok(());
+				}
+				_ => tokio::time::sleep(Duration::from_millis(100)).await,
+			}
+		}
+	});
+	marked.await??;
+	Ok(())
+}
@@ -0,0 +1,79 @@
+//! The RFQ broadcaster requests quotes for each committed intent once.
+
+mod common;
+
+use {
+	common::{
+		auctioneers, broadcast_tag, discover_all, intent, key, now_ms, usdc,
+		wnear,
+	},
+	futures::StreamExt,
+	mosaik::{discovery, primitives::Tag, *},
+	near_intents::{
+		auction::{AuctionCommand, AuctionConfig},
+		client::AuctionClient,
+		relay::RfqBroadcaster,
+		types::QuoteRequest,
+	},
+	std::time::Duration,
+};
+
+#[tokio::test]
+async fn solver_receives_a_request_for_each_submitted_intent()
+-> anyhow::Result<()> {
+	let network_id = NetworkId::random();
+	let (nodes, groups) =
+		auctioneers(network_id, 1, &AuctionConfig::default()).await?;
+	let solver = Network::new(network_id).await?;
+	discover_all(nodes.iter().chain([&solver])).await?;
+	let all = nodes.iter().chain([&solver]).collect::<Vec<_>>();
+	broadcast_tag(&nodes[0], "auctioneer", &all)?;
+
+	let producer = nodes[0].streams().produce::<QuoteRequest>();
+	RfqBroadcaster::new(groups[0].clone()).spawn(producer);
+	let auctioneer_tag = Tag::from("auctioneer");
+	let mut requests = solver
+		.streams()
+		.consumer::<QuoteRequest>()
+		.subscribe_if(move |peer: &discovery::PeerEntry| {
+			peer.tags().contains(&auctioneer_tag)
+		})
+		.build();
+	requests.when().subscribed().await;
+
+	let mut alice = intent("alice", 1, 1_000, 900);
+	alice.min_quote_deadline_ms = 5_000;
+	alice.sign(&key("alice"));
+	let now_ms = now_ms();
+	let deadline_ms = now_ms + 60_000;
+	let client = AuctionClient::new(groups.iter().cloned());
+	client
+		.execute(AuctionCommand::OpenRound { deadline_ms })
+		.await?;
+	client
+		.execute(AuctionCommand::SubmitIntent {
+			intent: alice.clone(),
+			now_ms,
+		})
+		.await?;
+
+	let next = tokio::time::timeout(Duration::from_secs(10), async {
+		requests.next().await
+	});
+	let request = next.await?.expect("request stream closed");
+	assert_eq!(request.intent_id, alice.id);
+	assert_eq!((request.asset_in, request.asset_out), (usdc(), wnear()));
+	assert_eq!(
+		(request.exact_amount_in, request.min_amount_out),
+		(1_000, 900)
+	);
+	assert_eq!(request.min_deadline_ms, 5_000);
+	assert_eq!(request.respond_by_ms, Some(deadline_ms));
+
+	// The intent is still pending, but isn't requested again.
+	let more = tokio::time::timeout(Duration::from_secs(1), async {
+		requests.next().await
+	});
+	assert!(more.await.is_err());
+	Ok(())
+}